@@ -16,6 +16,8 @@ fn default_config() -> Criterion {
 }
 
 criterion_main!(
+    src::algorithms::searching::bench,
     src::algorithms::sorting::bench,
+    src::algorithms::sorting::parallel_bench,
     src::concurrent::sync::bench,
 );
@@ -1 +1,2 @@
+pub mod searching;
 pub mod sorting;
@@ -0,0 +1,48 @@
+use lib_wc::searching::*;
+use rand_chacha::rand_core::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use rand_core::RngCore;
+
+static CAPACITY: usize = 1 << 20;
+static TARGETS: usize = 1000;
+
+/// A sorted slice of uniformly distributed values, and values to search it for
+fn values() -> (Vec<u32>, Vec<u32>) {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let mut values: Vec<u32> = (0..CAPACITY).map(|_| rng.next_u32()).collect();
+    values.sort_unstable();
+    let targets = (0..TARGETS).map(|_| rng.next_u32()).collect();
+    (values, targets)
+}
+
+macro_rules! searching_benchmark (
+    ($fn_name: ident, $search: expr) => {
+        fn $fn_name(bh: &mut criterion::Criterion) {
+            let (values, targets) = values();
+            bh.bench_function(stringify!($fn_name), move |bh| bh.iter(|| {
+                targets
+                    .iter()
+                    .map(|target| $search(&values, target))
+                    .fold(0, usize::wrapping_add)
+            }));
+        }
+    }
+);
+
+searching_benchmark!(lower_bound_search, lower_bound);
+searching_benchmark!(branchless_search, branchless_lower_bound);
+searching_benchmark!(exponential, |values, target| {
+    exponential_search(values, target).unwrap_or_else(|i| i)
+});
+searching_benchmark!(interpolation, |values, target| {
+    interpolation_search(values, target).unwrap_or_else(|i| i)
+});
+searching_benchmark!(slice_binary_search, |values: &[u32], target| {
+    values.binary_search(target).unwrap_or_else(|i| i)
+});
+
+criterion_group!(
+    name = bench;
+    config = crate::default_config();
+    targets = lower_bound_search, branchless_search, exponential, interpolation, slice_binary_search
+);
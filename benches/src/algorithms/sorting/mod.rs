@@ -1,7 +1,9 @@
+use criterion::{BatchSize, BenchmarkId, Throughput};
 use lib_wc::sorting::*;
 use rand_chacha::rand_core::SeedableRng;
 use rand_chacha::ChaCha8Rng;
 use rand_core::RngCore;
+use rayon::slice::ParallelSliceMut;
 
 static CAPACITY: usize = 10_000;
 
@@ -24,9 +26,78 @@ macro_rules! sorting_benchmark (
 sorting_benchmark!(bubble_sort, BubbleSort);
 sorting_benchmark!(quick_sort, QuickSort);
 sorting_benchmark!(insertion_sort, InsertionSort);
+sorting_benchmark!(merge_sort, MergeSort);
+sorting_benchmark!(heap_sort, HeapSort);
+sorting_benchmark!(pdq_sort, PatternDefeatingQuickSort);
+sorting_benchmark!(radix_sort, RadixSort);
+sorting_benchmark!(counting_sort, CountingSort);
+
+// Counting sort is meant for keys from a small range, and random `u32`s make it fall back to radix
+// sort
+fn counting_sort_small_range(bh: &mut criterion::Criterion) {
+    bh.bench_function("counting_sort_small_range", move |bh| {
+        bh.iter(|| {
+            let mut values: Vec<u8> = values().into_iter().map(|v| v as u8).collect();
+            CountingSort::sort(&mut values);
+        })
+    });
+}
 
 criterion_group!(
     name = bench;
     config = crate::default_config();
-    targets = bubble_sort, insertion_sort, quick_sort
+    targets = bubble_sort, insertion_sort, quick_sort, merge_sort, heap_sort, pdq_sort, radix_sort,
+        counting_sort, counting_sort_small_range
+);
+
+/// The lengths of the slices the parallel sorts are compared on
+static PARALLEL_LENGTHS: [usize; 3] = [1_000_000, 10_000_000, 100_000_000];
+
+type NamedSort = (&'static str, fn(&mut [u32]));
+
+fn random_values(len: usize) -> Vec<u32> {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    (0..len).map(|_| rng.next_u32()).collect()
+}
+
+// Compares the parallel sorts with the standard library's sort and rayon's parallel sorts
+fn parallel_sorting(bh: &mut criterion::Criterion) {
+    let sorts: [NamedSort; 5] = [
+        (
+            "parallel_merge_sort",
+            <ParallelMergeSort as Sort<u32>>::sort,
+        ),
+        (
+            "parallel_quick_sort",
+            <ParallelQuickSort as Sort<u32>>::sort,
+        ),
+        ("slice_sort_unstable", |values| values.sort_unstable()),
+        ("rayon_par_sort", |values| values.par_sort()),
+        ("rayon_par_sort_unstable", |values| {
+            values.par_sort_unstable()
+        }),
+    ];
+
+    let mut group = bh.benchmark_group("parallel_sorting");
+    group.sample_size(10);
+    for len in PARALLEL_LENGTHS {
+        let values = random_values(len);
+        group.throughput(Throughput::Elements(len as u64));
+        for (name, sort) in sorts {
+            group.bench_with_input(BenchmarkId::new(name, len), &values, |bh, values| {
+                bh.iter_batched_ref(
+                    || values.clone(),
+                    |values| sort(values),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(
+    name = parallel_bench;
+    config = crate::default_config();
+    targets = parallel_sorting
 );
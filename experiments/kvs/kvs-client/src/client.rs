@@ -0,0 +1,88 @@
+//! A connection to the server which is made when it is first needed, and made again after it fails
+
+use std::time::Duration;
+
+use kvs_common::connection::Connection;
+use kvs_common::requests::{Request, RequestFrame, Response, ResponseFrame};
+use kvs_common::Error;
+use tokio::time::timeout;
+
+/// Sends requests to the server at `addr`, one at a time
+#[derive(Debug)]
+pub struct Client {
+    addr: String,
+    /// How long to wait for the server to connect or to answer, or `None` to wait for ever
+    timeout: Option<Duration>,
+    conn: Option<Connection>,
+    next_id: u64,
+}
+
+impl Client {
+    pub fn new(addr: String, timeout: Option<Duration>) -> Self {
+        Self {
+            addr,
+            timeout,
+            conn: None,
+            next_id: 1,
+        }
+    }
+
+    /// Sends `request` and returns every response to it, which is more than one for a scan.
+    ///
+    /// The connection is dropped after any error, so that the next call starts afresh rather than
+    /// reading the answers meant for this one.
+    pub async fn call(&mut self, request: Request) -> kvs_common::Result<Vec<Response>> {
+        let res = match self.timeout {
+            Some(limit) => match timeout(limit, self.exchange(request)).await {
+                Ok(res) => res,
+                Err(_) => Err(Error::Message(format!(
+                    "timed out after {}s waiting for {}",
+                    limit.as_secs_f64(),
+                    self.addr
+                ))),
+            },
+            None => self.exchange(request).await,
+        };
+        if res.is_err() {
+            self.conn = None;
+        }
+        res
+    }
+
+    async fn exchange(&mut self, request: Request) -> kvs_common::Result<Vec<Response>> {
+        let conn = match &mut self.conn {
+            Some(conn) => conn,
+            None => {
+                let conn = Connection::dial(&self.addr).await.map_err(|e| {
+                    Error::Message(format!("couldn't connect to {}: {}", self.addr, e))
+                })?;
+                self.conn.insert(conn)
+            }
+        };
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let streaming = matches!(request, Request::Scan { .. });
+        conn.write(&RequestFrame { id, request }).await?;
+
+        // A scan streams its entries back, so keep reading until it completes
+        let mut responses = Vec::new();
+        loop {
+            let response = match conn.read::<ResponseFrame>().await? {
+                Some(ResponseFrame { id: got, response }) if got == id => response,
+                // Answers to a request which was given up on
+                Some(_) => continue,
+                None => return Err(Error::Message("the server closed the connection".into())),
+            };
+            let done = !streaming
+                || matches!(
+                    response,
+                    Response::ScanComplete { .. } | Response::Error { .. }
+                );
+            responses.push(response);
+            if done {
+                return Ok(responses);
+            }
+        }
+    }
+}
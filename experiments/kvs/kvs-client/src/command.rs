@@ -0,0 +1,174 @@
+//! The commands the client understands, on its command line and in the REPL
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Subcommand;
+use kvs_common::requests::{Op, Request};
+
+/// Turns a key or value typed by the user into bytes
+pub type Decode<'a> = &'a dyn Fn(&str) -> kvs_common::Result<Vec<u8>>;
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Retrieves the value of a key-value pair
+    #[command(arg_required_else_help = true)]
+    Get { key: String },
+    /// Inserts a key-value pair
+    #[command(arg_required_else_help = true)]
+    Put {
+        key: String,
+        #[command(flatten)]
+        value: Value,
+        /// How many seconds the key lives for before it expires
+        #[arg(long)]
+        ttl: Option<u64>,
+    },
+    /// Updates a key-value pair
+    #[command(arg_required_else_help = true)]
+    Update {
+        key: String,
+        #[command(flatten)]
+        value: Value,
+    },
+    /// Deletes a key-value pair
+    #[command(arg_required_else_help = true)]
+    Delete { key: String },
+    /// Lists the key-value pairs in a range of keys, in key order
+    Scan {
+        /// The first key in the range, inclusive
+        #[arg(long)]
+        start: Option<String>,
+        /// The end of the range, exclusive
+        #[arg(long)]
+        end: Option<String>,
+        /// The most entries to return in one page
+        #[arg(long)]
+        limit: Option<u32>,
+    },
+    /// Applies several changes, all or nothing
+    ///
+    /// Each change is either `put <key> <value>` or `delete <key>`, e.g.
+    /// `kvs-client batch put a 1 put b 2 delete c`
+    #[command(arg_required_else_help = true)]
+    Batch {
+        #[arg(num_args = 1.., allow_hyphen_values = true)]
+        ops: Vec<String>,
+    },
+    /// Sets a key to a new value only if it holds the expected one
+    #[command(arg_required_else_help = true)]
+    Cas {
+        key: String,
+        /// The value the key must hold, or leave it out if the key must not exist
+        #[arg(long)]
+        expected: Option<String>,
+        /// The value to set the key to, or leave it out to delete the key
+        #[arg(long)]
+        new: Option<String>,
+    },
+    /// Writes a copy of the store to a directory on the server
+    #[command(arg_required_else_help = true)]
+    Backup { dest: PathBuf },
+    /// Replaces the contents of the store with a copy in a directory on the server
+    #[command(arg_required_else_help = true)]
+    Restore { src: PathBuf },
+    /// Checks every record in the store, and lists the damaged ones
+    Verify,
+    /// Shows whether the server is a leader or a follower, and how far behind its followers are
+    Status,
+    /// Makes a follower stop following its leader, and take writes itself
+    Promote,
+    /// Makes the server follow a leader, replacing its contents with the leader's
+    #[command(arg_required_else_help = true)]
+    Follow { leader: String },
+    /// Shows the server's metrics, in the Prometheus text format
+    Stats,
+}
+
+/// The value to store, given either as an argument or as a file
+#[derive(Debug, clap::Args)]
+pub struct Value {
+    #[arg(required_unless_present = "value_file", conflicts_with = "value_file")]
+    value: Option<String>,
+    /// Reads the value from a file, byte for byte
+    #[arg(long)]
+    value_file: Option<PathBuf>,
+}
+
+impl Value {
+    fn into_bytes(self, decode: Decode) -> kvs_common::Result<Vec<u8>> {
+        match (self.value, self.value_file) {
+            (_, Some(path)) => Ok(std::fs::read(path)?),
+            (Some(value), None) => decode(&value),
+            (None, None) => unreachable!("clap requires one of the value arguments"),
+        }
+    }
+}
+
+impl Command {
+    /// Builds the request for the command, turning the keys and values it was given into bytes with
+    /// `decode`
+    pub fn into_request(self, decode: Decode) -> kvs_common::Result<Request> {
+        let bytes = |input: String| decode(&input);
+
+        Ok(match self {
+            Command::Get { key } => Request::Get { key: bytes(key)? },
+            Command::Put { key, value, ttl } => Request::Put {
+                key: bytes(key)?,
+                value: value.into_bytes(decode)?,
+                ttl: ttl.map(Duration::from_secs),
+            },
+            Command::Update { key, value } => Request::Update {
+                key: bytes(key)?,
+                value: value.into_bytes(decode)?,
+            },
+            Command::Delete { key } => Request::Delete { key: bytes(key)? },
+            Command::Scan { start, end, limit } => Request::Scan {
+                start: start.map(bytes).transpose()?,
+                end: end.map(bytes).transpose()?,
+                limit,
+            },
+            Command::Batch { ops } => Request::Batch(parse_ops(ops, decode)?),
+            Command::Cas { key, expected, new } => Request::CompareAndSwap {
+                key: bytes(key)?,
+                expected: expected.map(bytes).transpose()?,
+                new: new.map(bytes).transpose()?,
+            },
+            Command::Backup { dest } => Request::Backup { dest },
+            Command::Restore { src } => Request::Restore { src },
+            Command::Verify => Request::Verify,
+            Command::Status => Request::Status,
+            Command::Promote => Request::Promote,
+            Command::Follow { leader } => Request::Follow { leader },
+            Command::Stats => Request::Stats,
+        })
+    }
+}
+
+/// Parses the changes in a batch, which are given as `put <key> <value>` or `delete <key>`
+fn parse_ops(args: Vec<String>, decode: Decode) -> kvs_common::Result<Vec<Op>> {
+    let mut args = args.into_iter();
+    let mut ops = Vec::new();
+    let mut next = |what: &str| {
+        args.next()
+            .ok_or_else(|| kvs_common::Error::Message(format!("expected {}", what)))
+    };
+
+    while let Ok(op) = next("an operation") {
+        let op = match op.as_str() {
+            "put" => Op::Put {
+                key: decode(&next("a key")?)?,
+                value: decode(&next("a value")?)?,
+            },
+            "delete" => Op::Delete {
+                key: decode(&next("a key")?)?,
+            },
+            _ => {
+                let message = format!("expected put or delete, found {:?}", op);
+                return Err(kvs_common::Error::Message(message));
+            }
+        };
+        ops.push(op);
+    }
+    Ok(ops)
+}
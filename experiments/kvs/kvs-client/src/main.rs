@@ -1,28 +1,211 @@
-use clap::Parser;
-use kvs_common::connection::Connection;
-use kvs_common::requests::{Request, Response};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use kvs_common::encoding::Encoding;
 use kvs_common::DEFAULT_ADDRESS;
 
+use crate::client::Client;
+use crate::command::{Command, Decode};
+use crate::output::{Format, Output};
+
+mod client;
+mod command;
+mod output;
+mod repl;
+
+/// How long to wait for the server, unless told otherwise
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const EXIT_CODES: &str = "Exit codes:
+  0  success
+  1  the key wasn't found, the compare-and-swap didn't match, or damaged records were found
+  2  the command was invalid
+  3  the server answered with an error
+  4  the server couldn't be reached, or didn't answer in time";
+
 #[tokio::main(flavor = "current_thread")]
-async fn main() -> kvs_common::Result<()> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
-    let mut conn = Connection::dial(DEFAULT_ADDRESS).await?;
-    conn.write::<Request>(&cli.command).await?;
-    let response = conn.read::<Response>().await?;
-    match response {
-        Some(response) => {
-            println!("{:?}", response);
+    if cli.script.is_some() && cli.command.is_some() {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--script can't be given a command",
+            )
+            .exit();
+    }
+    let output = Output {
+        format: cli.output,
+        encoding: cli.encoding,
+    };
+    let timeout = (cli.timeout > 0).then(|| Duration::from_secs(cli.timeout));
+    let mut client = Client::new(cli.addr, timeout);
+
+    let outcome = match (cli.command, cli.script) {
+        (Some(command), _) => {
+            let encoding = cli.encoding;
+            run(&mut client, output, command, &|input| {
+                encoding.decode(input)
+            })
+            .await
+        }
+        (None, Some(path)) => repl::script(&mut client, output, &path).await,
+        (None, None) if std::io::stdin().is_terminal() => {
+            repl::interactive(&mut client, output).await
+        }
+        // Commands piped in are run as a script
+        (None, None) => repl::script(&mut client, output, "-".as_ref()).await,
+    };
+    ExitCode::from(outcome as u8)
+}
+
+/// How a command turned out, from best to worst, which decides the exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Outcome {
+    Success = 0,
+    /// The server said no: the key wasn't found, the compare-and-swap didn't match, or damaged
+    /// records were found
+    Negative = 1,
+    /// The command was invalid, so it wasn't sent
+    Usage = 2,
+    /// The server answered with an error
+    ServerError = 3,
+    /// The server couldn't be reached, or didn't answer in time
+    Unavailable = 4,
+}
+
+/// Sends the request for `command` and prints the answer
+pub async fn run(
+    client: &mut Client,
+    output: Output,
+    command: Command,
+    decode: Decode<'_>,
+) -> Outcome {
+    let request = match command.into_request(decode) {
+        Ok(request) => request,
+        Err(e) => {
+            output.error(Outcome::Usage, &e.to_string());
+            return Outcome::Usage;
+        }
+    };
+
+    match client.call(request).await {
+        Ok(responses) => {
+            output.responses(&responses);
+            output::outcome(&responses)
+        }
+        Err(e) => {
+            output.error(Outcome::Unavailable, &e.to_string());
+            Outcome::Unavailable
         }
-        None => println!("rip, no response"),
     }
-    Ok(())
 }
 
 /// A client to interact with the key-value store server
 #[derive(Debug, Parser)]
 #[command(name = "kvs-client")]
 #[command(about = "A client to interact with the key-value store server", long_about = None)]
+#[command(after_help = EXIT_CODES)]
 struct Cli {
+    /// The address of the server
+    #[arg(long, global = true, default_value = DEFAULT_ADDRESS)]
+    addr: String,
+    /// How the keys and values given as arguments are encoded, and how they are printed as JSON
+    #[arg(long, value_enum, global = true, default_value_t = Encoding::Utf8)]
+    encoding: Encoding,
+    /// How many seconds to wait for the server to connect or to answer, or 0 to wait for ever
+    #[arg(long, global = true, default_value_t = DEFAULT_TIMEOUT.as_secs())]
+    timeout: u64,
+    /// How the answers are printed
+    #[arg(long, value_enum, global = true, default_value_t = Format::Plain)]
+    output: Format,
+    /// Runs the commands in a file, one per line, or on stdin with `-`
+    #[arg(long)]
+    script: Option<PathBuf>,
+    /// The command to run, or leave it out to start a REPL
     #[command(subcommand)]
-    command: Request,
+    command: Option<Command>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvs_common::requests::{Request, Response};
+    use std::net::SocketAddr;
+    use tempfile::TempDir;
+    use tokio::net::TcpListener;
+
+    async fn start() -> (SocketAddr, TempDir) {
+        let dir = TempDir::new().unwrap();
+        let kv_store = kvs::KVStore::open(dir.path()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = kvs_server::Config::default();
+        tokio::spawn(kvs_server::accept_connections(listener, kv_store, config));
+        (addr, dir)
+    }
+
+    async fn run_script(client: &mut Client, dir: &TempDir, script: &str) -> Outcome {
+        let path = dir.path().join("script.kvs");
+        std::fs::write(&path, script).unwrap();
+        let output = Output {
+            format: Format::Json,
+            encoding: Encoding::Utf8,
+        };
+        repl::script(client, output, &path).await
+    }
+
+    #[test]
+    fn cli_is_well_formed() {
+        Cli::command().debug_assert();
+    }
+
+    #[tokio::test]
+    async fn scripts_stop_at_the_first_failure() {
+        let (addr, _dir) = start().await;
+        let scripts = TempDir::new().unwrap();
+        let mut client = Client::new(addr.to_string(), Some(DEFAULT_TIMEOUT));
+
+        let script = "
+            # keys which aren't found don't stop the script
+            put a 0x01
+            get missing
+            cas a --expected 2 --new 3
+            fetch a
+            put b 2
+        ";
+        let outcome = run_script(&mut client, &scripts, script).await;
+        assert_eq!(outcome, Outcome::Usage);
+        let get = |key: &[u8]| Request::Get { key: key.to_vec() };
+        let responses = client.call(get(b"a")).await.unwrap();
+        assert!(matches!(&responses[..], [Response::OkWithValue { value, .. }] if value == &[1]));
+        assert_eq!(
+            client.call(get(b"b")).await.unwrap(),
+            [Response::KeyNotFound]
+        );
+
+        let outcome = run_script(&mut client, &scripts, "get a\nexit\nfetch a\n").await;
+        assert_eq!(outcome, Outcome::Success);
+        let outcome = run_script(&mut client, &scripts, "restore /does/not/exist\n").await;
+        assert_eq!(outcome, Outcome::ServerError);
+    }
+
+    #[tokio::test]
+    async fn silent_servers_time_out() {
+        // The listener takes connections into its backlog, but never answers them
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = Client::new(addr.to_string(), Some(Duration::from_millis(100)));
+        let e = client.call(Request::Verify).await.unwrap_err();
+        assert!(e.to_string().contains("timed out"), "{}", e);
+
+        drop(listener);
+        let scripts = TempDir::new().unwrap();
+        let outcome = run_script(&mut client, &scripts, "verify\n").await;
+        assert_eq!(outcome, Outcome::Unavailable);
+    }
 }
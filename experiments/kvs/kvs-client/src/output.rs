@@ -0,0 +1,309 @@
+//! Printing the server's answers, for a person to read or as JSON for a program
+
+use std::io::Write;
+
+use clap::ValueEnum;
+use kvs_common::encoding::{self, Encoding};
+use kvs_common::requests::{Response, Role};
+use serde_json::{json, Value};
+
+use crate::Outcome;
+
+/// How the answers are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Format {
+    /// Text, with keys and values quoted, or written as hex if they aren't printable
+    #[default]
+    Plain,
+    /// A JSON object per command, on a line of its own
+    Json,
+}
+
+/// Where the answers are printed, and how
+#[derive(Debug, Clone, Copy)]
+pub struct Output {
+    pub format: Format,
+    /// How the keys and values in JSON are encoded
+    pub encoding: Encoding,
+}
+
+impl Output {
+    /// Prints the responses to a command.
+    ///
+    /// Errors are printed to stderr as text, and to stdout as JSON, so that a program reading the
+    /// JSON sees one answer for every command.
+    pub fn responses(&self, responses: &[Response]) {
+        match self.format {
+            Format::Plain => {
+                for response in responses {
+                    match response {
+                        Response::Error { .. } => print_err(&plain(response)),
+                        _ => print_out(&plain(response)),
+                    }
+                }
+            }
+            Format::Json => print_out(&format!("{}\n", json(responses, self.encoding))),
+        }
+    }
+
+    /// Prints an error which happened before the server could answer
+    pub fn error(&self, outcome: Outcome, message: &str) {
+        match self.format {
+            Format::Plain => print_err(&format!("Error: {}\n", message)),
+            Format::Json => {
+                let error = json!({
+                    "status": "error",
+                    "code": format!("{:?}", outcome),
+                    "message": message,
+                });
+                print_out(&format!("{}\n", error))
+            }
+        }
+    }
+}
+
+/// Whether the responses to a command are a success, a refusal, or an error
+pub fn outcome(responses: &[Response]) -> Outcome {
+    let outcomes = responses.iter().map(|response| match response {
+        Response::Error { .. } => Outcome::ServerError,
+        Response::KeyNotFound | Response::Mismatch { .. } => Outcome::Negative,
+        Response::Verified { corruptions, .. } if !corruptions.is_empty() => Outcome::Negative,
+        _ => Outcome::Success,
+    });
+    outcomes.max().unwrap_or(Outcome::Success)
+}
+
+// Printing fails when stdout is a closed pipe, such as `kvs-client scan | head`, and there's nobody
+// left to tell
+pub fn print_out(text: &str) {
+    let _ = std::io::stdout().lock().write_all(text.as_bytes());
+}
+
+fn print_err(text: &str) {
+    let _ = std::io::stderr().lock().write_all(text.as_bytes());
+}
+
+/// Formats a response as lines of text, with any keys and values formatted so that binary data
+/// can't garble the terminal
+fn plain(response: &Response) -> String {
+    match response {
+        Response::KeyNotFound => "Key not found\n".to_string(),
+        Response::Ok => "Ok\n".to_string(),
+        Response::OkWithValue { value, ttl: None } => format!("{}\n", encoding::format(value)),
+        Response::OkWithValue {
+            value,
+            ttl: Some(ttl),
+        } => format!(
+            "{} (expires in {}s)\n",
+            encoding::format(value),
+            ttl.as_secs()
+        ),
+        Response::Entry { key, value } => {
+            format!("{}: {}\n", encoding::format(key), encoding::format(value))
+        }
+        Response::ScanComplete { next: Some(next) } => {
+            format!("next: {}\n", encoding::format(next))
+        }
+        Response::ScanComplete { next: None } => String::new(),
+        Response::Swapped => "Swapped\n".to_string(),
+        Response::Mismatch {
+            current: Some(current),
+        } => format!("Mismatch, the key holds {}\n", encoding::format(current)),
+        Response::Mismatch { current: None } => "Mismatch, the key doesn't exist\n".to_string(),
+        Response::Verified {
+            segments,
+            records,
+            corruptions,
+        } => {
+            let mut out = format!("{} records in {} segments\n", records, segments);
+            for corruption in corruptions {
+                out += &format!(
+                    "Damaged record in segment {} at offset {}: {}\n",
+                    corruption.segment, corruption.offset, corruption.problem
+                );
+            }
+            out
+        }
+        Response::Log { records, next, .. } => {
+            format!("{} bytes of log, up to {:?}\n", records.len(), next)
+        }
+        Response::Status { role, followers } => {
+            let mut out = match role {
+                Role::Leader => "Leader\n".to_string(),
+                Role::Follower { leader } => format!("Following {}\n", leader),
+            };
+            for follower in followers {
+                out += &match follower.lag {
+                    Some(lag) => format!("Follower {}: {} bytes behind\n", follower.addr, lag),
+                    None => format!("Follower {}: catching up\n", follower.addr),
+                };
+            }
+            out
+        }
+        Response::Stats { text } => text.clone(),
+        Response::Error { code, message } => format!("Error ({:?}): {}\n", code, message),
+    }
+}
+
+/// Encodes a key or value for JSON, as a string in `encoding`, or as `{"hex": ...}` when that is
+/// UTF-8 and the bytes aren't
+fn bytes(bytes: &[u8], encoding: Encoding) -> Value {
+    match encoding.encode(bytes) {
+        Some(text) => Value::String(text),
+        None => json!({ "hex": Encoding::Hex.encode(bytes) }),
+    }
+}
+
+/// Gathers the responses to a command into a single JSON object.
+///
+/// Every object has a `status`, which is `ok`, `not_found`, `swapped`, `mismatch` or `error`, and
+/// the entries of a scan are collected into an array.
+fn json(responses: &[Response], encoding: Encoding) -> Value {
+    let bytes = |b: &[u8]| bytes(b, encoding);
+    let mut entries = Vec::new();
+    let mut object = json!({ "status": "ok" });
+
+    for response in responses {
+        object = match response {
+            Response::Entry { key, value } => {
+                entries.push(json!({ "key": bytes(key), "value": bytes(value) }));
+                continue;
+            }
+            Response::ScanComplete { next } => json!({
+                "status": "ok",
+                "entries": std::mem::take(&mut entries),
+                "next": next.as_deref().map(bytes),
+            }),
+            Response::KeyNotFound => json!({ "status": "not_found" }),
+            Response::Ok => json!({ "status": "ok" }),
+            Response::OkWithValue { value, ttl } => json!({
+                "status": "ok",
+                "value": bytes(value),
+                "ttl": ttl.map(|ttl| ttl.as_secs()),
+            }),
+            Response::Swapped => json!({ "status": "swapped" }),
+            Response::Mismatch { current } => json!({
+                "status": "mismatch",
+                "current": current.as_deref().map(bytes),
+            }),
+            Response::Verified {
+                segments,
+                records,
+                corruptions,
+            } => json!({
+                "status": "ok",
+                "segments": segments,
+                "records": records,
+                "corruptions": corruptions.iter().map(|corruption| json!({
+                    "segment": corruption.segment,
+                    "offset": corruption.offset,
+                    "problem": corruption.problem,
+                })).collect::<Vec<_>>(),
+            }),
+            Response::Log {
+                reset,
+                records,
+                next,
+            } => json!({
+                "status": "ok",
+                "reset": reset,
+                "bytes": records.len(),
+                "next": { "segment": next.segment, "offset": next.offset },
+            }),
+            Response::Status { role, followers } => json!({
+                "status": "ok",
+                "role": match role {
+                    Role::Leader => "leader",
+                    Role::Follower { .. } => "follower",
+                },
+                "leader": match role {
+                    Role::Leader => None,
+                    Role::Follower { leader } => Some(leader),
+                },
+                "followers": followers.iter().map(|follower| json!({
+                    "addr": follower.addr,
+                    "position": follower.position.map(|position| json!({
+                        "segment": position.segment,
+                        "offset": position.offset,
+                    })),
+                    "lag": follower.lag,
+                })).collect::<Vec<_>>(),
+            }),
+            Response::Stats { text } => json!({ "status": "ok", "stats": text }),
+            Response::Error { code, message } => json!({
+                "status": "error",
+                "code": format!("{:?}", code),
+                "message": message,
+            }),
+        };
+    }
+    object
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvs_common::requests::ErrorCode;
+    use std::time::Duration;
+
+    #[test]
+    fn scans_are_gathered_into_one_object() {
+        let responses = [
+            Response::Entry {
+                key: b"a".to_vec(),
+                value: b"1".to_vec(),
+            },
+            Response::Entry {
+                key: vec![0xff],
+                value: b"2".to_vec(),
+            },
+            Response::ScanComplete {
+                next: Some(b"c".to_vec()),
+            },
+        ];
+        assert_eq!(
+            json(&responses, Encoding::Utf8),
+            json!({
+                "status": "ok",
+                "entries": [
+                    { "key": "a", "value": "1" },
+                    { "key": { "hex": "ff" }, "value": "2" },
+                ],
+                "next": "c",
+            })
+        );
+        assert_eq!(outcome(&responses), Outcome::Success);
+    }
+
+    #[test]
+    fn values_are_encoded() {
+        let responses = [Response::OkWithValue {
+            value: vec![0, 255],
+            ttl: Some(Duration::from_secs(5)),
+        }];
+        assert_eq!(
+            json(&responses, Encoding::Base64),
+            json!({ "status": "ok", "value": "AP8=", "ttl": 5 })
+        );
+        assert_eq!(plain(&responses[0]), "0x00ff (expires in 5s)\n");
+    }
+
+    #[test]
+    fn outcomes() {
+        assert_eq!(outcome(&[Response::Ok]), Outcome::Success);
+        assert_eq!(outcome(&[Response::KeyNotFound]), Outcome::Negative);
+        assert_eq!(
+            outcome(&[Response::Mismatch { current: None }]),
+            Outcome::Negative
+        );
+        let error = Response::Error {
+            code: ErrorCode::NotLeader,
+            message: "follower".to_string(),
+        };
+        assert_eq!(
+            json(std::slice::from_ref(&error), Encoding::Utf8),
+            json!({ "status": "error", "code": "NotLeader", "message": "follower" })
+        );
+        assert_eq!(outcome(&[error]), Outcome::ServerError);
+    }
+}
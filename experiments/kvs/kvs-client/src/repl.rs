@@ -0,0 +1,299 @@
+//! Running commands typed into a REPL, or read from a script, one per line.
+//!
+//! A line is split into words as a shell would split it, and takes the same commands as the
+//! command line. The syntax of a key or value picks its encoding, see [`encoding::parse`].
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
+use kvs_common::encoding;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor};
+
+use crate::client::Client;
+use crate::command::Command;
+use crate::output::{self, Output};
+use crate::{run, Outcome};
+
+/// The file the REPL's history is kept in, in the home directory
+const HISTORY_FILE: &str = ".kvs_history";
+
+/// A line of a script, or of the REPL
+#[derive(Debug, Parser)]
+#[command(no_binary_name = true, disable_version_flag = true)]
+#[command(override_usage = "<COMMAND> [ARGS]")]
+#[command(about = "Keys and values are used as-is, or written as 0x<hex>, b64:<base64> or @<file>")]
+struct Line {
+    #[command(subcommand)]
+    command: LineCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum LineCommand {
+    #[command(flatten)]
+    Request(Command),
+    /// Leaves the REPL, or stops the script
+    #[command(alias = "quit")]
+    Exit,
+}
+
+/// What a line asks for
+#[derive(Debug)]
+enum Parsed {
+    /// A blank line or a comment, starting with `#`
+    Empty,
+    Command(Command),
+    Exit,
+    /// The text asked for by `help`
+    Help(String),
+    /// What is wrong with the line
+    Invalid(String),
+}
+
+fn parse(line: &str) -> Parsed {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Parsed::Empty;
+    }
+
+    let words = match shell_words::split(line) {
+        Ok(words) => words,
+        Err(e) => return Parsed::Invalid(e.to_string()),
+    };
+    match Line::try_parse_from(&words) {
+        Ok(Line {
+            command: LineCommand::Request(command),
+        }) => Parsed::Command(command),
+        Ok(Line {
+            command: LineCommand::Exit,
+        }) => Parsed::Exit,
+        Err(e) if e.kind() == ErrorKind::DisplayHelp => Parsed::Help(e.to_string()),
+        Err(e) if e.kind() == ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand => {
+            Parsed::Invalid(format!(
+                "{} needs arguments, see `help {}`",
+                words[0], words[0]
+            ))
+        }
+        // The rest of clap's message is a usage line for the whole REPL, which `help` shows anyway
+        Err(e) => {
+            let message = e.to_string();
+            let first = message.lines().next().unwrap_or_default();
+            Parsed::Invalid(first.trim_start_matches("error: ").to_string())
+        }
+    }
+}
+
+/// Reads commands from the terminal until `exit` or end of input, with line editing, history and
+/// tab completion of commands.
+///
+/// Failed commands are reported and the REPL carries on, so it always succeeds.
+pub async fn interactive(client: &mut Client, output: Output) -> Outcome {
+    let mut editor = match Editor::<Helper>::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            output.error(Outcome::Usage, &format!("couldn't start the REPL: {}", e));
+            return Outcome::Usage;
+        }
+    };
+    editor.set_helper(Some(Helper::new()));
+    let history = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+    if let Some(history) = &history {
+        // There is no history the first time
+        let _ = editor.load_history(history);
+    }
+
+    loop {
+        let line = match editor.readline("kvs> ") {
+            Ok(line) => line,
+            // Ctrl-C drops the line, and Ctrl-D leaves
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                output.error(Outcome::Usage, &e.to_string());
+                break;
+            }
+        };
+        editor.add_history_entry(line.as_str());
+
+        match parse(&line) {
+            Parsed::Empty => {}
+            Parsed::Exit => break,
+            Parsed::Help(text) => output::print_out(&text),
+            Parsed::Invalid(message) => output.error(Outcome::Usage, &message),
+            Parsed::Command(command) => {
+                run(client, output, command, &encoding::parse).await;
+            }
+        }
+    }
+
+    if let Some(history) = &history {
+        if let Err(e) = editor.save_history(history) {
+            output.error(Outcome::Usage, &format!("couldn't save the history: {}", e));
+        }
+    }
+    Outcome::Success
+}
+
+/// Runs the commands in the file at `path`, or on stdin if it is `-`.
+///
+/// The script stops at the first line which is invalid or fails, and its outcome is that line's.
+/// A key which isn't found, or a compare-and-swap which doesn't match, doesn't stop it.
+pub async fn script(client: &mut Client, output: Output, path: &Path) -> Outcome {
+    let lines: Box<dyn BufRead> = if path == Path::new("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        match File::open(path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => {
+                let message = format!("couldn't read {}: {}", path.display(), e);
+                output.error(Outcome::Usage, &message);
+                return Outcome::Usage;
+            }
+        }
+    };
+
+    for (n, line) in lines.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                let message = format!("couldn't read {}: {}", path.display(), e);
+                output.error(Outcome::Usage, &message);
+                return Outcome::Usage;
+            }
+        };
+
+        match parse(&line) {
+            Parsed::Empty => {}
+            Parsed::Exit => break,
+            Parsed::Help(text) => output::print_out(&text),
+            Parsed::Invalid(message) => {
+                let message = format!("{}:{}: {}", path.display(), n + 1, message);
+                output.error(Outcome::Usage, &message);
+                return Outcome::Usage;
+            }
+            Parsed::Command(command) => {
+                let outcome = run(client, output, command, &encoding::parse).await;
+                if outcome > Outcome::Negative {
+                    return outcome;
+                }
+            }
+        }
+    }
+    Outcome::Success
+}
+
+/// Completes the names of commands in the REPL
+struct Helper {
+    commands: Vec<String>,
+}
+
+impl Helper {
+    fn new() -> Self {
+        let line = Line::command();
+        let mut commands: Vec<_> = line
+            .get_subcommands()
+            .flat_map(|command| {
+                let aliases = command.get_all_aliases().map(str::to_string);
+                std::iter::once(command.get_name().to_string()).chain(aliases)
+            })
+            .collect();
+        commands.push("help".to_string());
+        commands.sort();
+        commands.dedup();
+        Self { commands }
+    }
+
+    /// The commands which could finish the word being typed at the end of `typed`, and where that
+    /// word starts. Only the first word of a line is a command.
+    fn candidates(&self, typed: &str) -> (usize, Vec<String>) {
+        let start = typed.len() - typed.trim_start().len();
+        let word = &typed[start..];
+        if word.contains(char::is_whitespace) {
+            return (typed.len(), Vec::new());
+        }
+        let matches = self
+            .commands
+            .iter()
+            .filter(|command| command.starts_with(word))
+            .cloned()
+            .collect();
+        (start, matches)
+    }
+}
+
+impl Completer for Helper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(&line[..pos]))
+    }
+}
+
+impl Hinter for Helper {
+    type Hint = String;
+}
+
+impl Highlighter for Helper {}
+
+impl Validator for Helper {}
+
+impl rustyline::Helper for Helper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_take_the_commands_of_the_command_line() {
+        assert!(matches!(parse("  "), Parsed::Empty));
+        assert!(matches!(parse("# a comment"), Parsed::Empty));
+        assert!(matches!(parse("quit"), Parsed::Exit));
+        assert!(matches!(
+            parse("put 'two words' \"a value\" --ttl 5"),
+            Parsed::Command(Command::Put { ttl: Some(5), .. })
+        ));
+        assert!(matches!(parse("help"), Parsed::Help(_)));
+        assert!(matches!(parse("help put"), Parsed::Help(text) if text.contains("--ttl")));
+
+        for (line, expected) in [
+            ("fetch a", "'fetch' wasn't recognized"),
+            ("get", "get needs arguments, see `help get`"),
+            ("put 'a", "missing closing quote"),
+            ("scan --limit many", "Invalid value 'many'"),
+        ] {
+            match parse(line) {
+                Parsed::Invalid(message) => assert!(
+                    message.contains(expected),
+                    "{:?} doesn't contain {:?}",
+                    message,
+                    expected
+                ),
+                parsed => panic!("{:?} parsed as {:?}", line, parsed),
+            }
+        }
+    }
+
+    #[test]
+    fn commands_are_completed() {
+        let helper = Helper::new();
+        assert_eq!(helper.candidates("g"), (0, vec!["get".to_string()]));
+        assert_eq!(
+            helper.candidates("  b"),
+            (2, vec!["backup".to_string(), "batch".to_string()])
+        );
+        assert_eq!(helper.candidates("qu"), (0, vec!["quit".to_string()]));
+        assert_eq!(helper.candidates("get ke"), (6, vec![]));
+    }
+}
@@ -1,19 +1,45 @@
-use std::fmt::Debug;
-use std::io::Cursor;
-
 use crate::Error;
-use bytes::BytesMut;
+use bytes::{Buf, BytesMut};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::{TcpStream, ToSocketAddrs};
 
+/// Every message starts with these bytes, so that anything else on the other end fails quickly
+const MAGIC: &[u8; 2] = b"KV";
+
+/// The version of the protocol spoken by this crate.
+///
+/// Peers which speak a different version are rejected with [`Error::UnsupportedVersion`].
+pub const PROTOCOL_VERSION: u8 = 6;
+
+/// The size of the header in front of every message: `[magic, version, len]`
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4;
+
+/// The largest message that will be accepted
+const MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+
+/// A connection which reads and writes framed messages
 #[derive(Debug)]
 pub struct Connection {
-    stream: BufWriter<TcpStream>,
+    reader: ConnectionReader,
+    writer: ConnectionWriter,
+}
+
+/// The half of a [`Connection`] which reads messages
+#[derive(Debug)]
+pub struct ConnectionReader {
+    stream: OwnedReadHalf,
     buffer: BytesMut,
 }
 
+/// The half of a [`Connection`] which writes messages
+#[derive(Debug)]
+pub struct ConnectionWriter {
+    stream: BufWriter<OwnedWriteHalf>,
+}
+
 impl Connection {
     /// Dial the given address and return a connection
     pub async fn dial<A: ToSocketAddrs>(addr: A) -> Result<Self, Error> {
@@ -24,21 +50,58 @@ impl Connection {
     /// Create a new `Connection`, backed by `socket`. Read and write buffers
     /// are initialized.
     pub fn new(socket: TcpStream) -> Connection {
+        let (read, write) = socket.into_split();
         Connection {
-            stream: BufWriter::new(socket),
-            buffer: BytesMut::with_capacity(4 * 1024),
+            reader: ConnectionReader {
+                stream: read,
+                buffer: BytesMut::with_capacity(4 * 1024),
+            },
+            writer: ConnectionWriter {
+                stream: BufWriter::new(write),
+            },
         }
     }
 
-    /// Write a serializable value into the stream
+    /// Splits the connection so that messages can be read and written concurrently
+    pub fn into_split(self) -> (ConnectionReader, ConnectionWriter) {
+        (self.reader, self.writer)
+    }
+
+    /// Write a serializable value into the stream, see [`ConnectionWriter::write`]
+    pub async fn write<T: Serialize>(&mut self, value: &T) -> crate::Result<()> {
+        self.writer.write(value).await
+    }
+
+    /// Reads a complete message from the stream, see [`ConnectionReader::read`]
+    pub async fn read<T: DeserializeOwned>(&mut self) -> crate::Result<Option<T>> {
+        self.reader.read().await
+    }
+}
+
+impl ConnectionWriter {
+    /// Write a serializable value into the stream.
+    ///
+    /// The value is framed as `[magic, version, len, payload]`, where the payload is the value
+    /// serialized with bincode.
     pub async fn write<T: Serialize>(&mut self, value: &T) -> crate::Result<()> {
         let buf = bincode::serialize(value).map_err(|_e| Error::Ignored)?;
+        if buf.len() > MAX_MESSAGE_LEN {
+            return Err("message too large".into());
+        }
+
+        self.stream.write_all(MAGIC).await?;
+        self.stream.write_u8(PROTOCOL_VERSION).await?;
+        self.stream.write_u32_le(buf.len() as u32).await?;
         self.stream.write_all(&buf).await?;
         self.stream.flush().await?;
         Ok(())
     }
+}
 
-    /// Reads from the socket until a complete message is received, or an error occurs
+impl ConnectionReader {
+    /// Reads from the socket until a complete message is received, or an error occurs.
+    ///
+    /// Returns `None` if the peer closed the connection cleanly between messages.
     pub async fn read<T: DeserializeOwned>(&mut self) -> crate::Result<Option<T>> {
         loop {
             if let Some(frame) = self.parse()? {
@@ -56,11 +119,131 @@ impl Connection {
     }
 
     /// Attempts to deserialize a T from the internal buffer.
+    ///
+    /// The header is checked as soon as enough of it has arrived, so a peer speaking something
+    /// else is rejected without waiting for a whole message.
     fn parse<T: DeserializeOwned>(&mut self) -> crate::Result<Option<T>> {
-        let mut buf = Cursor::new(&self.buffer[..]);
-        match bincode::deserialize_from(&mut buf) {
+        let magic_len = self.buffer.len().min(MAGIC.len());
+        if self.buffer[..magic_len] != MAGIC[..magic_len] {
+            return Err("the peer doesn't speak the kvs protocol".into());
+        }
+
+        if let Some(&version) = self.buffer.get(MAGIC.len()) {
+            if version != PROTOCOL_VERSION {
+                return Err(Error::UnsupportedVersion(version));
+            }
+        }
+
+        if self.buffer.len() < HEADER_LEN {
+            return Ok(None);
+        }
+
+        let len = (&self.buffer[MAGIC.len() + 1..HEADER_LEN]).get_u32_le() as usize;
+        if len > MAX_MESSAGE_LEN {
+            return Err("message too large".into());
+        }
+
+        if self.buffer.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+
+        self.buffer.advance(HEADER_LEN);
+        let payload = self.buffer.split_to(len);
+        match bincode::deserialize(&payload) {
             Ok(value) => Ok(Some(value)),
-            Err(_) => Ok(None),
+            Err(_) => Err("malformed message".into()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::requests::Request;
+    use tokio::net::TcpListener;
+
+    /// Returns a connection and the raw socket on the other end of it
+    async fn pair() -> (Connection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (conn, accepted) = tokio::join!(Connection::dial(addr), listener.accept());
+        (conn.unwrap(), accepted.unwrap().0)
+    }
+
+    #[tokio::test]
+    async fn binary_values_round_trip() {
+        let (mut client, server) = pair().await;
+        let mut server = Connection::new(server);
+
+        let requests = vec![
+            Request::Put {
+                key: vec![0, 255, b' '],
+                value: b"  padded  ".to_vec(),
+                ttl: None,
+            },
+            Request::Put {
+                key: b"empty".to_vec(),
+                value: vec![],
+                ttl: Some(std::time::Duration::from_millis(1500)),
+            },
+        ];
+
+        for request in &requests {
+            client.write(request).await.unwrap();
+        }
+        for request in &requests {
+            assert_eq!(
+                server.read::<Request>().await.unwrap().as_ref(),
+                Some(request)
+            );
+        }
+
+        drop(client);
+        assert_eq!(server.read::<Request>().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_other_protocol_versions() {
+        let (mut client, mut server) = pair().await;
+
+        server
+            .write_all(&[b'K', b'V', PROTOCOL_VERSION + 1, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        assert!(matches!(
+            client.read::<Request>().await,
+            Err(Error::UnsupportedVersion(v)) if v == PROTOCOL_VERSION + 1
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_unframed_messages() {
+        let (mut client, mut server) = pair().await;
+
+        // a message from a client which predates the protocol header
+        let unframed = bincode::serialize(&Request::Delete { key: vec![1] }).unwrap();
+        server.write_all(&unframed).await.unwrap();
+        assert!(matches!(
+            client.read::<Request>().await,
+            Err(Error::Message(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn halves_work_independently() {
+        let (client, server) = pair().await;
+        let (mut reader, mut writer) = client.into_split();
+        let mut server = Connection::new(server);
+
+        // the server echoes a request back, while the client is still waiting to read
+        let echo = tokio::spawn(async move {
+            let request = server.read::<Request>().await.unwrap().unwrap();
+            server.write(&request).await.unwrap();
+        });
+
+        let request = Request::Get { key: b"k".to_vec() };
+        writer.write(&request).await.unwrap();
+        assert_eq!(reader.read::<Request>().await.unwrap(), Some(request));
+        echo.await.unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+//! Converting keys and values between raw bytes and text that a person can type or read
+
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use clap::ValueEnum;
+
+/// How bytes are written as text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Encoding {
+    /// The text itself, as UTF-8
+    #[default]
+    Utf8,
+    /// Hexadecimal digits, e.g. `deadbeef`
+    Hex,
+    /// Standard base64, e.g. `3q2+7w==`
+    Base64,
+}
+
+impl Encoding {
+    /// Decodes `input` into the bytes it represents
+    pub fn decode(self, input: &str) -> crate::Result<Vec<u8>> {
+        match self {
+            Encoding::Utf8 => Ok(input.as_bytes().to_vec()),
+            Encoding::Hex => hex::decode(input)
+                .map_err(|e| crate::Error::Message(format!("invalid hex {:?}: {}", input, e))),
+            Encoding::Base64 => BASE64
+                .decode(input)
+                .map_err(|e| crate::Error::Message(format!("invalid base64 {:?}: {}", input, e))),
+        }
+    }
+
+    /// Encodes `bytes` as text, or returns `None` if they aren't valid UTF-8 and that is the
+    /// encoding
+    pub fn encode(self, bytes: &[u8]) -> Option<String> {
+        match self {
+            Encoding::Utf8 => String::from_utf8(bytes.to_vec()).ok(),
+            Encoding::Hex => Some(hex::encode(bytes)),
+            Encoding::Base64 => Some(BASE64.encode(bytes)),
+        }
+    }
+}
+
+/// Parses a key or value typed into a REPL, where the syntax picks the encoding:
+///
+/// * `0x` followed by hexadecimal digits, e.g. `0xdeadbeef`
+/// * `b64:` followed by base64, e.g. `b64:3q2+7w==`
+/// * `@` followed by a path, to use the contents of a file
+/// * anything else is used as-is
+pub fn parse(input: &str) -> crate::Result<Vec<u8>> {
+    if let Some(digits) = input.strip_prefix("0x") {
+        Encoding::Hex.decode(digits)
+    } else if let Some(encoded) = input.strip_prefix("b64:") {
+        Encoding::Base64.decode(encoded)
+    } else if let Some(path) = input.strip_prefix('@') {
+        Ok(std::fs::read(Path::new(path))?)
+    } else {
+        Ok(input.as_bytes().to_vec())
+    }
+}
+
+/// Formats bytes so they can be printed safely.
+///
+/// Printable UTF-8 text is quoted. Anything else, including text with control characters in it, is
+/// written as `0x` followed by hexadecimal digits, so it can be pasted back into [`parse`].
+pub fn format(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) if !text.chars().any(char::is_control) => format!("{:?}", text),
+        _ => format!("0x{}", hex::encode(bytes)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        assert_eq!(Encoding::Utf8.decode(" a b ").unwrap(), b" a b ");
+        assert_eq!(Encoding::Hex.decode("00ff").unwrap(), vec![0, 255]);
+        assert_eq!(Encoding::Base64.decode("AP8=").unwrap(), vec![0, 255]);
+        assert!(Encoding::Hex.decode("xyz").is_err());
+        assert!(Encoding::Base64.decode("!").is_err());
+    }
+
+    #[test]
+    fn encode_round_trips_through_decode() {
+        for encoding in [Encoding::Utf8, Encoding::Hex, Encoding::Base64] {
+            let text = encoding.encode(b"a b").unwrap();
+            assert_eq!(encoding.decode(&text).unwrap(), b"a b");
+        }
+        assert_eq!(Encoding::Utf8.encode(&[0, 255]), None);
+        assert_eq!(Encoding::Hex.encode(&[0, 255]).unwrap(), "00ff");
+    }
+
+    #[test]
+    fn parse_picks_encoding_from_syntax() {
+        assert_eq!(parse("hello").unwrap(), b"hello");
+        assert_eq!(parse("0x00ff").unwrap(), vec![0, 255]);
+        assert_eq!(parse("b64:AP8=").unwrap(), vec![0, 255]);
+        assert!(parse("@/does/not/exist").is_err());
+    }
+
+    #[test]
+    fn format_round_trips_through_parse() {
+        assert_eq!(format(b"hi there"), "\"hi there\"");
+        assert_eq!(format(b"tab\t"), "0x74616209");
+        assert_eq!(format(&[0, 255]), "0x00ff");
+        assert_eq!(parse(&format(&[0, 255])).unwrap(), vec![0, 255]);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod connection;
+pub mod encoding;
 pub mod requests;
 pub static DEFAULT_ADDRESS: &str = "localhost:7272";
 
@@ -10,8 +11,29 @@ pub enum Error {
     Ignored,
     Message(String),
     IoError(std::io::Error),
+    /// The peer speaks a different version of the protocol
+    UnsupportedVersion(u8),
 }
 
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::TracingInitializationError => write!(f, "failed to initialize tracing"),
+            Error::Ignored => write!(f, "ignored"),
+            Error::Message(message) => write!(f, "{}", message),
+            Error::IoError(e) => write!(f, "I/O error: {}", e),
+            Error::UnsupportedVersion(version) => write!(
+                f,
+                "the peer speaks version {} of the protocol, not {}",
+                version,
+                connection::PROTOCOL_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 impl From<&str> for Error {
     fn from(e: &str) -> Self {
         Error::Message(e.to_string())
@@ -1,26 +1,199 @@
-use clap::Subcommand;
 use serde_derive::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
 
-#[derive(Serialize, Deserialize, Debug, Subcommand)]
+/// A request to the server.
+///
+/// Keys and values are raw bytes, so they can hold anything.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum Request {
     /// Retrieves the value of a key-value pair
-    #[command(arg_required_else_help = true)]
-    Get { key: String },
-    /// Inserts a key-value pair
-    #[command(arg_required_else_help = true)]
-    Put { key: String, value: String },
-    /// Updates a key-value pair
-    #[command(arg_required_else_help = true)]
-    Update { key: String, value: String },
+    Get { key: Vec<u8> },
+    /// Inserts a key-value pair, which expires after `ttl` if one is given
+    Put {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    },
+    /// Updates a key-value pair, or responds with `KeyNotFound` if the key doesn't exist
+    Update { key: Vec<u8>, value: Vec<u8> },
     /// Deletes a key-value pair
-    #[command(arg_required_else_help = true)]
-    Delete { key: String },
+    Delete { key: Vec<u8> },
+    /// Lists the key-value pairs in a range of keys, in key order
+    ///
+    /// The response is a stream of `Entry`s followed by `ScanComplete`, which holds the key to
+    /// start the next page from if the scan stopped at `limit`.
+    Scan {
+        /// The first key in the range, inclusive
+        start: Option<Vec<u8>>,
+        /// The end of the range, exclusive
+        end: Option<Vec<u8>>,
+        /// The most entries to return in one page. The server returns at least one, if there are
+        /// any.
+        limit: Option<u32>,
+    },
+    /// Applies every operation, in order, or none of them
+    Batch(Vec<Op>),
+    /// Sets `key` to `new` if it currently holds `expected`, responding with `Swapped` or
+    /// `Mismatch`. `None` stands for a key which doesn't exist, so swapping to `None` deletes it.
+    CompareAndSwap {
+        key: Vec<u8>,
+        expected: Option<Vec<u8>>,
+        new: Option<Vec<u8>>,
+    },
+    /// Writes a consistent copy of the store to the directory `dest` on the server, while it
+    /// carries on serving writes
+    Backup { dest: PathBuf },
+    /// Replaces the contents of the store with the copy in the directory `src` on the server
+    Restore { src: PathBuf },
+    /// Reads every record in the store, responding with `Verified`
+    Verify,
+    /// Streams the log to a follower, starting just after `from`, or from the beginning of the log
+    /// if it is `None`.
+    ///
+    /// The response is an endless stream of `Log` chunks, with an empty chunk every so often when
+    /// nothing has been written, until the connection is closed.
+    Replicate { from: Option<LogPosition> },
+    /// Tells the leader that the follower on this connection has applied the log up to `position`.
+    /// It has no response.
+    Ack { position: LogPosition },
+    /// Responds with the server's `Status`
+    Status,
+    /// Makes a follower stop following its leader, and take writes itself
+    Promote,
+    /// Makes the server follow the leader at `leader`, dropping its own contents for the leader's
+    Follow { leader: String },
+    /// Responds with `Stats`, the server's metrics
+    Stats,
+}
+
+/// A single change in a [`Request::Batch`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Put { key: Vec<u8>, value: Vec<u8> },
+    Delete { key: Vec<u8> },
 }
 
 /// A response from the server.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum Response {
     KeyNotFound,
     Ok,
-    OkWithValue { value: String },
+    /// The value of a key, along with how long it has left before it expires, if it has a TTL
+    OkWithValue {
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    },
+    /// A key-value pair from a scan
+    Entry {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// The end of a scan, with the key to continue from if there are more entries in the range
+    ScanComplete {
+        next: Option<Vec<u8>>,
+    },
+    /// The compare-and-swap took place
+    Swapped,
+    /// The compare-and-swap didn't take place, because the key held `current` instead
+    Mismatch {
+        current: Option<Vec<u8>>,
+    },
+    /// Every record in the store was read, and these ones were damaged
+    Verified {
+        segments: u64,
+        records: u64,
+        corruptions: Vec<Corruption>,
+    },
+    /// A run of records from the leader's log, which a follower applies in order
+    Log {
+        /// Whether the follower must drop every key before it applies the records, because they
+        /// start over from the beginning of the log
+        reset: bool,
+        records: Vec<u8>,
+        /// The position just after the records, to ack and to replicate from next time
+        next: LogPosition,
+    },
+    /// The role of the server, and how far behind each of its followers is
+    Status {
+        role: Role,
+        followers: Vec<FollowerStatus>,
+    },
+    /// The server's metrics, in the Prometheus text format
+    Stats {
+        text: String,
+    },
+    /// The request failed
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+/// A request along with the id that the client chose for it.
+///
+/// A client can send many requests on one connection without waiting for the responses, which
+/// carry the id of the request they answer.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RequestFrame {
+    pub id: u64,
+    pub request: Request,
+}
+
+/// A response along with the id of the request it answers
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ResponseFrame {
+    pub id: u64,
+    pub response: Response,
+}
+
+/// A position in the log of the leader, see [`Request::Replicate`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogPosition {
+    pub segment: u32,
+    pub offset: u64,
+}
+
+/// Whether a server takes writes, or copies them from a leader
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Role {
+    Leader,
+    Follower { leader: String },
+}
+
+/// A follower which is replicating from the server
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FollowerStatus {
+    /// The address the follower is connected from
+    pub addr: String,
+    /// How far the follower has acked the log, if it has yet
+    pub position: Option<LogPosition>,
+    /// How many bytes of the log the follower has yet to ack
+    pub lag: Option<u64>,
+}
+
+/// A damaged record, reported by [`Response::Verified`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Corruption {
+    /// The segment which holds the record
+    pub segment: u32,
+    /// The position of the record in the segment
+    pub offset: u64,
+    /// What is wrong with the record
+    pub problem: String,
+}
+
+/// The kind of failure reported by [`Response::Error`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The request couldn't be understood, and the connection will be closed
+    BadRequest,
+    /// The store failed to read or write its files
+    Io,
+    /// The store found data that failed its checksum
+    Corruption,
+    /// Any other failure on the server
+    Internal,
+    /// The server is a follower, so it doesn't take writes. The message names its leader.
+    NotLeader,
 }
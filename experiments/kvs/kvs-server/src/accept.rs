@@ -1,12 +1,21 @@
 use crate::actors::System;
+use crate::metrics;
+use crate::Config;
 use kvs_common::connection::Connection;
 use tokio::net::TcpListener;
 use tokio::spawn;
 use tracing::info;
 
 /// Accepts connections and handles them in a loop
-pub async fn accept_connections(listener: TcpListener) -> crate::Result<()> {
-    let system = System::new()?;
+pub async fn accept_connections(
+    listener: TcpListener,
+    kv_store: kvs::KVStore,
+    config: Config,
+) -> crate::Result<()> {
+    let system = System::new(kv_store, &config);
+    if let Some(addr) = &config.metrics_addr {
+        metrics::spawn_http(addr, system.metrics()).await?;
+    }
 
     loop {
         let (stream, addr) = listener.accept().await?;
@@ -16,7 +25,7 @@ pub async fn accept_connections(listener: TcpListener) -> crate::Result<()> {
             let conn = Connection::new(stream);
             let mut system = system.clone();
             async move {
-                system.handle_connection(conn).await;
+                system.handle_connection(conn, addr).await;
             }
         });
     }
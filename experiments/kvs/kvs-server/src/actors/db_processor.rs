@@ -1,102 +1,293 @@
+use crate::actors::error_response;
+use crate::actors::lookup::{is_lookup, lookup};
+use crate::actors::replication;
 use crate::actors::writer::WriterHandle;
-use kvs_common::connection::Connection;
-use kvs_common::requests::{Request, Response};
-use std::io::Error;
-
-use tokio::spawn;
+use kvs::{Checkpoint, Error, KVReader, LogChunk, LogPosition, Report, Swap};
+use kvs_common::requests::{Corruption, Op, Request, Response};
+use std::thread;
+use tokio::sync::{oneshot, watch};
+use tracing::{info, warn};
 
 #[derive(Debug, Clone)]
 pub struct DbProcessorHandle {
     chan: tokio::sync::mpsc::Sender<DbProcessorMessage>,
 }
 
+/// Writes to the store, one request at a time
 struct DbProcessorActor {
     kv_store: kvs::KVStore,
+    reader: KVReader,
     chan: tokio::sync::mpsc::Receiver<DbProcessorMessage>,
-    writer: WriterHandle,
+    /// Changes after every write, to wake up the streams of the log to followers
+    changes: watch::Sender<u64>,
 }
 
 #[derive(Debug)]
-struct DbProcessorMessage {
-    conn: Connection,
-    request: Request,
+enum DbProcessorMessage {
+    /// Processes the request `id`, sending the responses to `writer`
+    Request {
+        id: u64,
+        request: Request,
+        writer: WriterHandle,
+    },
+    /// Compacts the store, dropping the records of overwritten, deleted and expired keys
+    Compact,
+    /// Applies a chunk of the log of `leader`, and records how far the store has got
+    ApplyLog {
+        leader: String,
+        chunk: LogChunk,
+        reply: oneshot::Sender<Result<(), Error>>,
+    },
+    /// Looks up how far the store has applied the log of `leader`
+    LeaderPosition {
+        leader: String,
+        reply: oneshot::Sender<Option<LogPosition>>,
+    },
+    /// Forgets how far the store has applied the log of its leader, once it has stopped following
+    ForgetLeader,
 }
 
 impl DbProcessorHandle {
-    pub fn new(writer: WriterHandle) -> crate::Result<Self> {
-        let kv_store = kvs::KVStore::open()?;
-
+    pub fn new(kv_store: kvs::KVStore, changes: watch::Sender<u64>) -> Self {
         let (tx, rx) = tokio::sync::mpsc::channel(100);
 
         let actor = DbProcessorActor {
+            reader: kv_store.reader(),
             kv_store,
             chan: rx,
-            writer,
+            changes,
         };
 
-        spawn(async move { actor.run().await });
+        // Writing to the store blocks, so the writes run on a thread of their own
+        thread::Builder::new()
+            .name("kvs-writer".to_string())
+            .spawn(move || actor.run())
+            .expect("failed to spawn the writer thread");
+
+        Self { chan: tx }
+    }
+
+    /// Processes the request `id`, sending the responses to `writer`
+    pub async fn send(&mut self, id: u64, request: Request, writer: WriterHandle) {
+        self.chan
+            .send(DbProcessorMessage::Request {
+                id,
+                request,
+                writer,
+            })
+            .await
+            .unwrap();
+    }
+
+    /// Compacts the store, in turn with the requests
+    pub async fn compact(&mut self) {
+        self.chan.send(DbProcessorMessage::Compact).await.unwrap();
+    }
+
+    /// Applies a chunk of the log of `leader`, in turn with the requests
+    pub async fn apply_log(&mut self, leader: String, chunk: LogChunk) -> Result<(), Error> {
+        let (reply, rx) = oneshot::channel();
+        let msg = DbProcessorMessage::ApplyLog {
+            leader,
+            chunk,
+            reply,
+        };
+        self.chan.send(msg).await.unwrap();
+        rx.await.unwrap()
+    }
 
-        Ok(Self { chan: tx })
+    /// How far the store has applied the log of `leader`, or `None` if it has to start over
+    pub async fn leader_position(&mut self, leader: String) -> Option<LogPosition> {
+        let (reply, rx) = oneshot::channel();
+        let msg = DbProcessorMessage::LeaderPosition { leader, reply };
+        self.chan.send(msg).await.unwrap();
+        rx.await.unwrap()
     }
 
-    pub async fn send(&mut self, conn: Connection, request: Request) {
+    /// Forgets how far the store has applied the log of its leader
+    pub async fn forget_leader(&mut self) {
         self.chan
-            .send(DbProcessorMessage { conn, request })
+            .send(DbProcessorMessage::ForgetLeader)
             .await
             .unwrap();
     }
 }
 
 impl DbProcessorActor {
-    async fn process(&mut self, msg: DbProcessorMessage) {
-        let res: Result<Response, Error> = match msg.request {
-            Request::Put { key, value } => {
-                match self.kv_store.insert(key.as_bytes(), value.as_bytes()) {
-                    Ok(_) => Ok(Response::Ok),
+    fn process(&mut self, id: u64, request: Request, mut writer: WriterHandle) {
+        let write = !is_lookup(&request);
+        let res: Result<Vec<Response>, Error> = match request {
+            Request::Put { key, value, ttl } => {
+                let res = match ttl {
+                    Some(ttl) => self.kv_store.insert_with_ttl(&key, &value, ttl),
+                    None => self.kv_store.insert(&key, &value),
+                };
+                match res {
+                    Ok(_) => Ok(vec![Response::Ok]),
                     Err(e) => Err(e),
                 }
             }
-            Request::Get { key } => match self.kv_store.get(key.as_bytes()) {
-                Ok(Some(v)) => {
-                    let string = String::from_utf8_lossy(&v);
-                    let trim = string.trim();
-                    match trim {
-                        // empty string means that the entry was removed, hence the key doesn't exist
-                        "" => Ok(Response::KeyNotFound),
-                        _ => Ok(Response::OkWithValue {
-                            value: string.to_string(),
-                        }),
-                    }
-                }
-                Ok(None) => Ok(Response::KeyNotFound),
+            Request::Update { key, value } => match self.kv_store.update(&key, &value) {
+                Ok(true) => Ok(vec![Response::Ok]),
+                Ok(false) => Ok(vec![Response::KeyNotFound]),
+                Err(e) => Err(e),
+            },
+            Request::Delete { key } => match self.kv_store.delete(&key) {
+                Ok(_) => Ok(vec![Response::Ok]),
                 Err(e) => Err(e),
             },
-            Request::Update { key, value } => {
-                match self.kv_store.update(key.as_bytes(), value.as_bytes()) {
-                    Ok(_) => Ok(Response::Ok),
+            Request::Batch(ops) => {
+                let ops: Vec<_> = ops
+                    .into_iter()
+                    .map(|op| match op {
+                        Op::Put { key, value } => kvs::Op::Put { key, value },
+                        Op::Delete { key } => kvs::Op::Delete { key },
+                    })
+                    .collect();
+                match self.kv_store.write_batch(&ops) {
+                    Ok(_) => Ok(vec![Response::Ok]),
                     Err(e) => Err(e),
                 }
             }
-            Request::Delete { key } => match self.kv_store.delete(key.as_bytes()) {
-                Ok(_) => Ok(Response::Ok),
+            Request::CompareAndSwap { key, expected, new } => {
+                match self
+                    .kv_store
+                    .compare_and_swap(&key, expected.as_deref(), new.as_deref())
+                {
+                    Ok(Swap::Swapped) => Ok(vec![Response::Swapped]),
+                    Ok(Swap::Mismatch { current }) => Ok(vec![Response::Mismatch { current }]),
+                    Err(e) => Err(e),
+                }
+            }
+            Request::Restore { src } => match self.kv_store.restore(&src) {
+                Ok(_) => Ok(vec![Response::Ok]),
                 Err(e) => Err(e),
             },
+            Request::Backup { dest } => {
+                return self.with_checkpoint(id, writer, move |checkpoint| {
+                    checkpoint.write_to(&dest)?;
+                    Ok(vec![Response::Ok])
+                })
+            }
+            Request::Verify => {
+                return self.with_checkpoint(id, writer, |checkpoint| {
+                    Ok(vec![verified(checkpoint.verify()?)])
+                })
+            }
+            // Only reached when the server has no lookup threads
+            request @ (Request::Get { .. } | Request::Scan { .. }) => {
+                Ok(lookup(&mut self.reader, request))
+            }
+            request @ (Request::Replicate { .. }
+            | Request::Ack { .. }
+            | Request::Status
+            | Request::Promote
+            | Request::Follow { .. }) => {
+                unreachable!("{:?} is served by the replication actor", request)
+            }
+            Request::Stats => unreachable!("stats are served by the connection's reader"),
         };
 
-        match res {
-            Ok(value) => {
-                let mut writer = self.writer.clone();
-                spawn(async move {
-                    writer.send(msg.conn, value).await;
-                });
-            }
-            Err(_) => {}
+        if write {
+            self.changes.send_modify(|n| *n += 1);
+        }
+        let responses = res.unwrap_or_else(|e| vec![error_response(e)]);
+        writer.blocking_send(id, responses);
+    }
+
+    /// Takes a checkpoint of the store, and hands it to `f` on a thread of its own so that the
+    /// writes can carry on while it is read
+    fn with_checkpoint<F>(&mut self, id: u64, mut writer: WriterHandle, f: F)
+    where
+        F: FnOnce(Checkpoint) -> Result<Vec<Response>, Error> + Send + 'static,
+    {
+        let checkpoint = match self.kv_store.checkpoint() {
+            Ok(checkpoint) => checkpoint,
+            Err(e) => return writer.blocking_send(id, vec![error_response(e)]),
+        };
+
+        thread::spawn(move || {
+            let responses = f(checkpoint).unwrap_or_else(|e| vec![error_response(e)]);
+            writer.blocking_send(id, responses);
+        });
+    }
+
+    /// Applies a chunk of the leader's log, and then records how far the store has got.
+    ///
+    /// The records are synced before the position is saved, so the position never runs ahead of
+    /// them, while applying records twice does no harm.
+    fn apply_log(&mut self, leader: &str, chunk: LogChunk) -> Result<(), Error> {
+        if chunk.records.is_empty() && !chunk.reset {
+            return Ok(());
+        }
+
+        let dir = self.kv_store.dir().to_path_buf();
+        if chunk.reset {
+            // A crash part-way through the reset must start it over
+            replication::forget_position(&dir)?;
+            self.kv_store.clear()?;
+        }
+        self.kv_store.apply_log(&chunk.records)?;
+        self.kv_store.flush()?;
+        replication::write_position(&dir, leader, chunk.next)?;
+
+        self.changes.send_modify(|n| *n += 1);
+        Ok(())
+    }
+
+    fn compact(&mut self) {
+        match self.kv_store.compact() {
+            Ok(()) => info!(
+                "Compacted the store, which holds {} keys",
+                self.kv_store.len()
+            ),
+            Err(e) => warn!("Failed to compact the store: {}", e),
         }
     }
 
-    async fn run(mut self) {
-        while let Some(msg) = self.chan.recv().await {
-            self.process(msg).await;
+    fn run(mut self) {
+        while let Some(msg) = self.chan.blocking_recv() {
+            match msg {
+                DbProcessorMessage::Request {
+                    id,
+                    request,
+                    writer,
+                } => self.process(id, request, writer),
+                DbProcessorMessage::Compact => self.compact(),
+                DbProcessorMessage::ApplyLog {
+                    leader,
+                    chunk,
+                    reply,
+                } => {
+                    let _ = reply.send(self.apply_log(&leader, chunk));
+                }
+                DbProcessorMessage::LeaderPosition { leader, reply } => {
+                    let _ = reply.send(replication::read_position(self.kv_store.dir(), &leader));
+                }
+                DbProcessorMessage::ForgetLeader => {
+                    if let Err(e) = replication::forget_position(self.kv_store.dir()) {
+                        warn!("Failed to forget the position in the leader's log: {}", e);
+                    }
+                }
+            }
         }
     }
 }
+
+fn verified(report: Report) -> Response {
+    let corruptions = report
+        .corruptions
+        .into_iter()
+        .map(|corruption| Corruption {
+            segment: corruption.file_id,
+            offset: corruption.offset,
+            problem: corruption.problem.to_string(),
+        })
+        .collect();
+
+    Response::Verified {
+        segments: report.segments as u64,
+        records: report.records,
+        corruptions,
+    }
+}
@@ -0,0 +1,170 @@
+use crate::actors::error_response;
+use crate::actors::writer::WriterHandle;
+use kvs::{ByteString, Error, KVReader};
+use kvs_common::requests::{Request, Response};
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The most entries that are returned by a single scan
+const MAX_SCAN_LIMIT: u32 = 1000;
+
+/// Serves gets and scans from a pool of threads, each with a [`KVReader`] of its own, so that
+/// reads neither wait for each other nor for writes.
+#[derive(Debug, Clone)]
+pub struct LookupHandle {
+    chan: tokio::sync::mpsc::Sender<LookupMessage>,
+}
+
+struct LookupActor {
+    reader: KVReader,
+    /// Shared by every thread in the pool, so that an idle thread picks up the next lookup
+    chan: Arc<Mutex<tokio::sync::mpsc::Receiver<LookupMessage>>>,
+}
+
+#[derive(Debug)]
+struct LookupMessage {
+    id: u64,
+    request: Request,
+    writer: WriterHandle,
+}
+
+impl LookupHandle {
+    pub fn new(reader: KVReader, threads: usize) -> Self {
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        let rx = Arc::new(Mutex::new(rx));
+
+        for i in 0..threads {
+            let actor = LookupActor {
+                reader: reader.clone(),
+                chan: rx.clone(),
+            };
+
+            // Reading from the store blocks, so the lookups run on threads of their own
+            thread::Builder::new()
+                .name(format!("kvs-lookup-{}", i))
+                .spawn(move || actor.run())
+                .expect("failed to spawn a lookup thread");
+        }
+
+        Self { chan: tx }
+    }
+
+    /// Looks up the request `id`, which must be a get or a scan, sending the responses to `writer`
+    pub async fn send(&mut self, id: u64, request: Request, writer: WriterHandle) {
+        self.chan
+            .send(LookupMessage {
+                id,
+                request,
+                writer,
+            })
+            .await
+            .unwrap();
+    }
+}
+
+impl LookupActor {
+    fn run(mut self) {
+        loop {
+            // Only one thread waits for a message at a time, while the rest wait for the lock
+            let msg = match self.chan.lock().unwrap().blocking_recv() {
+                Some(msg) => msg,
+                None => break,
+            };
+
+            let responses = lookup(&mut self.reader, msg.request);
+            let mut writer = msg.writer;
+            writer.blocking_send(msg.id, responses);
+        }
+    }
+}
+
+/// Whether a request only reads from the store, so that it can be served by a [`LookupHandle`]
+pub fn is_lookup(request: &Request) -> bool {
+    matches!(request, Request::Get { .. } | Request::Scan { .. })
+}
+
+/// Serves a get or a scan
+pub fn lookup(reader: &mut KVReader, request: Request) -> Vec<Response> {
+    let res = match request {
+        Request::Get { key } => match reader.get_with_ttl(&key) {
+            Ok(Some((value, ttl))) => Ok(vec![Response::OkWithValue { value, ttl }]),
+            Ok(None) => Ok(vec![Response::KeyNotFound]),
+            Err(e) => Err(e),
+        },
+        Request::Scan { start, end, limit } => scan(reader, start, end, limit),
+        _ => unreachable!("{:?} is not a lookup", request),
+    };
+
+    res.unwrap_or_else(|e| vec![error_response(e)])
+}
+
+/// Reads a page of entries from a range of keys, along with the key that the next page starts at
+fn scan(
+    reader: &mut KVReader,
+    start: Option<ByteString>,
+    end: Option<ByteString>,
+    limit: Option<u32>,
+) -> Result<Vec<Response>, Error> {
+    // A page with no entries would start the next one from where it started itself, so a client
+    // following the pages would never get to the end
+    let limit = limit.map_or(MAX_SCAN_LIMIT, |limit| limit.clamp(1, MAX_SCAN_LIMIT));
+    let start = start.map_or(Bound::Unbounded, Bound::Included);
+    let end = end.map_or(Bound::Unbounded, Bound::Excluded);
+
+    let mut entries = reader.scan::<ByteString, _>((start, end));
+    let mut responses = Vec::new();
+
+    for entry in entries.by_ref().take(limit as usize) {
+        let (key, value) = entry?;
+        responses.push(Response::Entry { key, value });
+    }
+
+    let next = entries.next().transpose()?.map(|(key, _)| key);
+    responses.push(Response::ScanComplete { next });
+
+    Ok(responses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn pages_of_zero_entries_still_make_progress() {
+        let dir = TempDir::new().unwrap();
+        let mut store = kvs::KVStore::open(dir.path()).unwrap();
+        for key in [b"a", b"b", b"c"] {
+            store.insert(key, b"1").unwrap();
+        }
+        let mut reader = store.reader();
+
+        let mut keys = Vec::new();
+        let mut start = None;
+        for _ in 0..4 {
+            let scan = Request::Scan {
+                start,
+                end: None,
+                limit: Some(0),
+            };
+            let mut responses = lookup(&mut reader, scan);
+            start = match responses.pop() {
+                Some(Response::ScanComplete { next }) => next,
+                response => panic!("unexpected response {:?}", response),
+            };
+            for response in responses {
+                match response {
+                    Response::Entry { key, .. } => keys.push(key),
+                    response => panic!("unexpected response {:?}", response),
+                }
+            }
+            if start.is_none() {
+                break;
+            }
+        }
+        // One page per entry, and the last one ends the scan
+        assert_eq!(start, None);
+        assert_eq!(keys, [b"a", b"b", b"c"]);
+    }
+}
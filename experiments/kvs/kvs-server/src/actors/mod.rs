@@ -1,6 +1,25 @@
+use kvs::Error;
+use kvs_common::requests::{ErrorCode, Response};
+
 pub use system::System;
 
 pub mod db_processor;
+pub mod lookup;
 pub mod reader;
+pub mod replication;
 mod system;
 pub mod writer;
+
+/// Reports a failure of the store to the client
+fn error_response(e: Error) -> Response {
+    let code = match e {
+        Error::Io(_) => ErrorCode::Io,
+        Error::Corruption { .. } => ErrorCode::Corruption,
+        Error::Locked(_) | Error::UnknownFormat(_) => ErrorCode::Internal,
+    };
+
+    Response::Error {
+        code,
+        message: e.to_string(),
+    }
+}
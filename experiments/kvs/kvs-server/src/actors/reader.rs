@@ -1,25 +1,60 @@
 use crate::actors::db_processor::DbProcessorHandle;
-use kvs_common::connection::Connection;
-use kvs_common::requests::Request;
+use crate::actors::error_response;
+use crate::actors::lookup::{is_lookup, LookupHandle};
+use crate::actors::replication::{is_replication, is_write, not_leader, ReplicationHandle};
+use crate::actors::writer::WriterHandle;
+use crate::metrics::{key_size, Metrics, Operation};
+use kvs_common::connection::{Connection, ConnectionReader};
+use kvs_common::requests::{ErrorCode, Request, RequestFrame, Response, Role};
+use kvs_common::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::spawn;
+use tokio::task::spawn_blocking;
+use tokio::time::timeout;
+use tracing::{debug, field, info, info_span};
 
 #[derive(Debug, Clone)]
 pub struct ReaderHandle {
-    chan: tokio::sync::mpsc::Sender<Connection>,
+    chan: tokio::sync::mpsc::Sender<(Connection, SocketAddr)>,
 }
 
 struct ReaderActor {
-    chan: tokio::sync::mpsc::Receiver<Connection>,
+    chan: tokio::sync::mpsc::Receiver<(Connection, SocketAddr)>,
+    handlers: Handlers,
+    idle_timeout: Duration,
+}
+
+/// Where the requests read from a connection are sent
+#[derive(Clone)]
+struct Handlers {
     db_processor: DbProcessorHandle,
+    lookups: Option<LookupHandle>,
+    replication: ReplicationHandle,
+    /// Counts the requests, and answers `Stats`
+    metrics: Arc<Metrics>,
 }
 
 impl ReaderHandle {
-    pub fn new(db_processor: DbProcessorHandle) -> Self {
+    pub fn new(
+        db_processor: DbProcessorHandle,
+        lookups: Option<LookupHandle>,
+        replication: ReplicationHandle,
+        metrics: Arc<Metrics>,
+        idle_timeout: Duration,
+    ) -> Self {
         let (tx, rx) = tokio::sync::mpsc::channel(100);
 
         let actor = ReaderActor {
             chan: rx,
-            db_processor,
+            handlers: Handlers {
+                db_processor,
+                lookups,
+                replication,
+                metrics,
+            },
+            idle_timeout,
         };
 
         spawn(async move { actor.run().await });
@@ -27,25 +62,125 @@ impl ReaderHandle {
         Self { chan: tx }
     }
 
-    pub async fn send(&mut self, conn: Connection) {
-        self.chan.send(conn).await.unwrap();
+    /// Serves the requests on a connection from the client at `peer`
+    pub async fn send(&mut self, conn: Connection, peer: SocketAddr) {
+        self.chan.send((conn, peer)).await.unwrap();
     }
 }
 
 impl ReaderActor {
-    async fn handle_connection(&mut self, mut conn: Connection) {
-        let mut db_processor = self.db_processor.clone();
-        spawn(async move {
-            if let Ok(Some(request)) = conn.read::<Request>().await {
-                println!("Got request: {:?}", request);
-                db_processor.send(conn, request).await;
-            }
-        });
+    async fn handle_connection(&mut self, conn: Connection, peer: SocketAddr) {
+        let (reader, writer) = conn.into_split();
+        let writer = WriterHandle::new(writer, self.handlers.metrics.clone());
+        let handlers = self.handlers.clone();
+        let idle_timeout = self.idle_timeout;
+        spawn(async move { serve(reader, writer, peer, handlers, idle_timeout).await });
     }
 
     async fn run(mut self) {
-        while let Some(conn) = self.chan.recv().await {
-            self.handle_connection(conn).await;
+        while let Some((conn, peer)) = self.chan.recv().await {
+            self.handle_connection(conn, peer).await;
         }
     }
 }
+
+/// Reads requests from a connection until it is closed, or it has been idle for `idle_timeout`.
+///
+/// Requests are passed on as soon as they are read, so a client can pipeline them, and the
+/// responses are sent back through `writer` tagged with the id of their request. Gets and scans go
+/// to the lookup threads, if there are any, so they may be answered before writes which were sent
+/// ahead of them. Writes are refused while the server is a follower.
+///
+/// Each request is counted in the metrics, and gets a span which its response is logged in.
+async fn serve(
+    mut reader: ConnectionReader,
+    mut writer: WriterHandle,
+    peer: SocketAddr,
+    handlers: Handlers,
+    idle_timeout: Duration,
+) {
+    let Handlers {
+        mut db_processor,
+        mut lookups,
+        mut replication,
+        metrics,
+    } = handlers;
+    let role = replication.role();
+    let _connection = metrics.connection();
+
+    loop {
+        let frame = match timeout(idle_timeout, reader.read::<RequestFrame>()).await {
+            Ok(Ok(Some(frame))) => frame,
+            Ok(Ok(None)) | Ok(Err(Error::IoError(_))) => break,
+            Ok(Err(e)) => {
+                // The rest of the stream can't be trusted after a bad message, so hang up
+                let error = Response::Error {
+                    code: ErrorCode::BadRequest,
+                    message: e.to_string(),
+                };
+                writer.send(0, vec![error]).await;
+                break;
+            }
+            Err(_) => {
+                info!("Closing connection after {:?} idle", idle_timeout);
+                break;
+            }
+        };
+
+        let op = Operation::of(&frame.request);
+        let span =
+            info_span!("request", id = frame.id, op = op.name(), key_size = field::Empty, %peer);
+        if let Some(size) = key_size(&frame.request) {
+            span.record("key_size", size);
+        }
+        debug!(parent: &span, "Received");
+        metrics.received(op);
+        // An ack has no response to wait for
+        if op != Operation::Ack {
+            writer.started(frame.id, op, Instant::now(), span).await;
+        }
+
+        let refused = match &*role.borrow() {
+            Role::Follower { leader } if is_write(&frame.request) => Some(not_leader(leader)),
+            _ => None,
+        };
+        if let Some(response) = refused {
+            writer.send(frame.id, vec![response]).await;
+            continue;
+        }
+
+        match &mut lookups {
+            _ if frame.request == Request::Stats => {
+                let metrics = metrics.clone();
+                let mut writer = writer.clone();
+                spawn(async move {
+                    // Finding the size of the log reads the directory, so it is done off the runtime
+                    let response = match spawn_blocking(move || metrics.render()).await {
+                        Ok(Ok(text)) => Response::Stats { text },
+                        Ok(Err(e)) => error_response(e),
+                        Err(e) => Response::Error {
+                            code: ErrorCode::Internal,
+                            message: e.to_string(),
+                        },
+                    };
+                    writer.send(frame.id, vec![response]).await;
+                });
+            }
+            _ if is_replication(&frame.request) => {
+                replication
+                    .send(peer, frame.id, frame.request, writer.clone())
+                    .await
+            }
+            Some(lookups) if is_lookup(&frame.request) => {
+                lookups.send(frame.id, frame.request, writer.clone()).await
+            }
+            _ => {
+                db_processor
+                    .send(frame.id, frame.request, writer.clone())
+                    .await
+            }
+        }
+    }
+
+    replication.disconnected(peer).await;
+}
@@ -0,0 +1,465 @@
+use crate::actors::db_processor::DbProcessorHandle;
+use crate::actors::error_response;
+use crate::actors::writer::WriterHandle;
+use kvs::{KVReader, LogChunk};
+use kvs_common::connection::Connection;
+use kvs_common::requests::{
+    ErrorCode, FollowerStatus, LogPosition, Request, RequestFrame, Response, ResponseFrame, Role,
+};
+use kvs_common::Error;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use tokio::spawn;
+use tokio::sync::watch;
+use tokio::task::{spawn_blocking, JoinHandle};
+use tokio::time::{sleep, timeout};
+use tracing::{info, warn};
+
+/// The most bytes of records that are sent to a follower in one chunk
+const MAX_CHUNK_LEN: usize = 1024 * 1024;
+
+/// How often a leader sends an empty chunk to a follower which has caught up, so that each of them
+/// knows the other is still there
+const HEARTBEAT: Duration = Duration::from_secs(1);
+
+/// How long a follower waits before it reconnects to its leader
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// The file in the data directory of a follower which records how far it has applied the log of
+/// its leader
+const POSITION_FILE: &str = "replica.pos";
+
+/// Streams the log to followers, and follows a leader when the server is a follower itself.
+///
+/// Which of the two the server is can be switched while it runs, which is how a follower is
+/// promoted when its leader fails.
+#[derive(Debug, Clone)]
+pub struct ReplicationHandle {
+    chan: tokio::sync::mpsc::Sender<ReplicationMessage>,
+    role: watch::Receiver<Role>,
+}
+
+struct ReplicationActor {
+    chan: tokio::sync::mpsc::Receiver<ReplicationMessage>,
+    reader: KVReader,
+    db_processor: DbProcessorHandle,
+    /// Changes whenever the store is written to
+    changes: watch::Receiver<u64>,
+    role: watch::Sender<Role>,
+    followers: HashMap<SocketAddr, Follower>,
+    /// Replicates from the leader, while the server is a follower
+    following: Option<JoinHandle<()>>,
+}
+
+/// A follower which is streaming the log from this server
+struct Follower {
+    position: Option<LogPosition>,
+    stream: JoinHandle<()>,
+}
+
+#[derive(Debug)]
+enum ReplicationMessage {
+    /// Processes the request `id` from the client at `peer`, sending the responses to `writer`
+    Request {
+        peer: SocketAddr,
+        id: u64,
+        request: Request,
+        writer: WriterHandle,
+    },
+    /// The connection from `peer` was closed
+    Disconnected { peer: SocketAddr },
+}
+
+impl ReplicationHandle {
+    pub fn new(
+        reader: KVReader,
+        db_processor: DbProcessorHandle,
+        changes: watch::Receiver<u64>,
+        leader: Option<String>,
+    ) -> Self {
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        let (role, role_rx) = watch::channel(Role::Leader);
+
+        let mut actor = ReplicationActor {
+            chan: rx,
+            reader,
+            db_processor,
+            changes,
+            role,
+            followers: HashMap::new(),
+            following: None,
+        };
+        if let Some(leader) = leader {
+            actor.follow(leader);
+        }
+
+        spawn(async move { actor.run().await });
+
+        Self {
+            chan: tx,
+            role: role_rx,
+        }
+    }
+
+    /// The role of the server, which is kept up to date
+    pub fn role(&self) -> watch::Receiver<Role> {
+        self.role.clone()
+    }
+
+    /// Processes the request `id`, which must be one for which [`is_replication`] holds
+    pub async fn send(
+        &mut self,
+        peer: SocketAddr,
+        id: u64,
+        request: Request,
+        writer: WriterHandle,
+    ) {
+        let msg = ReplicationMessage::Request {
+            peer,
+            id,
+            request,
+            writer,
+        };
+        self.chan.send(msg).await.unwrap();
+    }
+
+    /// Stops streaming the log to `peer`, if it is a follower
+    pub async fn disconnected(&mut self, peer: SocketAddr) {
+        let msg = ReplicationMessage::Disconnected { peer };
+        self.chan.send(msg).await.unwrap();
+    }
+}
+
+impl ReplicationActor {
+    async fn process(
+        &mut self,
+        peer: SocketAddr,
+        id: u64,
+        request: Request,
+        mut writer: WriterHandle,
+    ) {
+        let response = match request {
+            Request::Replicate { from } => {
+                info!("Streaming the log to {} from {:?}", peer, from);
+                let stream = spawn(stream(
+                    self.reader.clone(),
+                    id,
+                    from.map(to_kvs),
+                    writer,
+                    self.changes.clone(),
+                ));
+                let follower = Follower {
+                    position: from,
+                    stream,
+                };
+                if let Some(old) = self.followers.insert(peer, follower) {
+                    old.stream.abort();
+                }
+                return;
+            }
+            Request::Ack { position } => {
+                if let Some(follower) = self.followers.get_mut(&peer) {
+                    follower.position = Some(position);
+                }
+                return;
+            }
+            Request::Status => self.status().await,
+            Request::Promote => {
+                if let Some(following) = self.following.take() {
+                    following.abort();
+                }
+                self.db_processor.forget_leader().await;
+                self.role.send_replace(Role::Leader);
+                info!("Promoted to leader");
+                Response::Ok
+            }
+            Request::Follow { leader } => {
+                self.follow(leader);
+                Response::Ok
+            }
+            _ => unreachable!("{:?} is not a replication request", request),
+        };
+        writer.send(id, vec![response]).await;
+    }
+
+    /// Starts following `leader`, in place of any other leader
+    fn follow(&mut self, leader: String) {
+        if let Some(following) = self.following.take() {
+            following.abort();
+        }
+        info!("Following {}", leader);
+        self.role.send_replace(Role::Follower {
+            leader: leader.clone(),
+        });
+        self.following = Some(spawn(follow(leader, self.db_processor.clone())));
+    }
+
+    async fn status(&self) -> Response {
+        let reader = self.reader.clone();
+        let positions: Vec<_> = self
+            .followers
+            .iter()
+            .map(|(addr, follower)| (*addr, follower.position))
+            .collect();
+
+        // Finding the lag means looking at the segments, which blocks
+        let followers = spawn_blocking(move || {
+            positions
+                .into_iter()
+                .map(|(addr, position)| FollowerStatus {
+                    addr: addr.to_string(),
+                    position,
+                    lag: position.and_then(|position| reader.log_len_after(to_kvs(position)).ok()),
+                })
+                .collect()
+        })
+        .await
+        .unwrap();
+
+        Response::Status {
+            role: self.role.borrow().clone(),
+            followers,
+        }
+    }
+
+    async fn run(mut self) {
+        while let Some(msg) = self.chan.recv().await {
+            match msg {
+                ReplicationMessage::Request {
+                    peer,
+                    id,
+                    request,
+                    writer,
+                } => self.process(peer, id, request, writer).await,
+                ReplicationMessage::Disconnected { peer } => {
+                    if let Some(follower) = self.followers.remove(&peer) {
+                        info!("Follower {} disconnected", peer);
+                        follower.stream.abort();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether a request is served by a [`ReplicationHandle`]
+pub fn is_replication(request: &Request) -> bool {
+    matches!(
+        request,
+        Request::Replicate { .. }
+            | Request::Ack { .. }
+            | Request::Status
+            | Request::Promote
+            | Request::Follow { .. }
+    )
+}
+
+/// Whether a request changes the store, so that only a leader may serve it
+pub fn is_write(request: &Request) -> bool {
+    matches!(
+        request,
+        Request::Put { .. }
+            | Request::Update { .. }
+            | Request::Delete { .. }
+            | Request::Batch(_)
+            | Request::CompareAndSwap { .. }
+            | Request::Restore { .. }
+    )
+}
+
+/// The response to a write sent to a follower
+pub fn not_leader(leader: &str) -> Response {
+    Response::Error {
+        code: ErrorCode::NotLeader,
+        message: format!(
+            "this server is a follower, send writes to its leader at {}",
+            leader
+        ),
+    }
+}
+
+/// Sends the log to a follower as the request `id`, from `from` onwards, for as long as the follower
+/// is connected
+async fn stream(
+    reader: KVReader,
+    id: u64,
+    mut from: Option<kvs::LogPosition>,
+    mut writer: WriterHandle,
+    mut changes: watch::Receiver<u64>,
+) {
+    loop {
+        // Any write from here on wakes the stream up once it has caught up
+        changes.borrow_and_update();
+
+        let read = {
+            let reader = reader.clone();
+            spawn_blocking(move || reader.read_log(from, MAX_CHUNK_LEN))
+        };
+        let chunk = match read.await.unwrap() {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                writer.send(id, vec![error_response(e)]).await;
+                return;
+            }
+        };
+
+        let caught_up = chunk.records.is_empty() && !chunk.reset;
+        from = Some(chunk.next);
+        if !caught_up {
+            writer.send(id, vec![log_response(chunk)]).await;
+            continue;
+        }
+
+        match timeout(HEARTBEAT, changes.changed()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(_)) => return,
+            Err(_) => {
+                let heartbeat = log_response(LogChunk {
+                    reset: false,
+                    records: Vec::new(),
+                    next: chunk.next,
+                });
+                writer.send(id, vec![heartbeat]).await;
+            }
+        }
+        if writer.is_closed() {
+            return;
+        }
+    }
+}
+
+fn log_response(chunk: LogChunk) -> Response {
+    Response::Log {
+        reset: chunk.reset,
+        records: chunk.records,
+        next: to_common(chunk.next),
+    }
+}
+
+/// Replicates the log of `leader` into the store, reconnecting whenever the connection fails
+async fn follow(leader: String, mut db_processor: DbProcessorHandle) {
+    loop {
+        if let Err(e) = replicate(&leader, &mut db_processor).await {
+            warn!("Replicating from {} failed: {}", leader, e);
+        }
+        sleep(RETRY_DELAY).await;
+    }
+}
+
+async fn replicate(leader: &str, db_processor: &mut DbProcessorHandle) -> kvs_common::Result<()> {
+    let from = db_processor.leader_position(leader.to_string()).await;
+    let mut conn = Connection::dial(leader).await?;
+    let request = Request::Replicate {
+        from: from.map(to_common),
+    };
+    conn.write(&RequestFrame { id: 1, request }).await?;
+
+    // The leader sends a heartbeat while there is nothing to replicate, so a silent one has gone
+    while let Ok(frame) = timeout(3 * HEARTBEAT, conn.read::<ResponseFrame>()).await {
+        let response = match frame? {
+            Some(frame) => frame.response,
+            None => return Ok(()),
+        };
+
+        match response {
+            Response::Log {
+                reset,
+                records,
+                next,
+            } => {
+                let chunk = LogChunk {
+                    reset,
+                    records,
+                    next: to_kvs(next),
+                };
+                db_processor
+                    .apply_log(leader.to_string(), chunk)
+                    .await
+                    .map_err(|e| Error::Message(e.to_string()))?;
+
+                let request = Request::Ack { position: next };
+                conn.write(&RequestFrame { id: 2, request }).await?;
+            }
+            Response::Error { code, message } => {
+                return Err(Error::Message(format!("{:?}: {}", code, message)))
+            }
+            _ => {}
+        }
+    }
+
+    Err(Error::Message("the leader stopped responding".to_string()))
+}
+
+/// Reads how far the store has applied the log of `leader`, or `None` if it hasn't applied any of
+/// it, or has been following another leader
+pub fn read_position(dir: &Path, leader: &str) -> Option<kvs::LogPosition> {
+    let contents = fs::read_to_string(dir.join(POSITION_FILE)).ok()?;
+    let mut fields = contents.split_whitespace();
+    if fields.next()? != leader {
+        return None;
+    }
+
+    Some(kvs::LogPosition {
+        segment: fields.next()?.parse().ok()?,
+        offset: fields.next()?.parse().ok()?,
+    })
+}
+
+/// Records that the store has applied the log of `leader` up to `position`
+pub fn write_position(dir: &Path, leader: &str, position: kvs::LogPosition) -> io::Result<()> {
+    let path = dir.join(POSITION_FILE);
+    let tmp = path.with_extension("pos.tmp");
+    let contents = format!("{} {} {}\n", leader, position.segment, position.offset);
+    fs::write(&tmp, contents)?;
+    fs::rename(tmp, path)
+}
+
+/// Forgets how far the store has applied the log of its leader
+pub fn forget_position(dir: &Path) -> io::Result<()> {
+    match fs::remove_file(dir.join(POSITION_FILE)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn to_kvs(position: LogPosition) -> kvs::LogPosition {
+    kvs::LogPosition {
+        segment: position.segment,
+        offset: position.offset,
+    }
+}
+
+fn to_common(position: kvs::LogPosition) -> LogPosition {
+    LogPosition {
+        segment: position.segment,
+        offset: position.offset,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn positions_are_kept_per_leader() {
+        let dir = TempDir::new().unwrap();
+        let position = kvs::LogPosition {
+            segment: 3,
+            offset: 1234,
+        };
+        assert_eq!(read_position(dir.path(), "a:1"), None);
+
+        write_position(dir.path(), "a:1", position).unwrap();
+        assert_eq!(read_position(dir.path(), "a:1"), Some(position));
+        assert_eq!(read_position(dir.path(), "b:1"), None);
+
+        forget_position(dir.path()).unwrap();
+        forget_position(dir.path()).unwrap();
+        assert_eq!(read_position(dir.path(), "a:1"), None);
+    }
+}
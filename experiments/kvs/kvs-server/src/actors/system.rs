@@ -1,22 +1,63 @@
 use crate::actors::db_processor::DbProcessorHandle;
+use crate::actors::lookup::LookupHandle;
 use crate::actors::reader::ReaderHandle;
-use crate::actors::writer::WriterHandle;
+use crate::actors::replication::ReplicationHandle;
+use crate::metrics::Metrics;
+use crate::Config;
 use kvs_common::connection::Connection;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::time::{interval, MissedTickBehavior};
 
 #[derive(Clone)]
 pub struct System {
     reader: ReaderHandle,
+    metrics: Arc<Metrics>,
 }
 
 impl System {
-    pub fn new() -> crate::Result<Self> {
-        let writer = WriterHandle::new();
-        let db_processor = DbProcessorHandle::new(writer)?;
-        let reader = ReaderHandle::new(db_processor);
-        Ok(Self { reader })
+    pub fn new(kv_store: kvs::KVStore, config: &Config) -> Self {
+        let lookups =
+            (config.readers > 0).then(|| LookupHandle::new(kv_store.reader(), config.readers));
+        let reader = kv_store.reader();
+        let metrics = Arc::new(Metrics::new(kv_store.reader()));
+        let (changes, changes_rx) = watch::channel(0);
+        let db_processor = DbProcessorHandle::new(kv_store, changes);
+        if let Some(period) = config.compact_interval {
+            let mut db_processor = db_processor.clone();
+            tokio::spawn(async move {
+                let mut ticks = interval(period);
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                // The first tick is immediate, and there's nothing to gain from compacting on start
+                ticks.tick().await;
+                loop {
+                    ticks.tick().await;
+                    db_processor.compact().await;
+                }
+            });
+        }
+        let replication = ReplicationHandle::new(
+            reader,
+            db_processor.clone(),
+            changes_rx,
+            config.follow.clone(),
+        );
+        let reader = ReaderHandle::new(
+            db_processor,
+            lookups,
+            replication,
+            metrics.clone(),
+            config.idle_timeout,
+        );
+        Self { reader, metrics }
     }
 
-    pub async fn handle_connection(&mut self, conn: Connection) {
-        self.reader.send(conn).await;
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    pub async fn handle_connection(&mut self, conn: Connection, peer: SocketAddr) {
+        self.reader.send(conn, peer).await;
     }
 }
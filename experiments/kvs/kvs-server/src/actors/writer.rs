@@ -1,51 +1,119 @@
-use kvs_common::connection::Connection;
-use kvs_common::requests::Response;
+use crate::metrics::{Metrics, Operation};
+use kvs_common::connection::ConnectionWriter;
+use kvs_common::requests::{Response, ResponseFrame};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
 use tokio::spawn;
+use tracing::{debug, Span};
 
+/// Writes the responses for a single connection.
+///
+/// The connection is closed once every handle has been dropped and the responses sent so far have
+/// been written.
 #[derive(Debug, Clone)]
 pub struct WriterHandle {
     chan: tokio::sync::mpsc::Sender<WriterMessage>,
 }
 
 struct WriterActor {
+    conn: ConnectionWriter,
     chan: tokio::sync::mpsc::Receiver<WriterMessage>,
+    metrics: Arc<Metrics>,
+    /// The requests which haven't been answered yet, by id
+    pending: HashMap<u64, Pending>,
 }
 
 #[derive(Debug)]
-struct WriterMessage {
-    conn: Connection,
-    response: Response,
+enum WriterMessage {
+    Started { id: u64, pending: Pending },
+    Responses { id: u64, responses: Vec<Response> },
+}
+
+/// A request which is timed until its first response is written
+#[derive(Debug)]
+struct Pending {
+    op: Operation,
+    started: Instant,
+    span: Span,
 }
 
 impl WriterHandle {
-    pub fn new() -> Self {
+    pub fn new(conn: ConnectionWriter, metrics: Arc<Metrics>) -> Self {
         let (tx, rx) = tokio::sync::mpsc::channel(100);
 
-        let actor = WriterActor { chan: rx };
+        let actor = WriterActor {
+            conn,
+            chan: rx,
+            metrics,
+            pending: HashMap::new(),
+        };
 
         spawn(async move { actor.run().await });
 
         Self { chan: tx }
     }
 
-    pub async fn send(&mut self, conn: Connection, response: Response) {
-        self.chan
-            .send(WriterMessage { conn, response })
-            .await
-            .unwrap();
+    /// Starts timing the request `id`, which was read at `started`, until its first response is
+    /// sent. The time and the outcome are recorded under `op` and logged in `span`.
+    pub async fn started(&mut self, id: u64, op: Operation, started: Instant, span: Span) {
+        let pending = Pending { op, started, span };
+        let _ = self.chan.send(WriterMessage::Started { id, pending }).await;
+    }
+
+    /// Writes the responses to the request `id`, in order
+    pub async fn send(&mut self, id: u64, responses: Vec<Response>) {
+        // The writer stops when the connection fails, and then there is nobody to answer
+        let _ = self
+            .chan
+            .send(WriterMessage::Responses { id, responses })
+            .await;
+    }
+
+    /// Whether the connection has failed, so that nothing more can be sent
+    pub fn is_closed(&self) -> bool {
+        self.chan.is_closed()
+    }
+
+    /// Like [`WriterHandle::send`], for threads outside of the runtime
+    pub fn blocking_send(&mut self, id: u64, responses: Vec<Response>) {
+        let _ = self
+            .chan
+            .blocking_send(WriterMessage::Responses { id, responses });
     }
 }
 
 impl WriterActor {
-    async fn process(&mut self, mut msg: WriterMessage) {
-        spawn(async move {
-            let _ = msg.conn.write::<Response>(&msg.response).await;
-        });
+    async fn process(&mut self, msg: WriterMessage) -> kvs_common::Result<()> {
+        let (id, responses) = match msg {
+            WriterMessage::Started { id, pending } => {
+                self.pending.insert(id, pending);
+                return Ok(());
+            }
+            WriterMessage::Responses { id, responses } => (id, responses),
+        };
+
+        if let Some(pending) = self.pending.remove(&id) {
+            let latency = pending.started.elapsed();
+            let failed = responses
+                .iter()
+                .any(|response| matches!(response, Response::Error { .. }));
+            self.metrics.responded(pending.op, latency, failed);
+            debug!(parent: &pending.span, ?latency, failed, "Responded");
+        }
+
+        for response in responses {
+            let frame = ResponseFrame { id, response };
+            self.conn.write::<ResponseFrame>(&frame).await?;
+        }
+        Ok(())
     }
 
     async fn run(mut self) {
         while let Some(msg) = self.chan.recv().await {
-            self.process(msg).await;
+            if self.process(msg).await.is_err() {
+                break;
+            }
         }
     }
 }
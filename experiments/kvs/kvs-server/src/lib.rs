@@ -0,0 +1,606 @@
+//! A server for the key-value store, which speaks the protocol in [`kvs_common`]
+
+use std::thread;
+use std::time::Duration;
+
+mod accept;
+mod actors;
+mod metrics;
+
+pub use accept::accept_connections;
+
+pub type Result<T> = kvs_common::Result<T>;
+
+/// How long a connection may go without sending a request before it is closed, unless configured
+/// otherwise
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How often the store is compacted, unless configured otherwise
+const DEFAULT_COMPACT_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Options which configure how the server treats its connections
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// How long a connection may go without sending a request before it is closed
+    pub idle_timeout: Duration,
+    /// How many threads serve gets and scans alongside the writer. With none, the writer serves
+    /// them in turn with the writes.
+    pub readers: usize,
+    /// How often the store is compacted, to reclaim the space held by overwritten, deleted and
+    /// expired keys. With `None`, it never is.
+    pub compact_interval: Option<Duration>,
+    /// The address of the leader to follow, or `None` to take writes
+    pub follow: Option<String>,
+    /// The address to serve the metrics on over HTTP, at `/metrics`, or `None` to only serve them
+    /// as `Stats`
+    pub metrics_addr: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            readers: thread::available_parallelism().map_or(4, |n| n.get()),
+            compact_interval: Some(DEFAULT_COMPACT_INTERVAL),
+            follow: None,
+            metrics_addr: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvs_common::connection::Connection;
+    use kvs_common::requests::{
+        ErrorCode, Op, Request, RequestFrame, Response, ResponseFrame, Role,
+    };
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use tempfile::TempDir;
+    use tokio::net::TcpListener;
+
+    /// Starts a server for a new store, returning its address and the store's directory
+    async fn start(config: Config) -> (SocketAddr, TempDir) {
+        let dir = TempDir::new().unwrap();
+        let kv_store = kvs::KVStore::open(dir.path()).unwrap();
+        (serve(kv_store, config).await, dir)
+    }
+
+    async fn serve(kv_store: kvs::KVStore, config: Config) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(accept_connections(listener, kv_store, config));
+        addr
+    }
+
+    async fn call(conn: &mut Connection, id: u64, request: Request) -> Response {
+        conn.write(&RequestFrame { id, request }).await.unwrap();
+        let frame = conn.read::<ResponseFrame>().await.unwrap().unwrap();
+        assert_eq!(frame.id, id);
+        frame.response
+    }
+
+    fn put(key: &[u8], value: &[u8]) -> Request {
+        Request::Put {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            ttl: None,
+        }
+    }
+
+    /// Waits for the server at `addr` to hold `expected` for `key`, as a follower does eventually
+    async fn wait_for(addr: SocketAddr, key: &[u8], expected: Option<&[u8]>) {
+        let mut conn = Connection::dial(addr).await.unwrap();
+        for id in 0..100 {
+            let value = match call(&mut conn, id, Request::Get { key: key.to_vec() }).await {
+                Response::OkWithValue { value, .. } => Some(value),
+                Response::KeyNotFound => None,
+                response => panic!("unexpected response {:?}", response),
+            };
+            if value.as_deref() == expected {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("{} never held {:?} for {:?}", addr, expected, key);
+    }
+
+    fn follower_of(leader: SocketAddr) -> Config {
+        Config {
+            follow: Some(leader.to_string()),
+            ..Config::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn pipelined_requests_are_answered_by_id() {
+        let (addr, _dir) = start(Config::default()).await;
+        let mut conn = Connection::dial(addr).await.unwrap();
+
+        let put = Request::Put {
+            key: b"a".to_vec(),
+            value: b"1".to_vec(),
+            ttl: None,
+        };
+        assert_eq!(call(&mut conn, 1, put).await, Response::Ok);
+
+        // the gets may be answered in any order
+        let requests = [
+            Request::Delete { key: b"c".to_vec() },
+            Request::Get { key: b"a".to_vec() },
+            Request::Get { key: b"b".to_vec() },
+        ];
+        for (id, request) in requests.into_iter().enumerate() {
+            let frame = RequestFrame {
+                id: id as u64 + 10,
+                request,
+            };
+            conn.write(&frame).await.unwrap();
+        }
+
+        let mut responses = HashMap::new();
+        for _ in 0..3 {
+            let frame = conn.read::<ResponseFrame>().await.unwrap().unwrap();
+            responses.insert(frame.id, frame.response);
+        }
+        assert_eq!(responses[&10], Response::Ok);
+        assert_eq!(
+            responses[&11],
+            Response::OkWithValue {
+                value: b"1".to_vec(),
+                ttl: None
+            }
+        );
+        assert_eq!(responses[&12], Response::KeyNotFound);
+
+        // the connection keeps serving requests
+        let response = call(&mut conn, 13, Request::Delete { key: b"a".to_vec() }).await;
+        assert_eq!(response, Response::Ok);
+        let response = call(&mut conn, 14, Request::Get { key: b"a".to_vec() }).await;
+        assert_eq!(response, Response::KeyNotFound);
+    }
+
+    #[tokio::test]
+    async fn stats_count_requests() {
+        let (addr, _dir) = start(Config::default()).await;
+        let mut conn = Connection::dial(addr).await.unwrap();
+        let mut other = Connection::dial(addr).await.unwrap();
+
+        assert_eq!(call(&mut conn, 1, put(b"a", b"1")).await, Response::Ok);
+        let response = call(&mut other, 1, Request::Get { key: b"a".to_vec() }).await;
+        assert!(matches!(response, Response::OkWithValue { .. }));
+        assert_eq!(call(&mut conn, 2, put(b"b", b"2")).await, Response::Ok);
+        let update = Request::Update {
+            key: b"c".to_vec(),
+            value: b"3".to_vec(),
+        };
+        assert_eq!(call(&mut conn, 3, update).await, Response::KeyNotFound);
+        let response = call(&mut conn, 4, Request::Restore { src: "".into() }).await;
+        assert!(matches!(response, Response::Error { .. }));
+
+        let text = match call(&mut conn, 5, Request::Stats).await {
+            Response::Stats { text } => text,
+            response => panic!("unexpected response {:?}", response),
+        };
+        let lines: Vec<_> = text.lines().collect();
+        for expected in [
+            "kvs_requests_total{op=\"put\"} 2",
+            "kvs_requests_total{op=\"update\"} 1",
+            "kvs_request_errors_total{op=\"put\"} 0",
+            "kvs_request_errors_total{op=\"restore\"} 1",
+            "kvs_request_duration_seconds_count{op=\"put\"} 2",
+            // the stats request itself is counted, but hasn't been answered yet
+            "kvs_requests_total{op=\"stats\"} 1",
+            "kvs_request_duration_seconds_count{op=\"stats\"} 0",
+            "kvs_open_connections 2",
+            "kvs_keys 2",
+        ] {
+            assert!(lines.contains(&expected), "{} not in\n{}", expected, text);
+        }
+        assert!(!text.contains("op=\"scan\""));
+    }
+
+    #[tokio::test]
+    async fn conditional_writes() {
+        let (addr, _dir) = start(Config::default()).await;
+        let mut conn = Connection::dial(addr).await.unwrap();
+
+        let update = Request::Update {
+            key: b"a".to_vec(),
+            value: b"1".to_vec(),
+        };
+        assert_eq!(call(&mut conn, 1, update).await, Response::KeyNotFound);
+
+        let batch = Request::Batch(vec![
+            Op::Put {
+                key: b"a".to_vec(),
+                value: b"1".to_vec(),
+            },
+            Op::Put {
+                key: b"b".to_vec(),
+                value: b"2".to_vec(),
+            },
+        ]);
+        assert_eq!(call(&mut conn, 2, batch).await, Response::Ok);
+
+        let cas = |expected: &[u8], new: &[u8]| Request::CompareAndSwap {
+            key: b"a".to_vec(),
+            expected: Some(expected.to_vec()),
+            new: Some(new.to_vec()),
+        };
+        assert_eq!(call(&mut conn, 3, cas(b"1", b"3")).await, Response::Swapped);
+        assert_eq!(
+            call(&mut conn, 4, cas(b"1", b"4")).await,
+            Response::Mismatch {
+                current: Some(b"3".to_vec())
+            }
+        );
+        let response = call(&mut conn, 5, Request::Get { key: b"b".to_vec() }).await;
+        assert_eq!(
+            response,
+            Response::OkWithValue {
+                value: b"2".to_vec(),
+                ttl: None
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn store_errors_are_reported() {
+        let (addr, dir) = start(Config::default()).await;
+        let mut conn = Connection::dial(addr).await.unwrap();
+
+        let put = Request::Put {
+            key: b"key".to_vec(),
+            value: b"value".to_vec(),
+            ttl: None,
+        };
+        assert_eq!(call(&mut conn, 1, put).await, Response::Ok);
+
+        // flip the last byte of the value, under the store's feet
+        let segment = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|e| e == "log"))
+            .unwrap();
+        let mut contents = std::fs::read(&segment).unwrap();
+        *contents.last_mut().unwrap() ^= 1;
+        std::fs::write(&segment, contents).unwrap();
+
+        let response = call(
+            &mut conn,
+            2,
+            Request::Get {
+                key: b"key".to_vec(),
+            },
+        )
+        .await;
+        assert!(matches!(
+            response,
+            Response::Error {
+                code: ErrorCode::Corruption,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn malformed_requests_close_the_connection() {
+        let (addr, _dir) = start(Config::default()).await;
+        let mut conn = Connection::dial(addr).await.unwrap();
+
+        // a well-framed message which isn't a request
+        conn.write(&u32::MAX).await.unwrap();
+
+        let frame = conn.read::<ResponseFrame>().await.unwrap().unwrap();
+        assert!(matches!(
+            frame.response,
+            Response::Error {
+                code: ErrorCode::BadRequest,
+                ..
+            }
+        ));
+        assert_eq!(conn.read::<ResponseFrame>().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn idle_connections_are_closed() {
+        let config = Config {
+            idle_timeout: Duration::from_millis(50),
+            ..Config::default()
+        };
+        let (addr, _dir) = start(config).await;
+        let mut conn = Connection::dial(addr).await.unwrap();
+
+        let response = call(&mut conn, 1, Request::Get { key: b"a".to_vec() }).await;
+        assert_eq!(response, Response::KeyNotFound);
+
+        let closed = tokio::time::timeout(Duration::from_secs(5), conn.read::<ResponseFrame>());
+        assert_eq!(closed.await.unwrap().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn lookups_are_served_without_lookup_threads() {
+        let config = Config {
+            readers: 0,
+            ..Config::default()
+        };
+        let (addr, _dir) = start(config).await;
+        let mut conn = Connection::dial(addr).await.unwrap();
+
+        let put = Request::Put {
+            key: b"a".to_vec(),
+            value: b"1".to_vec(),
+            ttl: None,
+        };
+        assert_eq!(call(&mut conn, 1, put).await, Response::Ok);
+        let response = call(&mut conn, 2, Request::Get { key: b"a".to_vec() }).await;
+        assert_eq!(
+            response,
+            Response::OkWithValue {
+                value: b"1".to_vec(),
+                ttl: None
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn keys_expire() {
+        let config = Config {
+            compact_interval: Some(Duration::from_millis(10)),
+            ..Config::default()
+        };
+        let (addr, _dir) = start(config).await;
+        let mut conn = Connection::dial(addr).await.unwrap();
+
+        let hour = Duration::from_secs(3600);
+        for (id, (key, ttl)) in [(b"a", Duration::ZERO), (b"b", hour)]
+            .into_iter()
+            .enumerate()
+        {
+            let put = Request::Put {
+                key: key.to_vec(),
+                value: b"1".to_vec(),
+                ttl: Some(ttl),
+            };
+            assert_eq!(call(&mut conn, id as u64, put).await, Response::Ok);
+        }
+
+        // let a few compactions go by, which must keep the live key
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let response = call(&mut conn, 2, Request::Get { key: b"a".to_vec() }).await;
+        assert_eq!(response, Response::KeyNotFound);
+        let response = call(&mut conn, 3, Request::Get { key: b"b".to_vec() }).await;
+        match response {
+            Response::OkWithValue {
+                value,
+                ttl: Some(ttl),
+            } => {
+                assert_eq!(value, b"1");
+                assert!(ttl <= hour && ttl > hour - Duration::from_secs(60));
+            }
+            _ => panic!("unexpected response {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn backup_and_restore() {
+        let (addr, _dir) = start(Config::default()).await;
+        let backup = TempDir::new().unwrap();
+        let mut conn = Connection::dial(addr).await.unwrap();
+
+        let put = |key: &[u8]| Request::Put {
+            key: key.to_vec(),
+            value: b"1".to_vec(),
+            ttl: None,
+        };
+        assert_eq!(call(&mut conn, 1, put(b"a")).await, Response::Ok);
+        let request = Request::Backup {
+            dest: backup.path().to_path_buf(),
+        };
+        assert_eq!(call(&mut conn, 2, request).await, Response::Ok);
+        assert_eq!(call(&mut conn, 3, put(b"b")).await, Response::Ok);
+
+        match call(&mut conn, 4, Request::Verify).await {
+            Response::Verified {
+                records,
+                corruptions,
+                ..
+            } => {
+                assert_eq!(records, 2);
+                assert!(corruptions.is_empty());
+            }
+            response => panic!("unexpected response {:?}", response),
+        }
+
+        let request = Request::Restore {
+            src: backup.path().to_path_buf(),
+        };
+        assert_eq!(call(&mut conn, 5, request).await, Response::Ok);
+        let response = call(&mut conn, 6, Request::Get { key: b"b".to_vec() }).await;
+        assert_eq!(response, Response::KeyNotFound);
+        let response = call(&mut conn, 7, Request::Get { key: b"a".to_vec() }).await;
+        assert!(matches!(response, Response::OkWithValue { .. }));
+    }
+
+    #[tokio::test]
+    async fn followers_replicate_the_leader() {
+        let (leader, _leader_dir) = start(Config::default()).await;
+        let (follower, _follower_dir) = start(follower_of(leader)).await;
+        let mut conn = Connection::dial(leader).await.unwrap();
+
+        assert_eq!(call(&mut conn, 1, put(b"a", b"1")).await, Response::Ok);
+        let batch = Request::Batch(vec![
+            Op::Put {
+                key: b"b".to_vec(),
+                value: b"2".to_vec(),
+            },
+            Op::Delete { key: b"a".to_vec() },
+        ]);
+        assert_eq!(call(&mut conn, 2, batch).await, Response::Ok);
+        let expiring = Request::Put {
+            key: b"c".to_vec(),
+            value: b"3".to_vec(),
+            ttl: Some(Duration::from_secs(3600)),
+        };
+        assert_eq!(call(&mut conn, 3, expiring).await, Response::Ok);
+
+        wait_for(follower, b"c", Some(b"3")).await;
+        let mut follower_conn = Connection::dial(follower).await.unwrap();
+        let response = call(&mut follower_conn, 1, Request::Get { key: b"a".to_vec() }).await;
+        assert_eq!(response, Response::KeyNotFound);
+        let response = call(&mut follower_conn, 2, Request::Get { key: b"c".to_vec() }).await;
+        assert!(matches!(
+            response,
+            Response::OkWithValue { ttl: Some(_), .. }
+        ));
+
+        // followers don't take writes
+        let response = call(&mut follower_conn, 3, put(b"d", b"4")).await;
+        assert!(matches!(
+            response,
+            Response::Error {
+                code: ErrorCode::NotLeader,
+                ..
+            }
+        ));
+
+        // the follower acks what it has applied, so the leader sees it catch up
+        for id in 10.. {
+            if let Response::Status { role, followers } = call(&mut conn, id, Request::Status).await
+            {
+                assert_eq!(role, Role::Leader);
+                if followers.len() == 1 && followers[0].lag == Some(0) {
+                    break;
+                }
+            }
+            assert!(id < 100, "the follower never caught up");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn manual_failover() {
+        let (old_leader, _old_dir) = start(Config::default()).await;
+        let (new_leader, _new_dir) = start(follower_of(old_leader)).await;
+        let mut old = Connection::dial(old_leader).await.unwrap();
+        let mut new = Connection::dial(new_leader).await.unwrap();
+
+        assert_eq!(call(&mut old, 1, put(b"a", b"1")).await, Response::Ok);
+        wait_for(new_leader, b"a", Some(b"1")).await;
+
+        // the follower takes over, and the old leader takes a write which it never sees
+        assert_eq!(call(&mut new, 1, Request::Promote).await, Response::Ok);
+        assert_eq!(call(&mut new, 2, put(b"b", b"2")).await, Response::Ok);
+        assert_eq!(call(&mut old, 2, put(b"lost", b"3")).await, Response::Ok);
+
+        // the old leader starts over from the new leader's log
+        let follow = Request::Follow {
+            leader: new_leader.to_string(),
+        };
+        assert_eq!(call(&mut old, 3, follow).await, Response::Ok);
+        wait_for(old_leader, b"b", Some(b"2")).await;
+        wait_for(old_leader, b"lost", None).await;
+        wait_for(old_leader, b"a", Some(b"1")).await;
+
+        let response = call(&mut old, 4, put(b"c", b"4")).await;
+        assert!(matches!(
+            response,
+            Response::Error {
+                code: ErrorCode::NotLeader,
+                ..
+            }
+        ));
+        let response = call(&mut old, 5, Request::Status).await;
+        assert!(matches!(
+            response,
+            Response::Status {
+                role: Role::Follower { .. },
+                ..
+            }
+        ));
+    }
+
+    /// Gets are answered while the server is busy with large writes, each synced to disk, so
+    /// lookup threads should answer many more of them than the actor which does the writes.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn lookup_threads_answer_gets_during_writes() {
+        let without = gets_per_second(Config {
+            readers: 0,
+            ..Config::default()
+        })
+        .await;
+        let with = gets_per_second(Config {
+            readers: 4,
+            ..Config::default()
+        })
+        .await;
+        assert!(
+            with > 2.0 * without,
+            "{:.0} gets/s with 4 lookup threads, {:.0} without",
+            with,
+            without
+        );
+    }
+
+    async fn gets_per_second(config: Config) -> f64 {
+        const KEYS: u32 = 1000;
+        const CLIENTS: usize = 8;
+        const DURATION: Duration = Duration::from_secs(2);
+        const VALUE_LEN: usize = 1 << 20;
+
+        let dir = TempDir::new().unwrap();
+        let mut kv_store = kvs::KVStore::builder()
+            .durability(kvs::Durability::Always)
+            .open(dir.path())
+            .unwrap();
+        for i in 0..KEYS {
+            kv_store.insert(&i.to_le_bytes(), &[0; 100]).unwrap();
+        }
+        let addr = serve(kv_store, config).await;
+        let deadline = tokio::time::Instant::now() + DURATION;
+
+        let writer = tokio::spawn(async move {
+            let mut conn = Connection::dial(addr).await.unwrap();
+            for id in 0.. {
+                if tokio::time::Instant::now() > deadline {
+                    break;
+                }
+                let put = Request::Put {
+                    key: (id as u32 % KEYS).to_le_bytes().to_vec(),
+                    value: vec![1; VALUE_LEN],
+                    ttl: None,
+                };
+                assert_eq!(call(&mut conn, id, put).await, Response::Ok);
+            }
+        });
+
+        let readers: Vec<_> = (0..CLIENTS)
+            .map(|_| {
+                tokio::spawn(async move {
+                    let mut conn = Connection::dial(addr).await.unwrap();
+                    let mut gets = 0;
+                    while tokio::time::Instant::now() < deadline {
+                        let get = Request::Get {
+                            key: (gets % KEYS).to_le_bytes().to_vec(),
+                        };
+                        let response = call(&mut conn, gets as u64, get).await;
+                        assert!(matches!(response, Response::OkWithValue { .. }));
+                        gets += 1;
+                    }
+                    gets
+                })
+            })
+            .collect();
+
+        let mut gets = 0;
+        for reader in readers {
+            gets += reader.await.unwrap();
+        }
+        writer.await.unwrap();
+
+        gets as f64 / DURATION.as_secs_f64()
+    }
+}
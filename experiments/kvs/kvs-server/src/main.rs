@@ -1,19 +1,25 @@
+use clap::Parser;
 use kvs_common::{Error, DEFAULT_ADDRESS};
+use kvs_server::{Config, Result};
+use std::path::PathBuf;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::select;
 use tracing::info;
 
-mod accept;
-mod actors;
-
 #[tokio::main]
 async fn main() -> Result<()> {
     setup_logging()?;
-    let listener = TcpListener::bind(DEFAULT_ADDRESS).await?;
+    let cli = Cli::parse();
+
+    let kv_store = kvs::KVStore::open(&cli.data_dir).map_err(|e| Error::Message(e.to_string()))?;
+    info!("Opened store in {}", cli.data_dir.display());
+
+    let listener = TcpListener::bind(&cli.addr).await?;
     info!("Listening on {}", listener.local_addr()?);
 
     select! {
-        _res = accept::accept_connections(listener) => {
+        _res = kvs_server::accept_connections(listener, kv_store, cli.config()) => {
             info!("Accept loop exited");
         },
         _ = tokio::signal::ctrl_c() => {
@@ -24,8 +30,48 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// A server for the key-value store
+#[derive(Debug, Parser)]
+#[command(name = "kvs-server")]
+#[command(about = "A server for the key-value store", long_about = None)]
+struct Cli {
+    /// The directory which holds the store's data
+    #[arg(long, default_value = ".")]
+    data_dir: PathBuf,
+    /// The address to listen on
+    #[arg(long, default_value = DEFAULT_ADDRESS)]
+    addr: String,
+    /// How many seconds a connection may go without sending a request before it is closed
+    #[arg(long, default_value_t = Config::default().idle_timeout.as_secs())]
+    idle_timeout: u64,
+    /// How many threads serve gets and scans alongside the writer
+    #[arg(long, default_value_t = Config::default().readers)]
+    readers: usize,
+    /// How many seconds to wait between compactions of the store, or 0 to never compact it
+    #[arg(long, default_value_t = Config::default().compact_interval.map_or(0, |d| d.as_secs()))]
+    compact_interval: u64,
+    /// Follows the leader at this address, copying its writes and serving reads, instead of taking
+    /// writes itself
+    #[arg(long)]
+    follow: Option<String>,
+    /// Serves the metrics in the Prometheus text format at `http://<addr>/metrics`
+    #[arg(long)]
+    metrics_addr: Option<String>,
+}
+
+impl Cli {
+    fn config(&self) -> Config {
+        Config {
+            idle_timeout: Duration::from_secs(self.idle_timeout),
+            readers: self.readers,
+            compact_interval: (self.compact_interval > 0)
+                .then(|| Duration::from_secs(self.compact_interval)),
+            follow: self.follow.clone(),
+            metrics_addr: self.metrics_addr.clone(),
+        }
+    }
+}
+
 fn setup_logging() -> Result<()> {
     tracing_subscriber::fmt::try_init().map_err(|_| Error::TracingInitializationError)
 }
-
-pub type Result<T> = kvs_common::Result<T>;
@@ -0,0 +1,445 @@
+//! What the server has been doing, in numbers which can be scraped by Prometheus
+
+use kvs::KVReader;
+use kvs_common::requests::{Op, Request};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+/// The upper bounds of the latency histogram's buckets, in microseconds
+const BUCKETS: [u64; 16] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000, 2_500_000, 5_000_000, 10_000_000,
+];
+
+/// The kinds of request the server counts separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Get,
+    Put,
+    Update,
+    Delete,
+    Scan,
+    Batch,
+    CompareAndSwap,
+    Backup,
+    Restore,
+    Verify,
+    Replicate,
+    Ack,
+    Status,
+    Promote,
+    Follow,
+    Stats,
+}
+
+const OPERATIONS: [Operation; 16] = [
+    Operation::Get,
+    Operation::Put,
+    Operation::Update,
+    Operation::Delete,
+    Operation::Scan,
+    Operation::Batch,
+    Operation::CompareAndSwap,
+    Operation::Backup,
+    Operation::Restore,
+    Operation::Verify,
+    Operation::Replicate,
+    Operation::Ack,
+    Operation::Status,
+    Operation::Promote,
+    Operation::Follow,
+    Operation::Stats,
+];
+
+impl Operation {
+    pub fn of(request: &Request) -> Self {
+        match request {
+            Request::Get { .. } => Operation::Get,
+            Request::Put { .. } => Operation::Put,
+            Request::Update { .. } => Operation::Update,
+            Request::Delete { .. } => Operation::Delete,
+            Request::Scan { .. } => Operation::Scan,
+            Request::Batch(_) => Operation::Batch,
+            Request::CompareAndSwap { .. } => Operation::CompareAndSwap,
+            Request::Backup { .. } => Operation::Backup,
+            Request::Restore { .. } => Operation::Restore,
+            Request::Verify => Operation::Verify,
+            Request::Replicate { .. } => Operation::Replicate,
+            Request::Ack { .. } => Operation::Ack,
+            Request::Status => Operation::Status,
+            Request::Promote => Operation::Promote,
+            Request::Follow { .. } => Operation::Follow,
+            Request::Stats => Operation::Stats,
+        }
+    }
+
+    /// The name of the operation, as it appears in the `op` label
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::Get => "get",
+            Operation::Put => "put",
+            Operation::Update => "update",
+            Operation::Delete => "delete",
+            Operation::Scan => "scan",
+            Operation::Batch => "batch",
+            Operation::CompareAndSwap => "cas",
+            Operation::Backup => "backup",
+            Operation::Restore => "restore",
+            Operation::Verify => "verify",
+            Operation::Replicate => "replicate",
+            Operation::Ack => "ack",
+            Operation::Status => "status",
+            Operation::Promote => "promote",
+            Operation::Follow => "follow",
+            Operation::Stats => "stats",
+        }
+    }
+}
+
+/// The total length of the keys a request names, or `None` if it doesn't name any
+pub fn key_size(request: &Request) -> Option<usize> {
+    match request {
+        Request::Get { key }
+        | Request::Put { key, .. }
+        | Request::Update { key, .. }
+        | Request::Delete { key }
+        | Request::CompareAndSwap { key, .. } => Some(key.len()),
+        Request::Batch(ops) => Some(
+            ops.iter()
+                .map(|op| match op {
+                    Op::Put { key, .. } | Op::Delete { key } => key.len(),
+                })
+                .sum(),
+        ),
+        _ => None,
+    }
+}
+
+/// How many requests of one kind there have been, and how long they took
+#[derive(Debug, Default)]
+struct OperationStats {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    /// The number of requests which took at most each of [`BUCKETS`], and no less than the one
+    /// before it, with those which took longer than all of them at the end
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    latency_micros: AtomicU64,
+}
+
+/// The server's metrics, shared by every connection.
+///
+/// Requests are counted as they are read, and their latency is the time until their first response
+/// is written, so it includes the time they spent waiting in line.
+#[derive(Debug)]
+pub struct Metrics {
+    operations: [OperationStats; OPERATIONS.len()],
+    connections: AtomicU64,
+    reader: KVReader,
+}
+
+impl Metrics {
+    /// Creates the metrics for the store which `reader` reads from
+    pub fn new(reader: KVReader) -> Self {
+        Self {
+            operations: Default::default(),
+            connections: AtomicU64::new(0),
+            reader,
+        }
+    }
+
+    fn operation(&self, op: Operation) -> &OperationStats {
+        &self.operations[op as usize]
+    }
+
+    /// Counts a request which has been read
+    pub fn received(&self, op: Operation) {
+        self.operation(op).requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the time it took to answer a request, and whether it failed
+    pub fn responded(&self, op: Operation, latency: Duration, failed: bool) {
+        let stats = self.operation(op);
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        let bucket = BUCKETS.partition_point(|&bound| bound < micros);
+        stats.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        stats.latency_micros.fetch_add(micros, Ordering::Relaxed);
+        if failed {
+            stats.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a connection as open until the returned guard is dropped
+    pub fn connection(self: &Arc<Self>) -> ConnectionGuard {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(self.clone())
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    ///
+    /// Only the operations which have been requested are listed. This reads the size of the log
+    /// from disk, so it blocks.
+    pub fn render(&self) -> kvs::Result<String> {
+        let mut out = String::new();
+        let seen: Vec<_> = OPERATIONS
+            .iter()
+            .map(|&op| (op, self.operation(op)))
+            .filter(|(_, stats)| stats.requests.load(Ordering::Relaxed) > 0)
+            .collect();
+
+        header(&mut out, "kvs_requests_total", "counter", "Requests read");
+        for (op, stats) in &seen {
+            let requests = stats.requests.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "kvs_requests_total{{op=\"{}\"}} {}",
+                op.name(),
+                requests
+            );
+        }
+
+        header(
+            &mut out,
+            "kvs_request_errors_total",
+            "counter",
+            "Requests answered with an error",
+        );
+        for (op, stats) in &seen {
+            let errors = stats.errors.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "kvs_request_errors_total{{op=\"{}\"}} {}",
+                op.name(),
+                errors
+            );
+        }
+
+        header(
+            &mut out,
+            "kvs_request_duration_seconds",
+            "histogram",
+            "Time from reading a request to writing its first response",
+        );
+        for (op, stats) in &seen {
+            let name = op.name();
+            let mut count = 0;
+            for (i, bucket) in stats.buckets.iter().enumerate() {
+                count += bucket.load(Ordering::Relaxed);
+                let le = match BUCKETS.get(i) {
+                    Some(&micros) => seconds(micros),
+                    None => "+Inf".to_string(),
+                };
+                let _ = writeln!(
+                    out,
+                    "kvs_request_duration_seconds_bucket{{op=\"{}\",le=\"{}\"}} {}",
+                    name, le, count
+                );
+            }
+            let sum = seconds(stats.latency_micros.load(Ordering::Relaxed));
+            let _ = writeln!(
+                out,
+                "kvs_request_duration_seconds_sum{{op=\"{}\"}} {}",
+                name, sum
+            );
+            let _ = writeln!(
+                out,
+                "kvs_request_duration_seconds_count{{op=\"{}\"}} {}",
+                name, count
+            );
+        }
+
+        let gauges = [
+            (
+                "kvs_open_connections",
+                "Client connections which are open",
+                self.connections.load(Ordering::Relaxed),
+            ),
+            (
+                "kvs_keys",
+                "Keys in the index, including expired ones which compaction hasn't removed yet",
+                self.reader.len() as u64,
+            ),
+            (
+                "kvs_log_size_bytes",
+                "The size of the log on disk",
+                self.reader.log_size()?,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            header(&mut out, name, "gauge", help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        Ok(out)
+    }
+}
+
+/// Keeps a connection counted in [`Metrics`] for as long as it is held
+#[derive(Debug)]
+pub struct ConnectionGuard(Arc<Metrics>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn seconds(micros: u64) -> String {
+    format!("{}", micros as f64 / 1_000_000.0)
+}
+
+/// Serves the metrics over HTTP, as `GET /metrics`, to whoever connects to `listener`
+pub async fn serve_http(listener: TcpListener, metrics: Arc<Metrics>) -> crate::Result<()> {
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, metrics).await {
+                warn!("Failed to serve metrics to {}: {}", addr, e);
+            }
+        });
+    }
+}
+
+/// Answers a single HTTP request, and closes the connection
+async fn respond(mut stream: TcpStream, metrics: Arc<Metrics>) -> crate::Result<()> {
+    // Only the request line matters, and the headers after it are ignored
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request.split_whitespace();
+
+    let (status, body) = match (words.next(), words.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            match tokio::task::spawn_blocking(move || metrics.render()).await {
+                Ok(Ok(text)) => ("200 OK", text),
+                Ok(Err(e)) => ("500 Internal Server Error", format!("{}\n", e)),
+                Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+            }
+        }
+        (Some("GET"), Some(_)) => ("404 Not Found", "Not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "Only GET is supported\n".to_string(),
+        ),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Binds `addr` and serves the metrics on it in the background
+pub async fn spawn_http(addr: &str, metrics: Arc<Metrics>) -> crate::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+    tokio::spawn(serve_http(listener, metrics));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn latencies_fall_into_buckets() {
+        let dir = TempDir::new().unwrap();
+        let mut kv = kvs::KVStore::open(dir.path()).unwrap();
+        kv.insert(b"key", b"value").unwrap();
+        let metrics = Arc::new(Metrics::new(kv.reader()));
+
+        for latency in [50, 100, 101, 20_000_000] {
+            metrics.received(Operation::Get);
+            metrics.responded(Operation::Get, Duration::from_micros(latency), false);
+        }
+        metrics.received(Operation::Put);
+        metrics.responded(Operation::Put, Duration::from_millis(1), true);
+        let connection = metrics.connection();
+
+        let text = metrics.render().unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        for expected in [
+            "kvs_requests_total{op=\"get\"} 4",
+            "kvs_requests_total{op=\"put\"} 1",
+            "kvs_request_errors_total{op=\"get\"} 0",
+            "kvs_request_errors_total{op=\"put\"} 1",
+            "kvs_request_duration_seconds_bucket{op=\"get\",le=\"0.0001\"} 2",
+            "kvs_request_duration_seconds_bucket{op=\"get\",le=\"0.00025\"} 3",
+            "kvs_request_duration_seconds_bucket{op=\"get\",le=\"10\"} 3",
+            "kvs_request_duration_seconds_bucket{op=\"get\",le=\"+Inf\"} 4",
+            "kvs_request_duration_seconds_sum{op=\"get\"} 20.000251",
+            "kvs_request_duration_seconds_count{op=\"get\"} 4",
+            "kvs_request_duration_seconds_bucket{op=\"put\",le=\"0.0005\"} 0",
+            "kvs_request_duration_seconds_bucket{op=\"put\",le=\"0.001\"} 1",
+            "kvs_open_connections 1",
+            "kvs_keys 1",
+        ] {
+            assert!(lines.contains(&expected), "{} not in\n{}", expected, text);
+        }
+        assert!(!text.contains("op=\"scan\""));
+
+        drop(connection);
+        let text = metrics.render().unwrap();
+        assert!(text.lines().any(|line| line == "kvs_open_connections 0"));
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_over_http() {
+        let dir = TempDir::new().unwrap();
+        let kv = kvs::KVStore::open(dir.path()).unwrap();
+        let metrics = Arc::new(Metrics::new(kv.reader()));
+        metrics.received(Operation::Verify);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_http(listener, metrics));
+
+        let get = |path: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = get("/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("\r\n\r\n# HELP kvs_requests_total"));
+        assert!(response.contains("kvs_requests_total{op=\"verify\"} 1\n"));
+        assert!(response.contains("kvs_log_size_bytes "));
+
+        let response = get("/").await;
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{}",
+            response
+        );
+    }
+}
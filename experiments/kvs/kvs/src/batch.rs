@@ -0,0 +1,131 @@
+use std::io;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::record::{Entry, Kind};
+use crate::{ttl, ByteString};
+
+/// A single change in a batch, see [`crate::KVStore::write_batch`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// Sets the key to the value
+    Put { key: ByteString, value: ByteString },
+    /// Deletes the key
+    Delete { key: ByteString },
+}
+
+impl Op {
+    fn kind(&self) -> Kind {
+        match self {
+            Op::Put { .. } => Kind::Put,
+            Op::Delete { .. } => Kind::Delete,
+        }
+    }
+
+    /// The change as it is written to the log, for a key which never expires
+    pub(crate) fn to_entry(&self) -> Entry {
+        let (key, value) = match self {
+            Op::Put { key, value } => (key.clone(), value.clone()),
+            Op::Delete { key } => (key.clone(), ByteString::new()),
+        };
+        Entry {
+            kind: self.kind(),
+            expires_at: ttl::NEVER,
+            key,
+            value,
+        }
+    }
+}
+
+/// The outcome of [`crate::KVStore::compare_and_swap`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Swap {
+    /// The key held the expected value, and now holds the new one
+    Swapped,
+    /// The key held something else, and was left alone
+    Mismatch {
+        /// The value the key held, or `None` if it didn't exist
+        current: Option<ByteString>,
+    },
+}
+
+/// Encodes the operations of a batch, which are written to the log as the value of a single
+/// [`Kind::Batch`] record, so they share its checksum.
+///
+/// Each operation is laid out like a record without a checksum:
+/// `[kind, expires_at, key_len, val_len, key, value]`
+pub(crate) fn encode(ops: &[Entry]) -> ByteString {
+    let mut buf = ByteString::new();
+    for op in ops {
+        buf.push(op.kind as u8);
+        buf.extend_from_slice(&op.expires_at.to_le_bytes());
+        buf.extend_from_slice(&(op.key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&(op.value.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&op.key);
+        buf.extend_from_slice(&op.value);
+    }
+    buf
+}
+
+/// Decodes the operations of a batch, from the value of its record
+pub(crate) fn decode(mut buf: &[u8]) -> io::Result<Vec<Entry>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed batch record");
+
+    let mut ops = Vec::new();
+    while !buf.is_empty() {
+        let kind = match Kind::from_u8(buf.read_u8()?) {
+            Some(kind @ (Kind::Put | Kind::Delete)) => kind,
+            _ => return Err(invalid()),
+        };
+        let expires_at = buf.read_u64::<LittleEndian>()?;
+        let key_len = buf.read_u32::<LittleEndian>()? as usize;
+        let val_len = buf.read_u32::<LittleEndian>()? as usize;
+        if buf.len() < key_len + val_len {
+            return Err(invalid());
+        }
+
+        let (key, rest) = buf.split_at(key_len);
+        let (value, rest) = rest.split_at(val_len);
+        buf = rest;
+
+        ops.push(Entry {
+            kind,
+            expires_at,
+            key: key.to_vec(),
+            value: value.to_vec(),
+        });
+    }
+    Ok(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut ops: Vec<_> = [
+            Op::Put {
+                key: b"a".to_vec(),
+                value: b"1".to_vec(),
+            },
+            Op::Delete { key: b"b".to_vec() },
+        ]
+        .iter()
+        .map(Op::to_entry)
+        .collect();
+        ops[0].expires_at = 42;
+
+        let decoded = decode(&encode(&ops)).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].kind, Kind::Put);
+        assert_eq!(decoded[0].key, b"a");
+        assert_eq!(decoded[0].value, b"1");
+        assert_eq!(decoded[0].expires_at, 42);
+        assert_eq!(decoded[1].kind, Kind::Delete);
+        assert_eq!(decoded[1].key, b"b");
+        assert_eq!(decoded[1].expires_at, ttl::NEVER);
+
+        assert!(decode(&encode(&ops)[..5]).is_err());
+    }
+}
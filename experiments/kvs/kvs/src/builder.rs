@@ -0,0 +1,66 @@
+use std::path::Path;
+
+use crate::{Durability, KVStore, Result};
+
+/// The size at which segments are sealed and a new one is started, unless configured otherwise
+const DEFAULT_MAX_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Options which configure how a [`KVStore`] is opened
+///
+/// # Examples
+///
+/// ```no_run
+/// use kvs::{Durability, KVStore};
+///
+/// let store = KVStore::builder()
+///     .durability(Durability::Always)
+///     .open("/var/lib/kvs")
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct KVStoreBuilder {
+    pub(crate) durability: Durability,
+    pub(crate) create_dir: bool,
+    pub(crate) max_segment_size: u64,
+}
+
+impl KVStoreBuilder {
+    pub fn new() -> Self {
+        Self {
+            durability: Durability::default(),
+            create_dir: true,
+            max_segment_size: DEFAULT_MAX_SEGMENT_SIZE,
+        }
+    }
+
+    /// Sets when writes are synced to disk, see [`Durability`]
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Sets whether the data directory is created if it doesn't exist (the default)
+    pub fn create_dir(mut self, create_dir: bool) -> Self {
+        self.create_dir = create_dir;
+        self
+    }
+
+    /// Sets the size in bytes at which a segment is sealed and a new one is started.
+    ///
+    /// A record which is larger than this is written to a segment of its own.
+    pub fn max_segment_size(mut self, max_segment_size: u64) -> Self {
+        self.max_segment_size = max_segment_size;
+        self
+    }
+
+    /// Opens the store kept in the directory `path`
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<KVStore> {
+        KVStore::open_with(path.as_ref(), self)
+    }
+}
+
+impl Default for KVStoreBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
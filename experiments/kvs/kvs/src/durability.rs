@@ -0,0 +1,208 @@
+use std::fs::File;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Controls when writes to the log are flushed to stable storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    /// `fsync` the log after every write
+    Always,
+    /// `fsync` the log in the background at most once per interval,
+    /// so a crash loses at most one interval's worth of writes
+    GroupCommit(Duration),
+    /// Never `fsync` the log, leaving it up to the operating system
+    Never,
+}
+
+impl Default for Durability {
+    fn default() -> Self {
+        Durability::GroupCommit(Duration::from_millis(100))
+    }
+}
+
+/// An error from a background sync, which is kept until it can be returned to the store's caller
+type SyncError = Arc<Mutex<Option<io::Error>>>;
+
+/// Syncs the log according to a [`Durability`] policy
+#[derive(Debug)]
+pub(crate) struct Syncer {
+    durability: Durability,
+    group_commit: Option<GroupCommit>,
+    error: SyncError,
+}
+
+impl Syncer {
+    pub(crate) fn new(durability: Durability, log: &File) -> io::Result<Self> {
+        let error = SyncError::default();
+        let group_commit = match durability {
+            Durability::GroupCommit(interval) => Some(GroupCommit::spawn(
+                sync_data(log.try_clone()?),
+                interval,
+                error.clone(),
+            )),
+            Durability::Always | Durability::Never => None,
+        };
+
+        Ok(Self {
+            durability,
+            group_commit,
+            error,
+        })
+    }
+
+    /// Called when a new segment becomes the one being written to
+    pub(crate) fn rolled(&mut self, log: &File) -> io::Result<()> {
+        if let Durability::GroupCommit(interval) = self.durability {
+            // Replacing the thread syncs the previous segment one final time. If that fails, the
+            // error is returned by the next write.
+            self.group_commit = Some(GroupCommit::spawn(
+                sync_data(log.try_clone()?),
+                interval,
+                self.error.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Called after every write to the log.
+    ///
+    /// Returns the error from a background sync which failed since the last call, as the writes it
+    /// covered may have been lost.
+    pub(crate) fn wrote(&self, log: &File) -> io::Result<()> {
+        match self.durability {
+            Durability::Always => log.sync_data(),
+            Durability::GroupCommit(_) => {
+                if let Some(group_commit) = &self.group_commit {
+                    group_commit.dirty.store(true, Ordering::Release);
+                }
+                self.take_error()
+            }
+            Durability::Never => Ok(()),
+        }
+    }
+
+    /// Returns the error from a background sync which failed since the last call, if any
+    pub(crate) fn take_error(&self) -> io::Result<()> {
+        match self.error.lock().unwrap().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Stops the background thread after its final sync, returning any error from it
+    pub(crate) fn stop(&mut self) -> io::Result<()> {
+        self.group_commit = None;
+        self.take_error()
+    }
+}
+
+fn sync_data(log: File) -> impl FnMut() -> io::Result<()> + Send + 'static {
+    move || log.sync_data()
+}
+
+/// A background thread which periodically syncs the log if it has been written to
+#[derive(Debug)]
+struct GroupCommit {
+    dirty: Arc<AtomicBool>,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GroupCommit {
+    fn spawn(
+        mut sync: impl FnMut() -> io::Result<()> + Send + 'static,
+        interval: Duration,
+        error: SyncError,
+    ) -> Self {
+        let dirty = Arc::new(AtomicBool::new(false));
+        let (stop, stopped) = channel::<()>();
+
+        let thread = std::thread::spawn({
+            let dirty = dirty.clone();
+            move || loop {
+                let finished = match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+                };
+
+                if dirty.swap(false, Ordering::AcqRel) {
+                    if let Err(e) = sync() {
+                        // The first error is the one which explains what was lost
+                        error.lock().unwrap().get_or_insert(e);
+                    }
+                }
+
+                if finished {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            dirty,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for GroupCommit {
+    /// Stop the background thread, which performs one final sync before exiting
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn failing_syncer(interval: Duration) -> Syncer {
+        let error = SyncError::default();
+        let group_commit = GroupCommit::spawn(
+            || Err(io::Error::other("disk on fire")),
+            interval,
+            error.clone(),
+        );
+        Syncer {
+            durability: Durability::GroupCommit(interval),
+            group_commit: Some(group_commit),
+            error,
+        }
+    }
+
+    #[test]
+    fn failed_background_sync_is_returned_by_a_later_write() {
+        let log = tempfile::tempfile().unwrap();
+        let syncer = failing_syncer(Duration::from_millis(1));
+        assert!(syncer.wrote(&log).is_ok());
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let e = loop {
+            match syncer.wrote(&log) {
+                Err(e) => break e,
+                Ok(()) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(1)),
+                Ok(()) => panic!("the failed sync was never reported"),
+            }
+        };
+        assert_eq!(e.to_string(), "disk on fire");
+    }
+
+    #[test]
+    fn failed_final_sync_is_returned_by_stop() {
+        let log = tempfile::tempfile().unwrap();
+        // Too long an interval for the thread to sync before it is stopped
+        let mut syncer = failing_syncer(Duration::from_secs(60));
+        syncer.wrote(&log).unwrap();
+        assert!(syncer.stop().is_err());
+        assert!(syncer.stop().is_ok());
+    }
+}
@@ -0,0 +1,70 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The errors that can be returned by a [`crate::KVStore`]
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O error from the underlying files
+    Io(io::Error),
+    /// A record in the log failed its checksum
+    Corruption {
+        /// The segment which holds the corrupted record
+        file_id: u32,
+        /// The position of the corrupted record in the segment
+        offset: u64,
+        /// The checksum that was stored alongside the record
+        expected: u32,
+        /// The checksum computed from the record's contents
+        found: u32,
+    },
+    /// The data directory is already in use by another store
+    Locked(PathBuf),
+    /// A file in the data directory wasn't written by this version of the store
+    UnknownFormat(PathBuf),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Corruption {
+                file_id,
+                offset,
+                expected,
+                found,
+            } => write!(
+                f,
+                "data corruption encountered in segment {} at offset {} ({:08x} != {:08x})",
+                file_id, offset, found, expected
+            ),
+            Error::Locked(path) => write!(
+                f,
+                "the store in {} is already open elsewhere",
+                path.display()
+            ),
+            Error::UnknownFormat(path) => write!(
+                f,
+                "{} wasn't written by this version of the store",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Corruption { .. } | Error::Locked(_) | Error::UnknownFormat(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,94 @@
+use std::ops::Bound;
+use std::sync::RwLock;
+
+use crate::segment::Readers;
+use crate::{reader, ByteString, Index, IndexEntry, Result};
+
+/// An iterator over a range of the keys in a [`crate::KVStore`] and their values, in key order.
+///
+/// Values are read from the log as the iterator advances. The index is only locked while the
+/// iterator looks up its next key, so it sees writes made to keys it hasn't reached yet.
+pub struct Iter<'a> {
+    pub(crate) index: &'a RwLock<Index>,
+    pub(crate) readers: &'a mut Readers,
+    /// The part of the range which hasn't been visited yet
+    pub(crate) front: Bound<ByteString>,
+    pub(crate) back: Bound<ByteString>,
+    /// Keys which expire before this time are skipped
+    pub(crate) now: u64,
+}
+
+impl Iter<'_> {
+    /// Reads the value for a key, or returns `None` if it was removed since it was looked up
+    fn read(
+        &mut self,
+        key: ByteString,
+        entry: IndexEntry,
+    ) -> Option<Result<(ByteString, ByteString)>> {
+        match reader::read(self.index, self.readers, &key, entry) {
+            Ok(Some(value)) => Some(Ok((key, value))),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// Whether the rest of the range is empty, which `BTreeMap::range` would panic on
+    fn is_exhausted(&self) -> bool {
+        match (&self.front, &self.back) {
+            (Bound::Included(start), Bound::Included(end)) => start > end,
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) => start >= end,
+            _ => false,
+        }
+    }
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Result<(ByteString, ByteString)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.is_exhausted() {
+                return None;
+            }
+
+            let (key, entry) = {
+                let index = self.index.read().unwrap();
+                let range = index.range::<ByteString, _>((self.front.as_ref(), self.back.as_ref()));
+                let mut live = range.filter(|(_, entry)| !entry.is_expired(self.now));
+                let (key, entry) = live.next()?;
+                (key.clone(), *entry)
+            };
+
+            self.front = Bound::Excluded(key.clone());
+            if let Some(pair) = self.read(key, entry) {
+                return Some(pair);
+            }
+        }
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.is_exhausted() {
+                return None;
+            }
+
+            let (key, entry) = {
+                let index = self.index.read().unwrap();
+                let range = index.range::<ByteString, _>((self.front.as_ref(), self.back.as_ref()));
+                let mut live = range.rev().filter(|(_, entry)| !entry.is_expired(self.now));
+                let (key, entry) = live.next()?;
+                (key.clone(), *entry)
+            };
+
+            self.back = Bound::Excluded(key.clone());
+            if let Some(pair) = self.read(key, entry) {
+                return Some(pair);
+            }
+        }
+    }
+}
@@ -1,268 +1,1568 @@
-use bincode::deserialize_from;
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, OpenOptions};
 use std::io;
 use std::io::prelude::*;
-use std::io::{BufReader, BufWriter, SeekFrom};
-use std::path::Path;
+use std::io::SeekFrom;
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicU32;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use crc::{Crc, CRC_31_PHILIPS};
-use serde_derive::{Deserialize, Serialize};
+use fs2::FileExt;
+use tracing::{error, warn};
 
-/// The file which stores the index for the database
-static DB_INDEX: &str = "kvs.index";
-/// The file which stores the log of the database
-static DB_FILE: &str = "kvs.db";
+pub use crate::batch::{Op, Swap};
+pub use crate::builder::KVStoreBuilder;
+pub use crate::durability::Durability;
+use crate::durability::Syncer;
+pub use crate::error::{Error, Result};
+pub use crate::iter::Iter;
+pub use crate::reader::KVReader;
+use crate::record::{read_record, Kind, Record};
+pub use crate::replication::{LogChunk, LogPosition};
+use crate::segment::{Hint, Readers, SEGMENT_MAGIC};
+pub use crate::snapshot::{Checkpoint, Corruption, Problem, Report};
+
+mod batch;
+mod builder;
+mod durability;
+mod error;
+mod iter;
+mod reader;
+mod record;
+mod replication;
+mod segment;
+mod snapshot;
+mod ttl;
+
+/// The file which is locked while the database is open
+static DB_LOCK: &str = "kvs.lock";
+
+/// The position of the first record in a segment, just after its header
+const SEGMENT_START: u64 = SEGMENT_MAGIC.len() as u64;
 
 pub type ByteString = Vec<u8>;
 pub type ByteStr = [u8];
 
-const CRC_U32: Crc<u32> = Crc::<u32>::new(&CRC_31_PHILIPS);
+/// Where the latest record for a key lives in the log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct IndexEntry {
+    /// The segment which holds the record
+    pub(crate) file_id: u32,
+    /// The position of the record in the segment
+    pub(crate) offset: u64,
+    /// The length of the whole record, so it can be read in one go
+    pub(crate) len: u64,
+    /// When the key expires, see [`ttl`]
+    pub(crate) expires_at: u64,
+}
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct KVPair {
-    pub key: ByteString,
-    pub value: ByteString,
+impl IndexEntry {
+    pub(crate) fn is_expired(&self, now: u64) -> bool {
+        ttl::is_expired(self.expires_at, now)
+    }
 }
 
+/// Where the latest record for each key lives, in key order
+pub(crate) type Index = BTreeMap<ByteString, IndexEntry>;
+
 /// A key-value database adapted from 'Rust in Action'
+///
+/// The log is split into segments which are capped in size. When the active segment fills up it is
+/// sealed, and a hint file listing where each key was written is saved alongside it. On startup, the
+/// index is rebuilt from the hint files rather than by reading every segment.
+///
+/// The index is kept in key order, so ranges of keys can be scanned with [`KVStore::scan`].
+///
+/// A store has a single writer, but any number of [`KVReader`]s can read from it at the same time,
+/// see [`KVStore::reader`].
 #[derive(Debug)]
 pub struct KVStore {
-    log: File,
-    pub index: HashMap<ByteString, u64>,
+    dir: PathBuf,
+    /// The segment which new records are appended to
+    active: File,
+    active_id: u32,
+    active_len: u64,
+    /// Hints for the records in the active segment, which are written out when it is sealed
+    active_hints: Vec<Hint>,
+    /// Whether the active segment has records that its hint file doesn't cover yet
+    hints_dirty: bool,
+    /// Reads on behalf of the store, and holds the index which is shared with other readers
+    reader: KVReader,
+    max_segment_size: u64,
+    syncer: Syncer,
+    _lock: File,
 }
 
 impl KVStore {
-    pub fn open() -> io::Result<Self> {
-        let log = open(Path::new(DB_FILE))?;
-        let index = KVStore::open_index()?;
-        Ok(KVStore { log, index })
-    }
-
-    fn open_index() -> io::Result<HashMap<ByteString, u64>> {
-        match deserialize_from(open(Path::new(DB_INDEX))?) {
-            Ok(index) => Ok(index),
-            Err(_) => {
-                println!("error deserializing index, creating new index");
-                Ok(HashMap::new())
-            }
+    /// Opens the store kept in the directory `path` using the default options.
+    ///
+    /// The directory is created if it doesn't exist. Use [`KVStore::builder`] to configure how
+    /// the store is opened.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        KVStore::builder().open(path)
+    }
+
+    /// Returns a builder which can be used to configure how the store is opened
+    pub fn builder() -> KVStoreBuilder {
+        KVStoreBuilder::new()
+    }
+
+    fn open_with(dir: &Path, options: &KVStoreBuilder) -> Result<Self> {
+        if options.create_dir {
+            fs::create_dir_all(dir)?;
         }
+
+        // Hold an exclusive lock on the directory for as long as the store is open,
+        // so that no other process can write to the same log
+        let lock = open(&dir.join(DB_LOCK))?;
+        lock.try_lock_exclusive()
+            .map_err(|_| Error::Locked(dir.to_path_buf()))?;
+
+        let active_id = segment::list_segments(dir)?.pop().unwrap_or(0);
+        let active = segment::open_segment(dir, active_id)?;
+        let syncer = Syncer::new(options.durability, &active)?;
+
+        let mut store = KVStore {
+            dir: dir.to_path_buf(),
+            active,
+            active_id,
+            active_len: SEGMENT_START,
+            active_hints: Vec::new(),
+            hints_dirty: false,
+            reader: KVReader {
+                index: Arc::new(RwLock::new(Index::new())),
+                readers: Readers::new(dir, Arc::new(AtomicU32::new(0))),
+            },
+            max_segment_size: options.max_segment_size,
+            syncer,
+            _lock: lock,
+        };
+
+        store.rebuild(true)?;
+        Ok(store)
     }
 
-    /// Assumes that f is already at the right place in the file
-    fn process_record<R: Read>(f: &mut R) -> io::Result<KVPair> {
-        let saved_checksum = f.read_u32::<LittleEndian>()?;
-        let key_len = f.read_u32::<LittleEndian>()?;
-        let val_len = f.read_u32::<LittleEndian>()?;
-        let data_len = key_len + val_len;
+    /// Rebuilds the index from every segment, oldest first.
+    ///
+    /// Readers keep seeing the old index until the new one is complete.
+    fn rebuild(&mut self, use_hints: bool) -> Result<()> {
+        let mut index = Index::new();
 
-        let mut data = ByteString::with_capacity(data_len as usize);
+        for id in segment::list_segments(&self.dir)? {
+            let hints = self.read_segment(id, use_hints)?;
+            for hint in &hints {
+                apply(&mut index, id, hint);
+            }
 
-        {
-            f.by_ref().take(data_len as u64).read_to_end(&mut data)?;
+            if id == self.active_id {
+                self.active_hints = hints;
+            }
         }
 
-        debug_assert_eq!(data.len(), data_len as usize);
+        *self.reader.index.write().unwrap() = index;
+        self.active_len = self.active.metadata()?.len();
+        Ok(())
+    }
 
-        let checksum = CRC_U32.checksum(&data);
-        if checksum != saved_checksum {
-            panic!(
-                "data corruption encountered ({:08x} != {:08x})",
-                checksum, saved_checksum
+    /// Reads the hints for every record in a segment.
+    ///
+    /// The segment's hint file is trusted up to the segment length it was written at, and any
+    /// records after that point are read from the segment itself. If there is no hint file, or it
+    /// doesn't line up with the segment, the whole segment is read instead. A torn or corrupted
+    /// record at the end of the segment, such as one left behind by a crash part-way through a
+    /// write, is truncated away.
+    fn read_segment(&mut self, id: u32, use_hints: bool) -> Result<Vec<Hint>> {
+        let file = segment::open_segment(&self.dir, id)?;
+        let file_len = file.metadata()?.len();
+
+        let (covered, mut hints) = match use_hints
+            .then(|| segment::read_hints(&self.dir, id))
+            .flatten()
+        {
+            Some((covered, hints)) if covered <= file_len => (covered, hints),
+            _ => (SEGMENT_START, Vec::new()),
+        };
+
+        let mut scanned = segment::scan(&file, id, covered);
+        if covered > SEGMENT_START && !matches!(scanned, Ok(ref scan) if !scan.damaged) {
+            // The hints don't line up with the segment, so read all of it instead
+            hints.clear();
+            scanned = segment::scan(&file, id, SEGMENT_START);
+        }
+        let scanned = scanned?;
+
+        if scanned.damaged {
+            warn!(
+                segment = id,
+                offset = scanned.end,
+                "truncating damaged record at the end of segment"
             );
+            file.set_len(scanned.end)?;
+            file.sync_all()?;
         }
 
-        let value = data.split_off(key_len as usize);
-        let key = data;
+        let stale = scanned.end != covered;
+        hints.extend(scanned.hints);
 
-        Ok(KVPair { key, value })
+        if id == self.active_id {
+            self.hints_dirty = stale;
+        } else if stale {
+            // A sealed segment whose hints were lost, so save them for next time
+            segment::write_hints(&self.dir, id, scanned.end, &hints)?;
+        }
+
+        Ok(hints)
     }
 
-    pub fn seek_to_end(&mut self) -> io::Result<u64> {
-        self.log.seek(SeekFrom::End(0))
+    /// Rebuilds the index by reading every segment, ignoring the hint files.
+    ///
+    /// A torn or corrupted record at the end of a segment is truncated away.
+    pub fn load(&mut self) -> Result<()> {
+        self.rebuild(false)
     }
 
-    pub fn load(&mut self) -> io::Result<()> {
-        let mut f = BufReader::new(&mut self.log);
+    /// The directory which holds the store's files
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
 
-        loop {
-            let current_position = f.seek(SeekFrom::Current(0))?;
-            let maybe_kv = KVStore::process_record(&mut f);
-            let kv = match maybe_kv {
-                Ok(kv) => kv,
-                Err(err) => match err.kind() {
-                    io::ErrorKind::UnexpectedEof => {
-                        break;
-                    }
-                    _ => return Err(err),
-                },
-            };
+    /// Returns a reader which can be moved to another thread, to read from the store while it is
+    /// being written to
+    pub fn reader(&self) -> KVReader {
+        self.reader.clone()
+    }
 
-            self.index.insert(kv.key, current_position);
-        }
+    /// The number of keys in the store
+    pub fn len(&self) -> usize {
+        self.reader.len()
+    }
 
-        Ok(())
+    /// Whether the store has no keys
+    pub fn is_empty(&self) -> bool {
+        self.reader.is_empty()
+    }
+
+    /// Returns the value of `key`, or `None` if it doesn't exist or has expired
+    pub fn get(&mut self, key: &ByteStr) -> Result<Option<ByteString>> {
+        self.reader.get(key)
     }
 
-    pub fn get(&mut self, key: &ByteStr) -> io::Result<Option<ByteString>> {
-        let position = match self.index.get(key) {
-            None => return Ok(None),
-            Some(position) => *position,
+    /// Returns the value of `key` along with how long it has left to live, or `None` for the latter
+    /// if it never expires
+    pub fn get_with_ttl(
+        &mut self,
+        key: &ByteStr,
+    ) -> Result<Option<(ByteString, Option<Duration>)>> {
+        self.reader.get_with_ttl(key)
+    }
+
+    /// Iterates over every key and its value, in key order
+    pub fn iter(&mut self) -> Iter<'_> {
+        self.reader.iter()
+    }
+
+    /// Iterates over the keys in `range` and their values, in key order
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # fn main() -> kvs::Result<()> {
+    /// let mut store = kvs::KVStore::open("data")?;
+    ///
+    /// for pair in store.scan(b"a".to_vec()..b"c".to_vec()) {
+    ///     let (key, value) = pair?;
+    ///     println!("{:?} = {:?}", key, value);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn scan<T, R>(&mut self, range: R) -> Iter<'_>
+    where
+        T: Ord + ?Sized + ToOwned<Owned = ByteString>,
+        R: RangeBounds<T>,
+    {
+        self.reader.scan(range)
+    }
+
+    /// Iterates over the keys which start with `prefix` and their values, in key order
+    pub fn prefix(&mut self, prefix: &ByteStr) -> Iter<'_> {
+        self.reader.prefix(prefix)
+    }
+
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> Result<()> {
+        self.put(key, value, ttl::NEVER)
+    }
+
+    /// Inserts a key-value pair which expires after `ttl`.
+    ///
+    /// Once it has expired the key can't be read, and its record is removed by the next
+    /// [`KVStore::compact`].
+    pub fn insert_with_ttl(&mut self, key: &ByteStr, value: &ByteStr, ttl: Duration) -> Result<()> {
+        self.put(key, value, ttl::expires_at(ttl))
+    }
+
+    fn put(&mut self, key: &ByteStr, value: &ByteStr, expires_at: u64) -> Result<()> {
+        let record = record::encode(Kind::Put, expires_at, key, value);
+        self.append(&record, vec![(Kind::Put, expires_at, key.to_vec())])
+    }
+
+    /// Sets the value of a key which already exists, and clears its TTL.
+    ///
+    /// Returns `false`, without writing anything, if the key doesn't exist or has expired.
+    pub fn update(&mut self, key: &ByteStr, value: &ByteStr) -> Result<bool> {
+        let exists = match self.reader.index.read().unwrap().get(key) {
+            Some(entry) => !entry.is_expired(ttl::now()),
+            None => false,
         };
+        if !exists {
+            return Ok(false);
+        }
+
+        self.insert(key, value)?;
+        Ok(true)
+    }
+
+    #[inline]
+    pub fn delete(&mut self, key: &ByteStr) -> Result<()> {
+        let record = record::encode(Kind::Delete, ttl::NEVER, key, b"");
+        self.append(&record, vec![(Kind::Delete, ttl::NEVER, key.to_vec())])
+    }
+
+    /// Applies every operation in `ops`, in order, or none of them.
+    ///
+    /// The operations are written to the log as a single record, so a crash part-way through the
+    /// write loses the whole batch, and readers see either all of its changes or none of them.
+    pub fn write_batch(&mut self, ops: &[Op]) -> Result<()> {
+        if ops.is_empty() {
+            return Ok(());
+        }
 
-        let kv = self.get_at(position)?;
+        let ops: Vec<_> = ops.iter().map(Op::to_entry).collect();
+        self.append_batch(ops)
+    }
+
+    /// Writes the changes as a single [`Kind::Batch`] record
+    fn append_batch(&mut self, ops: Vec<record::Entry>) -> Result<()> {
+        let value = batch::encode(&ops);
+        if value.len() > u32::MAX as usize {
+            let message = "the batch is too large to be written as one record";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+        }
 
-        Ok(Some(kv.value))
+        let record = record::encode(Kind::Batch, ttl::NEVER, b"", &value);
+        let changes = ops
+            .into_iter()
+            .map(|op| (op.kind, op.expires_at, op.key))
+            .collect();
+        self.append(&record, changes)
     }
 
-    pub fn get_at(&mut self, position: u64) -> io::Result<KVPair> {
-        let mut f = BufReader::new(&mut self.log);
-        f.seek(SeekFrom::Start(position))?;
-        let kv = KVStore::process_record(&mut f)?;
-        Ok(kv)
+    /// Sets `key` to `new` if it currently holds `expected`, where `None` stands for a key that
+    /// doesn't exist. Setting it to `None` deletes the key.
+    ///
+    /// Nothing else can write to the store in between the comparison and the swap, so this can be
+    /// used to update a value without losing a concurrent update to it.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # fn main() -> kvs::Result<()> {
+    /// use kvs::Swap;
+    ///
+    /// let mut store = kvs::KVStore::open("data")?;
+    ///
+    /// // only one of these can succeed
+    /// assert_eq!(store.compare_and_swap(b"leader", None, Some(b"a"))?, Swap::Swapped);
+    /// assert!(matches!(
+    ///     store.compare_and_swap(b"leader", None, Some(b"b"))?,
+    ///     Swap::Mismatch { .. }
+    /// ));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn compare_and_swap(
+        &mut self,
+        key: &ByteStr,
+        expected: Option<&ByteStr>,
+        new: Option<&ByteStr>,
+    ) -> Result<Swap> {
+        let current = self.get(key)?;
+        if current.as_deref() != expected {
+            return Ok(Swap::Mismatch { current });
+        }
+
+        match new {
+            Some(value) => self.insert(key, value)?,
+            None if current.is_some() => self.delete(key)?,
+            None => {}
+        }
+        Ok(Swap::Swapped)
     }
 
-    pub fn find(&mut self, target: &ByteStr) -> io::Result<Option<(u64, ByteString)>> {
-        let mut f = BufReader::new(&mut self.log);
+    /// Appends a record to the active segment, sealing it first if the record wouldn't fit, and
+    /// then applies the record's changes to the index.
+    ///
+    /// The record is written with a single call so that a failed write can be rolled back, and is
+    /// then synced according to the store's [`Durability`].
+    fn append(&mut self, record: &[u8], changes: Vec<(Kind, u64, ByteString)>) -> Result<()> {
+        let len = record.len() as u64;
 
-        let mut found: Option<(u64, ByteString)> = None;
+        if self.active_len > SEGMENT_START && self.active_len + len > self.max_segment_size {
+            self.roll()?;
+        }
 
-        loop {
-            let position = f.seek(SeekFrom::Current(0))?;
+        let offset = self.active.seek(SeekFrom::End(0))?;
+        if let Err(e) = self.active.write_all(record) {
+            // Don't leave a partial record behind for the next write to be appended to
+            let _ = self.active.set_len(offset);
+            return Err(e.into());
+        }
+        self.active_len = offset + len;
 
-            let maybe_kv = KVStore::process_record(&mut f);
-            let kv = match maybe_kv {
-                Ok(kv) => kv,
-                Err(err) => match err.kind() {
-                    io::ErrorKind::UnexpectedEof => {
-                        break;
-                    }
-                    _ => return Err(err),
-                },
+        // Readers see every change in the record at once
+        let mut index = self.reader.index.write().unwrap();
+        for (kind, expires_at, key) in changes {
+            let hint = Hint {
+                kind,
+                key,
+                offset,
+                len,
+                expires_at,
             };
+            apply(&mut index, self.active_id, &hint);
+            self.active_hints.push(hint);
+        }
+        drop(index);
+        self.hints_dirty = true;
 
-            if kv.key == target {
-                found = Some((position, kv.value));
-            }
+        self.syncer.wrote(&self.active)?;
+        Ok(())
+    }
+
+    /// Rewrites the log so that it only holds the live value of each key, reclaiming the space used
+    /// by keys which have been overwritten, deleted or have expired.
+    ///
+    /// The active segment is sealed, and every live value is copied from the sealed segments to
+    /// the end of the log. The sealed segments are then removed, oldest first, so the log replays to
+    /// the same values whenever a crash interrupts the compaction.
+    pub fn compact(&mut self) -> Result<()> {
+        if self.active_len > SEGMENT_START {
+            self.roll()?;
+        }
+        let first = self.active_id;
+        let now = ttl::now();
+
+        let (live, expired): (Vec<_>, Vec<_>) = self
+            .reader
+            .index
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| entry.file_id < first)
+            .map(|(key, entry)| (key.clone(), *entry))
+            .partition(|(_, entry)| !entry.is_expired(now));
 
-            // important to keep looping until the end of the file,
-            // in case the key has been overwritten
+        // The records of expired keys are about to be removed, so the keys go with them
+        let mut index = self.reader.index.write().unwrap();
+        for (key, _) in expired {
+            index.remove(&key);
         }
+        drop(index);
 
-        Ok(found)
+        for (key, entry) in live {
+            let value = self.reader.readers.read(&key, entry)?;
+            let record = record::encode(Kind::Put, entry.expires_at, &key, &value);
+            self.append(&record, vec![(Kind::Put, entry.expires_at, key)])?;
+        }
+        self.flush()?;
+
+        for id in segment::list_segments(&self.dir)? {
+            if id >= first {
+                break;
+            }
+            if let Err(e) = fs::remove_file(segment::hint_path(&self.dir, id)) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e.into());
+                }
+            }
+            fs::remove_file(segment::segment_path(&self.dir, id))?;
+        }
+        segment::sync_dir(&self.dir)?;
+        self.reader.readers.remove_before(first);
+
+        Ok(())
     }
 
-    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
-        let position = self.insert_but_ignore_index(key, value)?;
-        self.index.insert(key.to_vec(), position);
+    /// Appends records read from another store's log by [`KVReader::read_log`], making this store a
+    /// replica of it.
+    ///
+    /// Records are applied one at a time, in order. Applying a record which has already been applied
+    /// leaves the same value behind, so a replica which is unsure how far it got can read the log
+    /// again from an earlier position.
+    pub fn apply_log(&mut self, mut records: &[u8]) -> Result<()> {
+        while !records.is_empty() {
+            let entry = match read_record(&mut &records[..])? {
+                Record::Valid(entry) => entry,
+                _ => {
+                    let message = "the log holds a damaged or partial record";
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+                }
+            };
+
+            let changes = match entry.kind {
+                Kind::Batch => batch::decode(&entry.value)?
+                    .into_iter()
+                    .map(|op| (op.kind, op.expires_at, op.key))
+                    .collect(),
+                Kind::Put | Kind::Delete => vec![(entry.kind, entry.expires_at, entry.key.clone())],
+            };
+            let (record, rest) = records.split_at(entry.record_len() as usize);
+            self.append(record, changes)?;
+            records = rest;
+        }
         Ok(())
     }
 
-    /// Writes a variable-sized byte buffer to disk to represent the key-value pair.
+    /// Deletes every key, in a single batch
+    pub fn clear(&mut self) -> Result<()> {
+        let ops: Vec<_> = self
+            .reader
+            .index
+            .read()
+            .unwrap()
+            .keys()
+            .map(|key| Op::Delete { key: key.clone() }.to_entry())
+            .collect();
+
+        if ops.is_empty() {
+            return Ok(());
+        }
+        self.append_batch(ops)
+    }
+
+    /// Takes a checkpoint of the store as it is now, which can be verified or copied elsewhere
+    /// while the store carries on being written to and compacted.
     ///
-    /// The format on disk is as follows:
-    /// `[crc, key_len, val_len, key, value]`
+    /// This only opens the segments, so it doesn't hold up the writer for long. On platforms other
+    /// than Unix, compaction can't remove a segment while a checkpoint holds it open.
+    pub fn checkpoint(&self) -> Result<Checkpoint> {
+        Checkpoint::take(&self.dir, Some((self.active_id, self.active_len)))
+    }
+
+    /// Writes a consistent copy of the store to the directory `dest`, checking every record of the
+    /// copy against its checksum.
+    ///
+    /// This is [`KVStore::checkpoint`] followed by [`Checkpoint::write_to`]. To carry on writing
+    /// while the copy is made, take the checkpoint and write it out on another thread.
     ///
-    /// Where crc, key_len, and val_len all have known sizes, and key and value are variable-sized.
-    /// Since the size of the first three values is known, we can use them
+    /// # Examples
     ///
-    /// The crc is a 32-bit checksum of the key and value, and is used to detect data corruption.
-    pub fn insert_but_ignore_index(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<u64> {
-        let mut f = BufWriter::new(&mut self.log);
+    /// ```no_run
+    /// # fn main() -> kvs::Result<()> {
+    /// let mut store = kvs::KVStore::open("data")?;
+    ///
+    /// let checkpoint = store.checkpoint()?;
+    /// let backup = std::thread::spawn(move || checkpoint.write_to("backup"));
+    /// store.insert(b"key", b"value")?;
+    /// backup.join().unwrap()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn snapshot<P: AsRef<Path>>(&self, dest: P) -> Result<()> {
+        self.checkpoint()?.write_to(dest)
+    }
 
-        // Get the byte length of the key & value
-        let key_len = key.len();
-        let val_len = value.len();
+    /// Reads every record in the store, reporting where any damaged ones are
+    pub fn verify(&self) -> Result<Report> {
+        self.checkpoint()?.verify()
+    }
 
-        // Create a buffer to hold the bytes for the key & value
-        let mut tmp = ByteString::with_capacity(key_len + val_len);
+    /// Replaces the contents of the store with those of the snapshot in `src`, such as one written
+    /// by [`KVStore::snapshot`]. Keys keep the expiry times they had in the snapshot.
+    ///
+    /// The snapshot is checked before anything is written, and then applied as a single batch, so
+    /// readers and a crash see either the old contents or the restored ones. The batch is built in
+    /// memory, so the snapshot's live values must fit in memory.
+    pub fn restore<P: AsRef<Path>>(&mut self, src: P) -> Result<()> {
+        let entries = Checkpoint::open(src)?.entries()?;
 
-        // Write all of the bytes for the key
-        for byte in key {
-            tmp.push(*byte);
+        let mut ops = Vec::with_capacity(entries.len());
+        {
+            let index = self.reader.index.read().unwrap();
+            let restored: HashSet<&ByteStr> = entries.iter().map(|(key, _, _)| &key[..]).collect();
+            for key in index.keys().filter(|key| !restored.contains(&key[..])) {
+                ops.push(record::Entry {
+                    kind: Kind::Delete,
+                    expires_at: ttl::NEVER,
+                    key: key.clone(),
+                    value: ByteString::new(),
+                });
+            }
         }
-
-        // Next, write all of the bytes for the value
-        for byte in value {
-            tmp.push(*byte);
+        for (key, expires_at, value) in entries {
+            ops.push(record::Entry {
+                kind: Kind::Put,
+                expires_at,
+                key,
+                value,
+            });
         }
 
-        let checksum = CRC_U32.checksum(&tmp);
+        if ops.is_empty() {
+            return Ok(());
+        }
+        self.append_batch(ops)?;
+        self.flush()
+    }
 
-        let next_byte = SeekFrom::End(0);
-        let current_position = f.seek(SeekFrom::Current(0))?;
-        f.seek(next_byte)?;
+    /// Seals the active segment and starts a new one
+    fn roll(&mut self) -> Result<()> {
+        self.active.sync_all()?;
+        segment::write_hints(
+            &self.dir,
+            self.active_id,
+            self.active_len,
+            &self.active_hints,
+        )?;
 
-        // 1. Write the checksum (known size)
-        f.write_u32::<LittleEndian>(checksum)?;
-        // 2. Write the key (known size)
-        f.write_u32::<LittleEndian>(key_len as u32)?;
-        // 3. Write the value (known size)
-        f.write_u32::<LittleEndian>(val_len as u32)?;
-        // 4. write the byte buffer (variable size)
-        f.write_all(&tmp)?;
+        let id = self.active_id + 1;
+        let active = segment::open_segment(&self.dir, id)?;
+        segment::sync_dir(&self.dir)?;
+        self.syncer.rolled(&active)?;
 
-        Ok(current_position)
+        self.active = active;
+        self.active_id = id;
+        self.active_len = SEGMENT_START;
+        self.active_hints.clear();
+        self.hints_dirty = false;
+        Ok(())
     }
 
-    #[inline]
-    pub fn update(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
-        self.insert(key, value)
+    /// Syncs the active segment to disk and then saves its hints alongside it
+    ///
+    /// Returns the error from a background sync which failed since the last write, if any, as the
+    /// writes it covered may have been lost.
+    pub fn flush(&mut self) -> Result<()> {
+        self.syncer.take_error()?;
+        self.active.sync_data()?;
+        segment::write_hints(
+            &self.dir,
+            self.active_id,
+            self.active_len,
+            &self.active_hints,
+        )?;
+
+        self.hints_dirty = false;
+        Ok(())
     }
 
-    #[inline]
-    pub fn delete(&mut self, key: &ByteStr) -> io::Result<()> {
-        self.insert(key, b"")
+    /// Flushes the store and closes it, reporting any error that occurs along the way
+    pub fn close(mut self) -> Result<()> {
+        self.syncer.stop()?;
+        self.flush()
     }
 }
 
 impl Drop for KVStore {
-    /// Serialize the index to disk when the [`KVStore`] is dropped
+    /// Save the hints for the active segment when the [`KVStore`] is dropped
     fn drop(&mut self) {
-        let f = match open(Path::new(DB_INDEX)) {
-            Ok(f) => f,
-            Err(_) => {
-                panic!("error opening the index, potential data loss");
-            }
-        };
+        if !self.hints_dirty {
+            return;
+        }
 
-        match bincode::serialize(&self.index) {
-            Ok(bytes) => {
-                let mut writer = BufWriter::new(f);
-                match writer.write_all(&bytes) {
-                    Ok(_) => {}
-                    Err(_) => {
-                        panic!("error writing to the index, potential data loss");
-                    }
-                }
-            }
-            Err(_) => {
-                panic!("error serializing index, potential data loss");
-            }
+        if let Err(e) = self.flush() {
+            error!(
+                error = %e,
+                "error writing hints, they will be rebuilt from the log"
+            );
         }
     }
 }
 
+/// Applies a record to the index
+fn apply(index: &mut Index, file_id: u32, hint: &Hint) {
+    match hint.kind {
+        Kind::Put => {
+            let entry = IndexEntry {
+                file_id,
+                offset: hint.offset,
+                len: hint.len,
+                expires_at: hint.expires_at,
+            };
+            index.insert(hint.key.clone(), entry);
+        }
+        Kind::Delete => {
+            index.remove(&hint.key);
+        }
+        Kind::Batch => unreachable!("batches are applied one operation at a time"),
+    }
+}
+
+/// Returns the smallest key which is greater than every key starting with `prefix`,
+/// or `None` if there isn't one
+fn prefix_end(prefix: &ByteStr) -> Option<ByteString> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
 fn open(f: &Path) -> io::Result<File> {
     OpenOptions::new()
         .read(true)
         .write(true)
         .create(true)
+        .truncate(false)
         .open(f)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::CRC_U32;
+    use super::*;
+    use crate::record::HEADER_LEN;
+    use crate::segment::{hint_path, segment_path};
+    use tempfile::TempDir;
+
+    fn open_in(dir: &TempDir) -> KVStore {
+        KVStore::builder()
+            .durability(Durability::Never)
+            .open(dir.path())
+            .unwrap()
+    }
+
+    fn open_small(dir: &TempDir) -> KVStore {
+        KVStore::builder()
+            .durability(Durability::Never)
+            .max_segment_size(64)
+            .open(dir.path())
+            .unwrap()
+    }
+
+    fn remove_hints(dir: &TempDir) {
+        for entry in fs::read_dir(dir.path()).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().unwrap() == "hint" {
+                fs::remove_file(path).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn reopen_uses_hints() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_in(&dir);
+        kv.insert(b"a", b"1").unwrap();
+        kv.insert(b"b", b"2").unwrap();
+        kv.insert(b"a", b"3").unwrap();
+        kv.close().unwrap();
+
+        let mut kv = open_in(&dir);
+        assert_eq!(kv.get(b"a").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(kv.get(b"b").unwrap(), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn rebuilds_missing_hints() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_small(&dir);
+        for i in 0..20u8 {
+            kv.insert(&[i], b"value").unwrap();
+        }
+        kv.close().unwrap();
+
+        remove_hints(&dir);
+
+        let mut kv = open_small(&dir);
+        for i in 0..20u8 {
+            assert_eq!(kv.get(&[i]).unwrap(), Some(b"value".to_vec()));
+        }
+        assert!(hint_path(dir.path(), 0).exists());
+    }
+
+    #[test]
+    fn replays_records_written_after_the_hints() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_in(&dir);
+        kv.insert(b"a", b"1").unwrap();
+        kv.flush().unwrap();
+        let hints = hint_path(dir.path(), 0);
+        let stale_hints = fs::read(&hints).unwrap();
+        kv.insert(b"b", b"2").unwrap();
+        kv.close().unwrap();
+
+        // simulate a crash, which leaves the hints from the last flush behind
+        fs::write(&hints, stale_hints).unwrap();
+
+        let mut kv = open_in(&dir);
+        assert_eq!(kv.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(kv.get(b"b").unwrap(), Some(b"2".to_vec()));
+    }
 
     #[test]
-    fn crc_u32_test() {
-        let data = b"hello world";
+    fn truncates_torn_tail() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_in(&dir);
+        kv.insert(b"a", b"1").unwrap();
+        kv.insert(b"b", b"2").unwrap();
+        kv.close().unwrap();
 
-        let a = CRC_U32.checksum(data);
-        let b = CRC_U32.checksum(data);
+        let log_path = segment_path(dir.path(), 0);
+        let log_len = fs::metadata(&log_path).unwrap().len();
 
-        assert_eq!(a, b);
+        // a record header which promises more data than was written
+        let mut log = OpenOptions::new().append(true).open(&log_path).unwrap();
+        log.write_all(&[0xde, 0xad, 0xbe, 0xef, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+            .unwrap();
+        log.write_all(&[1, 0, 0, 0, 100, 0, 0, 0, b'c']).unwrap();
+        drop(log);
+
+        let mut kv = open_in(&dir);
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), log_len);
+        assert_eq!(kv.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(kv.get(b"b").unwrap(), Some(b"2".to_vec()));
+
+        kv.insert(b"c", b"3").unwrap();
+        kv.close().unwrap();
+
+        let mut kv = open_in(&dir);
+        assert_eq!(kv.get(b"c").unwrap(), Some(b"3".to_vec()));
+    }
+
+    #[test]
+    fn corrupted_record_is_an_error() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_in(&dir);
+        kv.insert(b"a", b"1").unwrap();
+        kv.insert(b"b", b"2").unwrap();
+        kv.close().unwrap();
+
+        // flip the value of the first record
+        let log_path = segment_path(dir.path(), 0);
+        let mut log = fs::read(&log_path).unwrap();
+        log[(SEGMENT_START + HEADER_LEN) as usize + 1] = b'9';
+        fs::write(&log_path, log).unwrap();
+
+        let mut kv = open_in(&dir);
+        assert!(matches!(
+            kv.get(b"a"),
+            Err(Error::Corruption {
+                file_id: 0,
+                offset: SEGMENT_START,
+                ..
+            })
+        ));
+        assert_eq!(kv.get(b"b").unwrap(), Some(b"2".to_vec()));
+        kv.close().unwrap();
+
+        remove_hints(&dir);
+        assert!(matches!(
+            KVStore::builder()
+                .durability(Durability::Never)
+                .open(dir.path()),
+            Err(Error::Corruption {
+                offset: SEGMENT_START,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn rolls_segments_at_the_size_cap() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_small(&dir);
+        for i in 0..20u8 {
+            kv.insert(&[i], b"value").unwrap();
+        }
+        for i in 0..10u8 {
+            kv.delete(&[i]).unwrap();
+        }
+        assert_eq!(kv.len(), 10);
+        kv.close().unwrap();
+
+        let segments = segment::list_segments(dir.path()).unwrap();
+        assert!(segments.len() > 2);
+        for id in segments {
+            assert!(fs::metadata(segment_path(dir.path(), id)).unwrap().len() <= 64);
+            assert!(hint_path(dir.path(), id).exists());
+        }
+
+        let mut kv = open_small(&dir);
+        assert_eq!(kv.len(), 10);
+        for i in 0..10u8 {
+            assert_eq!(kv.get(&[i]).unwrap(), None);
+        }
+        for i in 10..20u8 {
+            assert_eq!(kv.get(&[i]).unwrap(), Some(b"value".to_vec()));
+        }
+    }
+
+    #[test]
+    fn startup_reads_hints_instead_of_sealed_segments() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_small(&dir);
+        for i in 0..20u8 {
+            kv.insert(&[i], b"value").unwrap();
+        }
+        kv.close().unwrap();
+
+        // damage the first record of a sealed segment, which is only noticed when it is read
+        let log_path = segment_path(dir.path(), 0);
+        let mut log = fs::read(&log_path).unwrap();
+        log[(SEGMENT_START + HEADER_LEN) as usize + 1] = b'!';
+        fs::write(&log_path, log).unwrap();
+
+        let mut kv = open_small(&dir);
+        assert!(matches!(kv.get(&[0]), Err(Error::Corruption { .. })));
+        assert_eq!(kv.get(&[19]).unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn deleted_keys_stay_deleted() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_in(&dir);
+        kv.insert(b"a", b"1").unwrap();
+        kv.delete(b"a").unwrap();
+        assert_eq!(kv.get(b"a").unwrap(), None);
+        assert!(kv.is_empty());
+        kv.close().unwrap();
+
+        let mut kv = open_in(&dir);
+        assert_eq!(kv.get(b"a").unwrap(), None);
+        kv.load().unwrap();
+        assert_eq!(kv.get(b"a").unwrap(), None);
+    }
+
+    #[test]
+    fn group_commit() {
+        let dir = TempDir::new().unwrap();
+
+        let durability = Durability::GroupCommit(std::time::Duration::from_millis(1));
+        let mut kv = KVStore::builder()
+            .durability(durability)
+            .max_segment_size(1024)
+            .open(dir.path())
+            .unwrap();
+        for i in 0..100u32 {
+            kv.insert(&i.to_le_bytes(), b"value").unwrap();
+        }
+        kv.close().unwrap();
+
+        let mut kv = KVStore::builder()
+            .durability(Durability::Always)
+            .open(dir.path())
+            .unwrap();
+        for i in 0..100u32 {
+            assert_eq!(kv.get(&i.to_le_bytes()).unwrap(), Some(b"value".to_vec()));
+        }
+    }
+
+    #[test]
+    fn directory_is_locked_while_open() {
+        let dir = TempDir::new().unwrap();
+
+        let kv = open_in(&dir);
+        assert!(matches!(KVStore::open(dir.path()), Err(Error::Locked(_))));
+        drop(kv);
+
+        let mut kv = open_in(&dir);
+        kv.insert(b"a", b"1").unwrap();
+    }
+
+    #[test]
+    fn stores_in_different_directories_are_independent() {
+        let a = TempDir::new().unwrap();
+        let b = TempDir::new().unwrap();
+
+        let mut kv_a = open_in(&a);
+        let mut kv_b = open_in(&b);
+        kv_a.insert(b"key", b"a").unwrap();
+        kv_b.insert(b"key", b"b").unwrap();
+
+        assert_eq!(kv_a.get(b"key").unwrap(), Some(b"a".to_vec()));
+        assert_eq!(kv_b.get(b"key").unwrap(), Some(b"b".to_vec()));
+    }
+
+    #[test]
+    fn creates_missing_directory() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("nested").join("store");
+
+        assert!(KVStore::builder().create_dir(false).open(&path).is_err());
+
+        let mut kv = KVStore::open(&path).unwrap();
+        kv.insert(b"a", b"1").unwrap();
+        assert!(segment_path(&path, 0).exists());
+    }
+
+    fn collect(iter: Iter<'_>) -> Vec<(ByteString, ByteString)> {
+        iter.collect::<Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn scan_returns_keys_in_order() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_small(&dir);
+        for key in [&b"c"[..], b"a", b"d", b"b", b"e"] {
+            kv.insert(key, key).unwrap();
+        }
+        kv.insert(b"b", b"B").unwrap();
+        kv.delete(b"d").unwrap();
+
+        let pair = |k: &[u8], v: &[u8]| (k.to_vec(), v.to_vec());
+        let all = vec![
+            pair(b"a", b"a"),
+            pair(b"b", b"B"),
+            pair(b"c", b"c"),
+            pair(b"e", b"e"),
+        ];
+
+        assert_eq!(collect(kv.iter()), all);
+        assert_eq!(collect(kv.scan(b"b".to_vec()..b"e".to_vec())), all[1..3]);
+        assert_eq!(collect(kv.scan(b"bb".to_vec()..)), all[2..]);
+        assert!(collect(kv.scan(b"e".to_vec()..b"b".to_vec())).is_empty());
+        assert_eq!(
+            kv.iter()
+                .rev()
+                .map(|pair| pair.unwrap().0)
+                .collect::<Vec<_>>(),
+            vec![b"e".to_vec(), b"c".to_vec(), b"b".to_vec(), b"a".to_vec()]
+        );
+        kv.close().unwrap();
+
+        let mut kv = open_small(&dir);
+        assert_eq!(collect(kv.iter()), all);
+    }
+
+    #[test]
+    fn prefix_scan() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_in(&dir);
+        for key in [
+            &b"tenant/1/color"[..],
+            b"tenant/1/size",
+            b"tenant/10/color",
+            b"tenant/2/color",
+            b"tenants",
+            b"\xff\xff",
+            b"\xff\xff\x01",
+        ] {
+            kv.insert(key, b"").unwrap();
+        }
+
+        let keys =
+            |iter: Iter<'_>| -> Vec<ByteString> { iter.map(|pair| pair.unwrap().0).collect() };
+
+        assert_eq!(
+            keys(kv.prefix(b"tenant/1/")),
+            vec![b"tenant/1/color".to_vec(), b"tenant/1/size".to_vec()]
+        );
+        assert_eq!(keys(kv.prefix(b"tenant/")).len(), 4);
+        assert_eq!(keys(kv.prefix(b"")).len(), 7);
+        assert_eq!(keys(kv.prefix(b"\xff")).len(), 2);
+        assert!(keys(kv.prefix(b"nope")).is_empty());
+    }
+
+    #[test]
+    fn prefix_end_skips_max_bytes() {
+        assert_eq!(prefix_end(b"ab"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_end(b"a\xff"), Some(b"b".to_vec()));
+        assert_eq!(prefix_end(b"\xff\xff"), None);
+        assert_eq!(prefix_end(b""), None);
+    }
+
+    #[test]
+    fn readers_see_writes() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_small(&dir);
+        let mut reader = kv.reader();
+        kv.insert(b"a", b"1").unwrap();
+        assert_eq!(reader.get(b"a").unwrap(), Some(b"1".to_vec()));
+
+        // including writes to segments which didn't exist when the reader was created
+        for i in 0..20u8 {
+            kv.insert(&[i], b"value").unwrap();
+        }
+        assert_eq!(reader.len(), 21);
+        assert_eq!(reader.get(&[19]).unwrap(), Some(b"value".to_vec()));
+
+        kv.delete(b"a").unwrap();
+        assert_eq!(reader.get(b"a").unwrap(), None);
+    }
+
+    #[test]
+    fn iterators_see_writes_ahead_of_them() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_in(&dir);
+        kv.insert(b"a", b"1").unwrap();
+        kv.insert(b"c", b"3").unwrap();
+
+        let mut reader = kv.reader();
+        let mut iter = reader.iter();
+        assert_eq!(iter.next().unwrap().unwrap().0, b"a");
+        kv.insert(b"b", b"2").unwrap();
+        assert_eq!(iter.next().unwrap().unwrap().0, b"b");
+        assert_eq!(iter.next().unwrap().unwrap().0, b"c");
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn readers_run_alongside_the_writer() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_small(&dir);
+        for i in 0..100u32 {
+            kv.insert(&i.to_le_bytes(), &i.to_be_bytes()).unwrap();
+        }
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let mut reader = kv.reader();
+                std::thread::spawn(move || {
+                    for _ in 0..10 {
+                        for i in 0..100u32 {
+                            let value = reader.get(&i.to_le_bytes()).unwrap();
+                            assert_eq!(value, Some(i.to_be_bytes().to_vec()));
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for i in 100..200u32 {
+            kv.insert(&i.to_le_bytes(), &i.to_be_bytes()).unwrap();
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(kv.len(), 200);
+    }
+
+    #[test]
+    fn batches_are_applied_together() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_in(&dir);
+        kv.insert(b"c", b"3").unwrap();
+        let ops = vec![
+            Op::Put {
+                key: b"a".to_vec(),
+                value: b"1".to_vec(),
+            },
+            Op::Put {
+                key: b"b".to_vec(),
+                value: b"2".to_vec(),
+            },
+            Op::Delete { key: b"c".to_vec() },
+            Op::Put {
+                key: b"a".to_vec(),
+                value: b"4".to_vec(),
+            },
+        ];
+        kv.write_batch(&ops).unwrap();
+
+        let expected = vec![
+            (b"a".to_vec(), b"4".to_vec()),
+            (b"b".to_vec(), b"2".to_vec()),
+        ];
+        assert_eq!(collect(kv.iter()), expected);
+        kv.close().unwrap();
+
+        let mut kv = open_in(&dir);
+        assert_eq!(collect(kv.iter()), expected);
+        kv.load().unwrap();
+        assert_eq!(collect(kv.iter()), expected);
+    }
+
+    #[test]
+    fn torn_batch_is_lost_entirely() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_in(&dir);
+        kv.insert(b"a", b"1").unwrap();
+        let ops = vec![
+            Op::Put {
+                key: b"a".to_vec(),
+                value: b"2".to_vec(),
+            },
+            Op::Put {
+                key: b"b".to_vec(),
+                value: b"2".to_vec(),
+            },
+        ];
+        kv.write_batch(&ops).unwrap();
+        kv.close().unwrap();
+
+        // a crash part-way through writing the batch
+        let log_path = segment_path(dir.path(), 0);
+        let log_len = fs::metadata(&log_path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&log_path)
+            .unwrap()
+            .set_len(log_len - 1)
+            .unwrap();
+
+        let mut kv = open_in(&dir);
+        assert_eq!(kv.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(kv.get(b"b").unwrap(), None);
+    }
+
+    #[test]
+    fn update_requires_an_existing_key() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_in(&dir);
+        assert!(!kv.update(b"a", b"1").unwrap());
+        assert_eq!(kv.get(b"a").unwrap(), None);
+
+        kv.insert(b"a", b"1").unwrap();
+        assert!(kv.update(b"a", b"2").unwrap());
+        assert_eq!(kv.get(b"a").unwrap(), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_in(&dir);
+        assert_eq!(
+            kv.compare_and_swap(b"k", None, Some(b"1")).unwrap(),
+            Swap::Swapped
+        );
+        assert_eq!(
+            kv.compare_and_swap(b"k", None, Some(b"2")).unwrap(),
+            Swap::Mismatch {
+                current: Some(b"1".to_vec())
+            }
+        );
+        assert_eq!(
+            kv.compare_and_swap(b"k", Some(b"1"), Some(b"2")).unwrap(),
+            Swap::Swapped
+        );
+        assert_eq!(kv.get(b"k").unwrap(), Some(b"2".to_vec()));
+
+        // swapping to `None` deletes the key
+        assert_eq!(
+            kv.compare_and_swap(b"k", Some(b"2"), None).unwrap(),
+            Swap::Swapped
+        );
+        assert_eq!(kv.get(b"k").unwrap(), None);
+        assert_eq!(
+            kv.compare_and_swap(b"k", Some(b"2"), None).unwrap(),
+            Swap::Mismatch { current: None }
+        );
+    }
+
+    #[test]
+    fn keys_expire() {
+        let dir = TempDir::new().unwrap();
+        let hour = Duration::from_secs(3600);
+
+        let mut kv = open_in(&dir);
+        kv.insert_with_ttl(b"expired", b"1", Duration::ZERO)
+            .unwrap();
+        kv.insert_with_ttl(b"live", b"2", hour).unwrap();
+        kv.insert(b"forever", b"3").unwrap();
+
+        assert_eq!(kv.get(b"expired").unwrap(), None);
+        let (value, ttl) = kv.get_with_ttl(b"live").unwrap().unwrap();
+        assert_eq!(value, b"2");
+        assert!(ttl.unwrap() > hour - Duration::from_secs(60) && ttl.unwrap() <= hour);
+        assert_eq!(
+            kv.get_with_ttl(b"forever").unwrap(),
+            Some((b"3".to_vec(), None))
+        );
+        assert!(!kv.update(b"expired", b"4").unwrap());
+
+        let keys = |kv: &mut KVStore| -> Vec<ByteString> {
+            kv.iter().map(|pair| pair.unwrap().0).collect()
+        };
+        assert_eq!(keys(&mut kv), vec![b"forever".to_vec(), b"live".to_vec()]);
+        kv.close().unwrap();
+
+        // the expiry times are kept in the hints as well as the log
+        let mut kv = open_in(&dir);
+        assert_eq!(kv.get(b"expired").unwrap(), None);
+        assert!(kv.get_with_ttl(b"live").unwrap().unwrap().1.is_some());
+        kv.load().unwrap();
+        assert_eq!(kv.get(b"expired").unwrap(), None);
+        assert_eq!(keys(&mut kv), vec![b"forever".to_vec(), b"live".to_vec()]);
+    }
+
+    fn log_size(dir: &TempDir) -> u64 {
+        segment::log_size(dir.path()).unwrap()
+    }
+
+    #[test]
+    fn compaction_reclaims_space() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_small(&dir);
+        for i in 0..20u8 {
+            kv.insert(&[i], b"old").unwrap();
+        }
+        for i in 0..20u8 {
+            match i % 4 {
+                0 => kv.insert(&[i], b"new").unwrap(),
+                1 => kv.delete(&[i]).unwrap(),
+                2 => kv.insert_with_ttl(&[i], b"gone", Duration::ZERO).unwrap(),
+                _ => {}
+            }
+        }
+        let mut reader = kv.reader();
+        assert_eq!(reader.get(&[0]).unwrap(), Some(b"new".to_vec()));
+
+        let expected = collect(kv.iter());
+        let before = log_size(&dir);
+        kv.compact().unwrap();
+
+        assert!(log_size(&dir) < before / 2);
+        assert_eq!(reader.log_size().unwrap(), log_size(&dir));
+        assert_eq!(kv.len(), 10);
+        assert_eq!(collect(kv.iter()), expected);
+        // readers which had the old segments open carry on from the new ones
+        assert_eq!(reader.get(&[0]).unwrap(), Some(b"new".to_vec()));
+        assert_eq!(reader.get(&[3]).unwrap(), Some(b"old".to_vec()));
+        kv.close().unwrap();
+
+        let mut kv = open_small(&dir);
+        assert_eq!(collect(kv.iter()), expected);
+        kv.load().unwrap();
+        assert_eq!(collect(kv.iter()), expected);
+        assert_eq!(kv.len(), 10);
+    }
+
+    #[test]
+    fn interrupted_compaction_keeps_values() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_small(&dir);
+        for i in 0..10u8 {
+            kv.insert(&[i], b"old").unwrap();
+        }
+        for i in 0..10u8 {
+            kv.delete(&[i]).unwrap();
+        }
+        kv.insert(&[0], b"new").unwrap();
+        kv.close().unwrap();
+
+        // save the segments before compaction, and put the oldest ones back afterwards, as if the
+        // compaction had crashed part-way through removing them
+        let saved: Vec<_> = segment::list_segments(dir.path())
+            .unwrap()
+            .into_iter()
+            .map(|id| (id, fs::read(segment_path(dir.path(), id)).unwrap()))
+            .collect();
+
+        let mut kv = open_small(&dir);
+        kv.compact().unwrap();
+        kv.close().unwrap();
+        for (id, contents) in &saved[saved.len() / 2..] {
+            fs::write(segment_path(dir.path(), *id), contents).unwrap();
+        }
+
+        let mut kv = open_small(&dir);
+        assert_eq!(kv.len(), 1);
+        assert_eq!(kv.get(&[0]).unwrap(), Some(b"new".to_vec()));
+        for i in 1..10u8 {
+            assert_eq!(kv.get(&[i]).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn snapshot_is_taken_while_writing() {
+        let dir = TempDir::new().unwrap();
+        let backup = TempDir::new().unwrap();
+
+        let mut kv = open_small(&dir);
+        for i in 0..10u8 {
+            kv.insert(&[i], b"old").unwrap();
+        }
+        kv.insert_with_ttl(b"ttl", b"1", Duration::from_secs(3600))
+            .unwrap();
+        let expected = collect(kv.iter());
+
+        let checkpoint = kv.checkpoint().unwrap();
+        for i in 0..10u8 {
+            kv.insert(&[i], b"new").unwrap();
+        }
+        kv.compact().unwrap();
+        checkpoint.write_to(backup.path()).unwrap();
+
+        assert!(Checkpoint::open(backup.path())
+            .unwrap()
+            .verify()
+            .unwrap()
+            .is_ok());
+        let mut copy = open_small(&backup);
+        assert_eq!(collect(copy.iter()), expected);
+        assert!(copy.get_with_ttl(b"ttl").unwrap().unwrap().1.is_some());
+
+        // the snapshot doesn't overwrite another store
+        assert!(kv.snapshot(backup.path()).is_err());
+    }
+
+    #[test]
+    fn verify_reports_damaged_records() {
+        let dir = TempDir::new().unwrap();
+
+        let mut kv = open_in(&dir);
+        for i in 0..4u8 {
+            kv.insert(&[i], b"value").unwrap();
+        }
+        assert_eq!(
+            kv.verify().unwrap(),
+            Report {
+                segments: 1,
+                records: 4,
+                corruptions: vec![],
+            }
+        );
+        kv.close().unwrap();
+
+        // flip a byte in the values of the second and third records, and tear the fourth
+        let record_len = HEADER_LEN + 1 + 5;
+        let path = segment_path(dir.path(), 0);
+        let mut log = fs::read(&path).unwrap();
+        for i in 1..3 {
+            log[(SEGMENT_START + record_len * (i + 1)) as usize - 1] ^= 1;
+        }
+        log.truncate(log.len() - 1);
+        fs::write(&path, log).unwrap();
+
+        let report = Checkpoint::open(dir.path()).unwrap().verify().unwrap();
+        assert_eq!(report.records, 1);
+        let damaged: Vec<_> = report
+            .corruptions
+            .iter()
+            .map(|c| (c.offset, matches!(c.problem, Problem::Checksum { .. })))
+            .collect();
+        assert_eq!(
+            damaged,
+            vec![
+                (SEGMENT_START + record_len, true),
+                (SEGMENT_START + record_len * 2, true),
+                (SEGMENT_START + record_len * 3, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn restore_replaces_contents() {
+        let dir = TempDir::new().unwrap();
+        let backup = TempDir::new().unwrap();
+
+        let mut kv = open_in(&dir);
+        kv.insert(b"a", b"1").unwrap();
+        kv.insert_with_ttl(b"b", b"2", Duration::from_secs(3600))
+            .unwrap();
+        kv.snapshot(backup.path()).unwrap();
+        let expected = collect(kv.iter());
+
+        kv.delete(b"a").unwrap();
+        kv.insert(b"b", b"3").unwrap();
+        kv.insert(b"c", b"4").unwrap();
+        let mut reader = kv.reader();
+
+        kv.restore(backup.path()).unwrap();
+        assert_eq!(collect(reader.iter()), expected);
+        assert!(kv.get_with_ttl(b"b").unwrap().unwrap().1.is_some());
+        kv.close().unwrap();
+
+        let mut kv = open_in(&dir);
+        assert_eq!(collect(kv.iter()), expected);
+        kv.load().unwrap();
+        assert_eq!(collect(kv.iter()), expected);
+    }
+
+    #[test]
+    fn restore_rejects_damaged_snapshots() {
+        let dir = TempDir::new().unwrap();
+        let backup = TempDir::new().unwrap();
+
+        let mut kv = open_in(&dir);
+        kv.insert(b"a", b"1").unwrap();
+        kv.insert(b"b", b"2").unwrap();
+        kv.snapshot(backup.path()).unwrap();
+
+        let path = segment_path(backup.path(), 0);
+        let mut log = fs::read(&path).unwrap();
+        log[SEGMENT_START as usize + HEADER_LEN as usize] ^= 1;
+        fs::write(&path, log).unwrap();
+
+        kv.insert(b"c", b"3").unwrap();
+        assert!(kv.restore(backup.path()).is_err());
+        assert_eq!(kv.len(), 3);
+    }
+
+    /// Copies what has been written to `leader` since `from` to `replica`
+    fn replicate(leader: &KVStore, replica: &mut KVStore, from: Option<LogPosition>) -> LogChunk {
+        let chunk = leader.reader().read_log(from, 64).unwrap();
+        if chunk.reset {
+            replica.clear().unwrap();
+        }
+        replica.apply_log(&chunk.records).unwrap();
+        chunk
+    }
+
+    #[test]
+    fn replicas_follow_the_log() {
+        let leader_dir = TempDir::new().unwrap();
+        let replica_dir = TempDir::new().unwrap();
+
+        let mut leader = open_small(&leader_dir);
+        let mut replica = open_in(&replica_dir);
+        replica.insert(b"stale", b"0").unwrap();
+
+        for i in 0..10u8 {
+            leader.insert(&[i], b"1").unwrap();
+        }
+        leader
+            .write_batch(&[Op::Delete { key: vec![0] }, Op::Delete { key: vec![1] }])
+            .unwrap();
+
+        // read the log a chunk at a time until the replica has caught up
+        let mut chunk = replicate(&leader, &mut replica, None);
+        assert!(chunk.reset);
+        while !chunk.records.is_empty() {
+            chunk = replicate(&leader, &mut replica, Some(chunk.next));
+            assert!(!chunk.reset);
+        }
+        assert_eq!(collect(replica.iter()), collect(leader.iter()));
+        assert_eq!(leader.reader().log_len_after(chunk.next).unwrap(), 0);
+
+        leader.insert(b"new", b"2").unwrap();
+        assert!(leader.reader().log_len_after(chunk.next).unwrap() > 0);
+        let caught_up = replicate(&leader, &mut replica, Some(chunk.next));
+        assert_eq!(replica.get(b"new").unwrap(), Some(b"2".to_vec()));
+
+        // once the position has been compacted away, the replica starts over
+        leader.compact().unwrap();
+        leader.delete(b"new").unwrap();
+        let mut chunk = replicate(&leader, &mut replica, Some(chunk.next));
+        assert!(chunk.reset);
+        while !chunk.records.is_empty() {
+            chunk = replicate(&leader, &mut replica, Some(chunk.next));
+        }
+        assert_eq!(collect(replica.iter()), collect(leader.iter()));
+        assert!(caught_up.next < chunk.next);
     }
 }
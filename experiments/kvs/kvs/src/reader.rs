@@ -0,0 +1,150 @@
+use std::io;
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::replication::{self, LogChunk, LogPosition};
+use crate::segment::{self, Readers};
+use crate::{prefix_end, ttl, ByteStr, ByteString, Error, Index, IndexEntry, Iter, Result};
+
+/// Reads from a [`crate::KVStore`] while it is being written to.
+///
+/// A reader shares the store's index, and opens file handles of its own. Readers on different
+/// threads don't wait for each other, and only wait for the writer while it updates the index.
+/// Cloning a reader gives the clone its own file handles.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> kvs::Result<()> {
+/// let mut store = kvs::KVStore::open("data")?;
+/// let mut reader = store.reader();
+///
+/// let handle = std::thread::spawn(move || reader.get(b"key"));
+/// store.insert(b"other", b"value")?;
+/// println!("{:?}", handle.join().unwrap()?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct KVReader {
+    pub(crate) index: Arc<RwLock<Index>>,
+    pub(crate) readers: Readers,
+}
+
+impl KVReader {
+    /// The number of keys in the store
+    pub fn len(&self) -> usize {
+        self.index.read().unwrap().len()
+    }
+
+    /// Whether the store has no keys
+    pub fn is_empty(&self) -> bool {
+        self.index.read().unwrap().is_empty()
+    }
+
+    /// Returns the value of `key`, or `None` if it doesn't exist or has expired
+    pub fn get(&mut self, key: &ByteStr) -> Result<Option<ByteString>> {
+        Ok(self.get_with_ttl(key)?.map(|(value, _)| value))
+    }
+
+    /// Returns the value of `key` along with how long it has left to live, or `None` for the latter
+    /// if it never expires
+    pub fn get_with_ttl(
+        &mut self,
+        key: &ByteStr,
+    ) -> Result<Option<(ByteString, Option<Duration>)>> {
+        let now = ttl::now();
+        let entry = match self.index.read().unwrap().get(key) {
+            Some(entry) if !entry.is_expired(now) => *entry,
+            _ => return Ok(None),
+        };
+
+        let value = read(&self.index, &mut self.readers, key, entry)?;
+        Ok(value.map(|value| (value, ttl::remaining(entry.expires_at, now))))
+    }
+
+    /// Iterates over every key and its value, in key order
+    pub fn iter(&mut self) -> Iter<'_> {
+        self.scan::<ByteString, _>(..)
+    }
+
+    /// Iterates over the keys in `range` and their values, in key order, skipping expired keys
+    pub fn scan<T, R>(&mut self, range: R) -> Iter<'_>
+    where
+        T: Ord + ?Sized + ToOwned<Owned = ByteString>,
+        R: RangeBounds<T>,
+    {
+        Iter {
+            index: &self.index,
+            readers: &mut self.readers,
+            front: to_owned(range.start_bound()),
+            back: to_owned(range.end_bound()),
+            now: ttl::now(),
+        }
+    }
+
+    /// Iterates over the keys which start with `prefix` and their values, in key order
+    pub fn prefix(&mut self, prefix: &ByteStr) -> Iter<'_> {
+        let start = Bound::Included(prefix);
+        match prefix_end(prefix) {
+            Some(end) => self.scan::<ByteStr, _>((start, Bound::Excluded(&end[..]))),
+            None => self.scan::<ByteStr, _>((start, Bound::Unbounded)),
+        }
+    }
+}
+
+impl KVReader {
+    /// Reads the records written to the log after `from`, so that they can be applied to a replica
+    /// with [`crate::KVStore::apply_log`].
+    ///
+    /// Reading starts over from the beginning of the log when `from` is `None`, or has been
+    /// compacted away, which the chunk reports with [`LogChunk::reset`]. An empty chunk means the
+    /// reader has caught up with the writer.
+    pub fn read_log(&self, from: Option<LogPosition>, max_len: usize) -> Result<LogChunk> {
+        replication::read_log(self.readers.dir(), from, max_len)
+    }
+
+    /// The number of bytes which have been written to the log after `position`, which is how far
+    /// behind a replica that has applied the log up to there is
+    pub fn log_len_after(&self, position: LogPosition) -> Result<u64> {
+        replication::log_len_after(self.readers.dir(), position)
+    }
+
+    /// The number of bytes the log takes up on disk, including what compaction would reclaim
+    pub fn log_size(&self) -> Result<u64> {
+        Ok(segment::log_size(self.readers.dir())?)
+    }
+}
+
+fn to_owned<T: ToOwned<Owned = ByteString> + ?Sized>(bound: Bound<&T>) -> Bound<ByteString> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.to_owned()),
+        Bound::Excluded(key) => Bound::Excluded(key.to_owned()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Reads the value of `key` from the record its index entry points to.
+///
+/// If compaction has removed the record's segment in the meantime, the key is looked up again,
+/// and `None` is returned if it has gone.
+pub(crate) fn read(
+    index: &RwLock<Index>,
+    readers: &mut Readers,
+    key: &ByteStr,
+    mut entry: IndexEntry,
+) -> Result<Option<ByteString>> {
+    loop {
+        match readers.read(key, entry) {
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+                match index.read().unwrap().get(key) {
+                    Some(moved) if moved.file_id != entry.file_id => entry = *moved,
+                    Some(_) => return Err(e.into()),
+                    None => return Ok(None),
+                }
+            }
+            value => return value.map(Some),
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::io;
+use std::io::prelude::*;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crc::{Crc, CRC_31_PHILIPS};
+
+use crate::ByteString;
+
+pub(crate) const CRC_U32: Crc<u32> = Crc::<u32>::new(&CRC_31_PHILIPS);
+
+/// The size of a record's header: `[crc, kind, expires_at, key_len, val_len]`
+pub(crate) const HEADER_LEN: u64 = 21;
+
+/// What a record in the log represents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    /// The key was set to the record's value
+    Put = 0,
+    /// The key was deleted, the record has no value
+    Delete = 1,
+    /// Several changes which are applied together, the record has no key and its value holds the
+    /// changes, see [`crate::batch::encode`]
+    Batch = 2,
+}
+
+impl Kind {
+    pub(crate) fn from_u8(byte: u8) -> Option<Kind> {
+        match byte {
+            0 => Some(Kind::Put),
+            1 => Some(Kind::Delete),
+            2 => Some(Kind::Batch),
+            _ => None,
+        }
+    }
+}
+
+/// A record which has been read back from the log
+#[derive(Debug)]
+pub(crate) struct Entry {
+    pub(crate) kind: Kind,
+    /// When the key expires, see [`crate::ttl`]
+    pub(crate) expires_at: u64,
+    pub(crate) key: ByteString,
+    pub(crate) value: ByteString,
+}
+
+impl Entry {
+    /// The number of bytes this record occupies in the log
+    pub(crate) fn record_len(&self) -> u64 {
+        HEADER_LEN + self.key.len() as u64 + self.value.len() as u64
+    }
+}
+
+/// The outcome of reading a single record from the log
+pub(crate) enum Record {
+    /// A complete record whose checksum matches its contents
+    Valid(Entry),
+    /// A complete record whose checksum does not match its contents
+    Corrupt { expected: u32, found: u32, len: u64 },
+    /// The log ends part-way through the record
+    Torn,
+    /// The log ends cleanly before the record
+    End,
+}
+
+/// Encodes a record as a variable-sized byte buffer.
+///
+/// The format on disk is as follows:
+/// `[crc, kind, expires_at, key_len, val_len, key, value]`
+///
+/// Where crc, kind, expires_at, key_len, and val_len all have known sizes, and key and value are
+/// variable-sized. Since the size of the first five values is known, we can use them to find the end
+/// of the record.
+///
+/// The crc is a 32-bit checksum of everything that follows it, and is used to detect data corruption.
+pub(crate) fn encode(kind: Kind, expires_at: u64, key: &[u8], value: &[u8]) -> ByteString {
+    let mut record = ByteString::with_capacity(HEADER_LEN as usize + key.len() + value.len());
+
+    // Leave room for the checksum, which is filled in last
+    record.extend_from_slice(&[0; 4]);
+    record.push(kind as u8);
+    record.extend_from_slice(&expires_at.to_le_bytes());
+    record.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    record.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    record.extend_from_slice(key);
+    record.extend_from_slice(value);
+
+    let checksum = CRC_U32.checksum(&record[4..]);
+    (&mut record[..4])
+        .write_u32::<LittleEndian>(checksum)
+        .expect("the buffer has room for the checksum");
+
+    record
+}
+
+/// Reads the record at the reader's current position
+pub(crate) fn read_record<R: Read>(f: &mut R) -> io::Result<Record> {
+    let mut header = [0u8; HEADER_LEN as usize];
+    match read_up_to(f, &mut header)? {
+        0 => return Ok(Record::End),
+        n if n < header.len() => return Ok(Record::Torn),
+        _ => {}
+    }
+
+    let mut fields = &header[..];
+    let saved_checksum = fields.read_u32::<LittleEndian>()?;
+    let kind = fields.read_u8()?;
+    let expires_at = fields.read_u64::<LittleEndian>()?;
+    let key_len = fields.read_u32::<LittleEndian>()?;
+    let val_len = fields.read_u32::<LittleEndian>()?;
+    let data_len = key_len as u64 + val_len as u64;
+
+    let mut data = ByteString::new();
+    f.by_ref().take(data_len).read_to_end(&mut data)?;
+
+    if (data.len() as u64) < data_len {
+        return Ok(Record::Torn);
+    }
+
+    let mut digest = CRC_U32.digest();
+    digest.update(&header[4..]);
+    digest.update(&data);
+    let checksum = digest.finalize();
+
+    let kind = match Kind::from_u8(kind) {
+        Some(kind) if checksum == saved_checksum => kind,
+        _ => {
+            return Ok(Record::Corrupt {
+                expected: saved_checksum,
+                found: checksum,
+                len: HEADER_LEN + data_len,
+            })
+        }
+    };
+
+    let value = data.split_off(key_len as usize);
+    let key = data;
+
+    Ok(Record::Valid(Entry {
+        kind,
+        expires_at,
+        key,
+        value,
+    }))
+}
+
+/// Fills as much of `buf` as possible, returning the number of bytes read
+pub(crate) fn read_up_to<R: Read>(f: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match f.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc_u32_test() {
+        let data = b"hello world";
+
+        let a = CRC_U32.checksum(data);
+        let b = CRC_U32.checksum(data);
+
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn round_trip() {
+        let record = encode(Kind::Put, 1234, b"key", b"value");
+        assert_eq!(record.len() as u64, HEADER_LEN + 8);
+
+        match read_record(&mut &record[..]).unwrap() {
+            Record::Valid(entry) => {
+                assert_eq!(entry.kind, Kind::Put);
+                assert_eq!(entry.expires_at, 1234);
+                assert_eq!(entry.key, b"key");
+                assert_eq!(entry.value, b"value");
+                assert_eq!(entry.record_len(), record.len() as u64);
+            }
+            _ => panic!("expected a valid record"),
+        }
+    }
+
+    #[test]
+    fn detects_damage() {
+        let record = encode(Kind::Delete, 0, b"key", b"");
+
+        assert!(matches!(
+            read_record(&mut &record[..record.len() - 1]).unwrap(),
+            Record::Torn
+        ));
+
+        // the checksum covers the header as well as the data
+        let mut corrupt = record.clone();
+        corrupt[4] = Kind::Put as u8;
+        assert!(matches!(
+            read_record(&mut &corrupt[..]).unwrap(),
+            Record::Corrupt { .. }
+        ));
+
+        assert!(matches!(read_record(&mut &[][..]).unwrap(), Record::End));
+    }
+}
@@ -0,0 +1,130 @@
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
+use std::path::Path;
+
+use crate::record::{self, read_record, Record};
+use crate::segment::{self, segment_path};
+use crate::{ByteString, Error, Result, SEGMENT_START};
+
+/// A position in the log of a store, just after a record or at the start of a segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LogPosition {
+    pub segment: u32,
+    pub offset: u64,
+}
+
+/// A run of records read from the log by [`crate::KVReader::read_log`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogChunk {
+    /// Whether the records start over from the beginning of the log, because the position they
+    /// were asked for has been compacted away. A replica must drop every key it has before it
+    /// applies them.
+    pub reset: bool,
+    /// The records, as they are laid out in the log
+    pub records: ByteString,
+    /// The position to read the next chunk from
+    pub next: LogPosition,
+}
+
+/// Reads whole records from the log in `dir`, starting at `from`, until there are about `max_len`
+/// bytes of them or the end of the log is reached.
+///
+/// The log can be appended to in the meantime, so a record which is only partly written yet is left
+/// for the next call.
+pub(crate) fn read_log(dir: &Path, from: Option<LogPosition>, max_len: usize) -> Result<LogChunk> {
+    let segments = segment::list_segments(dir)?;
+    let start = LogPosition {
+        segment: segments.first().copied().unwrap_or(0),
+        offset: SEGMENT_START,
+    };
+    let (mut position, reset) = match from {
+        Some(from) if from.segment >= start.segment => (from, false),
+        _ => (start, true),
+    };
+
+    let mut records = ByteString::new();
+    loop {
+        let file = match File::open(segment_path(dir, position.segment)) {
+            Ok(file) => file,
+            // Compaction removed the segment since it was listed
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !reset => {
+                return read_log(dir, None, max_len)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e.into()),
+        };
+        let mut f = BufReader::new(file);
+        f.seek(SeekFrom::Start(position.offset))?;
+
+        loop {
+            if records.len() >= max_len {
+                return Ok(LogChunk {
+                    reset,
+                    records,
+                    next: position,
+                });
+            }
+
+            match read_record(&mut f)? {
+                Record::Valid(entry) => {
+                    position.offset += entry.record_len();
+                    records.extend_from_slice(&record::encode(
+                        entry.kind,
+                        entry.expires_at,
+                        &entry.key,
+                        &entry.value,
+                    ));
+                }
+                Record::Corrupt {
+                    expected, found, ..
+                } => {
+                    return Err(Error::Corruption {
+                        file_id: position.segment,
+                        offset: position.offset,
+                        expected,
+                        found,
+                    })
+                }
+                // The segment was sealed if there is a later one, so it has nothing left to read
+                Record::Torn | Record::End => break,
+            }
+        }
+
+        match segments.iter().find(|&&id| id > position.segment) {
+            Some(&next) => {
+                position = LogPosition {
+                    segment: next,
+                    offset: SEGMENT_START,
+                }
+            }
+            None => break,
+        }
+    }
+
+    Ok(LogChunk {
+        reset,
+        records,
+        next: position,
+    })
+}
+
+/// The number of bytes in the log in `dir` after `position`
+pub(crate) fn log_len_after(dir: &Path, position: LogPosition) -> Result<u64> {
+    let mut len = 0;
+    for id in segment::list_segments(dir)? {
+        let from = match id {
+            id if id < position.segment => continue,
+            id if id == position.segment => position.offset,
+            _ => SEGMENT_START,
+        };
+        let size = match std::fs::metadata(segment_path(dir, id)) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        len += size.saturating_sub(from);
+    }
+    Ok(len)
+}
@@ -0,0 +1,499 @@
+use std::collections::{hash_map, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::record::{read_record, read_up_to, Kind, Record, CRC_U32};
+use crate::snapshot::{Corruption, Problem, Report};
+use crate::{batch, ByteStr, ByteString, Error, IndexEntry, Result};
+
+/// Every segment starts with this header, which identifies the format of the records inside it
+pub(crate) const SEGMENT_MAGIC: &[u8; 4] = b"kvs\x03";
+/// Every hint file starts with this header
+const HINT_MAGIC: &[u8; 4] = b"kvh\x03";
+
+/// Describes where a record was written to a segment, without its value.
+///
+/// The index can be rebuilt from a segment's hints without reading the segment itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Hint {
+    pub(crate) kind: Kind,
+    pub(crate) key: ByteString,
+    pub(crate) offset: u64,
+    pub(crate) len: u64,
+    pub(crate) expires_at: u64,
+}
+
+/// The records read from a segment by [`scan`]
+pub(crate) struct Scan {
+    pub(crate) hints: Vec<Hint>,
+    /// The position just after the last intact record
+    pub(crate) end: u64,
+    /// Whether the segment has a torn or corrupted record after `end`
+    pub(crate) damaged: bool,
+}
+
+pub(crate) fn segment_path(dir: &Path, id: u32) -> PathBuf {
+    dir.join(format!("{:010}.log", id))
+}
+
+pub(crate) fn hint_path(dir: &Path, id: u32) -> PathBuf {
+    dir.join(format!("{:010}.hint", id))
+}
+
+/// Lists the ids of the segments in `dir`, in the order they were written
+pub(crate) fn list_segments(dir: &Path) -> io::Result<Vec<u32>> {
+    let mut ids = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("log") {
+            continue;
+        }
+
+        if let Some(id) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse().ok())
+        {
+            ids.push(id);
+        }
+    }
+
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+/// The total size in bytes of the segments in `dir`
+pub(crate) fn log_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for id in list_segments(dir)? {
+        match fs::metadata(segment_path(dir, id)) {
+            Ok(metadata) => size += metadata.len(),
+            // Compaction removed the segment since it was listed
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(size)
+}
+
+/// Opens a segment for reading and writing, creating it if it doesn't exist
+pub(crate) fn open_segment(dir: &Path, id: u32) -> Result<File> {
+    let path = segment_path(dir, id);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+
+    let mut magic = [0u8; SEGMENT_MAGIC.len()];
+    let n = read_up_to(&mut file, &mut magic)?;
+
+    if n < magic.len() && magic[..n] == SEGMENT_MAGIC[..n] {
+        // A new segment, or one whose header was torn by a crash
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(SEGMENT_MAGIC)?;
+        file.sync_all()?;
+    } else if magic != *SEGMENT_MAGIC {
+        return Err(Error::UnknownFormat(path));
+    }
+
+    Ok(file)
+}
+
+/// Opens a segment for reading only, such as one in a snapshot
+pub(crate) fn open_sealed(dir: &Path, id: u32) -> Result<File> {
+    let path = segment_path(dir, id);
+    let mut file = File::open(&path)?;
+
+    let mut magic = [0u8; SEGMENT_MAGIC.len()];
+    if read_up_to(&mut file, &mut magic)? < magic.len() || magic != *SEGMENT_MAGIC {
+        return Err(Error::UnknownFormat(path));
+    }
+    Ok(file)
+}
+
+/// Reads the records in a segment, starting at `from`.
+///
+/// A torn or corrupted record at the end of the segment is reported in the result, while a
+/// corrupted record anywhere else is an error.
+pub(crate) fn scan(file: &File, id: u32, from: u64) -> Result<Scan> {
+    let file_len = file.metadata()?.len();
+    let mut f = BufReader::new(file);
+    let mut position = f.seek(SeekFrom::Start(from))?;
+    let mut hints = Vec::new();
+
+    loop {
+        let damaged = match read_record(&mut f)? {
+            Record::Valid(entry) => {
+                let len = entry.record_len();
+                let ops = match entry.kind {
+                    // Every change in a batch points back to the batch's record
+                    Kind::Batch => batch::decode(&entry.value)?,
+                    Kind::Put | Kind::Delete => vec![entry],
+                };
+                for op in ops {
+                    hints.push(Hint {
+                        kind: op.kind,
+                        key: op.key,
+                        offset: position,
+                        len,
+                        expires_at: op.expires_at,
+                    });
+                }
+                position += len;
+                continue;
+            }
+            Record::End => false,
+            Record::Torn => true,
+            Record::Corrupt { len, .. } if position + len == file_len => true,
+            Record::Corrupt {
+                expected, found, ..
+            } => {
+                return Err(Error::Corruption {
+                    file_id: id,
+                    offset: position,
+                    expected,
+                    found,
+                })
+            }
+        };
+
+        return Ok(Scan {
+            hints,
+            end: position,
+            damaged,
+        });
+    }
+}
+
+/// Reads every record in the first `len` bytes of a segment, adding them to `report` along with
+/// any which are damaged.
+///
+/// Unlike [`scan`], this carries on past a corrupted record, trusting the lengths in its header.
+pub(crate) fn verify(file: &File, id: u32, len: u64, report: &mut Report) -> Result<()> {
+    let mut f = file;
+    let mut position = f.seek(SeekFrom::Start(SEGMENT_MAGIC.len() as u64))?;
+    let mut f = BufReader::new(f.take(len.saturating_sub(position)));
+
+    loop {
+        let offset = position;
+        let problem = match read_record(&mut f)? {
+            Record::Valid(entry) => {
+                report.records += 1;
+                position += entry.record_len();
+                if entry.kind != Kind::Batch || batch::decode(&entry.value).is_ok() {
+                    continue;
+                }
+                Problem::MalformedBatch
+            }
+            Record::Corrupt {
+                expected,
+                found,
+                len,
+            } => {
+                // The rest of the segment can still be read, as long as the lengths are intact
+                position += len;
+                Problem::Checksum { expected, found }
+            }
+            Record::Torn => Problem::Truncated,
+            Record::End => break,
+        };
+
+        let truncated = problem == Problem::Truncated;
+        report.corruptions.push(Corruption {
+            file_id: id,
+            offset,
+            problem,
+        });
+        if truncated {
+            break;
+        }
+    }
+
+    report.segments += 1;
+    Ok(())
+}
+
+/// Writes the hint file for a segment, covering its first `segment_len` bytes.
+///
+/// Only the last hint for each key is kept, since it supersedes the earlier ones. The file is
+/// written to a temporary path and renamed into place, so a crash never leaves a partial hint file.
+pub(crate) fn write_hints(dir: &Path, id: u32, segment_len: u64, hints: &[Hint]) -> io::Result<()> {
+    let mut last = HashMap::with_capacity(hints.len());
+    for (i, hint) in hints.iter().enumerate() {
+        last.insert(&hint.key, i);
+    }
+
+    let mut buf = ByteString::new();
+    buf.extend_from_slice(HINT_MAGIC);
+    buf.write_u64::<LittleEndian>(segment_len)?;
+    buf.write_u64::<LittleEndian>(last.len() as u64)?;
+
+    for (i, hint) in hints.iter().enumerate() {
+        if last[&hint.key] != i {
+            continue;
+        }
+
+        buf.write_u8(hint.kind as u8)?;
+        buf.write_u32::<LittleEndian>(hint.key.len() as u32)?;
+        buf.write_u64::<LittleEndian>(hint.offset)?;
+        buf.write_u64::<LittleEndian>(hint.len)?;
+        buf.write_u64::<LittleEndian>(hint.expires_at)?;
+        buf.extend_from_slice(&hint.key);
+    }
+
+    let checksum = CRC_U32.checksum(&buf);
+    buf.write_u32::<LittleEndian>(checksum)?;
+
+    let path = hint_path(dir, id);
+    let tmp = path.with_extension("hint.tmp");
+    let mut f = File::create(&tmp)?;
+    f.write_all(&buf)?;
+    f.sync_all()?;
+    fs::rename(&tmp, &path)?;
+    sync_dir(dir)
+}
+
+/// Reads the hint file for a segment, returning the length of the segment it covers alongside it.
+///
+/// Returns `None` if there is no hint file, or it can't be trusted.
+pub(crate) fn read_hints(dir: &Path, id: u32) -> Option<(u64, Vec<Hint>)> {
+    let buf = fs::read(hint_path(dir, id)).ok()?;
+
+    let (contents, checksum) = buf.split_at(buf.len().checked_sub(4)?);
+    if !contents.starts_with(HINT_MAGIC)
+        || CRC_U32.checksum(contents) != (&checksum[..]).read_u32::<LittleEndian>().ok()?
+    {
+        return None;
+    }
+
+    let mut f = &contents[HINT_MAGIC.len()..];
+    let segment_len = f.read_u64::<LittleEndian>().ok()?;
+    let count = f.read_u64::<LittleEndian>().ok()?;
+
+    let mut hints = Vec::with_capacity(count.min(1 << 20) as usize);
+    for _ in 0..count {
+        let kind = Kind::from_u8(f.read_u8().ok()?)?;
+        let key_len = f.read_u32::<LittleEndian>().ok()? as usize;
+        let offset = f.read_u64::<LittleEndian>().ok()?;
+        let len = f.read_u64::<LittleEndian>().ok()?;
+        let expires_at = f.read_u64::<LittleEndian>().ok()?;
+        if f.len() < key_len {
+            return None;
+        }
+        let (key, rest) = f.split_at(key_len);
+        f = rest;
+
+        hints.push(Hint {
+            kind,
+            key: key.to_vec(),
+            offset,
+            len,
+            expires_at,
+        });
+    }
+
+    Some((segment_len, hints))
+}
+
+/// Reads records from the segments, keeping a handle open to each segment it has read from
+#[derive(Debug)]
+pub(crate) struct Readers {
+    dir: PathBuf,
+    files: HashMap<u32, File>,
+    /// The id of the oldest segment which hasn't been removed by compaction, shared by every reader
+    oldest: Arc<AtomicU32>,
+}
+
+impl Readers {
+    pub(crate) fn new(dir: &Path, oldest: Arc<AtomicU32>) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            files: HashMap::new(),
+            oldest,
+        }
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Records that the segments before `id` have been removed, so that every reader lets go of them
+    pub(crate) fn remove_before(&self, id: u32) {
+        self.oldest.store(id, Ordering::Release);
+    }
+
+    /// Reads the value of `key` from the record which its index entry points to
+    pub(crate) fn read(&mut self, key: &ByteStr, entry: IndexEntry) -> Result<ByteString> {
+        // Let go of segments which were compacted away, so their space can be reclaimed
+        let oldest = self.oldest.load(Ordering::Acquire);
+        self.files.retain(|&id, _| id >= oldest);
+
+        let file = match self.files.entry(entry.file_id) {
+            hash_map::Entry::Occupied(file) => file.into_mut(),
+            hash_map::Entry::Vacant(file) => {
+                file.insert(File::open(segment_path(&self.dir, entry.file_id))?)
+            }
+        };
+
+        // The index knows the length of the whole record, so it can be read in one go
+        let mut buf = vec![0; entry.len as usize];
+        file.seek(SeekFrom::Start(entry.offset))?;
+        file.read_exact(&mut buf)?;
+
+        let record = match read_record(&mut &buf[..])? {
+            Record::Valid(record) => record,
+            Record::Corrupt {
+                expected, found, ..
+            } => {
+                return Err(Error::Corruption {
+                    file_id: entry.file_id,
+                    offset: entry.offset,
+                    expected,
+                    found,
+                })
+            }
+            Record::Torn | Record::End => {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+            }
+        };
+
+        let value = match record.kind {
+            Kind::Put => Some(record.value),
+            // The last change to the key in the batch is the one which took effect
+            Kind::Batch => batch::decode(&record.value)?
+                .into_iter()
+                .rev()
+                .find(|op| op.key == key)
+                .filter(|op| op.kind == Kind::Put)
+                .map(|op| op.value),
+            Kind::Delete => None,
+        };
+
+        value.ok_or_else(|| {
+            let message = "the index points to a record which doesn't set the key";
+            io::Error::new(io::ErrorKind::InvalidData, message).into()
+        })
+    }
+}
+
+impl Clone for Readers {
+    /// Returns readers for the same directory which open files of their own, so that they can be
+    /// used at the same time as these ones
+    fn clone(&self) -> Self {
+        Self::new(&self.dir, self.oldest.clone())
+    }
+}
+
+/// Makes changes to the entries of a directory durable, such as newly created or renamed files
+pub(crate) fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::{encode, HEADER_LEN};
+    use tempfile::TempDir;
+
+    #[test]
+    fn hints_round_trip() {
+        let dir = TempDir::new().unwrap();
+
+        let hints = vec![
+            Hint {
+                kind: Kind::Put,
+                key: b"a".to_vec(),
+                offset: 4,
+                len: 23,
+                expires_at: 0,
+            },
+            Hint {
+                kind: Kind::Put,
+                key: b"b".to_vec(),
+                offset: 27,
+                len: 23,
+                expires_at: 1234,
+            },
+            Hint {
+                kind: Kind::Delete,
+                key: b"a".to_vec(),
+                offset: 50,
+                len: 22,
+                expires_at: 0,
+            },
+        ];
+        write_hints(dir.path(), 7, 72, &hints).unwrap();
+
+        // only the last hint for each key is kept
+        let (segment_len, read) = read_hints(dir.path(), 7).unwrap();
+        assert_eq!(segment_len, 72);
+        assert_eq!(read, hints[1..]);
+    }
+
+    #[test]
+    fn damaged_hints_are_ignored() {
+        let dir = TempDir::new().unwrap();
+        assert!(read_hints(dir.path(), 0).is_none());
+
+        write_hints(dir.path(), 0, 4, &[]).unwrap();
+        assert!(read_hints(dir.path(), 0).is_some());
+
+        let path = hint_path(dir.path(), 0);
+        let mut buf = fs::read(&path).unwrap();
+        buf[5] ^= 1;
+        fs::write(&path, buf).unwrap();
+        assert!(read_hints(dir.path(), 0).is_none());
+    }
+
+    #[test]
+    fn segments_are_listed_in_order() {
+        let dir = TempDir::new().unwrap();
+        for id in [10, 2, 1] {
+            open_segment(dir.path(), id).unwrap();
+        }
+        write_hints(dir.path(), 1, 4, &[]).unwrap();
+
+        assert_eq!(list_segments(dir.path()).unwrap(), vec![1, 2, 10]);
+    }
+
+    #[test]
+    fn older_formats_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        fs::write(segment_path(dir.path(), 0), b"kvs\x01").unwrap();
+
+        assert!(matches!(
+            open_segment(dir.path(), 0),
+            Err(Error::UnknownFormat(_))
+        ));
+    }
+
+    #[test]
+    fn scan_reports_damaged_tail() {
+        let dir = TempDir::new().unwrap();
+        let mut file = open_segment(dir.path(), 0).unwrap();
+        file.seek(SeekFrom::End(0)).unwrap();
+        file.write_all(&encode(Kind::Put, 0, b"a", b"1")).unwrap();
+        file.write_all(&encode(Kind::Put, 0, b"b", b"2")[..5])
+            .unwrap();
+
+        let scanned = scan(&file, 0, SEGMENT_MAGIC.len() as u64).unwrap();
+        assert_eq!(scanned.hints.len(), 1);
+        assert_eq!(scanned.end, SEGMENT_MAGIC.len() as u64 + HEADER_LEN + 2);
+        assert!(scanned.damaged);
+    }
+}
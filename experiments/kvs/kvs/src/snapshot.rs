@@ -0,0 +1,186 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+
+use crate::segment::{self, Readers};
+use crate::{apply, ttl, ByteString, Index, Result, SEGMENT_START};
+
+/// The segments which made up a store at a point in time, see [`crate::KVStore::checkpoint`].
+///
+/// A checkpoint holds a handle to each segment and remembers how long it was, so it keeps seeing
+/// the same records while the store goes on writing to the active segment, and after compaction has
+/// removed the segments from the directory.
+#[derive(Debug)]
+pub struct Checkpoint {
+    dir: PathBuf,
+    segments: Vec<(u32, File, u64)>,
+}
+
+impl Checkpoint {
+    /// Takes a checkpoint of the segments in `dir`, which must not be written to in the meantime.
+    ///
+    /// `active` is the id and length of the segment which is being appended to, if there is one.
+    pub(crate) fn take(dir: &Path, active: Option<(u32, u64)>) -> Result<Self> {
+        let mut segments = Vec::new();
+        for id in segment::list_segments(dir)? {
+            let file = segment::open_sealed(dir, id)?;
+            let len = match active {
+                Some((active_id, len)) if active_id == id => len,
+                _ => file.metadata()?.len(),
+            };
+            segments.push((id, file, len));
+        }
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            segments,
+        })
+    }
+
+    /// Opens the store in `dir` without locking it or changing it, such as a snapshot written by
+    /// [`Checkpoint::write_to`]
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        Self::take(dir.as_ref(), None)
+    }
+
+    /// Reads every record, reporting those which are damaged rather than stopping at the first one
+    pub fn verify(&self) -> Result<Report> {
+        let mut report = Report::default();
+        for (id, file, len) in &self.segments {
+            segment::verify(file, *id, *len, &mut report)?;
+        }
+        Ok(report)
+    }
+
+    /// Copies the segments into the directory `dest`, which is created if it doesn't exist, and
+    /// checks every record of the copy against its checksum.
+    ///
+    /// The copy is a store of its own, which can be opened or passed to
+    /// [`crate::KVStore::restore`]. If the copy fails, `dest` is left with part of it.
+    pub fn write_to<P: AsRef<Path>>(&self, dest: P) -> Result<()> {
+        let dest = dest.as_ref();
+        fs::create_dir_all(dest)?;
+        if !segment::list_segments(dest)?.is_empty() {
+            let message = format!("{} already holds a store", dest.display());
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, message).into());
+        }
+
+        for (id, file, len) in &self.segments {
+            let mut f = file;
+            f.seek(SeekFrom::Start(0))?;
+            let path = segment::segment_path(dest, *id);
+            let mut copy = File::create(&path)?;
+            io::copy(&mut f.take(*len), &mut copy)?;
+            copy.sync_all()?;
+
+            // Read the copy back rather than trusting it, and save its hints along the way
+            let scanned = segment::scan(&File::open(&path)?, *id, SEGMENT_START)?;
+            if scanned.damaged || scanned.end != *len {
+                let message = format!(
+                    "segment {} of the snapshot is damaged at offset {}",
+                    id, scanned.end
+                );
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+            }
+            segment::write_hints(dest, *id, scanned.end, &scanned.hints)?;
+        }
+
+        segment::sync_dir(dest)?;
+        Ok(())
+    }
+
+    /// Reads the live value of every key, along with when it expires.
+    ///
+    /// Fails if any of the records are damaged.
+    pub(crate) fn entries(&self) -> Result<Vec<(ByteString, u64, ByteString)>> {
+        let mut index = Index::new();
+        for (id, file, len) in &self.segments {
+            let scanned = segment::scan(file, *id, SEGMENT_START)?;
+            if scanned.damaged || scanned.end < *len {
+                let message = format!("segment {} is damaged at offset {}", id, scanned.end);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+            }
+            for hint in scanned.hints.iter().filter(|hint| hint.offset < *len) {
+                apply(&mut index, *id, hint);
+            }
+        }
+
+        let now = ttl::now();
+        let mut readers = Readers::new(&self.dir, Arc::new(AtomicU32::new(0)));
+        let mut entries = Vec::with_capacity(index.len());
+        for (key, entry) in index {
+            if entry.is_expired(now) {
+                continue;
+            }
+            let value = readers.read(&key, entry)?;
+            entries.push((key, entry.expires_at, value));
+        }
+        Ok(entries)
+    }
+}
+
+/// What [`Checkpoint::verify`] found
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// The number of segments which were read
+    pub segments: usize,
+    /// The number of records which were intact
+    pub records: u64,
+    /// Where each damaged record is
+    pub corruptions: Vec<Corruption>,
+}
+
+impl Report {
+    /// Whether every record was intact
+    pub fn is_ok(&self) -> bool {
+        self.corruptions.is_empty()
+    }
+}
+
+/// A damaged record, found by [`Checkpoint::verify`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Corruption {
+    /// The segment which holds the record
+    pub file_id: u32,
+    /// The position of the record in the segment
+    pub offset: u64,
+    pub problem: Problem,
+}
+
+/// What is wrong with a damaged record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    /// The record failed its checksum
+    Checksum { expected: u32, found: u32 },
+    /// The segment ends part-way through the record
+    Truncated,
+    /// The record passed its checksum, but the batch it holds can't be decoded
+    MalformedBatch,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Checksum { expected, found } => {
+                write!(f, "checksum mismatch ({:08x} != {:08x})", found, expected)
+            }
+            Problem::Truncated => write!(f, "the segment ends part-way through the record"),
+            Problem::MalformedBatch => write!(f, "malformed batch"),
+        }
+    }
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "segment {} at offset {}: {}",
+            self.file_id, self.offset, self.problem
+        )
+    }
+}
@@ -0,0 +1,29 @@
+//! Expiry times, which are kept in the log and the index as milliseconds since the Unix epoch
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The expiry time of a key which never expires
+pub(crate) const NEVER: u64 = 0;
+
+/// The current time, in milliseconds since the Unix epoch
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// The expiry time of a key which is set now, to live for `ttl`
+pub(crate) fn expires_at(ttl: Duration) -> u64 {
+    // Don't let a key with a TTL of zero be mistaken for one that never expires
+    now().saturating_add(ttl.as_millis() as u64).max(NEVER + 1)
+}
+
+/// Whether a key which expires at `expires_at` has expired by `now`
+pub(crate) fn is_expired(expires_at: u64, now: u64) -> bool {
+    expires_at != NEVER && expires_at <= now
+}
+
+/// How long a key has left to live, or `None` if it never expires
+pub(crate) fn remaining(expires_at: u64, now: u64) -> Option<Duration> {
+    (expires_at != NEVER).then(|| Duration::from_millis(expires_at.saturating_sub(now)))
+}
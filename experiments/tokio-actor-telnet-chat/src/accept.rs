@@ -3,11 +3,12 @@ use std::io;
 
 use crate::main_loop::{ServerHandle, ToServer};
 use crate::client::{spawn_client, ClientInfo};
+use crate::limits::Config;
 
 use tokio::net::TcpListener;
 
-pub async fn start_accept(bind: SocketAddr, mut handle: ServerHandle) {
-    let res = accept_loop(bind, handle.clone()).await;
+pub async fn start_accept(bind: SocketAddr, mut handle: ServerHandle, config: Config) {
+    let res = accept_loop(bind, handle.clone(), config).await;
     match res {
         Ok(()) => {},
         Err(err) => {
@@ -18,7 +19,8 @@ pub async fn start_accept(bind: SocketAddr, mut handle: ServerHandle) {
 
 pub async fn accept_loop(
     bind: SocketAddr,
-    handle: ServerHandle
+    handle: ServerHandle,
+    config: Config,
 ) -> Result<(), io::Error> {
 
     let listen = TcpListener::bind(bind).await?;
@@ -33,6 +35,7 @@ pub async fn accept_loop(
             id,
             tcp,
             handle: handle.clone(),
+            config,
         };
 
         spawn_client(data);
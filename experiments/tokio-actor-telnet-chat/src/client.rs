@@ -1,18 +1,24 @@
 use std::io;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use futures::sink::SinkExt;
 use futures::stream::StreamExt;
 use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpStream, tcp::{ReadHalf, WriteHalf}};
-use tokio::sync::mpsc::{unbounded_channel, UnboundedSender, UnboundedReceiver};
-use tokio::sync::mpsc::{channel, Sender, Receiver};
+use tokio::sync::mpsc::{channel, Sender, Receiver, error::TrySendError};
 use tokio::sync::oneshot;
 use tokio::{try_join, select};
 use tokio::task::JoinHandle;
-use tokio_util::codec::FramedRead;
+use tokio::time::timeout;
+use tokio_util::codec::{FramedRead, FramedWrite};
 
 use crate::ClientId;
+use crate::command::{self, Command};
+use crate::limits::{Config, Next, Outbox, RateLimit};
 use crate::main_loop::{ServerHandle, ToServer};
+use crate::negotiation::Negotiation;
 use crate::telnet::{TelnetCodec, Item};
 
 /// Messages received from the main loop.
@@ -25,20 +31,26 @@ pub enum FromServer {
 pub struct ClientHandle {
     pub id: ClientId,
     ip: SocketAddr,
-    chan: Sender<FromServer>,
+    outbox: Arc<Outbox>,
     kill: JoinHandle<()>,
 }
 
 impl ClientHandle {
-    /// Send a message to this client actor. Will emit an error if sending does
-    /// not succeed immediately, as this means that forwarding messages to the
-    /// tcp connection cannot keep up.
+    /// Send a message to this client actor. Will emit an error if the message
+    /// doesn't fit in the client's outbox and the slow consumer policy is to
+    /// disconnect it, as this means that forwarding messages to the tcp
+    /// connection cannot keep up. The other policies drop messages instead.
     pub fn send(&mut self, msg: FromServer) -> Result<(), io::Error> {
-        if self.chan.try_send(msg).is_err() {
-            Err(io::Error::new(io::ErrorKind::BrokenPipe, "Can't keep up or dead"))
-        } else {
-            Ok(())
-        }
+        let FromServer::Message(msg) = msg;
+        self.outbox.push(msg)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Can't keep up"))
+    }
+
+    /// Tell the client why it is being disconnected, and then close its
+    /// connection. The actor is not killed when the handle is dropped after
+    /// this, so that it can say goodbye.
+    pub fn disconnect(&self, reason: &str) {
+        self.outbox.close(reason.to_string());
     }
 
     /// Kill the actor.
@@ -48,9 +60,25 @@ impl ClientHandle {
     }
 }
 
+#[cfg(test)]
+impl ClientHandle {
+    /// A handle to no actor at all, so that tests of the main loop can read
+    /// what it sends to the client from `outbox`.
+    pub(crate) fn detached(id: ClientId, outbox: Arc<Outbox>) -> Self {
+        ClientHandle {
+            id,
+            ip: SocketAddr::from(([127, 0, 0, 1], 0)),
+            outbox,
+            kill: tokio::spawn(async {}),
+        }
+    }
+}
+
 impl Drop for ClientHandle {
     fn drop(&mut self) {
-        self.kill.abort()
+        if !self.outbox.is_closed() {
+            self.kill.abort()
+        }
     }
 }
 
@@ -61,25 +89,28 @@ pub struct ClientInfo {
     pub id: ClientId,
     pub handle: ServerHandle,
     pub tcp: TcpStream,
+    pub config: Config,
 }
 
 /// This struct stores the information used internally by this client actor.
 struct ClientData {
     id: ClientId,
     handle: ServerHandle,
-    recv: Receiver<FromServer>,
+    outbox: Arc<Outbox>,
+    rate_limit: RateLimit,
     tcp: TcpStream,
 }
 
 /// Spawn a new client actor.
 pub fn spawn_client(info: ClientInfo) {
-    let (send, recv) = channel(64);
+    let outbox = Arc::new(Outbox::new(info.config.outbox_bytes, info.config.slow_consumer));
 
     let data = ClientData {
         id: info.id,
         handle: info.handle.clone(),
         tcp: info.tcp,
-        recv,
+        outbox: outbox.clone(),
+        rate_limit: RateLimit::new(info.config.lines_per_second, info.config.burst, Instant::now()),
     };
 
     // This spawns the new task.
@@ -91,7 +122,7 @@ pub fn spawn_client(info: ClientInfo) {
     let handle = ClientHandle {
         id: info.id,
         ip: info.ip,
-        chan: send,
+        outbox,
         kill,
     };
 
@@ -114,6 +145,8 @@ async fn start_client(my_handle: oneshot::Receiver<ClientHandle>, mut data: Clie
 
     // We sent the client handle to the main loop. Start talking to the tcp
     // connection.
+    let id = data.id;
+    let mut handle = data.handle.clone();
     let res = client_loop(data).await;
     match res {
         Ok(()) => {},
@@ -121,6 +154,9 @@ async fn start_client(my_handle: oneshot::Receiver<ClientHandle>, mut data: Clie
             eprintln!("Something went wrong: {}.", err);
         },
     }
+
+    // Let the main loop know, so it can tell the others in our room.
+    handle.send(ToServer::Disconnected(id)).await;
 }
 
 /// This method performs the actual job of running the client actor.
@@ -128,57 +164,109 @@ async fn client_loop(mut data: ClientData) -> Result<(), io::Error> {
     let (read, write) = data.tcp.split();
 
     // communication between tcp_read and tcp_write
-    let (send, recv) = unbounded_channel();
+    let (send, recv) = channel(REPLIES_LEN);
 
-    let ((), ()) = try_join! {
-        tcp_read(data.id, read, data.handle, send),
-        tcp_write(write, data.recv, recv),
-    }?;
+    let res = try_join! {
+        tcp_read(data.id, read, data.handle, data.rate_limit, send),
+        tcp_write(write, &data.outbox, recv),
+    };
 
     let _ = data.tcp.shutdown().await;
 
-    Ok(())
+    match res {
+        Ok(((), ())) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::ConnectionAborted => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// How long a client that is being disconnected gets to read why.
+const GOODBYE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returned by `tcp_write` once the server has closed the connection, so that
+/// `try_join!` stops `tcp_read` too.
+fn closed_by_server() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "Closed by the server")
 }
 
 #[derive(Debug)]
 enum InternalMsg {
     GotAreYouThere,
-    SendDont(u8),
-    SendWont(u8),
-    SendDo(u8),
+    /// A reply to a command which didn't need the main loop.
+    Notice(String),
+    /// A WILL, WONT, DO or DONT from the client.
+    Negotiate(Item),
+    /// The client's window is this wide, or 0 if it doesn't know.
+    Width(u16),
+}
+
+/// How many replies `tcp_read` can queue for `tcp_write`. Replies to commands
+/// and telnet options aren't rate limited, so a client which sends them faster
+/// than it reads the replies fills the queue, and is disconnected.
+const REPLIES_LEN: usize = 64;
+
+/// Queues a reply for `tcp_write`, failing if the client isn't reading them.
+fn reply(to_tcp_write: &Sender<InternalMsg>, msg: InternalMsg) -> Result<(), io::Error> {
+    match to_tcp_write.try_send(msg) {
+        Ok(()) => Ok(()),
+        Err(TrySendError::Full(_)) => Err(io::Error::other("Too many replies waiting for the client")),
+        Err(TrySendError::Closed(_)) => panic!("Should not be closed."),
+    }
 }
 
 async fn tcp_read(
     id: ClientId,
     read: ReadHalf<'_>,
     mut handle: ServerHandle,
-    to_tcp_write: UnboundedSender<InternalMsg>,
+    mut rate_limit: RateLimit,
+    to_tcp_write: Sender<InternalMsg>,
 ) -> Result<(), io::Error> {
     let mut telnet = FramedRead::new(read, TelnetCodec::new());
+    // Whether the client has been told that its lines are being dropped since
+    // it last sent one which wasn't.
+    let mut throttled = false;
 
     while let Some(item) = telnet.next().await {
         match item? {
-            Item::Line(line) => {
-                handle.send(ToServer::Message(id, line)).await;
+            Item::Line(line) => match command::parse(&line) {
+                Some(Ok(Command::Quit)) => {
+                    reply(&to_tcp_write, InternalMsg::Notice("Bye.".to_string()))?;
+                    // Returning closes `to_tcp_write`, so `tcp_write` stops
+                    // once it has said goodbye.
+                    return Ok(());
+                },
+                _ if !rate_limit.allow(Instant::now()) => {
+                    if !throttled {
+                        throttled = true;
+                        let text = "You are typing too fast, so some of your lines were dropped.";
+                        reply(&to_tcp_write, InternalMsg::Notice(text.to_string()))?;
+                    }
+                },
+                None => {
+                    throttled = false;
+                    handle.send(ToServer::Message(id, line)).await
+                },
+                Some(Ok(cmd)) => {
+                    throttled = false;
+                    handle.send(ToServer::Command(id, cmd)).await
+                },
+                Some(Err(err)) => {
+                    reply(&to_tcp_write, InternalMsg::Notice(err))?;
+                },
             },
             Item::AreYouThere => {
-                to_tcp_write.send(InternalMsg::GotAreYouThere)
-                    .expect("Should not be closed.");
+                reply(&to_tcp_write, InternalMsg::GotAreYouThere)?;
             },
             Item::GoAhead => { /* ignore */ },
             Item::InterruptProcess => return Ok(()),
-            Item::Will(3) => { // suppress go-ahead
-                to_tcp_write.send(InternalMsg::SendDo(3))
-                    .expect("Should not be closed.");
+            item @ Item::Will(_) | item @ Item::Wont(_) | item @ Item::Do(_) | item @ Item::Dont(_) => {
+                reply(&to_tcp_write, InternalMsg::Negotiate(item))?;
             },
-            Item::Will(i) => {
-                to_tcp_write.send(InternalMsg::SendDont(i))
-                    .expect("Should not be closed.");
-            },
-            Item::Do(i) => {
-                to_tcp_write.send(InternalMsg::SendWont(i))
-                    .expect("Should not be closed.");
+            Item::WindowSize { width, .. } => {
+                reply(&to_tcp_write, InternalMsg::Width(width))?;
             },
+            // Nothing depends on the terminal type yet, nor on other options.
+            Item::TerminalType(_) | Item::Subnegotiation(..) => {},
             item => {
                 return Err(io::Error::new(
                     io::ErrorKind::Other,
@@ -193,34 +281,59 @@ async fn tcp_read(
     Ok(())
 }
 
+/// Lines are not wrapped for windows narrower than this, which are more likely
+/// to be a confused client than a real terminal.
+const MIN_WRAP_WIDTH: usize = 20;
+
 async fn tcp_write(
-    mut write: WriteHalf<'_>,
-    mut recv: Receiver<FromServer>,
-    mut from_tcp_read: UnboundedReceiver<InternalMsg>,
+    write: WriteHalf<'_>,
+    outbox: &Outbox,
+    mut from_tcp_read: Receiver<InternalMsg>,
 ) -> Result<(), io::Error> {
+    let mut telnet = FramedWrite::new(write, TelnetCodec::new());
+    let mut negotiation = Negotiation::new();
+    let mut width = None;
+
+    for item in negotiation.start() {
+        telnet.feed(item).await?;
+    }
+    telnet.flush().await?;
+
     loop {
         select! {
-            msg = recv.recv() => match msg {
-                Some(FromServer::Message(msg)) => {
-                    write.write_all(&msg).await?;
-                    write.write_all(&[13, 10]).await?;
+            next = outbox.pop() => match next {
+                Next::Message(msg) => {
+                    write_text(&mut telnet, &msg, width).await?;
                 },
-                None => {
-                    break;
+                Next::Missed(n) => {
+                    let text = match n {
+                        1 => "You missed a message, because you fell behind.".to_string(),
+                        n => format!("You missed {} messages, because you fell behind.", n),
+                    };
+                    write_text(&mut telnet, text.as_bytes(), width).await?;
+                },
+                Next::Close(reason) => {
+                    let goodbye = write_text(&mut telnet, reason.as_bytes(), width);
+                    // A client that fell behind may never read this.
+                    let _ = timeout(GOODBYE_TIMEOUT, goodbye).await;
+                    return Err(closed_by_server());
                 },
             },
             msg = from_tcp_read.recv() => match msg {
                 Some(InternalMsg::GotAreYouThere) => {
-                    write.write_all(b"Yes.\r\n").await?;
+                    telnet.send(Item::Line(b"Yes.".to_vec())).await?;
                 },
-                Some(InternalMsg::SendDont(i)) => {
-                    write.write_all(&[0xff, 254, i]).await?;
+                Some(InternalMsg::Notice(text)) => {
+                    write_text(&mut telnet, text.as_bytes(), width).await?;
                 },
-                Some(InternalMsg::SendWont(i)) => {
-                    write.write_all(&[0xff, 252, i]).await?;
+                Some(InternalMsg::Negotiate(item)) => {
+                    for reply in negotiation.receive(&item) {
+                        telnet.feed(reply).await?;
+                    }
+                    telnet.flush().await?;
                 },
-                Some(InternalMsg::SendDo(i)) => {
-                    write.write_all(&[0xff, 253, i]).await?;
+                Some(InternalMsg::Width(w)) => {
+                    width = Some(usize::from(w)).filter(|w| *w >= MIN_WRAP_WIDTH);
                 },
                 None => {
                     break;
@@ -231,3 +344,86 @@ async fn tcp_write(
 
     Ok(())
 }
+
+/// Writes text which may have several lines, wrapping them to `width`.
+async fn write_text(
+    telnet: &mut FramedWrite<WriteHalf<'_>, TelnetCodec>,
+    text: &[u8],
+    width: Option<usize>,
+) -> Result<(), io::Error> {
+    for line in text.split(|byte| *byte == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        match width {
+            Some(width) => {
+                for part in wrap(line, width) {
+                    telnet.feed(Item::Line(part.to_vec())).await?;
+                }
+            },
+            None => telnet.feed(Item::Line(line.to_vec())).await?,
+        }
+    }
+    telnet.flush().await
+}
+
+/// Splits a line into lines at most `width` characters long, breaking it at a
+/// space where there is one. Bytes which aren't UTF-8 count as a character
+/// each.
+fn wrap(mut line: &[u8], width: usize) -> Vec<&[u8]> {
+    // The index of each character after the first, and the end.
+    let boundaries = |line: &[u8]| {
+        let starts = (1..line.len()).filter(|i| line[*i] & 0xc0 != 0x80);
+        starts.chain(std::iter::once(line.len())).collect::<Vec<_>>()
+    };
+
+    let mut lines = Vec::new();
+    loop {
+        let ends = boundaries(line);
+        if ends.len() <= width {
+            lines.push(line);
+            return lines;
+        }
+        // Break at the last space that leaves at most `width` characters,
+        // dropping the space, or in the middle of a word that long.
+        let end = ends[width - 1];
+        match line[..=end].iter().rposition(|byte| *byte == b' ') {
+            Some(space) if space > 0 => {
+                lines.push(&line[..space]);
+                line = &line[space + 1..];
+            },
+            _ => {
+                lines.push(&line[..end]);
+                line = &line[end..];
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_wrapped_at_spaces() {
+        let wrapped = |line: &str, width| {
+            wrap(line.as_bytes(), width).into_iter()
+                .map(|part| String::from_utf8(part.to_vec()).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(wrapped("short", 10), vec!["short"]);
+        assert_eq!(wrapped("", 10), vec![""]);
+        assert_eq!(wrapped("the quick brown fox", 10), vec!["the quick", "brown fox"]);
+        assert_eq!(wrapped("exactly ten", 11), vec!["exactly ten"]);
+        assert_eq!(wrapped("abcdefghijkl mn", 5), vec!["abcde", "fghij", "kl mn"]);
+        assert_eq!(wrapped("ünïcödé wörds", 7), vec!["ünïcödé", "wörds"]);
+    }
+
+    #[test]
+    fn replies_are_bounded() {
+        let (send, _recv) = channel(REPLIES_LEN);
+        for _ in 0..REPLIES_LEN {
+            reply(&send, InternalMsg::GotAreYouThere).unwrap();
+        }
+        assert!(reply(&send, InternalMsg::GotAreYouThere).is_err());
+    }
+}
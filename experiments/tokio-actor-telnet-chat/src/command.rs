@@ -0,0 +1,125 @@
+/// The longest nickname a client can pick.
+pub const MAX_NICK_LEN: usize = 32;
+
+/// A command typed by a client, as a line starting with a slash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// `/nick <name>` changes the client's nickname.
+    Nick(String),
+    /// `/join <room>` moves the client to a room, leaving the one it is in.
+    Join(String),
+    /// `/leave` leaves the current room.
+    Leave,
+    /// `/who` lists the people in the current room.
+    Who,
+    /// `/msg <nick> <text>` sends a message to one person only.
+    Msg(String, Vec<u8>),
+    /// `/quit` disconnects.
+    Quit,
+    /// `/help` lists the commands.
+    Help,
+}
+
+pub const HELP: &str = "\
+Commands:
+  /nick <name>        change your nickname
+  /join <room>        move to a room
+  /leave              leave the room you are in
+  /who                list the people in your room
+  /msg <nick> <text>  send a private message
+  /quit               disconnect
+Anything else is said to the room you are in.";
+
+/// Parses a line typed by a client. Returns `None` if the line is not a
+/// command, and should be said to the room instead.
+pub fn parse(line: &[u8]) -> Option<Result<Command, String>> {
+    if line.first() != Some(&b'/') {
+        return None;
+    }
+
+    let line = &line[1..];
+    let (name, rest) = split_word(line);
+    let name = String::from_utf8_lossy(name).to_lowercase();
+    let (arg, tail) = split_word(rest);
+
+    let cmd = match (name.as_str(), arg.is_empty()) {
+        ("nick", false) if tail.is_empty() => valid_name(arg, "nickname").map(Command::Nick),
+        ("join", false) if tail.is_empty() => valid_name(arg, "room name").map(Command::Join),
+        ("leave", true) => Ok(Command::Leave),
+        ("who", true) => Ok(Command::Who),
+        ("msg", false) if !tail.is_empty() => {
+            valid_name(arg, "nickname").map(|nick| Command::Msg(nick, tail.to_vec()))
+        },
+        ("quit", _) => Ok(Command::Quit),
+        ("help", _) => Ok(Command::Help),
+        ("nick", _) => Err("Usage: /nick <name>".to_string()),
+        ("join", _) => Err("Usage: /join <room>".to_string()),
+        ("leave", _) => Err("Usage: /leave".to_string()),
+        ("who", _) => Err("Usage: /who".to_string()),
+        ("msg", _) => Err("Usage: /msg <nick> <text>".to_string()),
+        _ => Err(format!("Unknown command /{}. Try /help.", name)),
+    };
+
+    Some(cmd)
+}
+
+/// Splits off the first word of `line`, returning it and the rest of the line
+/// with the whitespace around it trimmed.
+fn split_word(line: &[u8]) -> (&[u8], &[u8]) {
+    let line = trim_start(line);
+    let end = line.iter().position(u8::is_ascii_whitespace).unwrap_or(line.len());
+    (&line[..end], trim_start(&line[end..]))
+}
+
+fn trim_start(line: &[u8]) -> &[u8] {
+    let start = line.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(line.len());
+    &line[start..]
+}
+
+/// Checks that a nickname or room name is printable UTF-8 and not too long.
+fn valid_name(name: &[u8], what: &str) -> Result<String, String> {
+    let name = match std::str::from_utf8(name) {
+        Ok(name) => name,
+        Err(_) => return Err(format!("The {} must be valid UTF-8.", what)),
+    };
+    if name.chars().count() > MAX_NICK_LEN {
+        return Err(format!("The {} can be at most {} characters.", what, MAX_NICK_LEN));
+    }
+    if name.chars().any(char::is_control) {
+        return Err(format!("The {} can't contain control characters.", what));
+    }
+    Ok(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_ok(line: &str) -> Command {
+        parse(line.as_bytes()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse(b"hello /nick"), None);
+        assert_eq!(parse_ok("/nick alice"), Command::Nick("alice".to_string()));
+        assert_eq!(parse_ok("/JOIN  ops "), Command::Join("ops".to_string()));
+        assert_eq!(parse_ok("/leave"), Command::Leave);
+        assert_eq!(parse_ok("/who"), Command::Who);
+        assert_eq!(
+            parse_ok("/msg bob  are you  there?"),
+            Command::Msg("bob".to_string(), b"are you  there?".to_vec()),
+        );
+        assert_eq!(parse_ok("/quit bye all"), Command::Quit);
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        for line in &["/nick", "/nick two words", "/join", "/msg bob", "/who me", "/dance"] {
+            assert!(parse(line.as_bytes()).unwrap().is_err(), "{}", line);
+        }
+        let long = format!("/nick {}", "a".repeat(MAX_NICK_LEN + 1));
+        assert!(parse(long.as_bytes()).unwrap().is_err());
+        assert!(parse(b"/nick \xff").unwrap().is_err());
+    }
+}
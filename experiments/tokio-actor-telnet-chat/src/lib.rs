@@ -1,8 +1,19 @@
 pub mod accept;
 pub mod client;
+pub mod command;
+pub mod limits;
+pub mod negotiation;
 pub mod telnet;
 pub mod main_loop;
 
+use std::fmt;
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct ClientId(usize);
 
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
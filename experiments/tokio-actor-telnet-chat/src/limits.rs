@@ -0,0 +1,313 @@
+//! Limits on how much each client can make the server hold on to: an outbox
+//! with a byte budget for the messages waiting to be written to it, and a rate
+//! limit on the lines it sends.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use tokio::sync::Notify;
+
+/// What to do with a client when a message for it doesn't fit in its outbox,
+/// because it reads slower than the room talks.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SlowConsumer {
+    /// Disconnect the client, telling it why.
+    Disconnect,
+    /// Drop the oldest waiting messages to make room for the new one.
+    DropOldest,
+    /// Drop the new message.
+    DropNewest,
+}
+
+impl FromStr for SlowConsumer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "disconnect" => Ok(SlowConsumer::Disconnect),
+            "drop-oldest" => Ok(SlowConsumer::DropOldest),
+            "drop-newest" => Ok(SlowConsumer::DropNewest),
+            _ => Err(format!(
+                "Unknown policy {}, expected disconnect, drop-oldest or drop-newest.", s,
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SlowConsumer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SlowConsumer::Disconnect => "disconnect",
+            SlowConsumer::DropOldest => "drop-oldest",
+            SlowConsumer::DropNewest => "drop-newest",
+        })
+    }
+}
+
+/// The limits applied to every client.
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    pub slow_consumer: SlowConsumer,
+    /// How many bytes of messages can wait to be written to a client.
+    pub outbox_bytes: usize,
+    /// How many lines a client can send per second, on average, or 0 for no
+    /// limit.
+    pub lines_per_second: u32,
+    /// How many lines a client can send at once, after being quiet.
+    pub burst: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            slow_consumer: SlowConsumer::Disconnect,
+            outbox_bytes: 64 * 1024,
+            lines_per_second: 5,
+            burst: 10,
+        }
+    }
+}
+
+/// What the writer of a client's connection should do next.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Next {
+    Message(Vec<u8>),
+    /// Tell the client that this many messages were dropped.
+    Missed(usize),
+    /// Tell the client why it is being disconnected, then close the
+    /// connection.
+    Close(String),
+}
+
+#[derive(Debug)]
+enum Entry {
+    Message(Vec<u8>),
+    Missed(usize),
+}
+
+#[derive(Default, Debug)]
+struct State {
+    queue: VecDeque<Entry>,
+    /// The length of the messages in `queue`.
+    bytes: usize,
+    closed: Option<String>,
+}
+
+/// The messages waiting to be written to a client, shared by the main loop,
+/// which pushes them, and the client actor, which pops them.
+#[derive(Debug)]
+pub struct Outbox {
+    state: Mutex<State>,
+    wake: Notify,
+    budget: usize,
+    policy: SlowConsumer,
+}
+
+/// The message didn't fit, and the policy is to disconnect the client.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Full;
+
+impl Outbox {
+    pub fn new(budget: usize, policy: SlowConsumer) -> Self {
+        Outbox {
+            state: Mutex::new(State::default()),
+            wake: Notify::new(),
+            budget,
+            policy,
+        }
+    }
+
+    /// Queues a message, applying the slow consumer policy if it doesn't fit.
+    /// A message always fits in an empty outbox, however long it is.
+    ///
+    /// Messages for a closed outbox are dropped.
+    pub fn push(&self, msg: Vec<u8>) -> Result<(), Full> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed.is_some() {
+            return Ok(());
+        }
+
+        let fits = |state: &State| state.bytes == 0 || state.bytes + msg.len() <= self.budget;
+        if !fits(&state) {
+            match self.policy {
+                SlowConsumer::Disconnect => return Err(Full),
+                SlowConsumer::DropNewest => {
+                    match state.queue.back_mut() {
+                        Some(Entry::Missed(n)) => *n += 1,
+                        _ => state.queue.push_back(Entry::Missed(1)),
+                    }
+                    return Ok(());
+                },
+                SlowConsumer::DropOldest => {
+                    let mut missed = 0;
+                    while !fits(&state) {
+                        match state.queue.pop_front() {
+                            Some(Entry::Message(old)) => {
+                                state.bytes -= old.len();
+                                missed += 1;
+                            },
+                            Some(Entry::Missed(n)) => missed += n,
+                            None => break,
+                        }
+                    }
+                    if missed > 0 {
+                        state.queue.push_front(Entry::Missed(missed));
+                    }
+                },
+            }
+        }
+
+        state.bytes += msg.len();
+        state.queue.push_back(Entry::Message(msg));
+        drop(state);
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    /// Drops the waiting messages and has the writer send `reason` to the
+    /// client before closing the connection.
+    pub fn close(&self, reason: String) {
+        let mut state = self.state.lock().unwrap();
+        if state.closed.is_none() {
+            state.queue.clear();
+            state.bytes = 0;
+            state.closed = Some(reason);
+        }
+        drop(state);
+        self.wake.notify_one();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed.is_some()
+    }
+
+    /// Waits for something to write to the client. Only one task may call
+    /// this.
+    pub async fn pop(&self) -> Next {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                match state.queue.pop_front() {
+                    Some(Entry::Message(msg)) => {
+                        state.bytes -= msg.len();
+                        return Next::Message(msg);
+                    },
+                    Some(Entry::Missed(n)) => return Next::Missed(n),
+                    None => {},
+                }
+                if let Some(reason) = &state.closed {
+                    return Next::Close(reason.clone());
+                }
+            }
+            self.wake.notified().await;
+        }
+    }
+}
+
+/// A token bucket, which lets a client send `burst` lines at once, and then
+/// refills at `per_second` lines per second.
+#[derive(Debug)]
+pub struct RateLimit {
+    per_second: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimit {
+    pub fn new(per_second: u32, burst: u32, now: Instant) -> Self {
+        let burst = f64::from(burst.max(1));
+        RateLimit {
+            per_second: f64::from(per_second),
+            burst,
+            tokens: burst,
+            last: now,
+        }
+    }
+
+    /// Takes a token for a line sent at `now`, or returns false if there are
+    /// none left.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        if self.per_second == 0.0 {
+            return true;
+        }
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.burst);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn drain(outbox: &Outbox) -> Vec<Next> {
+        let mut out = Vec::new();
+        let mut state = outbox.state.lock().unwrap();
+        while let Some(entry) = state.queue.pop_front() {
+            out.push(match entry {
+                Entry::Message(msg) => Next::Message(msg),
+                Entry::Missed(n) => Next::Missed(n),
+            });
+        }
+        state.bytes = 0;
+        out
+    }
+
+    fn msg(text: &str) -> Next {
+        Next::Message(text.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn slow_consumer_policies() {
+        let outbox = Outbox::new(10, SlowConsumer::Disconnect);
+        assert_eq!(outbox.push(b"12345".to_vec()), Ok(()));
+        assert_eq!(outbox.push(b"67890".to_vec()), Ok(()));
+        assert_eq!(outbox.push(b"x".to_vec()), Err(Full));
+
+        let outbox = Outbox::new(10, SlowConsumer::DropNewest);
+        for text in &["12345", "67890", "a", "b", "c"] {
+            assert_eq!(outbox.push(text.as_bytes().to_vec()), Ok(()));
+        }
+        assert_eq!(drain(&outbox), vec![msg("12345"), msg("67890"), Next::Missed(3)]);
+
+        let outbox = Outbox::new(10, SlowConsumer::DropOldest);
+        for text in &["12345", "67890", "abc", "defghijklmnop"] {
+            assert_eq!(outbox.push(text.as_bytes().to_vec()), Ok(()));
+        }
+        assert_eq!(drain(&outbox), vec![Next::Missed(3), msg("defghijklmnop")]);
+    }
+
+    #[tokio::test]
+    async fn closing_drops_waiting_messages() {
+        let outbox = Outbox::new(10, SlowConsumer::Disconnect);
+        outbox.push(b"hello".to_vec()).unwrap();
+        assert_eq!(outbox.pop().await, msg("hello"));
+        outbox.push(b"lost".to_vec()).unwrap();
+        outbox.close("Bye.".to_string());
+        outbox.push(b"ignored".to_vec()).unwrap();
+        assert_eq!(outbox.pop().await, Next::Close("Bye.".to_string()));
+    }
+
+    #[test]
+    fn rate_limit_refills() {
+        let start = Instant::now();
+        let mut limit = RateLimit::new(2, 3, start);
+        assert!((0..3).all(|_| limit.allow(start)));
+        assert!(!limit.allow(start));
+        assert!(limit.allow(start + Duration::from_millis(500)));
+        assert!(!limit.allow(start + Duration::from_millis(600)));
+        assert!((0..3).all(|_| limit.allow(start + Duration::from_secs(60))));
+        assert!(!limit.allow(start + Duration::from_secs(60)));
+    }
+}
@@ -1,10 +1,58 @@
+use std::process;
+
+use telnet_chat::limits::Config;
+
+const USAGE: &str = "\
+Usage: telnet-chat [OPTIONS]
+
+Options:
+  --slow-consumer <POLICY>  what to do when a client falls behind: disconnect,
+                            drop-oldest or drop-newest [default: disconnect]
+  --outbox-bytes <BYTES>    how much can wait to be sent to a client [default: 65536]
+  --rate <LINES>            lines a client can send per second, 0 for no limit [default: 5]
+  --burst <LINES>           lines a client can send at once [default: 10]";
+
+/// Parses the command line, or returns what is wrong with it.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Config, String> {
+    let mut config = Config::default();
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+        let value = match args.next() {
+            Some(value) => value,
+            None => return Err(format!("{} needs a value.", arg)),
+        };
+        let invalid = |_| format!("Invalid value {} for {}.", value, arg);
+        match arg.as_str() {
+            "--slow-consumer" => config.slow_consumer = value.parse()?,
+            "--outbox-bytes" => config.outbox_bytes = value.parse().map_err(invalid)?,
+            "--rate" => config.lines_per_second = value.parse().map_err(invalid)?,
+            "--burst" => config.burst = value.parse().map_err(invalid)?,
+            _ => return Err(format!("Unknown option {}.", arg)),
+        }
+    }
+
+    Ok(config)
+}
+
 #[tokio::main]
 async fn main() {
+    let config = match parse_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        },
+    };
+
     let (handle, join) = telnet_chat::main_loop::spawn_main_loop();
 
     tokio::spawn(async move {
         let bind = ([0, 0, 0, 0], 3456).into();
-        telnet_chat::accept::start_accept(bind, handle).await;
+        telnet_chat::accept::start_accept(bind, handle, config).await;
     });
 
     println!("Starting on port 3456");
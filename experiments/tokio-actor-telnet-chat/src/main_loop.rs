@@ -1,5 +1,5 @@
 use std::io;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
 use tokio::sync::mpsc::Sender;
 
@@ -8,6 +8,7 @@ use tokio::task::JoinHandle;
 
 use crate::ClientId;
 use crate::client::{ClientHandle, FromServer};
+use crate::command::{Command, HELP};
 
 /// This struct is used by client actors to send messages to the main loop. The
 /// message type is `ToServer`.
@@ -31,7 +32,11 @@ impl ServerHandle {
 /// The message type used when a client actor sends messages to the main loop.
 pub enum ToServer {
     NewClient(ClientHandle),
+    /// A line which is said to the client's room.
     Message(ClientId, Vec<u8>),
+    Command(ClientId, Command),
+    /// The client's connection has closed.
+    Disconnected(ClientId),
     FatalError(io::Error),
 }
 
@@ -56,9 +61,31 @@ pub fn spawn_main_loop() -> (ServerHandle, JoinHandle<()>) {
     (handle, join)
 }
 
+/// The room that new clients are put in.
+const LOBBY: &str = "lobby";
+
+/// How many messages each room keeps for the people who join it.
+const HISTORY_LEN: usize = 20;
+
+#[derive(Debug)]
+struct Client {
+    handle: ClientHandle,
+    nick: String,
+    room: Option<String>,
+}
+
+#[derive(Default, Debug)]
+struct Room {
+    members: HashSet<ClientId>,
+    /// The most recent messages said in the room, oldest first.
+    history: VecDeque<Vec<u8>>,
+}
+
 #[derive(Default, Debug)]
 struct Data {
-    clients: HashMap<ClientId, ClientHandle>,
+    clients: HashMap<ClientId, Client>,
+    nicks: HashMap<String, ClientId>,
+    rooms: HashMap<String, Room>,
 }
 
 async fn main_loop(
@@ -69,39 +96,443 @@ async fn main_loop(
     while let Some(msg) = recv.recv().await {
         match msg {
             ToServer::NewClient(handle) => {
-                data.clients.insert(handle.id, handle);
+                let id = handle.id;
+                let nick = data.guest_nick(id);
+                data.nicks.insert(nick.clone(), id);
+                data.clients.insert(id, Client { handle, nick: nick.clone(), room: None });
+                data.tell(id, format!("Welcome, {}! Type /help for the commands.", nick));
+                data.join(id, LOBBY.to_string());
             },
             ToServer::Message(from_id, msg) => {
-                // If we fail to send messages to any actor, we need to remove
-                // it, but we can't do so while iterating.
-                let mut to_remove = Vec::new();
+                let (nick, room) = match data.clients.get(&from_id) {
+                    Some(Client { nick, room: Some(room), .. }) => (nick.clone(), room.clone()),
+                    Some(_) => {
+                        data.tell(from_id, "You aren't in a room. Use /join <room>.");
+                        continue;
+                    },
+                    None => continue,
+                };
+
+                let mut line = format!("{}: ", nick).into_bytes();
+                line.extend_from_slice(&msg);
+                data.remember(&room, line.clone());
+                data.say(&room, Some(from_id), line);
+            },
+            ToServer::Command(id, cmd) => data.command(id, cmd),
+            ToServer::Disconnected(id) => data.remove(id, "has quit"),
+            // This message comes only from the accept loop.
+            ToServer::FatalError(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+impl Data {
+    fn command(&mut self, id: ClientId, cmd: Command) {
+        match cmd {
+            Command::Nick(nick) => self.rename(id, nick),
+            Command::Join(room) => self.join(id, room),
+            Command::Leave => match self.leave(id, "has left") {
+                Some(room) => self.tell(id, format!("You left #{}.", room)),
+                None => self.tell(id, "You aren't in a room."),
+            },
+            Command::Who => self.who(id),
+            Command::Msg(to, text) => self.private(id, &to, text),
+            Command::Help => self.tell(id, HELP),
+            // The client disconnects itself, and we hear about it then.
+            Command::Quit => {},
+        }
+    }
+
+    /// Picks the nickname of a client that just connected. Someone else may
+    /// already have taken `guest<id>` with /nick, so it gets a suffix then.
+    fn guest_nick(&self, id: ClientId) -> String {
+        let mut nick = format!("guest{}", id);
+        let mut n = 1;
+        while self.nicks.contains_key(&nick) {
+            n += 1;
+            nick = format!("guest{}-{}", id, n);
+        }
+        nick
+    }
+
+    fn nick(&self, id: ClientId) -> String {
+        self.clients.get(&id).map(|client| client.nick.clone()).unwrap_or_default()
+    }
+
+    /// Sends a notice to a single client.
+    fn tell(&mut self, id: ClientId, text: impl Into<String>) {
+        let failed = match self.clients.get_mut(&id) {
+            Some(client) => {
+                let msg = FromServer::Message(text.into().into_bytes());
+                client.handle.send(msg).is_err()
+            },
+            None => false,
+        };
+        if failed {
+            self.too_slow(id);
+        }
+    }
 
-                // Iterate through clients so we can send the message.
-                for (id, handle) in data.clients.iter_mut() {
-                    let id = *id;
+    /// Keeps a message in a room's history, for the people who join later.
+    fn remember(&mut self, room: &str, line: Vec<u8>) {
+        if let Some(room) = self.rooms.get_mut(room) {
+            if room.history.len() == HISTORY_LEN {
+                room.history.pop_front();
+            }
+            room.history.push_back(line);
+        }
+    }
+
+    /// Sends a line to everyone in a room except `except`.
+    fn say(&mut self, room: &str, except: Option<ClientId>, line: Vec<u8>) {
+        let members = match self.rooms.get(room) {
+            Some(room) => room.members.iter().copied().collect::<Vec<_>>(),
+            None => return,
+        };
 
-                    // Don't send it to the client who sent it to us.
-                    if id == from_id { continue; }
+        // If we fail to send messages to any actor, we need to remove it, but
+        // we can't do so while iterating.
+        let mut to_remove = Vec::new();
 
-                    let msg = FromServer::Message(msg.clone());
+        for id in members {
+            // Don't send it to the client who sent it to us.
+            if Some(id) == except { continue; }
 
-                    if handle.send(msg).is_err() {
-                        // Remove this client.
-                        to_remove.push(id);
-                    }
+            if let Some(client) = self.clients.get_mut(&id) {
+                if client.handle.send(FromServer::Message(line.clone())).is_err() {
+                    to_remove.push(id);
                 }
+            }
+        }
+
+        for id in to_remove {
+            self.too_slow(id);
+        }
+    }
+
+    /// Announces something a client did to everyone else in its room.
+    fn announce(&mut self, id: ClientId, what: &str) {
+        let room = match self.clients.get(&id).and_then(|client| client.room.clone()) {
+            Some(room) => room,
+            None => return,
+        };
+        let line = format!("* {} {}", self.nick(id), what).into_bytes();
+        self.say(&room, Some(id), line);
+    }
+
+    fn rename(&mut self, id: ClientId, nick: String) {
+        if self.nicks.get(&nick) == Some(&id) {
+            self.tell(id, format!("You are already known as {}.", nick));
+            return;
+        }
+        if self.nicks.contains_key(&nick) {
+            self.tell(id, format!("The nickname {} is taken.", nick));
+            return;
+        }
+
+        let old = self.nick(id);
+        self.nicks.remove(&old);
+        self.nicks.insert(nick.clone(), id);
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.nick = nick.clone();
+        }
+        self.tell(id, format!("You are now known as {}.", nick));
+        self.announce(id, &format!("was {}", old));
+    }
+
+    fn join(&mut self, id: ClientId, room: String) {
+        let current = self.clients.get(&id).and_then(|client| client.room.as_ref());
+        if current == Some(&room) {
+            self.tell(id, format!("You are already in #{}.", room));
+            return;
+        }
 
-                // Remove those clients.
-                for id in to_remove {
-                    // The destructor of ClientHandle will kill the actor when
-                    // we remove it from the HashMap.
-                    data.clients.remove(&id);
+        self.leave(id, &format!("has left for #{}", room));
+        let history = {
+            let entry = self.rooms.entry(room.clone()).or_default();
+            entry.members.insert(id);
+            entry.history.iter().cloned().collect::<Vec<_>>()
+        };
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.room = Some(room.clone());
+        }
+
+        self.tell(id, format!("You joined #{}.", room));
+        if !history.is_empty() {
+            self.tell(id, format!("--- the last {} messages in #{} ---", history.len(), room));
+            for line in history {
+                let failed = match self.clients.get_mut(&id) {
+                    Some(client) => client.handle.send(FromServer::Message(line)).is_err(),
+                    None => return,
+                };
+                if failed {
+                    self.too_slow(id);
+                    return;
                 }
+            }
+            self.tell(id, "---");
+        }
+        self.announce(id, "has joined");
+    }
+
+    /// Takes a client out of its room, announcing why. Returns the room, if it
+    /// was in one.
+    fn leave(&mut self, id: ClientId, why: &str) -> Option<String> {
+        let room = self.clients.get(&id).and_then(|client| client.room.clone())?;
+
+        self.announce(id, why);
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.room = None;
+        }
+        let empty = match self.rooms.get_mut(&room) {
+            Some(entry) => {
+                entry.members.remove(&id);
+                entry.members.is_empty()
             },
-            // This message comes only from the accept loop.
-            ToServer::FatalError(err) => return Err(err),
+            None => false,
+        };
+        // Empty rooms are forgotten, along with their history, except for the
+        // lobby.
+        if empty && room != LOBBY {
+            self.rooms.remove(&room);
         }
+        Some(room)
     }
 
-    Ok(())
+    fn who(&mut self, id: ClientId) {
+        let room = self.clients.get(&id).and_then(|client| client.room.clone());
+        let (mut nicks, text) = match &room {
+            Some(room) => {
+                let members = self.rooms.get(room).map(|room| &room.members);
+                let nicks: Vec<_> = members.into_iter().flatten().map(|id| self.nick(*id)).collect();
+                (nicks, format!("In #{}: ", room))
+            },
+            None => {
+                let nicks: Vec<_> = self.nicks.keys().cloned().collect();
+                (nicks, "Online: ".to_string())
+            },
+        };
+        nicks.sort();
+        self.tell(id, text + &nicks.join(", "));
+    }
+
+    fn private(&mut self, from: ClientId, to: &str, text: Vec<u8>) {
+        let to_id = match self.nicks.get(to) {
+            Some(to_id) => *to_id,
+            None => {
+                self.tell(from, format!("No one is called {}.", to));
+                return;
+            },
+        };
+
+        let mut line = format!("[private] {}: ", self.nick(from)).into_bytes();
+        line.extend_from_slice(&text);
+        let failed = match self.clients.get_mut(&to_id) {
+            Some(client) => client.handle.send(FromServer::Message(line)).is_err(),
+            None => false,
+        };
+        if failed {
+            self.too_slow(to_id);
+        }
+    }
+
+    /// Disconnects a client whose outbox is full, telling it why.
+    fn too_slow(&mut self, id: ClientId) {
+        if let Some(client) = self.clients.get(&id) {
+            let reason = "You were disconnected, because too many messages were waiting for you.";
+            client.handle.disconnect(reason);
+        }
+        self.remove(id, "can't keep up and was disconnected");
+    }
+
+    /// Forgets a client, announcing why to its room. The destructor of
+    /// `ClientHandle` kills the actor, unless it is saying goodbye.
+    fn remove(&mut self, id: ClientId, why: &str) {
+        if !self.clients.contains_key(&id) {
+            return;
+        }
+        self.leave(id, why);
+        if let Some(client) = self.clients.remove(&id) {
+            self.nicks.remove(&client.nick);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    use crate::command;
+    use crate::limits::{Next, Outbox, SlowConsumer};
+
+    /// A client as the main loop sees it, with the outbox its actor would
+    /// write to the connection.
+    struct Peer {
+        id: ClientId,
+        outbox: Arc<Outbox>,
+    }
+
+    impl Peer {
+        fn new(id: usize) -> Self {
+            Peer {
+                id: ClientId(id),
+                outbox: Arc::new(Outbox::new(64 * 1024, SlowConsumer::Disconnect)),
+            }
+        }
+
+        fn connect(&self) -> ToServer {
+            ToServer::NewClient(ClientHandle::detached(self.id, self.outbox.clone()))
+        }
+
+        fn say(&self, text: &str) -> ToServer {
+            ToServer::Message(self.id, text.as_bytes().to_vec())
+        }
+
+        fn run(&self, line: &str) -> ToServer {
+            ToServer::Command(self.id, command::parse(line.as_bytes()).unwrap().unwrap())
+        }
+
+        /// The lines sent to this client since this was last called.
+        fn received(&self) -> Vec<String> {
+            let mut lines = Vec::new();
+            while let Some(Next::Message(msg)) = self.outbox.pop().now_or_never() {
+                lines.push(String::from_utf8(msg).unwrap());
+            }
+            lines
+        }
+    }
+
+    /// Runs the main loop until it has handled all of `msgs`.
+    async fn run_main_loop(msgs: Vec<ToServer>) {
+        let (send, recv) = channel(msgs.len());
+        for msg in msgs {
+            assert!(send.try_send(msg).is_ok());
+        }
+        drop(send);
+        main_loop(recv).await.unwrap();
+    }
+
+    fn welcome(nick: &str) -> String {
+        format!("Welcome, {}! Type /help for the commands.", nick)
+    }
+
+    #[tokio::test]
+    async fn rooms() {
+        let (alice, bob) = (Peer::new(0), Peer::new(1));
+        run_main_loop(vec![
+            alice.connect(),
+            bob.connect(),
+            alice.say("hi"),
+            bob.run("/join ops"),
+            alice.say("anyone?"),
+            bob.run("/join ops"),
+            alice.run("/who"),
+            bob.run("/leave"),
+            bob.say("hello?"),
+            bob.run("/who"),
+        ]).await;
+
+        assert_eq!(alice.received(), [
+            welcome("guest0"),
+            "You joined #lobby.".to_string(),
+            "* guest1 has joined".to_string(),
+            "* guest1 has left for #ops".to_string(),
+            "In #lobby: guest0".to_string(),
+        ]);
+        assert_eq!(bob.received(), [
+            welcome("guest1"),
+            "You joined #lobby.".to_string(),
+            "guest0: hi".to_string(),
+            "You joined #ops.".to_string(),
+            "You are already in #ops.".to_string(),
+            "You left #ops.".to_string(),
+            "You aren't in a room. Use /join <room>.".to_string(),
+            "Online: guest0, guest1".to_string(),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn joining_sends_the_recent_history() {
+        let (alice, bob) = (Peer::new(0), Peer::new(1));
+        let mut msgs = vec![alice.connect()];
+        msgs.extend((0..HISTORY_LEN + 5).map(|i| alice.say(&i.to_string())));
+        // The history of a room is forgotten once everyone has left it.
+        msgs.extend(vec![
+            alice.run("/join ops"),
+            alice.say("secret"),
+            alice.run("/leave"),
+            bob.connect(),
+            bob.run("/join ops"),
+        ]);
+        run_main_loop(msgs).await;
+
+        let mut expected = vec![
+            welcome("guest1"),
+            "You joined #lobby.".to_string(),
+            format!("--- the last {} messages in #lobby ---", HISTORY_LEN),
+        ];
+        expected.extend((5..HISTORY_LEN + 5).map(|i| format!("guest0: {}", i)));
+        expected.push("---".to_string());
+        expected.push("You joined #ops.".to_string());
+        assert_eq!(bob.received(), expected);
+    }
+
+    #[tokio::test]
+    async fn nicknames_are_unique() {
+        let (alice, bob, carol) = (Peer::new(0), Peer::new(1), Peer::new(2));
+        run_main_loop(vec![
+            alice.connect(),
+            alice.run("/nick guest1"),
+            // The guest name bob would get is taken.
+            bob.connect(),
+            bob.run("/nick guest1"),
+            bob.run("/nick guest1-2"),
+            bob.run("/nick guest0"),
+            // The name bob just gave up is free again.
+            carol.connect(),
+            carol.run("/nick guest1-2"),
+            carol.run("/who"),
+        ]).await;
+
+        assert_eq!(alice.received(), [
+            welcome("guest0"),
+            "You joined #lobby.".to_string(),
+            "You are now known as guest1.".to_string(),
+            "* guest1-2 has joined".to_string(),
+            "* guest0 was guest1-2".to_string(),
+            "* guest2 has joined".to_string(),
+            "* guest1-2 was guest2".to_string(),
+        ]);
+        assert_eq!(bob.received(), [
+            welcome("guest1-2"),
+            "You joined #lobby.".to_string(),
+            "The nickname guest1 is taken.".to_string(),
+            "You are already known as guest1-2.".to_string(),
+            "You are now known as guest0.".to_string(),
+            "* guest2 has joined".to_string(),
+            "* guest1-2 was guest2".to_string(),
+        ]);
+        assert_eq!(carol.received().last().unwrap(), "In #lobby: guest0, guest1, guest1-2");
+    }
+
+    #[tokio::test]
+    async fn private_messages() {
+        let (alice, bob, carol) = (Peer::new(0), Peer::new(1), Peer::new(2));
+        run_main_loop(vec![
+            alice.connect(),
+            bob.connect(),
+            carol.connect(),
+            bob.run("/join ops"),
+            bob.run("/nick bob"),
+            alice.run("/msg bob  psst,  over here"),
+            alice.run("/msg guest1 hello?"),
+        ]).await;
+
+        assert_eq!(alice.received().last().unwrap(), "No one is called guest1.");
+        assert_eq!(bob.received().last().unwrap(), "[private] guest0: psst,  over here");
+        assert!(carol.received().iter().all(|line| !line.contains("psst")));
+    }
 }
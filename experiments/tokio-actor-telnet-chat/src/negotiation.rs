@@ -0,0 +1,260 @@
+//! Telnet option negotiation, using the Q method of RFC 1143, which keeps both
+//! ends from looping when they ask for an option at the same time.
+//!
+//! Each option has two sides: whether we perform it, which the client asks for
+//! with DO and we agree to with WILL, and whether the client performs it, which
+//! we ask for with DO and it agrees to with WILL.
+
+use std::collections::HashMap;
+
+use crate::telnet::{option, Item, TERMINAL_TYPE_SEND};
+
+/// The state of one side of an option. While we wait for an answer, the other
+/// end may be asked to flip it back once it has answered, which is `queued`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Q {
+    No,
+    Yes,
+    WantNo { queued: bool },
+    WantYes { queued: bool },
+}
+
+/// What to answer with, if anything, after a change to one side of an option:
+/// `Some(true)` is WILL or DO, and `Some(false)` is WONT or DONT.
+type Answer = Option<bool>;
+
+impl Q {
+    /// The other end said it wants the option on.
+    fn got_enable(&mut self, agree: bool) -> Answer {
+        match *self {
+            Q::No if agree => {
+                *self = Q::Yes;
+                Some(true)
+            },
+            Q::No => Some(false),
+            Q::Yes => None,
+            // It shouldn't have answered our DONT with a WILL, so leave it
+            // off.
+            Q::WantNo { queued: false } => {
+                *self = Q::No;
+                None
+            },
+            Q::WantNo { queued: true } | Q::WantYes { queued: false } => {
+                *self = Q::Yes;
+                None
+            },
+            Q::WantYes { queued: true } => {
+                *self = Q::WantNo { queued: false };
+                Some(false)
+            },
+        }
+    }
+
+    /// The other end said it wants the option off, which it must be allowed.
+    fn got_disable(&mut self) -> Answer {
+        match *self {
+            Q::No => None,
+            Q::Yes => {
+                *self = Q::No;
+                Some(false)
+            },
+            Q::WantNo { queued: true } => {
+                *self = Q::WantYes { queued: false };
+                Some(true)
+            },
+            Q::WantNo { queued: false } | Q::WantYes { .. } => {
+                *self = Q::No;
+                None
+            },
+        }
+    }
+
+    /// We want the option on.
+    fn ask_enable(&mut self) -> Answer {
+        match *self {
+            Q::No => {
+                *self = Q::WantYes { queued: false };
+                Some(true)
+            },
+            Q::WantNo { queued: false } => {
+                *self = Q::WantNo { queued: true };
+                None
+            },
+            Q::WantYes { queued: true } => {
+                *self = Q::WantYes { queued: false };
+                None
+            },
+            Q::Yes | Q::WantNo { queued: true } | Q::WantYes { queued: false } => None,
+        }
+    }
+
+    /// We want the option off.
+    fn ask_disable(&mut self) -> Answer {
+        match *self {
+            Q::Yes => {
+                *self = Q::WantNo { queued: false };
+                Some(false)
+            },
+            Q::WantNo { queued: true } => {
+                *self = Q::WantNo { queued: false };
+                None
+            },
+            Q::WantYes { queued: false } => {
+                *self = Q::WantYes { queued: true };
+                None
+            },
+            Q::No | Q::WantNo { queued: false } | Q::WantYes { queued: true } => None,
+        }
+    }
+}
+
+/// Whether we agree to perform an option when the client asks. The client
+/// echoes what it types itself, so we refuse to echo.
+fn accept_local(option: u8) -> bool {
+    option == option::SUPPRESS_GO_AHEAD
+}
+
+/// Whether we agree to the client performing an option when it offers.
+fn accept_remote(option: u8) -> bool {
+    matches!(option, option::SUPPRESS_GO_AHEAD | option::NAWS | option::TERMINAL_TYPE)
+}
+
+/// The options of one connection, on both sides.
+#[derive(Debug, Default)]
+pub struct Negotiation {
+    local: HashMap<u8, Q>,
+    remote: HashMap<u8, Q>,
+}
+
+impl Negotiation {
+    pub fn new() -> Self {
+        Negotiation::default()
+    }
+
+    /// What the server asks for when a client connects: its window size, so
+    /// that long lines can be wrapped, and its terminal type.
+    pub fn start(&mut self) -> Vec<Item> {
+        let mut out = Vec::new();
+        out.extend(self.enable_remote(option::NAWS));
+        out.extend(self.enable_remote(option::TERMINAL_TYPE));
+        out
+    }
+
+    /// Handles a WILL, WONT, DO or DONT from the client, returning what to
+    /// send back. Other items are ignored.
+    pub fn receive(&mut self, item: &Item) -> Vec<Item> {
+        let mut out = Vec::new();
+        match *item {
+            Item::Will(option) => {
+                let was_on = self.remote(option);
+                let answer = side(&mut self.remote, option).got_enable(accept_remote(option));
+                out.extend(answer.map(|yes| if yes { Item::Do(option) } else { Item::Dont(option) }));
+                // The client only sends its terminal type when asked.
+                if option == option::TERMINAL_TYPE && !was_on && self.remote(option) {
+                    out.push(Item::Subnegotiation(option, vec![TERMINAL_TYPE_SEND]));
+                }
+            },
+            Item::Wont(option) => {
+                let answer = side(&mut self.remote, option).got_disable();
+                out.extend(answer.map(|yes| if yes { Item::Do(option) } else { Item::Dont(option) }));
+            },
+            Item::Do(option) => {
+                let answer = side(&mut self.local, option).got_enable(accept_local(option));
+                out.extend(answer.map(|yes| if yes { Item::Will(option) } else { Item::Wont(option) }));
+            },
+            Item::Dont(option) => {
+                let answer = side(&mut self.local, option).got_disable();
+                out.extend(answer.map(|yes| if yes { Item::Will(option) } else { Item::Wont(option) }));
+            },
+            _ => {},
+        }
+        out
+    }
+
+    /// Asks to perform an option ourselves.
+    pub fn enable_local(&mut self, option: u8) -> Option<Item> {
+        side(&mut self.local, option).ask_enable().map(|_| Item::Will(option))
+    }
+
+    /// Stops performing an option ourselves.
+    pub fn disable_local(&mut self, option: u8) -> Option<Item> {
+        side(&mut self.local, option).ask_disable().map(|_| Item::Wont(option))
+    }
+
+    /// Asks the client to perform an option.
+    pub fn enable_remote(&mut self, option: u8) -> Option<Item> {
+        side(&mut self.remote, option).ask_enable().map(|_| Item::Do(option))
+    }
+
+    /// Asks the client to stop performing an option.
+    pub fn disable_remote(&mut self, option: u8) -> Option<Item> {
+        side(&mut self.remote, option).ask_disable().map(|_| Item::Dont(option))
+    }
+
+    /// Whether we perform an option.
+    pub fn local(&self, option: u8) -> bool {
+        self.local.get(&option) == Some(&Q::Yes)
+    }
+
+    /// Whether the client performs an option.
+    pub fn remote(&self, option: u8) -> bool {
+        self.remote.get(&option) == Some(&Q::Yes)
+    }
+}
+
+fn side(sides: &mut HashMap<u8, Q>, option: u8) -> &mut Q {
+    sides.entry(option).or_insert(Q::No)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_are_negotiated() {
+        let mut neg = Negotiation::new();
+        assert_eq!(neg.start(), vec![Item::Do(option::NAWS), Item::Do(option::TERMINAL_TYPE)]);
+
+        // Agreeing to what we asked for needs no answer, except for asking for
+        // the terminal type.
+        assert_eq!(neg.receive(&Item::Will(option::NAWS)), vec![]);
+        assert_eq!(
+            neg.receive(&Item::Will(option::TERMINAL_TYPE)),
+            vec![Item::Subnegotiation(option::TERMINAL_TYPE, vec![TERMINAL_TYPE_SEND])],
+        );
+        assert!(neg.remote(option::NAWS));
+
+        // Offers are accepted or refused.
+        assert_eq!(neg.receive(&Item::Do(option::SUPPRESS_GO_AHEAD)), vec![Item::Will(3)]);
+        assert_eq!(neg.receive(&Item::Do(option::ECHO)), vec![Item::Wont(option::ECHO)]);
+        assert_eq!(neg.receive(&Item::Will(42)), vec![Item::Dont(42)]);
+        assert!(neg.local(option::SUPPRESS_GO_AHEAD));
+        assert!(!neg.local(option::ECHO));
+
+        // A repeated offer gets no answer, so the two ends don't loop.
+        assert_eq!(neg.receive(&Item::Do(option::SUPPRESS_GO_AHEAD)), vec![]);
+        assert_eq!(neg.receive(&Item::Wont(option::NAWS)), vec![Item::Dont(option::NAWS)]);
+        assert_eq!(neg.receive(&Item::Wont(option::NAWS)), vec![]);
+    }
+
+    #[test]
+    fn changes_of_mind_are_queued() {
+        let mut neg = Negotiation::new();
+        assert_eq!(neg.enable_local(option::ECHO), Some(Item::Will(option::ECHO)));
+        // Changing our mind before the answer waits for it, and changing it
+        // back cancels that.
+        assert_eq!(neg.disable_local(option::ECHO), None);
+        assert_eq!(neg.enable_local(option::ECHO), None);
+        assert_eq!(neg.disable_local(option::ECHO), None);
+
+        // Once the client agrees, we say we won't after all.
+        assert_eq!(neg.receive(&Item::Do(option::ECHO)), vec![Item::Wont(option::ECHO)]);
+        assert!(!neg.local(option::ECHO));
+        assert_eq!(neg.receive(&Item::Dont(option::ECHO)), vec![]);
+        assert!(!neg.local(option::ECHO));
+
+        assert_eq!(neg.enable_local(option::ECHO), Some(Item::Will(option::ECHO)));
+        assert_eq!(neg.receive(&Item::Do(option::ECHO)), vec![]);
+        assert!(neg.local(option::ECHO));
+    }
+}
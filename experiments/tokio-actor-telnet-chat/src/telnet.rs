@@ -1,21 +1,45 @@
 use std::io;
-use tokio_util::codec::Decoder;
+use tokio_util::codec::{Decoder, Encoder};
 
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, BufMut, BytesMut};
+
+/// The codes of the telnet options the server knows about.
+pub mod option {
+    pub const ECHO: u8 = 1;
+    pub const SUPPRESS_GO_AHEAD: u8 = 3;
+    pub const TERMINAL_TYPE: u8 = 24;
+    /// Negotiate About Window Size.
+    pub const NAWS: u8 = 31;
+}
+
+const IAC: u8 = 0xff;
+
+/// The longest subnegotiation we accept, to bound how much a client can make
+/// us buffer.
+const MAX_SUBNEGOTIATION: usize = 1024;
+
+/// TERMINAL-TYPE subnegotiation commands: the client sends IS with its
+/// terminal type, after the server asks with SEND.
+const TERMINAL_TYPE_IS: u8 = 0;
+pub const TERMINAL_TYPE_SEND: u8 = 1;
 
 pub struct TelnetCodec {
     current_line: Vec<u8>,
+    /// Whether the last byte ended a line with a carriage return, so that a
+    /// newline or NUL after it doesn't end another one.
+    after_cr: bool,
 }
 
 impl TelnetCodec {
     pub fn new() -> Self {
         TelnetCodec {
             current_line: Vec::with_capacity(1024),
+            after_cr: false,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Item {
     Line(Vec<u8>),
     SE,
@@ -25,11 +49,17 @@ pub enum Item {
     AbortOutput,
     AreYouThere,
     GoAhead,
-    SB,
     Will(u8),
     Wont(u8),
     Do(u8),
     Dont(u8),
+    /// The size of the client's window, from a NAWS subnegotiation. Either
+    /// is 0 if the client doesn't know.
+    WindowSize { width: u16, height: u16 },
+    /// The client's terminal type, from a TERMINAL-TYPE IS subnegotiation.
+    TerminalType(String),
+    /// Any other subnegotiation, with the option and its unescaped payload.
+    Subnegotiation(u8, Vec<u8>),
 }
 
 impl Decoder for TelnetCodec {
@@ -71,14 +101,23 @@ impl Decoder for TelnetCodec {
                 }
             } else {
                 let byte = src.get_u8();
+                let after_cr = std::mem::replace(&mut self.after_cr, false);
 
                 match byte {
-                    10 => {
+                    // Lines end with CR LF, or CR NUL in character mode, but
+                    // some clients send only one of CR or LF.
+                    0 | 10 if after_cr => {},
+                    10 | 13 => {
+                        self.after_cr = byte == 13;
                         let line = self.current_line.to_vec();
                         self.current_line.clear();
 
                         return Ok(Some(Item::Line(line)));
                     },
+                    // backspace and delete
+                    8 | 127 => {
+                        self.current_line.pop();
+                    },
                     0 ..= 31 => {
                         // ignore
                     },
@@ -89,6 +128,104 @@ impl Decoder for TelnetCodec {
     }
 }
 
+impl Encoder<Item> for TelnetCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match item {
+            Item::Line(line) => {
+                put_escaped(&line, dst);
+                dst.put_slice(b"\r\n");
+            },
+            Item::SE => dst.put_slice(&[IAC, 240]),
+            Item::DataMark => dst.put_slice(&[IAC, 242]),
+            Item::Break => dst.put_slice(&[IAC, 243]),
+            Item::InterruptProcess => dst.put_slice(&[IAC, 244]),
+            Item::AbortOutput => dst.put_slice(&[IAC, 245]),
+            Item::AreYouThere => dst.put_slice(&[IAC, 246]),
+            Item::GoAhead => dst.put_slice(&[IAC, 249]),
+            Item::Will(option) => dst.put_slice(&[IAC, 251, option]),
+            Item::Wont(option) => dst.put_slice(&[IAC, 252, option]),
+            Item::Do(option) => dst.put_slice(&[IAC, 253, option]),
+            Item::Dont(option) => dst.put_slice(&[IAC, 254, option]),
+            Item::WindowSize { width, height } => {
+                let mut data = width.to_be_bytes().to_vec();
+                data.extend_from_slice(&height.to_be_bytes());
+                put_subnegotiation(option::NAWS, &data, dst);
+            },
+            Item::TerminalType(name) => {
+                let mut data = vec![TERMINAL_TYPE_IS];
+                data.extend_from_slice(name.as_bytes());
+                put_subnegotiation(option::TERMINAL_TYPE, &data, dst);
+            },
+            Item::Subnegotiation(option, data) => put_subnegotiation(option, &data, dst),
+        }
+        Ok(())
+    }
+}
+
+/// Writes `data`, doubling the IAC bytes so they aren't read as commands.
+fn put_escaped(data: &[u8], dst: &mut BytesMut) {
+    for chunk in data.split_inclusive(|byte| *byte == IAC) {
+        dst.put_slice(chunk);
+        if chunk.last() == Some(&IAC) {
+            dst.put_u8(IAC);
+        }
+    }
+}
+
+fn put_subnegotiation(option: u8, data: &[u8], dst: &mut BytesMut) {
+    dst.put_slice(&[IAC, 250, option]);
+    put_escaped(data, dst);
+    dst.put_slice(&[IAC, 240]);
+}
+
+/// Turns a subnegotiation into an item, decoding the payloads of the options
+/// we know.
+fn subnegotiation(option: u8, data: Vec<u8>) -> Item {
+    match (option, data.as_slice()) {
+        (option::NAWS, &[w1, w2, h1, h2]) => Item::WindowSize {
+            width: u16::from_be_bytes([w1, w2]),
+            height: u16::from_be_bytes([h1, h2]),
+        },
+        (option::TERMINAL_TYPE, [TERMINAL_TYPE_IS, name @ ..]) => {
+            Item::TerminalType(String::from_utf8_lossy(name).into_owned())
+        },
+        _ => Item::Subnegotiation(option, data),
+    }
+}
+
+/// Parses a subnegotiation, which starts with IAC SB and the option, and ends
+/// with IAC SE.
+fn try_parse_subnegotiation(bytes: &[u8]) -> (ParseIacResult, usize) {
+    let mut data = Vec::new();
+    let mut i = 3;
+
+    while i < bytes.len() && i <= MAX_SUBNEGOTIATION {
+        if bytes[i] != IAC {
+            data.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        match bytes.get(i + 1) {
+            None => break,
+            Some(&IAC) => data.push(IAC),
+            Some(240) => return (ParseIacResult::Item(subnegotiation(bytes[2], data)), i + 2),
+            Some(cmd) => {
+                let err = format!("Unexpected IAC command {} in a subnegotiation.", cmd);
+                return (ParseIacResult::Invalid(err), 0);
+            },
+        }
+        i += 2;
+    }
+
+    if i > MAX_SUBNEGOTIATION {
+        let err = "Subnegotiation is too long.".to_string();
+        return (ParseIacResult::Invalid(err), 0);
+    }
+    (ParseIacResult::NeedMore, 0)
+}
+
 enum ParseIacResult {
     Invalid(String),
     NeedMore,
@@ -108,7 +245,7 @@ fn try_parse_iac(bytes: &[u8]) -> (ParseIacResult, usize) {
     if bytes[0] != 0xff {
         unreachable!();
     }
-    if is_three_byte_iac(bytes[1]) && bytes.len() < 3 {
+    if (is_three_byte_iac(bytes[1]) || bytes[1] == 250) && bytes.len() < 3 {
         return (ParseIacResult::NeedMore, 0);
     }
 
@@ -123,7 +260,7 @@ fn try_parse_iac(bytes: &[u8]) -> (ParseIacResult, usize) {
         247 => (ParseIacResult::EraseCharacter, 2),
         248 => (ParseIacResult::EraseLine, 2),
         249 => (ParseIacResult::Item(Item::GoAhead), 2),
-        250 => (ParseIacResult::Item(Item::SB), 2),
+        250 => try_parse_subnegotiation(bytes),
         251 => (ParseIacResult::Item(Item::Will(bytes[2])), 3),
         252 => (ParseIacResult::Item(Item::Wont(bytes[2])), 3),
         253 => (ParseIacResult::Item(Item::Do(bytes[2])), 3),
@@ -139,3 +276,65 @@ fn is_three_byte_iac(byte: u8) -> bool {
         _ => false,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(codec: &mut TelnetCodec, bytes: &[u8]) -> Vec<Item> {
+        let mut src = BytesMut::from(bytes);
+        let mut items = Vec::new();
+        while let Some(item) = codec.decode(&mut src).unwrap() {
+            items.push(item);
+        }
+        items
+    }
+
+    #[test]
+    fn encoded_items_decode_to_themselves() {
+        let items = vec![
+            Item::Line(b"hello \xff world".to_vec()),
+            Item::Will(option::ECHO),
+            Item::Dont(option::SUPPRESS_GO_AHEAD),
+            Item::WindowSize { width: 80, height: 0xff18 },
+            Item::TerminalType("XTERM-256COLOR".to_string()),
+            Item::Subnegotiation(option::TERMINAL_TYPE, vec![TERMINAL_TYPE_SEND]),
+            Item::AreYouThere,
+        ];
+
+        let mut codec = TelnetCodec::new();
+        let mut bytes = BytesMut::new();
+        for item in items.clone() {
+            codec.encode(item, &mut bytes).unwrap();
+        }
+
+        // Feeding the bytes one at a time checks that partial items wait for
+        // the rest.
+        let mut decoded = Vec::new();
+        let mut src = BytesMut::new();
+        for byte in bytes {
+            src.put_u8(byte);
+            while let Some(item) = codec.decode(&mut src).unwrap() {
+                decoded.push(item);
+            }
+        }
+        assert_eq!(decoded, items);
+    }
+
+    #[test]
+    fn lines_end_with_cr_or_lf() {
+        let mut codec = TelnetCodec::new();
+        let items = decode_all(&mut codec, b"one\r\ntwo\r\0thres\x7fe\nfour\r");
+        let lines = ["one", "two", "three", "four"];
+        let expected: Vec<_> = lines.iter().map(|line| Item::Line(line.as_bytes().to_vec())).collect();
+        assert_eq!(items, expected);
+    }
+
+    #[test]
+    fn long_subnegotiations_are_refused() {
+        let mut codec = TelnetCodec::new();
+        let mut src = BytesMut::from(&[IAC, 250, option::TERMINAL_TYPE][..]);
+        src.put_slice(&[b'x'; MAX_SUBNEGOTIATION]);
+        assert!(codec.decode(&mut src).is_err());
+    }
+}
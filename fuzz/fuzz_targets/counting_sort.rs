@@ -0,0 +1,10 @@
+#![no_main]
+extern crate lib_wc as wc;
+use libfuzzer_sys::fuzz_target;
+use wc::sorting::*;
+
+fuzz_target!(|data: &[u8]| {
+    let mut data = data.to_vec();
+    CountingSort::sort(&mut data);
+    assert!(is_sorted(&data));
+});
@@ -0,0 +1,15 @@
+#![no_main]
+extern crate lib_wc as wc;
+use libfuzzer_sys::fuzz_target;
+use wc::sorting::*;
+
+fuzz_target!(|data: &[u8]| {
+    // Splitting on a byte gives byte strings of many lengths
+    let mut strings: Vec<&[u8]> = data.split(|&b| b == b'\n').collect();
+    RadixSort::sort_bytes(&mut strings);
+    assert!(is_sorted(&strings));
+
+    let mut data = data.to_vec();
+    RadixSort::sort(&mut data);
+    assert!(is_sorted(&data));
+});
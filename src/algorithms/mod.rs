@@ -1 +1,3 @@
+pub mod searching;
+pub mod selection;
 pub mod sorting;
@@ -0,0 +1,424 @@
+//! Implementations of searching algorithms over sorted slices
+//!
+//! The `_by` versions take a comparator, and search slices sorted in its order, which are the
+//! slices that [`is_sorted_by`] accepts with the same comparator. They find where `target` is, or
+//! would be, in that order.
+//!
+//! [`is_sorted_by`]: crate::sorting::is_sorted_by
+use std::cmp::Ordering;
+use std::ops::Range;
+
+use crate::algorithms::sorting::RadixKey;
+
+/// Returns the index of the first element which is not less than `target`, which is where
+/// `target` would be inserted to keep the slice sorted, before any elements equal to it
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::searching::lower_bound;
+///
+/// let arr = [1, 2, 2, 2, 5];
+/// assert_eq!(lower_bound(&arr, &2), 1);
+/// assert_eq!(lower_bound(&arr, &3), 4);
+/// assert_eq!(lower_bound(&arr, &9), 5);
+/// ```
+pub fn lower_bound<T: Ord>(arr: &[T], target: &T) -> usize {
+    lower_bound_by(arr, target, T::cmp)
+}
+
+/// Returns the index of the first element which is not less than `target`, in the order given by
+/// a comparator
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::searching::lower_bound_by;
+///
+/// let arr = [9, 7, 7, 3];
+/// assert_eq!(lower_bound_by(&arr, &7, |a, b| b.cmp(a)), 1);
+/// ```
+pub fn lower_bound_by<T, F>(arr: &[T], target: &T, mut compare: F) -> usize
+where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    partition_point(arr, |x| compare(x, target) == Ordering::Less)
+}
+
+/// Returns the index of the first element which is greater than `target`, which is where
+/// `target` would be inserted to keep the slice sorted, after any elements equal to it
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::searching::upper_bound;
+///
+/// let arr = [1, 2, 2, 2, 5];
+/// assert_eq!(upper_bound(&arr, &2), 4);
+/// assert_eq!(upper_bound(&arr, &0), 0);
+/// ```
+pub fn upper_bound<T: Ord>(arr: &[T], target: &T) -> usize {
+    upper_bound_by(arr, target, T::cmp)
+}
+
+/// Returns the index of the first element which is greater than `target`, in the order given by
+/// a comparator
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::searching::upper_bound_by;
+///
+/// let arr = [9, 7, 7, 3];
+/// assert_eq!(upper_bound_by(&arr, &7, |a, b| b.cmp(a)), 3);
+/// ```
+pub fn upper_bound_by<T, F>(arr: &[T], target: &T, mut compare: F) -> usize
+where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    partition_point(arr, |x| compare(x, target) != Ordering::Greater)
+}
+
+/// Returns the range of indices of the elements equal to `target`, which is empty, and starts
+/// where `target` would be inserted, if there are none
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::searching::equal_range;
+///
+/// let arr = [1, 2, 2, 2, 5];
+/// assert_eq!(equal_range(&arr, &2), 1..4);
+/// assert_eq!(equal_range(&arr, &4), 4..4);
+/// ```
+pub fn equal_range<T: Ord>(arr: &[T], target: &T) -> Range<usize> {
+    equal_range_by(arr, target, T::cmp)
+}
+
+/// Returns the range of indices of the elements equal to `target`, in the order given by a
+/// comparator
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::searching::equal_range_by;
+///
+/// let words = ["a", "bb", "cc", "dd", "eee"];
+/// assert_eq!(equal_range_by(&words, &"xx", |a, b| a.len().cmp(&b.len())), 1..4);
+/// ```
+pub fn equal_range_by<T, F>(arr: &[T], target: &T, mut compare: F) -> Range<usize>
+where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    let start = lower_bound_by(arr, target, &mut compare);
+    // The elements before `start` are all less than `target`
+    let end = start + upper_bound_by(&arr[start..], target, compare);
+    start..end
+}
+
+/// Returns the index of the first element for which `pred` is false, given that it is true for
+/// every element before that one and false for every element after
+fn partition_point<T, P>(arr: &[T], mut pred: P) -> usize
+where
+    P: FnMut(&T) -> bool,
+{
+    let (mut lo, mut hi) = (0, arr.len());
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if pred(&arr[mid]) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+/// A binary search which compiles to a loop without any branches on the comparisons, so that the
+/// processor has nothing to mispredict. It returns the same index as [`lower_bound`].
+///
+/// The slice is halved each time by moving the start of the search past the first half or not,
+/// which can be done with a conditional move, and the loop always runs ⌈log₂ n⌉ times. This is
+/// quicker than [`lower_bound`] for slices which fit in the cache.
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::searching::branchless_lower_bound;
+///
+/// let arr: Vec<u32> = (0..100).map(|i| i * 2).collect();
+/// assert_eq!(branchless_lower_bound(&arr, &42), 21);
+/// assert_eq!(branchless_lower_bound(&arr, &43), 22);
+/// ```
+pub fn branchless_lower_bound<T: Ord>(arr: &[T], target: &T) -> usize {
+    branchless_lower_bound_by(arr, target, T::cmp)
+}
+
+/// A branchless binary search in the order given by a comparator, which returns the same index
+/// as [`lower_bound_by`]
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::searching::branchless_lower_bound_by;
+///
+/// let arr = [9, 7, 7, 3];
+/// assert_eq!(branchless_lower_bound_by(&arr, &7, |a, b| b.cmp(a)), 1);
+/// ```
+pub fn branchless_lower_bound_by<T, F>(arr: &[T], target: &T, mut compare: F) -> usize
+where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    if arr.is_empty() {
+        return 0;
+    }
+    // The answer is in `base..=base + size`
+    let mut base = 0;
+    let mut size = arr.len();
+    while size > 1 {
+        let half = size / 2;
+        let mid = base + half;
+        let is_less = compare(&arr[mid], target) == Ordering::Less;
+        base = if is_less { mid } else { base };
+        size -= half;
+    }
+    base + (compare(&arr[base], target) == Ordering::Less) as usize
+}
+
+/// An exponential (galloping) search, which looks at indices 0, 1, 3, 7, 15, and so on until it
+/// passes `target`, and then binary searches the last gap.
+///
+/// It takes O(log i) time to find an element at index `i`, so it suits targets near the start of
+/// very large slices, such as when merging or intersecting sorted slices, where each search starts
+/// where the last one left off.
+///
+/// Like [`slice::binary_search`], it returns `Ok` with the index of the first element equal to
+/// `target` if there is one, and `Err` with where it would be inserted otherwise.
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::searching::exponential_search;
+///
+/// let arr: Vec<u64> = (0..1_000_000).map(|i| i * 3).collect();
+/// assert_eq!(exponential_search(&arr, &30), Ok(10));
+/// assert_eq!(exponential_search(&arr, &31), Err(11));
+/// ```
+pub fn exponential_search<T: Ord>(arr: &[T], target: &T) -> Result<usize, usize> {
+    exponential_search_by(arr, target, T::cmp)
+}
+
+/// An exponential search in the order given by a comparator
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::searching::exponential_search_by;
+///
+/// let arr = [9, 7, 7, 3];
+/// assert_eq!(exponential_search_by(&arr, &7, |a, b| b.cmp(a)), Ok(1));
+/// assert_eq!(exponential_search_by(&arr, &1, |a, b| b.cmp(a)), Err(4));
+/// ```
+pub fn exponential_search_by<T, F>(arr: &[T], target: &T, mut compare: F) -> Result<usize, usize>
+where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    let index = gallop(arr.len(), |i| compare(&arr[i], target) == Ordering::Less);
+    match arr.get(index) {
+        Some(x) if compare(x, target) == Ordering::Equal => Ok(index),
+        _ => Err(index),
+    }
+}
+
+/// Returns the first index for which `pred` is false, given that it is true for every index
+/// before that one and false for every index after, without knowing how many indices there are.
+///
+/// This is an exponential search over a sequence which is only known by its elements, such as a
+/// monotonic function, or a sorted file or stream which is too large to know the length of. It
+/// takes O(log i) calls of `pred` to find index `i`, and returns `usize::MAX` if `pred` is true
+/// for every index below that.
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::searching::unbounded_partition_point;
+///
+/// // The least n such that n * n >= 1_000_000_007
+/// let root = unbounded_partition_point(|n| (n as u64).pow(2) < 1_000_000_007);
+/// assert_eq!(root, 31_623);
+/// ```
+pub fn unbounded_partition_point<P>(pred: P) -> usize
+where
+    P: FnMut(usize) -> bool,
+{
+    gallop(usize::MAX, pred)
+}
+
+/// Returns the first index in `0..len` for which `pred` is false, or `len` if there is none
+fn gallop<P>(len: usize, mut pred: P) -> usize
+where
+    P: FnMut(usize) -> bool,
+{
+    // `pred` is true before `lo`, and false at `hi`, if `hi` is in bounds
+    let mut lo = 0;
+    let mut hi = 1usize.min(len);
+    while hi < len && pred(hi - 1) {
+        lo = hi;
+        hi = hi.saturating_mul(2).min(len);
+    }
+
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if pred(mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+/// An interpolation search, which guesses where `target` is from where it falls between the
+/// least and greatest elements, as one looks up a word in a dictionary.
+///
+/// For uniformly distributed keys it takes O(log log n) probes. It is no slower than a binary
+/// search whatever the keys, as whenever a guess fails to halve the part of the slice left to
+/// search, the next probe is a binary search's.
+///
+/// Like [`exponential_search`], it returns `Ok` with the index of the first element equal to
+/// `target` if there is one, and `Err` with where it would be inserted otherwise.
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::searching::interpolation_search;
+///
+/// let arr: Vec<i64> = (-500..500).map(|i| i * 10).collect();
+/// assert_eq!(interpolation_search(&arr, &-4990), Ok(1));
+/// assert_eq!(interpolation_search(&arr, &5), Err(501));
+/// ```
+pub fn interpolation_search<T: RadixKey>(arr: &[T], target: &T) -> Result<usize, usize> {
+    // `to_bits` keeps the order of the keys, and so their distances, in an unsigned integer
+    let key = target.to_bits();
+    // The first element not less than `target` is in `lo..=hi`
+    let (mut lo, mut hi) = (0, arr.len());
+    let mut interpolate = true;
+
+    while lo < hi {
+        let (first, last) = (arr[lo].to_bits(), arr[hi - 1].to_bits());
+        // The answer is at one end, so `lo` is left there
+        if key <= first {
+            break;
+        }
+        if key > last {
+            lo = hi;
+            break;
+        }
+
+        let mid = if interpolate {
+            // `first < key <= last`, so the guess is in `lo + 1..hi`
+            let fraction = (key - first) as f64 / (last - first) as f64;
+            let guess = lo + (fraction * (hi - 1 - lo) as f64) as usize;
+            guess.clamp(lo + 1, hi - 1)
+        } else {
+            lo + (hi - lo) / 2
+        };
+
+        let len = hi - lo;
+        if arr[mid].to_bits() < key {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+        interpolate = hi - lo <= len / 2;
+    }
+
+    match arr.get(lo) {
+        Some(x) if x == target => Ok(lo),
+        _ => Err(lo),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    fn sorted(mut arr: Vec<i32>) -> Vec<i32> {
+        arr.sort();
+        arr
+    }
+
+    /// Where `target` goes in `arr`, by a linear scan
+    fn expected(arr: &[i32], target: i32) -> (usize, usize) {
+        let lower = arr.iter().take_while(|&&x| x < target).count();
+        let upper = arr.iter().take_while(|&&x| x <= target).count();
+        (lower, upper)
+    }
+
+    #[quickcheck]
+    fn bounds_like_a_scan(arr: Vec<i32>, target: i32) -> bool {
+        let arr = sorted(arr);
+        let (lower, upper) = expected(&arr, target);
+        lower_bound(&arr, &target) == lower
+            && upper_bound(&arr, &target) == upper
+            && equal_range(&arr, &target) == (lower..upper)
+            && branchless_lower_bound(&arr, &target) == lower
+    }
+
+    #[quickcheck]
+    fn searches_like_a_scan(arr: Vec<i32>, target: i32, present: bool) -> bool {
+        let arr = sorted(arr);
+        // Search for an element of the slice half of the time
+        let target = match arr.get(target.unsigned_abs() as usize % (arr.len() + 1)) {
+            Some(&x) if present => x,
+            _ => target,
+        };
+        let (lower, upper) = expected(&arr, target);
+        let result = if lower < upper { Ok(lower) } else { Err(lower) };
+        exponential_search(&arr, &target) == result && interpolation_search(&arr, &target) == result
+    }
+
+    #[quickcheck]
+    fn comparators_accept_what_is_sorted_by_accepts(arr: Vec<u8>, target: u8) -> bool {
+        let mut arr = arr;
+        arr.sort_by(|a, b| b.cmp(a));
+        let descending = |a: &u8, b: &u8| b.cmp(a);
+        let lower = arr.iter().take_while(|&&x| x > target).count();
+        let upper = arr.iter().take_while(|&&x| x >= target).count();
+        let result = if lower < upper { Ok(lower) } else { Err(lower) };
+
+        crate::sorting::is_sorted_by(&arr, descending)
+            && lower_bound_by(&arr, &target, descending) == lower
+            && upper_bound_by(&arr, &target, descending) == upper
+            && equal_range_by(&arr, &target, descending) == (lower..upper)
+            && branchless_lower_bound_by(&arr, &target, descending) == lower
+            && exponential_search_by(&arr, &target, descending) == result
+    }
+
+    #[test]
+    fn interpolation_on_skewed_keys() {
+        // Keys which are far from uniform make interpolation guess badly
+        let arr: Vec<u64> = (0..64).map(|i| 1 << i).chain([u64::MAX; 3]).collect();
+        for (i, x) in arr.iter().enumerate().take(64) {
+            assert_eq!(interpolation_search(&arr, x), Ok(i));
+            if i >= 2 {
+                assert_eq!(interpolation_search(&arr, &(x - 1)), Err(i));
+            }
+        }
+        assert_eq!(interpolation_search(&arr, &u64::MAX), Ok(64));
+        assert_eq!(interpolation_search(&[i8::MIN, 0, i8::MAX], &-1), Err(1));
+    }
+
+    #[test]
+    fn unbounded() {
+        assert_eq!(unbounded_partition_point(|_| false), 0);
+        assert_eq!(unbounded_partition_point(|i| i < 1), 1);
+        assert_eq!(unbounded_partition_point(|i| i < 1 << 40), 1 << 40);
+        assert_eq!(
+            unbounded_partition_point(|i| i < usize::MAX - 1),
+            usize::MAX - 1
+        );
+        assert_eq!(unbounded_partition_point(|_| true), usize::MAX);
+    }
+}
@@ -0,0 +1,446 @@
+//! Selection algorithms, which find the k-th least elements of a slice, or the greatest few of an
+//! iterator, without sorting all of it
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use crate::algorithms::sorting::heap_sort::{heap_sort_by, sift_down};
+use crate::algorithms::sorting::insertion_sort::insertion_sort_by;
+use crate::algorithms::sorting::less;
+use crate::algorithms::sorting::pdqsort::{choose_pivot, pdqsort_by};
+use crate::algorithms::sorting::quicksort::partition;
+
+/// Slices this short are insertion sorted rather than partitioned
+const MAX_INSERTION: usize = 10;
+
+/// Reorders a slice so that the element at `index` is the one which would be there if the slice
+/// were sorted, and returns it. The elements before it are no greater than it, and the elements
+/// after it are no less than it.
+///
+/// This is a quickselect, which takes O(n) time on average. When the pivots keep splitting the
+/// slice unevenly it switches to the median of medians for its pivots (introselect), so it takes
+/// O(n) time whatever the input.
+///
+/// # Panics
+///
+/// Panics if `index` is out of bounds.
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::selection::select_nth;
+///
+/// let mut arr = [9, 1, 8, 2, 7, 3];
+/// assert_eq!(*select_nth(&mut arr, 2), 3);
+/// assert!(arr[..2].iter().all(|&x| x <= 3));
+/// assert!(arr[3..].iter().all(|&x| x >= 3));
+/// ```
+pub fn select_nth<T: Ord>(arr: &mut [T], index: usize) -> &mut T {
+    select_nth_by(arr, index, T::cmp)
+}
+
+/// Reorders a slice as [`select_nth`] does, in the order given by a comparator
+///
+/// # Panics
+///
+/// Panics if `index` is out of bounds.
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::selection::select_nth_by;
+///
+/// let mut arr = [9, 1, 8, 2, 7, 3];
+/// assert_eq!(*select_nth_by(&mut arr, 0, |a, b| b.cmp(a)), 9);
+/// ```
+pub fn select_nth_by<T, F>(arr: &mut [T], index: usize, compare: F) -> &mut T
+where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    assert!(
+        index < arr.len(),
+        "index {} out of range for slice of length {}",
+        index,
+        arr.len()
+    );
+    // The number of unbalanced partitions allowed before switching to the median of medians
+    let limit = usize::BITS - arr.len().leading_zeros();
+    select(arr, index, &mut less(compare), limit);
+    &mut arr[index]
+}
+
+/// Moves the element which belongs at `index` there, with no greater elements before it and no
+/// less elements after it
+fn select<T, F>(mut arr: &mut [T], mut index: usize, is_less: &mut F, mut limit: u32)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    loop {
+        let len = arr.len();
+        if len <= MAX_INSERTION {
+            insertion_sort_by(arr, is_less);
+            return;
+        }
+
+        let pivot = if limit > 0 {
+            choose_pivot(arr, is_less).0
+        } else {
+            median_of_medians(arr, is_less)
+        };
+        // `partition` takes the last element as its pivot
+        arr.swap(pivot, len - 1);
+        let mid = partition(arr, 0, len as isize - 1, is_less) as usize;
+        if mid.min(len - mid) < len / 8 {
+            limit = limit.saturating_sub(1);
+        }
+
+        match index.cmp(&mid) {
+            Ordering::Equal => return,
+            Ordering::Less => arr = &mut { arr }[..mid],
+            Ordering::Greater => {
+                arr = &mut { arr }[mid + 1..];
+                index -= mid + 1;
+            }
+        }
+    }
+}
+
+/// Picks the median of the medians of groups of five as a pivot, which is greater than and less
+/// than at least 3/10 of the slice, and returns its index
+fn median_of_medians<T, F>(arr: &mut [T], is_less: &mut F) -> usize
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    // Move the median of each group to the front. The groups before `group` have been done, so
+    // whatever is swapped out of the front is in one of them.
+    let groups = arr.len() / 5;
+    for group in 0..groups {
+        let start = group * 5;
+        insertion_sort_by(&mut arr[start..start + 5], is_less);
+        arr.swap(group, start + 2);
+    }
+
+    let mid = groups / 2;
+    let limit = usize::BITS - groups.leading_zeros();
+    select(&mut arr[..groups], mid, is_less, limit);
+    mid
+}
+
+/// Sorts the `k` least elements of a slice into `arr[..k]`, leaving the rest in no particular
+/// order, in O(n + k log k) time. The sort is not stable.
+///
+/// If `k` is greater than the length of the slice, the whole slice is sorted.
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::selection::partial_sort;
+///
+/// let mut arr = [5, 9, 1, 7, 3, 8, 2];
+/// partial_sort(&mut arr, 3);
+/// assert_eq!(arr[..3], [1, 2, 3]);
+/// ```
+pub fn partial_sort<T: Ord>(arr: &mut [T], k: usize) {
+    partial_sort_by(arr, k, T::cmp)
+}
+
+/// Sorts the `k` least elements of a slice as [`partial_sort`] does, in the order given by a
+/// comparator
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::selection::partial_sort_by;
+///
+/// let mut arr = [5, 9, 1, 7, 3, 8, 2];
+/// partial_sort_by(&mut arr, 2, |a, b| b.cmp(a));
+/// assert_eq!(arr[..2], [9, 8]);
+/// ```
+pub fn partial_sort_by<T, F>(arr: &mut [T], k: usize, compare: F)
+where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    let k = k.min(arr.len());
+    if k == 0 {
+        return;
+    }
+    let mut is_less = less(compare);
+    let limit = usize::BITS - arr.len().leading_zeros();
+    select(arr, k - 1, &mut is_less, limit);
+    pdqsort_by(&mut arr[..k - 1], &mut is_less);
+}
+
+/// Returns the `k` greatest elements of an iterator, greatest first.
+///
+/// Only `k` elements are kept at a time, in a heap whose root is the least of them, so this takes
+/// O(n log k) time and O(k) space however long the iterator is.
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::selection::top_k;
+///
+/// let words = ["kiwi", "fig", "banana", "apple", "cherry"];
+/// assert_eq!(top_k(words.iter().map(|w| w.len()), 2), [6, 6]);
+/// assert_eq!(top_k(words, 3), ["kiwi", "fig", "cherry"]);
+/// ```
+pub fn top_k<T, I>(iter: I, k: usize) -> Vec<T>
+where
+    T: Ord,
+    I: IntoIterator<Item = T>,
+{
+    top_k_by(iter, k, T::cmp)
+}
+
+/// Returns the `k` greatest elements of an iterator, greatest first, in the order given by a
+/// comparator
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::selection::top_k_by;
+///
+/// // The three least, least first
+/// assert_eq!(top_k_by([4, 1, 5, 9, 2, 6], 3, |a, b| b.cmp(a)), [1, 2, 4]);
+/// ```
+pub fn top_k_by<T, I, F>(iter: I, k: usize, mut compare: F) -> Vec<T>
+where
+    I: IntoIterator<Item = T>,
+    F: FnMut(&T, &T) -> Ordering,
+{
+    let iter = iter.into_iter();
+    if k == 0 {
+        return Vec::new();
+    }
+
+    // Heap sorting by "greater" rather than "less" puts the least element at the root
+    let mut is_greater = |a: &T, b: &T| compare(a, b) == Ordering::Greater;
+    let mut heap = Vec::with_capacity(k.min(iter.size_hint().0));
+    for item in iter {
+        if heap.len() < k {
+            heap.push(item);
+            if heap.len() == k {
+                for node in (0..k / 2).rev() {
+                    sift_down(&mut heap, node, &mut is_greater);
+                }
+            }
+        } else if is_greater(&item, &heap[0]) {
+            heap[0] = item;
+            sift_down(&mut heap, 0, &mut is_greater);
+        }
+    }
+
+    heap_sort_by(&mut heap, &mut is_greater);
+    heap
+}
+
+/// The median of a stream of elements, kept up to date as each one arrives.
+///
+/// The lesser half of the elements is kept in a max-heap and the greater half in a min-heap, so
+/// the median is at the root of one of them. Adding an element takes O(log n) time, and finding
+/// the median O(1).
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::selection::RunningMedian;
+///
+/// let mut median = RunningMedian::new();
+/// median.push(5);
+/// median.push(1);
+/// assert_eq!(median.middle(), Some((&1, &5)));
+/// median.push(3);
+/// assert_eq!(median.median(), Some(&3));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RunningMedian<T> {
+    /// The lesser half of the elements, and the middle one if there are an odd number
+    lower: BinaryHeap<T>,
+    upper: BinaryHeap<Reverse<T>>,
+}
+
+impl<T: Ord> RunningMedian<T> {
+    pub fn new() -> Self {
+        Self {
+            lower: BinaryHeap::new(),
+            upper: BinaryHeap::new(),
+        }
+    }
+
+    /// Adds an element to the stream
+    pub fn push(&mut self, item: T) {
+        match self.lower.peek() {
+            Some(top) if item > *top => self.upper.push(Reverse(item)),
+            _ => self.lower.push(item),
+        }
+
+        // Keep the halves the same size, or the lower one larger by one
+        if self.lower.len() > self.upper.len() + 1 {
+            if let Some(top) = self.lower.pop() {
+                self.upper.push(Reverse(top));
+            }
+        } else if self.upper.len() > self.lower.len() {
+            if let Some(Reverse(bottom)) = self.upper.pop() {
+                self.lower.push(bottom);
+            }
+        }
+    }
+
+    /// The median of the elements so far, or the lesser of the two middle elements if there are
+    /// an even number of them
+    pub fn median(&self) -> Option<&T> {
+        self.lower.peek()
+    }
+
+    /// The two middle elements so far, which are the same element if there are an odd number of
+    /// them
+    pub fn middle(&self) -> Option<(&T, &T)> {
+        let lower = self.lower.peek()?;
+        match self.upper.peek() {
+            Some(Reverse(upper)) if self.upper.len() == self.lower.len() => Some((lower, upper)),
+            _ => Some((lower, lower)),
+        }
+    }
+
+    /// Takes the median of the elements so far, as [`RunningMedian::median`] finds it
+    pub fn into_median(mut self) -> Option<T> {
+        self.lower.pop()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lower.len() + self.upper.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lower.is_empty()
+    }
+}
+
+impl<T: Ord> Default for RunningMedian<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord> Extend<T> for RunningMedian<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.push(item);
+        }
+    }
+}
+
+impl<T: Ord> FromIterator<T> for RunningMedian<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut median = Self::new();
+        median.extend(iter);
+        median
+    }
+}
+
+/// Returns the median of an iterator's elements, or the lesser of the two middle elements if
+/// there are an even number of them, keeping only a [`RunningMedian`] as it goes
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::selection::median;
+///
+/// assert_eq!(median([7, 1, 4, 9, 2]), Some(4));
+/// assert_eq!(median([7, 1, 4, 9]), Some(4));
+/// assert_eq!(median(Vec::<u8>::new()), None);
+/// ```
+pub fn median<T, I>(iter: I) -> Option<T>
+where
+    T: Ord,
+    I: IntoIterator<Item = T>,
+{
+    iter.into_iter().collect::<RunningMedian<T>>().into_median()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::sorting::is_sorted;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn select_nth_like_sort(arr: Vec<i32>, index: usize) -> bool {
+        if arr.is_empty() {
+            return true;
+        }
+        let index = index % arr.len();
+        let mut sorted = arr.clone();
+        sorted.sort();
+
+        let mut arr = arr;
+        let nth = *select_nth(&mut arr, index);
+        nth == sorted[index]
+            && arr[..index].iter().all(|&x| x <= nth)
+            && arr[index + 1..].iter().all(|&x| x >= nth)
+    }
+
+    #[quickcheck]
+    fn partial_sort_like_sort(arr: Vec<i32>, k: usize) -> bool {
+        let k = k % (arr.len() + 2);
+        let mut sorted = arr.clone();
+        sorted.sort();
+
+        let mut arr = arr;
+        partial_sort(&mut arr, k);
+        let k = k.min(arr.len());
+        arr[..k] == sorted[..k] && {
+            arr.sort();
+            arr == sorted
+        }
+    }
+
+    #[quickcheck]
+    fn top_k_like_sort(arr: Vec<i32>, k: u8) -> bool {
+        let k = k as usize;
+        let mut sorted = arr.clone();
+        sorted.sort_by(|a, b| b.cmp(a));
+        sorted.truncate(k);
+        top_k(arr, k) == sorted
+    }
+
+    #[quickcheck]
+    fn median_like_sort(arr: Vec<i32>) -> bool {
+        let mut sorted = arr.clone();
+        sorted.sort();
+        let expected = sorted.get((sorted.len().max(1) - 1) / 2).copied();
+
+        let running: RunningMedian<i32> = arr.iter().copied().collect();
+        let middle = sorted.len().checked_sub(1).map(|last| {
+            let (lower, upper) = (last / 2, last.div_ceil(2));
+            (&sorted[lower], &sorted[upper])
+        });
+        median(arr) == expected && running.middle() == middle && running.len() == sorted.len()
+    }
+
+    #[test]
+    fn median_of_medians_pivots() {
+        let mut arr: Vec<u32> = (0..5000).map(|i| (i * 7919) % 5000).collect();
+        let mut less = |a: &u32, b: &u32| a < b;
+        // Without any partitions to spare, every pivot is a median of medians
+        for index in [0, 1, 2500, 4321, 4999] {
+            select(&mut arr, index, &mut less, 0);
+            assert_eq!(arr[index], index as u32);
+        }
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut arr = vec![3u8; 1000];
+        arr.extend(vec![1u8; 1000]);
+        assert_eq!(*select_nth(&mut arr, 999), 1);
+        assert_eq!(*select_nth(&mut arr, 1000), 3);
+        partial_sort(&mut arr, 1500);
+        assert!(is_sorted(&arr[..1500]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn select_out_of_bounds() {
+        select_nth(&mut [1, 2, 3], 3);
+    }
+}
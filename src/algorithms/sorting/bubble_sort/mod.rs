@@ -1,9 +1,14 @@
+use std::cmp::Ordering;
+
 use crate::algorithms::sorting::Sort;
 
 pub struct BubbleSort {}
 
-impl<T: Ord> Sort<T> for BubbleSort {
-    fn sort(arr: &mut [T]) {
+impl<T> Sort<T> for BubbleSort {
+    fn sort_by<F>(arr: &mut [T], mut compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
         if arr.is_empty() {
             return;
         }
@@ -12,7 +17,7 @@ impl<T: Ord> Sort<T> for BubbleSort {
         while !sorted {
             sorted = true;
             for i in 0..n - 1 {
-                if arr[i] > arr[i + 1] {
+                if compare(&arr[i], &arr[i + 1]) == Ordering::Greater {
                     arr.swap(i, i + 1);
                     sorted = false;
                 }
@@ -0,0 +1,110 @@
+use std::cmp::Ordering;
+
+use crate::algorithms::sorting::merge_sort::merge_sort_by;
+use crate::algorithms::sorting::radix_sort::lsd_radix_sort;
+use crate::algorithms::sorting::{less, RadixKey, Sort};
+
+/// A counting sort, which counts how many times each key occurs and then writes out that many of
+/// each, in order. It takes O(n + k) time, where k is the difference between the least and the
+/// greatest key, so it suits keys from a small range, such as bytes, ages or scores.
+///
+/// When the range is much wider than the slice is long, counting would take longer than sorting,
+/// so [`Sort::sort`] falls back to [`RadixSort`]. [`Sort::sort_by`] has to compare elements, so it
+/// falls back to [`MergeSort`].
+///
+/// [`RadixSort`]: crate::sorting::RadixSort
+/// [`MergeSort`]: crate::sorting::MergeSort
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::sorting::{CountingSort, Sort};
+///
+/// let mut ages: Vec<u8> = vec![34, 7, 61, 34, 19, 7];
+/// CountingSort::sort(&mut ages);
+/// assert_eq!(ages, [7, 7, 19, 34, 34, 61]);
+/// ```
+pub struct CountingSort {}
+
+impl<T: RadixKey> Sort<T> for CountingSort {
+    fn sort(arr: &mut [T])
+    where
+        T: Ord,
+    {
+        counting_sort(arr)
+    }
+
+    fn sort_by<F>(arr: &mut [T], compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        merge_sort_by(arr, &mut Vec::new(), &mut less(compare))
+    }
+}
+
+fn counting_sort<T: RadixKey>(arr: &mut [T]) {
+    let (min, max) = match (arr.iter().min(), arr.iter().max()) {
+        (Some(min), Some(max)) => (min.to_bits(), max.to_bits()),
+        _ => return,
+    };
+
+    // A radix sort makes a pass over the slice and its 256 counts for each byte of the keys
+    let radix_cost = T::BYTES as u128 * (arr.len() as u128 + 256);
+    if max - min >= radix_cost {
+        lsd_radix_sort(arr);
+        return;
+    }
+
+    let mut counts = vec![0usize; (max - min) as usize + 1];
+    for key in arr.iter() {
+        counts[(key.to_bits() - min) as usize] += 1;
+    }
+    let mut offset = 0;
+    for count in counts.iter_mut() {
+        let start = offset;
+        offset += *count;
+        *count = start;
+    }
+
+    // Writing the keys from a copy keeps equal keys in order
+    let keys = arr.to_vec();
+    for key in keys {
+        let start = &mut counts[(key.to_bits() - min) as usize];
+        arr[*start] = key;
+        *start += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::sorting::is_sorted;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn is_sorted_property(arr: Vec<i32>) -> bool {
+        let mut arr = arr;
+        CountingSort::sort(&mut arr);
+        is_sorted(&arr)
+    }
+
+    #[quickcheck]
+    fn sorts_like_std(arr: Vec<i16>) -> bool {
+        let mut expected = arr.clone();
+        expected.sort();
+        let mut arr = arr;
+        CountingSort::sort(&mut arr);
+        arr == expected
+    }
+
+    #[test]
+    fn wide_ranges() {
+        let mut arr = vec![u128::MAX, 0, 3, u128::MAX - 1, 3];
+        CountingSort::sort(&mut arr);
+        assert_eq!(arr, [0, 3, 3, u128::MAX - 1, u128::MAX]);
+
+        let mut arr = vec![i64::MIN, i64::MAX, 0];
+        CountingSort::sort(&mut arr);
+        assert_eq!(arr, [i64::MIN, 0, i64::MAX]);
+    }
+}
@@ -0,0 +1,147 @@
+//! A small LZ77 compressor for spilled runs, in the style of LZ4.
+//!
+//! A block is encoded as a sequence of literal bytes, each followed by a match, which repeats bytes
+//! written earlier in the block. Lengths and distances are varints:
+//!
+//! ```text
+//! literal length, literals, match length, match distance, ..., literal length, literals
+//! ```
+//!
+//! The block always ends with literals, which may be none.
+use std::io;
+
+/// Matches shorter than this take more bytes to encode than they save
+const MIN_MATCH: usize = 4;
+
+/// The compressor remembers where it last saw `1 << HASH_BITS` sequences of bytes
+const HASH_BITS: u32 = 12;
+
+/// Appends the encoding of `input` to `out`
+pub(crate) fn compress(input: &[u8], out: &mut Vec<u8>) {
+    // Where each sequence of `MIN_MATCH` bytes was last seen, plus one, with 0 meaning nowhere
+    let mut seen = vec![0usize; 1 << HASH_BITS];
+    let mut literals = 0;
+    let mut i = 0;
+
+    while i + MIN_MATCH <= input.len() {
+        let slot = &mut seen[hash(&input[i..i + MIN_MATCH])];
+        let candidate = slot.checked_sub(1);
+        *slot = i + 1;
+
+        match candidate {
+            Some(from) if input[from..from + MIN_MATCH] == input[i..i + MIN_MATCH] => {
+                let mut len = MIN_MATCH;
+                while i + len < input.len() && input[from + len] == input[i + len] {
+                    len += 1;
+                }
+                write_varint(out, i - literals);
+                out.extend_from_slice(&input[literals..i]);
+                write_varint(out, len);
+                write_varint(out, i - from);
+                i += len;
+                literals = i;
+            }
+            _ => i += 1,
+        }
+    }
+
+    write_varint(out, input.len() - literals);
+    out.extend_from_slice(&input[literals..]);
+}
+
+/// Appends the block of `len` bytes that `input` encodes to `out`
+pub(crate) fn decompress(mut input: &[u8], out: &mut Vec<u8>, len: usize) -> io::Result<()> {
+    let end = out.len() + len;
+    let start = out.len();
+    loop {
+        let len = read_varint(&mut input)?;
+        if len > input.len() || len > end - out.len() {
+            return Err(corrupt());
+        }
+        let (literals, rest) = input.split_at(len);
+        out.extend_from_slice(literals);
+        input = rest;
+        if input.is_empty() {
+            return if out.len() == end {
+                Ok(())
+            } else {
+                Err(corrupt())
+            };
+        }
+
+        let len = read_varint(&mut input)?;
+        let distance = read_varint(&mut input)?;
+        if len > end - out.len() || distance == 0 || distance > out.len() - start {
+            return Err(corrupt());
+        }
+        // The match may overlap the bytes it writes, so they are copied one at a time
+        let from = out.len() - distance;
+        out.reserve(len);
+        for i in from..from + len {
+            out.push(out[i]);
+        }
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (word.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Writes `value` seven bits at a time, least significant first, with the top bit of each byte
+/// set if more follow
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> io::Result<usize> {
+    let mut value = 0usize;
+    for shift in (0..usize::BITS).step_by(7) {
+        let (&byte, rest) = input.split_first().ok_or_else(corrupt)?;
+        *input = rest;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(corrupt())
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupt compressed run")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn round_trips(input: Vec<u8>, repeats: u8) -> bool {
+        // Repeating the input gives the compressor matches to find
+        let input = input.repeat(repeats as usize % 4 + 1);
+        let mut compressed = Vec::new();
+        compress(&input, &mut compressed);
+        let mut out = Vec::new();
+        decompress(&compressed, &mut out, input.len()).is_ok() && out == input
+    }
+
+    #[quickcheck]
+    fn rejects_garbage_without_panicking(input: Vec<u8>, len: u16) -> bool {
+        let mut out = Vec::new();
+        let _ = decompress(&input, &mut out, len as usize);
+        out.len() <= len as usize
+    }
+
+    #[test]
+    fn compresses_repetitive_input() {
+        let input = b"the same record, ".repeat(1000);
+        let mut compressed = Vec::new();
+        compress(&input, &mut compressed);
+        assert!(compressed.len() < input.len() / 10);
+    }
+}
@@ -0,0 +1,465 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::{any, env, error, fmt, io, mem, vec};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::algorithms::sorting::{MergeSort, Sort};
+use run::{Run, RunReader, RunWriter, TempDir, BLOCK_SIZE};
+
+mod compression;
+mod run;
+
+/// The most runs that are merged at once, each of which has a file open
+const MAX_FAN_IN: usize = 128;
+
+#[derive(Debug)]
+pub enum ExternalSortError {
+    IOError(io::Error),
+    Bincode(bincode::Error),
+}
+
+type Result<T> = std::result::Result<T, ExternalSortError>;
+
+/// How runs are compressed when they are spilled to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// Runs are written as they are encoded
+    #[default]
+    None,
+    /// Runs are compressed with a small LZ77 compressor, which suits records that repeat
+    /// themselves, such as text, at the cost of some time
+    Lz,
+}
+
+/// An external merge sort, for sorting more records than fit in memory.
+///
+/// Records are read from an iterator into a run until the run reaches the memory budget. The run
+/// is sorted with `S`, and spilled to a temporary file, encoded with bincode. Once every record has
+/// been read, the runs are merged, taking the least record of any run from a heap each time, into
+/// the iterator that [`ExternalSort::sort`] returns. Records which fit within the budget are never
+/// written to disk.
+///
+/// The memory a run takes is estimated as the size of its records plus the length of their
+/// encoding. Merging reads a block of 64 KiB from each run, so as many runs are merged at once as
+/// the budget has room for, and the runs are merged in more than one pass if there are more.
+///
+/// With a stable `S`, such as the default [`MergeSort`], the sort is stable.
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::sorting::{Compression, ExternalSort};
+///
+/// let words = ["pear", "fig", "apple", "kiwi", "date", "plum"].map(String::from);
+/// let sorted: Vec<String> = ExternalSort::new()
+///     .memory_budget(64)
+///     .compression(Compression::Lz)
+///     .sort(words)?
+///     .collect::<Result<_, _>>()?;
+/// assert_eq!(sorted, ["apple", "date", "fig", "kiwi", "pear", "plum"]);
+/// # Ok::<(), lib_wc::sorting::ExternalSortError>(())
+/// ```
+pub struct ExternalSort<S = MergeSort> {
+    memory_budget: usize,
+    temp_dir: PathBuf,
+    compression: Compression,
+    sort: PhantomData<S>,
+}
+
+impl ExternalSort {
+    /// An external sort using [`MergeSort`] for its runs, with a memory budget of 64 MiB, which
+    /// spills uncompressed runs to the system's temporary directory
+    pub fn new() -> Self {
+        ExternalSort {
+            memory_budget: 64 * 1024 * 1024,
+            temp_dir: env::temp_dir(),
+            compression: Compression::None,
+            sort: PhantomData,
+        }
+    }
+}
+
+impl Default for ExternalSort {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// `S` is only a marker, so cloning or printing the sort doesn't need it to be `Clone` or `Debug`
+
+impl<S> Clone for ExternalSort<S> {
+    fn clone(&self) -> Self {
+        ExternalSort {
+            memory_budget: self.memory_budget,
+            temp_dir: self.temp_dir.clone(),
+            compression: self.compression,
+            sort: PhantomData,
+        }
+    }
+}
+
+impl<S> fmt::Debug for ExternalSort<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExternalSort")
+            .field("memory_budget", &self.memory_budget)
+            .field("temp_dir", &self.temp_dir)
+            .field("compression", &self.compression)
+            .field("sort", &any::type_name::<S>())
+            .finish()
+    }
+}
+
+impl<S> ExternalSort<S> {
+    /// Sets roughly how many bytes of records are held in memory at once
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = bytes;
+        self
+    }
+
+    /// Sets the directory that runs are spilled to. Each sort makes a directory of its own in
+    /// it, which is removed along with the runs once the sorted records have been read.
+    pub fn temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = dir.into();
+        self
+    }
+
+    /// Sets how runs are compressed when they are spilled
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sorts runs with another sort
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use lib_wc::sorting::{ExternalSort, PatternDefeatingQuickSort};
+    ///
+    /// let sorted = ExternalSort::new()
+    ///     .with_sort::<PatternDefeatingQuickSort>()
+    ///     .sort((0..1000u32).rev())?
+    ///     .collect::<Result<Vec<_>, _>>()?;
+    /// assert!(sorted.into_iter().eq(0..1000));
+    /// # Ok::<(), lib_wc::sorting::ExternalSortError>(())
+    /// ```
+    pub fn with_sort<R>(self) -> ExternalSort<R> {
+        ExternalSort {
+            memory_budget: self.memory_budget,
+            temp_dir: self.temp_dir,
+            compression: self.compression,
+            sort: PhantomData,
+        }
+    }
+
+    /// Sorts the records of an iterator, returning an iterator of them in order.
+    ///
+    /// Reading the runs back can fail, so the iterator yields `Result`s, and ends after the
+    /// first error.
+    pub fn sort<T, I>(&self, records: I) -> Result<SortedIter<T>>
+    where
+        T: Serialize + DeserializeOwned + Ord,
+        I: IntoIterator<Item = T>,
+        S: Sort<T>,
+    {
+        let mut dir = None;
+        let mut runs = Vec::new();
+        let mut run = Vec::new();
+        let mut used = 0;
+
+        for record in records {
+            let size = mem::size_of::<T>() + bincode::serialized_size(&record)? as usize;
+            if !run.is_empty() && used + size > self.memory_budget {
+                let dir = match &mut dir {
+                    Some(dir) => dir,
+                    None => dir.insert(TempDir::new_in(&self.temp_dir)?),
+                };
+                runs.push(self.spill(&mut run, dir)?);
+                used = 0;
+            }
+            run.push(record);
+            used += size;
+        }
+
+        let dir = match dir {
+            Some(dir) => dir,
+            None => {
+                S::sort(&mut run);
+                return Ok(SortedIter {
+                    records: Records::InMemory(run.into_iter()),
+                    dir: None,
+                });
+            }
+        };
+        runs.push(self.spill(&mut run, &dir)?);
+        drop(run);
+
+        // Merging consecutive groups keeps the runs in the order they were read, so that the sort
+        // stays stable
+        let fan_in = (self.memory_budget / BLOCK_SIZE).clamp(2, MAX_FAN_IN);
+        while runs.len() > fan_in {
+            let mut merged = Vec::new();
+            let mut rest = runs.into_iter();
+            loop {
+                let group: Vec<Run> = rest.by_ref().take(fan_in).collect();
+                match group.len() {
+                    0 => break,
+                    1 => merged.extend(group),
+                    _ => {
+                        let mut writer = RunWriter::create(&dir, self.compression)?;
+                        for record in Merge::<T>::new(group, self.compression)? {
+                            writer.write(&record?)?;
+                        }
+                        merged.push(writer.finish()?);
+                    }
+                }
+            }
+            runs = merged;
+        }
+
+        Ok(SortedIter {
+            records: Records::Merge(Merge::new(runs, self.compression)?),
+            dir: Some(dir),
+        })
+    }
+
+    /// Sorts a run and writes it to a file, leaving it empty
+    fn spill<T>(&self, run: &mut Vec<T>, dir: &TempDir) -> Result<Run>
+    where
+        T: Serialize + Ord,
+        S: Sort<T>,
+    {
+        S::sort(run);
+        let mut writer = RunWriter::create(dir, self.compression)?;
+        for record in run.drain(..) {
+            writer.write(&record)?;
+        }
+        writer.finish()
+    }
+}
+
+/// The records sorted by [`ExternalSort::sort`], in order
+pub struct SortedIter<T> {
+    records: Records<T>,
+    // Dropped last, once the runs in it have been closed
+    dir: Option<TempDir>,
+}
+
+enum Records<T> {
+    InMemory(vec::IntoIter<T>),
+    Merge(Merge<T>),
+}
+
+impl<T: DeserializeOwned + Ord> Iterator for SortedIter<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.records {
+            Records::InMemory(records) => records.next().map(Ok),
+            Records::Merge(merge) => merge.next(),
+        }
+    }
+}
+
+/// A k-way merge of sorted runs
+struct Merge<T> {
+    /// The next record of each run which has any left, with the index of the run
+    heads: BinaryHeap<Reverse<(T, usize)>>,
+    runs: Vec<RunReader<T>>,
+    failed: bool,
+}
+
+impl<T: DeserializeOwned + Ord> Merge<T> {
+    fn new(runs: Vec<Run>, compression: Compression) -> Result<Self> {
+        let mut heads = BinaryHeap::with_capacity(runs.len());
+        let mut readers = Vec::with_capacity(runs.len());
+        for (i, run) in runs.into_iter().enumerate() {
+            let mut reader = RunReader::open(run, compression)?;
+            if let Some(record) = reader.next_record()? {
+                heads.push(Reverse((record, i)));
+            }
+            readers.push(reader);
+        }
+        Ok(Merge {
+            heads,
+            runs: readers,
+            failed: false,
+        })
+    }
+}
+
+impl<T: DeserializeOwned + Ord> Iterator for Merge<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        // Equal records are taken from the earliest run first, which keeps the merge stable
+        let Reverse((record, i)) = self.heads.pop()?;
+        match self.runs[i].next_record() {
+            Ok(Some(next)) => self.heads.push(Reverse((next, i))),
+            Ok(None) => {}
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(record))
+    }
+}
+
+impl fmt::Display for ExternalSortError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExternalSortError::IOError(e) => write!(f, "I/O error during external sort: {}", e),
+            ExternalSortError::Bincode(e) => {
+                write!(f, "failed to encode or decode a record: {}", e)
+            }
+        }
+    }
+}
+
+impl error::Error for ExternalSortError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ExternalSortError::IOError(e) => Some(e),
+            ExternalSortError::Bincode(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for ExternalSortError {
+    fn from(e: io::Error) -> Self {
+        ExternalSortError::IOError(e)
+    }
+}
+
+impl From<bincode::Error> for ExternalSortError {
+    fn from(e: bincode::Error) -> Self {
+        ExternalSortError::Bincode(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::sorting::{ByKey, PatternDefeatingQuickSort};
+    use quickcheck_macros::quickcheck;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+    use std::fs;
+
+    fn sorted<T, S>(sort: &ExternalSort<S>, records: Vec<T>) -> Vec<T>
+    where
+        T: Serialize + DeserializeOwned + Ord,
+        S: Sort<T>,
+    {
+        sort.sort(records).unwrap().collect::<Result<_>>().unwrap()
+    }
+
+    #[quickcheck]
+    fn sorts_like_std(records: Vec<(u16, String)>, compress: bool) -> bool {
+        let compression = if compress {
+            Compression::Lz
+        } else {
+            Compression::None
+        };
+        let sort = ExternalSort::new()
+            .memory_budget(256)
+            .compression(compression);
+
+        let mut expected = records.clone();
+        expected.sort();
+        sorted(&sort, records) == expected
+    }
+
+    #[test]
+    fn many_runs() {
+        let parent = TempDir::new_in(&env::temp_dir()).unwrap();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let records: Vec<u64> = (0..20_000).map(|_| rng.gen_range(0..5000)).collect();
+        let mut expected = records.clone();
+        expected.sort();
+
+        for compression in [Compression::None, Compression::Lz] {
+            // A run holds 64 records, and runs are merged in pairs
+            let sort = ExternalSort::new()
+                .memory_budget(1024)
+                .temp_dir(parent.path())
+                .compression(compression)
+                .with_sort::<PatternDefeatingQuickSort>();
+            let mut iter = sort.sort(records.clone()).unwrap();
+            assert_eq!(fs::read_dir(parent.path()).unwrap().count(), 1);
+
+            let first = iter.next().unwrap().unwrap();
+            let rest = iter.by_ref().collect::<Result<Vec<_>>>().unwrap();
+            assert_eq!([first], expected[..1]);
+            assert_eq!(rest, expected[1..]);
+
+            // The runs are removed along with the iterator
+            drop(iter);
+            assert_eq!(fs::read_dir(parent.path()).unwrap().count(), 0);
+        }
+    }
+
+    #[test]
+    fn fits_in_memory() {
+        let parent = TempDir::new_in(&env::temp_dir()).unwrap();
+        let sort = ExternalSort::new().temp_dir(parent.path());
+        let records: Vec<String> = (0..1000).rev().map(|i| format!("{:04}", i)).collect();
+        let iter = sort.sort(records).unwrap();
+        assert_eq!(fs::read_dir(parent.path()).unwrap().count(), 0);
+        assert!(iter
+            .map(Result::unwrap)
+            .eq((0..1000).map(|i| format!("{:04}", i))));
+    }
+
+    #[test]
+    fn is_stable() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let records: Vec<ByKey> = (0..5000)
+            .map(|tag| ByKey {
+                key: rng.gen_range(0..10),
+                tag,
+            })
+            .collect();
+        let mut expected = records.clone();
+        expected.sort_by_key(|r| r.key);
+
+        let sort = ExternalSort::new().memory_budget(512);
+        let sorted = sorted(&sort, records);
+        assert!(sorted
+            .iter()
+            .map(|r| r.tag)
+            .eq(expected.iter().map(|r| r.tag)));
+    }
+
+    #[test]
+    fn clone_and_debug_need_nothing_of_the_run_sort() {
+        let sort = ExternalSort::new()
+            .memory_budget(16)
+            .with_sort::<PatternDefeatingQuickSort>();
+        let copy = sort.clone();
+        assert_eq!(copy.memory_budget, 16);
+        assert!(format!("{:?}", copy).contains("PatternDefeatingQuickSort"));
+    }
+
+    #[test]
+    fn bad_temp_dir() {
+        let file = TempDir::new_in(&env::temp_dir()).unwrap();
+        let not_a_dir = file.path().join("file");
+        fs::write(&not_a_dir, b"").unwrap();
+
+        let sort = ExternalSort::new().memory_budget(0).temp_dir(&not_a_dir);
+        let err = sort.sort(vec![3, 1, 2]).err().unwrap();
+        assert!(matches!(err, ExternalSortError::IOError(_)));
+        assert!(err
+            .to_string()
+            .starts_with("I/O error during external sort: "));
+    }
+}
@@ -0,0 +1,218 @@
+//! Sorted runs, spilled to temporary files.
+//!
+//! Records are encoded with bincode into blocks of about [`BLOCK_SIZE`] bytes, each of which may be
+//! compressed. Each block is written after a header of two little-endian `u32`s: the length of the
+//! block as written, and the length of its records once decompressed.
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::compression::{compress, decompress};
+use super::{Compression, Result};
+
+/// Runs are written and read this many bytes of records at a time
+pub(crate) const BLOCK_SIZE: usize = 64 * 1024;
+
+/// A directory of runs, which is removed along with whatever is left in it when dropped
+#[derive(Debug)]
+pub(crate) struct TempDir {
+    path: PathBuf,
+    next_run: AtomicUsize,
+}
+
+impl TempDir {
+    /// Creates a directory in `parent`, which is created too if it doesn't exist
+    pub(crate) fn new_in(parent: &Path) -> io::Result<Self> {
+        static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+        fs::create_dir_all(parent)?;
+        loop {
+            let name = format!(
+                "lib-wc-sort-{}-{}",
+                process::id(),
+                NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = parent.join(name);
+            match fs::create_dir(&path) {
+                Ok(()) => {
+                    return Ok(TempDir {
+                        path,
+                        next_run: AtomicUsize::new(0),
+                    })
+                }
+                // Left behind by an earlier process with the same id
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn next_path(&self) -> PathBuf {
+        let run = self.next_run.fetch_add(1, Ordering::Relaxed);
+        self.path.join(format!("run-{}.bin", run))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A sorted run in a file, which is removed when the run is dropped
+#[derive(Debug)]
+pub(crate) struct Run {
+    path: PathBuf,
+    records: usize,
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Writes records, which must already be sorted, to a new run
+pub(crate) struct RunWriter<T> {
+    file: BufWriter<File>,
+    run: Run,
+    compression: Compression,
+    block: Vec<u8>,
+    compressed: Vec<u8>,
+    record: PhantomData<fn(&T)>,
+}
+
+impl<T: Serialize> RunWriter<T> {
+    pub(crate) fn create(dir: &TempDir, compression: Compression) -> Result<Self> {
+        let path = dir.next_path();
+        let file = BufWriter::new(File::create(&path)?);
+        Ok(RunWriter {
+            file,
+            run: Run { path, records: 0 },
+            compression,
+            block: Vec::new(),
+            compressed: Vec::new(),
+            record: PhantomData,
+        })
+    }
+
+    pub(crate) fn write(&mut self, record: &T) -> Result<()> {
+        bincode::serialize_into(&mut self.block, record)?;
+        self.run.records += 1;
+        if self.block.len() >= BLOCK_SIZE {
+            self.write_block()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> Result<Run> {
+        self.write_block()?;
+        self.file.flush()?;
+        Ok(self.run)
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let encoded = match self.compression {
+            Compression::None => &self.block,
+            Compression::Lz => {
+                self.compressed.clear();
+                compress(&self.block, &mut self.compressed);
+                &self.compressed
+            }
+        };
+        self.file.write_all(&header(encoded.len())?)?;
+        self.file.write_all(&header(self.block.len())?)?;
+        self.file.write_all(encoded)?;
+        self.block.clear();
+        Ok(())
+    }
+}
+
+fn header(len: usize) -> io::Result<[u8; 4]> {
+    let len = u32::try_from(len)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "record too large to spill"))?;
+    Ok(len.to_le_bytes())
+}
+
+/// Reads the records of a run back, in order
+pub(crate) struct RunReader<T> {
+    file: BufReader<File>,
+    compression: Compression,
+    remaining: usize,
+    block: Vec<u8>,
+    encoded: Vec<u8>,
+    pos: usize,
+    record: PhantomData<fn() -> T>,
+    // Dropped last, once the file is closed
+    run: Run,
+}
+
+impl<T: DeserializeOwned> RunReader<T> {
+    pub(crate) fn open(run: Run, compression: Compression) -> Result<Self> {
+        Ok(RunReader {
+            file: BufReader::new(File::open(&run.path)?),
+            compression,
+            remaining: run.records,
+            block: Vec::new(),
+            encoded: Vec::new(),
+            pos: 0,
+            record: PhantomData,
+            run,
+        })
+    }
+
+    /// Reads the next record, or `None` once they have all been read
+    pub(crate) fn next_record(&mut self) -> Result<Option<T>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        if self.pos == self.block.len() {
+            self.read_block()?;
+        }
+
+        let mut records = &self.block[self.pos..];
+        let record = bincode::deserialize_from(&mut records)?;
+        self.pos = self.block.len() - records.len();
+        self.remaining -= 1;
+        Ok(Some(record))
+    }
+
+    fn read_block(&mut self) -> io::Result<()> {
+        let mut header = [0; 8];
+        self.file.read_exact(&mut header)?;
+        let encoded_len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let block_len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+        self.block.clear();
+        self.pos = 0;
+        match self.compression {
+            Compression::None => read_exactly(&mut self.file, &mut self.block, encoded_len),
+            Compression::Lz => {
+                self.encoded.clear();
+                read_exactly(&mut self.file, &mut self.encoded, encoded_len)?;
+                decompress(&self.encoded, &mut self.block, block_len as usize)
+            }
+        }
+    }
+}
+
+fn read_exactly(file: &mut impl Read, buf: &mut Vec<u8>, len: u32) -> io::Result<()> {
+    let read = file.take(len as u64).read_to_end(buf)?;
+    if read < len as usize {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use std::cmp::Ordering;
+
+use crate::algorithms::sorting::{less, Sort};
+
+/// Sorts in place in O(n log n) time whatever the input, by building a max-heap
+/// and repeatedly moving its root to the end of the slice. The sort is not
+/// stable.
+pub struct HeapSort {}
+
+impl<T> Sort<T> for HeapSort {
+    fn sort_by<F>(arr: &mut [T], compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        heap_sort_by(arr, &mut less(compare))
+    }
+}
+
+pub(crate) fn heap_sort_by<T, F>(arr: &mut [T], is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let len = arr.len();
+    for node in (0..len / 2).rev() {
+        sift_down(arr, node, is_less);
+    }
+    for end in (1..len).rev() {
+        arr.swap(0, end);
+        sift_down(&mut arr[..end], 0, is_less);
+    }
+}
+
+/// Moves `arr[node]` down the heap until neither of its children is greater.
+pub(crate) fn sift_down<T, F>(arr: &mut [T], mut node: usize, is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    loop {
+        let mut child = 2 * node + 1;
+        if child >= arr.len() {
+            return;
+        }
+        if child + 1 < arr.len() && is_less(&arr[child], &arr[child + 1]) {
+            child += 1;
+        }
+        if !is_less(&arr[node], &arr[child]) {
+            return;
+        }
+        arr.swap(node, child);
+        node = child;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::sorting::is_sorted;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn is_sorted_property(arr: Vec<i32>) -> bool {
+        let mut arr = arr;
+        HeapSort::sort(&mut arr);
+        is_sorted(&arr)
+    }
+
+    #[test]
+    fn basic() {
+        let mut res = vec![10, 8, 4, 3, 1, 9, 2, 7, 5, 6];
+        HeapSort::sort(&mut res);
+        assert_eq!(res, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn empty() {
+        let mut res: Vec<i32> = vec![];
+        HeapSort::sort(&mut res);
+        assert!(res.is_empty());
+    }
+}
@@ -1,15 +1,29 @@
 pub struct InsertionSort {}
-use crate::algorithms::sorting::Sort;
+use std::cmp::Ordering;
 
-impl<T: Ord> Sort<T> for InsertionSort {
-    fn sort(arr: &mut [T]) {
-        let len = arr.len();
-        for i in 1..len {
-            let mut j = i;
-            while j > 0 && arr[j] < arr[j - 1] {
-                arr.swap(j, j - 1);
-                j -= 1;
-            }
+use crate::algorithms::sorting::{less, Sort};
+
+impl<T> Sort<T> for InsertionSort {
+    fn sort_by<F>(arr: &mut [T], compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        insertion_sort_by(arr, &mut less(compare))
+    }
+}
+
+/// Sorts `arr` by moving each element left past the ones that are greater,
+/// which is fast for short or nearly sorted slices. The sort is stable.
+pub(crate) fn insertion_sort_by<T, F>(arr: &mut [T], is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let len = arr.len();
+    for i in 1..len {
+        let mut j = i;
+        while j > 0 && is_less(&arr[j], &arr[j - 1]) {
+            arr.swap(j, j - 1);
+            j -= 1;
         }
     }
 }
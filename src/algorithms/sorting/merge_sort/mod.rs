@@ -0,0 +1,253 @@
+use std::cmp::Ordering;
+use std::{mem, ptr};
+
+use crate::algorithms::sorting::insertion_sort::insertion_sort_by;
+use crate::algorithms::sorting::{less, Sort};
+
+/// Slices this short are insertion sorted rather than split further
+const MAX_INSERTION: usize = 20;
+
+/// A stable top-down merge sort, which takes O(n log n) time and needs a buffer
+/// half the length of the slice.
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::sorting::{MergeSort, Sort};
+///
+/// let mut arr = [5, 3, 1, 4, 2];
+/// MergeSort::sort(&mut arr);
+/// assert_eq!(arr, [1, 2, 3, 4, 5]);
+/// ```
+pub struct MergeSort {}
+
+impl<T> Sort<T> for MergeSort {
+    fn sort_by<F>(arr: &mut [T], compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        merge_sort_by(arr, &mut Vec::new(), &mut less(compare))
+    }
+}
+
+impl MergeSort {
+    /// Sorts a slice using `buf` for scratch space, so that sorting many slices
+    /// allocates only as often as the buffer has to grow.
+    ///
+    /// `buf` is left empty, but keeps its capacity.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use lib_wc::sorting::MergeSort;
+    ///
+    /// let mut buf = Vec::new();
+    /// for mut arr in [vec![3, 1, 2], vec![9, 8, 7, 6]] {
+    ///     MergeSort::sort_with_buffer(&mut arr, &mut buf);
+    ///     assert!(lib_wc::sorting::is_sorted(&arr));
+    /// }
+    /// ```
+    pub fn sort_with_buffer<T: Ord>(arr: &mut [T], buf: &mut Vec<T>) {
+        merge_sort_by(arr, buf, &mut T::lt)
+    }
+}
+
+pub(crate) fn merge_sort_by<T, F>(arr: &mut [T], buf: &mut Vec<T>, is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    // Values of a zero-sized type can't be told apart, so any order is sorted
+    if mem::size_of::<T>() == 0 {
+        return;
+    }
+    // Only the spare capacity is used, so the buffer never owns the elements
+    // copied into it
+    buf.clear();
+    buf.reserve(arr.len() / 2);
+    // SAFETY: `buf` has room for `arr.len() / 2` elements, and doesn't overlap `arr`
+    unsafe { sort(arr, buf.as_mut_ptr(), is_less) }
+}
+
+/// Sorts `arr`, using `buf` for scratch space.
+///
+/// # Safety
+///
+/// `buf` must be valid for writes of `arr.len() / 2` elements, and must not overlap `arr`.
+pub(crate) unsafe fn sort<T, F>(arr: &mut [T], buf: *mut T, is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let len = arr.len();
+    if len <= MAX_INSERTION {
+        insertion_sort_by(arr, is_less);
+        return;
+    }
+
+    let mid = len / 2;
+    sort(&mut arr[..mid], buf, is_less);
+    sort(&mut arr[mid..], buf, is_less);
+    // The halves are already in order, which is common for nearly sorted input
+    if !is_less(&arr[mid], &arr[mid - 1]) {
+        return;
+    }
+    // SAFETY: `buf` has room for `mid` elements, and doesn't overlap `arr`
+    unsafe { merge(arr, mid, buf, is_less) }
+}
+
+/// Merges the sorted runs `arr[..mid]` and `arr[mid..]`.
+///
+/// The left run is moved into `buf`, and the runs are merged from there and
+/// from the right run into the front of `arr`. Everything written is behind
+/// what is still to be read from the right run, so nothing is overwritten.
+///
+/// # Safety
+///
+/// `buf` must be valid for writes of `mid` elements, and must not overlap `arr`.
+unsafe fn merge<T, F>(arr: &mut [T], mid: usize, buf: *mut T, is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let v = arr.as_mut_ptr();
+    let end = v.add(arr.len());
+    ptr::copy_nonoverlapping(v, buf, mid);
+
+    // If `is_less` panics, dropping the hole moves what is left of the left run
+    // back into the gap in `arr`, so that each element is still there once.
+    let mut hole = Hole {
+        start: buf,
+        end: buf.add(mid),
+        dest: v,
+    };
+    let mut right = v.add(mid);
+
+    while hole.start < hole.end && right < end {
+        // Taking from the left run unless the right is strictly less keeps the
+        // sort stable
+        let from = if is_less(&*right, &*hole.start) {
+            let from = right;
+            right = right.add(1);
+            from
+        } else {
+            let from = hole.start;
+            hole.start = hole.start.add(1);
+            from
+        };
+        ptr::copy_nonoverlapping(from, hole.dest, 1);
+        hole.dest = hole.dest.add(1);
+    }
+    // Dropping the hole moves what is left of the left run into place, and
+    // what is left of the right run is already there
+}
+
+/// The elements of `start..end` belong at `dest`
+pub(crate) struct Hole<T> {
+    pub(crate) start: *mut T,
+    pub(crate) end: *mut T,
+    pub(crate) dest: *mut T,
+}
+
+impl<T> Drop for Hole<T> {
+    fn drop(&mut self) {
+        // SAFETY: `start..end` is a run of initialized elements in the buffer,
+        // and `dest` is the gap of the same length that they were moved out of
+        unsafe {
+            let len = self.end.offset_from(self.start) as usize;
+            ptr::copy_nonoverlapping(self.start, self.dest, len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::sorting::{is_sorted, ByKey};
+    use quickcheck_macros::quickcheck;
+    use std::cmp::Ordering;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+    #[quickcheck]
+    fn is_sorted_property(arr: Vec<i32>) -> bool {
+        let mut arr = arr;
+        MergeSort::sort(&mut arr);
+        is_sorted(&arr)
+    }
+
+    #[quickcheck]
+    fn is_stable(arr: Vec<(u8, u32)>) -> bool {
+        let mut expected = arr.clone();
+        expected.sort_by_key(|(key, _)| *key);
+
+        let mut arr: Vec<_> = arr
+            .into_iter()
+            .map(|(key, tag)| ByKey { key, tag })
+            .collect();
+        MergeSort::sort(&mut arr);
+        arr.iter().map(|e| (e.key, e.tag)).eq(expected)
+    }
+
+    #[test]
+    fn basic() {
+        let mut res = vec![10, 8, 4, 3, 1, 9, 2, 7, 5, 6];
+        MergeSort::sort(&mut res);
+        assert_eq!(res, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn reuses_the_buffer() {
+        let mut buf = Vec::with_capacity(1000);
+        let mut arr: Vec<String> = (0..2000).rev().map(|i| i.to_string()).collect();
+        MergeSort::sort_with_buffer(&mut arr, &mut buf);
+        assert!(is_sorted(&arr));
+        assert!(buf.is_empty());
+        assert_eq!(buf.capacity(), 1000);
+    }
+
+    #[test]
+    fn panics_leave_every_element_in_place() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        static COMPARISONS: AtomicUsize = AtomicUsize::new(0);
+        static PANIC_AT: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(PartialEq, Eq)]
+        struct Bomb(u32);
+
+        impl PartialOrd for Bomb {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for Bomb {
+            fn cmp(&self, other: &Self) -> Ordering {
+                if COMPARISONS.fetch_add(1, SeqCst) == PANIC_AT.load(SeqCst) {
+                    panic!("boom");
+                }
+                self.0.cmp(&other.0)
+            }
+        }
+
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, SeqCst);
+            }
+        }
+
+        // Panicking at many points makes sure some of them are in a merge
+        for panic_at in (0..1200).step_by(37) {
+            PANIC_AT.store(panic_at, SeqCst);
+            COMPARISONS.store(0, SeqCst);
+            DROPS.store(0, SeqCst);
+
+            let mut arr: Vec<Bomb> = (0..200).rev().map(Bomb).collect();
+            let res = panic::catch_unwind(AssertUnwindSafe(|| MergeSort::sort(&mut arr)));
+            assert!(res.is_err());
+
+            let mut values: Vec<u32> = arr.iter().map(|b| b.0).collect();
+            values.sort_unstable();
+            assert!(values.into_iter().eq(0..200));
+            drop(arr);
+            assert_eq!(DROPS.load(SeqCst), 200);
+        }
+    }
+}
@@ -1,15 +1,113 @@
 //! Implementations of sorting algorithms
+use std::cmp::Ordering;
+
 pub use bubble_sort::BubbleSort;
+pub use counting_sort::CountingSort;
+pub use external_sort::{Compression, ExternalSort, ExternalSortError, SortedIter};
+pub use heap_sort::HeapSort;
 pub use insertion_sort::InsertionSort;
+pub use merge_sort::MergeSort;
+pub use parallel_merge_sort::ParallelMergeSort;
+pub use parallel_quicksort::ParallelQuickSort;
+pub use pdqsort::PatternDefeatingQuickSort;
 pub use quicksort::QuickSort;
+pub use radix_sort::{RadixKey, RadixSort};
 mod bubble_sort;
-mod insertion_sort;
-mod quicksort;
+mod counting_sort;
+mod external_sort;
+pub(crate) mod heap_sort;
+pub(crate) mod insertion_sort;
+mod merge_sort;
+mod parallel_merge_sort;
+mod parallel_quicksort;
+pub(crate) mod pdqsort;
+pub(crate) mod quicksort;
+mod radix_sort;
 
 /// Types that implement this trait can sort slices of data
-pub trait Sort<T: Ord> {
+///
+/// Implementations only have to provide [`Sort::sort_by`], which needs no `Ord` bound, as the
+/// comparator decides the order.
+pub trait Sort<T> {
     /// Sorts a slice of data
-    fn sort(arr: &mut [T]);
+    fn sort(arr: &mut [T])
+    where
+        T: Ord,
+    {
+        Self::sort_by(arr, T::cmp)
+    }
+
+    /// Sorts a slice of data in the order given by a comparator
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use lib_wc::sorting::{QuickSort, Sort};
+    ///
+    /// let mut arr = [1, 5, 2, 4, 3];
+    /// QuickSort::sort_by(&mut arr, |a, b| b.cmp(a));
+    /// assert_eq!(arr, [5, 4, 3, 2, 1]);
+    /// ```
+    fn sort_by<F>(arr: &mut [T], compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering;
+
+    /// Sorts a slice of data by a key extracted from each element
+    ///
+    /// The key is extracted each time two elements are compared, so use
+    /// [`Sort::sort_by_cached_key`] if that is expensive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use lib_wc::sorting::{MergeSort, Sort};
+    ///
+    /// let mut people = [("ann", 31), ("bob", 25), ("cat", 28)];
+    /// MergeSort::sort_by_key(&mut people, |(_, age)| *age);
+    /// assert_eq!(people, [("bob", 25), ("cat", 28), ("ann", 31)]);
+    /// ```
+    fn sort_by_key<K, F>(arr: &mut [T], mut f: F)
+    where
+        K: Ord,
+        F: FnMut(&T) -> K,
+    {
+        Self::sort_by(arr, |a, b| f(a).cmp(&f(b)))
+    }
+
+    /// Sorts a slice of data by a key extracted from each element, extracting each key only once
+    ///
+    /// The keys are sorted along with the index of their element, so this sort is stable even
+    /// when `Self` is not.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use lib_wc::sorting::{HeapSort, Sort};
+    ///
+    /// let mut arr = [-5i32, 4, 1, -3, 2];
+    /// HeapSort::sort_by_cached_key(&mut arr, |x| x.to_string());
+    /// assert_eq!(arr, [-3, -5, 1, 2, 4]);
+    /// ```
+    fn sort_by_cached_key<K, F>(arr: &mut [T], f: F)
+    where
+        K: Ord,
+        F: FnMut(&T) -> K,
+        Self: Sort<(K, usize)>,
+    {
+        let mut keys: Vec<(K, usize)> = arr.iter().map(f).zip(0..).collect();
+        <Self as Sort<(K, usize)>>::sort(&mut keys);
+
+        // `keys[i].1` is where the element that belongs at `i` was. Elements before `i` have been
+        // swapped out of their places, so follow where they went.
+        for i in 0..arr.len() {
+            let mut from = keys[i].1;
+            while from < i {
+                from = keys[from].1;
+            }
+            keys[i].1 = from;
+            arr.swap(i, from);
+        }
+    }
 }
 
 /// Checks if a slice of data is sorted
@@ -28,17 +126,87 @@ pub trait Sort<T: Ord> {
 /// assert_eq!(false, is_sorted(&mut arr));
 /// ```
 pub fn is_sorted<T: Ord>(arr: &[T]) -> bool {
-    for i in 1..arr.len() {
-        if arr[i - 1] > arr[i] {
-            return false;
-        }
+    is_sorted_by(arr, T::cmp)
+}
+
+/// Checks if a slice of data is sorted in the order given by a comparator, as [`Sort::sort_by`]
+/// would sort it
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::sorting::is_sorted_by;
+///
+/// assert!(is_sorted_by(&[3, 2, 2, 1], |a, b| b.cmp(a)));
+/// assert!(!is_sorted_by(&[1, 2], |a, b| b.cmp(a)));
+/// ```
+pub fn is_sorted_by<T, F>(arr: &[T], mut compare: F) -> bool
+where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    arr.windows(2)
+        .all(|pair| compare(&pair[0], &pair[1]) != Ordering::Greater)
+}
+
+/// Checks if a slice of data is sorted by a key extracted from each element, as
+/// [`Sort::sort_by_key`] would sort it
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::sorting::is_sorted_by_key;
+///
+/// assert!(is_sorted_by_key(&["d", "a", "cc", "bb"], |s| s.len()));
+/// ```
+pub fn is_sorted_by_key<T, K, F>(arr: &[T], mut f: F) -> bool
+where
+    K: Ord,
+    F: FnMut(&T) -> K,
+{
+    is_sorted_by(arr, |a, b| f(a).cmp(&f(b)))
+}
+
+/// Turns a comparator into the `is_less` function the implementations are written with
+pub(crate) fn less<T>(mut compare: impl FnMut(&T, &T) -> Ordering) -> impl FnMut(&T, &T) -> bool {
+    move |a, b| compare(a, b) == Ordering::Less
+}
+
+/// Compares only by key, so that the tags show whether equal keys kept their order
+#[cfg(test)]
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct ByKey {
+    pub(crate) key: u8,
+    pub(crate) tag: u32,
+}
+
+#[cfg(test)]
+impl PartialEq for ByKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+#[cfg(test)]
+impl Eq for ByKey {}
+
+#[cfg(test)]
+impl PartialOrd for ByKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+impl Ord for ByKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key)
     }
-    true
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use quickcheck_macros::quickcheck;
 
     #[test]
     fn test_is_sorted() {
@@ -52,4 +220,54 @@ mod tests {
         let mut arr = [1, 2, 3, 4, 5, 4];
         assert_eq!(false, is_sorted(&mut arr));
     }
+
+    /// Checks the comparator methods of `S` against the standard library's
+    fn sorts_like_std<S>(arr: &[(u8, u8)]) -> bool
+    where
+        S: Sort<(u8, u8)> + Sort<(String, usize)>,
+    {
+        let mut expected = arr.to_vec();
+        expected.sort_by(|a, b| b.cmp(a));
+        let mut descending = arr.to_vec();
+        S::sort_by(&mut descending, |a, b| b.cmp(a));
+
+        // Elements with equal keys may be in any order, unless the sort is stable
+        let mut by_key = arr.to_vec();
+        S::sort_by_key(&mut by_key, |(_, b)| *b);
+        let mut permuted = by_key.clone();
+        permuted.sort();
+        let mut sorted = arr.to_vec();
+        sorted.sort();
+
+        let mut expected_cached = arr.to_vec();
+        expected_cached.sort_by_cached_key(|(_, b)| b.to_string());
+        let mut cached = arr.to_vec();
+        S::sort_by_cached_key(&mut cached, |(_, b)| b.to_string());
+
+        descending == expected
+            && is_sorted_by_key(&by_key, |(_, b)| *b)
+            && permuted == sorted
+            && cached == expected_cached
+    }
+
+    #[quickcheck]
+    fn comparators_sort_like_std(arr: Vec<(u8, u8)>) -> bool {
+        sorts_like_std::<BubbleSort>(&arr)
+            && sorts_like_std::<InsertionSort>(&arr)
+            && sorts_like_std::<QuickSort>(&arr)
+            && sorts_like_std::<MergeSort>(&arr)
+            && sorts_like_std::<HeapSort>(&arr)
+            && sorts_like_std::<PatternDefeatingQuickSort>(&arr)
+            && sorts_like_std::<ParallelMergeSort>(&arr)
+            && sorts_like_std::<ParallelQuickSort>(&arr)
+    }
+
+    #[test]
+    fn merge_sort_by_key_is_stable() {
+        let mut arr: Vec<(u32, u32)> = (0..1000).rev().map(|i| (i % 7, i)).collect();
+        let mut expected = arr.clone();
+        expected.sort_by_key(|(key, _)| *key);
+        MergeSort::sort_by_key(&mut arr, |(key, _)| *key);
+        assert_eq!(arr, expected);
+    }
 }
@@ -0,0 +1,267 @@
+use std::cmp::Ordering;
+use std::mem::{self, MaybeUninit};
+use std::{ptr, slice};
+
+use crate::algorithms::sorting::merge_sort::{self, merge_sort_by, Hole};
+use crate::algorithms::sorting::{less, Sort};
+
+/// Slices this short are sorted on one thread, as splitting them further costs more than it saves
+const SEQUENTIAL_CUTOFF: usize = 1 << 13;
+
+/// A stable merge sort which sorts the two halves of a slice on rayon's thread pool, in parallel,
+/// before merging them. Long merges are split in two and done in parallel as well, and the whole
+/// sort shares one buffer as long as the slice.
+///
+/// [`Sort::sort`] sorts in parallel. A comparator has to be shared between threads for that, so
+/// it has to be `Fn` and `Sync` and is given to [`ParallelMergeSort::par_sort_by`].
+/// [`Sort::sort_by`] takes any `FnMut`, and so sorts on the current thread.
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::sorting::{ParallelMergeSort, Sort};
+///
+/// let mut arr: Vec<u64> = (0..100_000).rev().collect();
+/// ParallelMergeSort::sort(&mut arr);
+/// assert!(arr.iter().copied().eq(0..100_000));
+/// ```
+pub struct ParallelMergeSort {}
+
+impl<T: Send> Sort<T> for ParallelMergeSort {
+    fn sort(arr: &mut [T])
+    where
+        T: Ord,
+    {
+        ParallelMergeSort::par_sort_by(arr, T::cmp)
+    }
+
+    fn sort_by<F>(arr: &mut [T], compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        merge_sort_by(arr, &mut Vec::new(), &mut less(compare))
+    }
+}
+
+impl ParallelMergeSort {
+    /// Sorts a slice in parallel, in the order given by a comparator
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use lib_wc::sorting::ParallelMergeSort;
+    ///
+    /// let mut arr: Vec<i32> = (0..50_000).collect();
+    /// ParallelMergeSort::par_sort_by(&mut arr, |a, b| b.cmp(a));
+    /// assert!(arr.iter().copied().eq((0..50_000).rev()));
+    /// ```
+    pub fn par_sort_by<T, F>(arr: &mut [T], compare: F)
+    where
+        T: Send,
+        F: Fn(&T, &T) -> Ordering + Sync,
+    {
+        // Values of a zero-sized type can't be told apart, so any order is sorted
+        if mem::size_of::<T>() == 0 {
+            return;
+        }
+        // Only the spare capacity is used, so the buffer never owns the elements copied into it
+        let mut buf = Vec::with_capacity(arr.len());
+        let buf = &mut buf.spare_capacity_mut()[..arr.len()];
+        par_merge_sort(arr, buf, &|a: &T, b: &T| compare(a, b) == Ordering::Less)
+    }
+}
+
+/// Sorts `arr`, using `buf`, which is as long as it, for scratch space
+fn par_merge_sort<T, F>(arr: &mut [T], buf: &mut [MaybeUninit<T>], is_less: &F)
+where
+    T: Send,
+    F: Fn(&T, &T) -> bool + Sync,
+{
+    let len = arr.len();
+    if len <= SEQUENTIAL_CUTOFF {
+        // SAFETY: `buf` has room for `len` elements, and doesn't overlap `arr`
+        unsafe { merge_sort::sort(arr, buf.as_mut_ptr().cast(), &mut |a, b| is_less(a, b)) };
+        return;
+    }
+
+    let mid = len / 2;
+    let (left, right) = arr.split_at_mut(mid);
+    let (left_buf, right_buf) = buf.split_at_mut(mid);
+    rayon::join(
+        || par_merge_sort(left, left_buf, is_less),
+        || par_merge_sort(right, right_buf, is_less),
+    );
+    // The halves are already in order, which is common for nearly sorted input
+    if !is_less(&arr[mid], &arr[mid - 1]) {
+        return;
+    }
+
+    // The runs are copied into `buf` and merged from there back into `arr`. If `is_less` panics,
+    // dropping the hole moves the copy back, so that each element is still in `arr` once.
+    //
+    // SAFETY: `buf` has room for `len` elements, and doesn't overlap `arr`. The merge only
+    // overwrites `arr` with copies of the elements in `buf`.
+    unsafe {
+        let v = arr.as_mut_ptr();
+        let copy = buf.as_mut_ptr().cast::<T>();
+        ptr::copy_nonoverlapping(v, copy, len);
+        let hole = Hole {
+            start: copy,
+            end: copy.add(len),
+            dest: v,
+        };
+        let (left, right) = slice::from_raw_parts_mut(copy, len).split_at_mut(mid);
+        par_merge(left, right, slice::from_raw_parts_mut(v, len), is_less);
+        mem::forget(hole);
+    }
+}
+
+/// Merges the sorted runs `left` and `right` into `dest`. A long merge is split into two shorter
+/// ones, which are done in parallel.
+///
+/// # Safety
+///
+/// `dest` must be as long as both runs together. Its elements are overwritten without being
+/// dropped, so they must be owned somewhere else, and the runs are left holding copies.
+unsafe fn par_merge<T, F>(left: &mut [T], right: &mut [T], dest: &mut [T], is_less: &F)
+where
+    T: Send,
+    F: Fn(&T, &T) -> bool + Sync,
+{
+    if dest.len() <= SEQUENTIAL_CUTOFF {
+        merge(left, right, dest, is_less);
+        return;
+    }
+
+    // The middle element of the longer run splits the other run where it would be merged into
+    // it: the elements of `right` which are equal to it go after it, and those of `left` before,
+    // which keeps the merge stable.
+    let (i, j) = if left.len() >= right.len() {
+        let i = left.len() / 2;
+        (i, right.partition_point(|r| is_less(r, &left[i])))
+    } else {
+        let j = right.len() / 2;
+        (left.partition_point(|l| !is_less(&right[j], l)), j)
+    };
+    let (left_lo, left_hi) = left.split_at_mut(i);
+    let (right_lo, right_hi) = right.split_at_mut(j);
+    let (dest_lo, dest_hi) = dest.split_at_mut(i + j);
+    rayon::join(
+        || par_merge(left_lo, right_lo, dest_lo, is_less),
+        || par_merge(left_hi, right_hi, dest_hi, is_less),
+    );
+}
+
+/// Merges the sorted runs `left` and `right` into `dest`, on the current thread.
+///
+/// # Safety
+///
+/// As for [`par_merge`].
+unsafe fn merge<T, F>(left: &[T], right: &[T], dest: &mut [T], is_less: &F)
+where
+    F: Fn(&T, &T) -> bool,
+{
+    let dest = dest.as_mut_ptr();
+    let (mut l, mut r) = (0, 0);
+    while l < left.len() && r < right.len() {
+        // Taking from the left run unless the right is strictly less keeps the sort stable
+        let from = if is_less(&right[r], &left[l]) {
+            r += 1;
+            &right[r - 1]
+        } else {
+            l += 1;
+            &left[l - 1]
+        };
+        ptr::copy_nonoverlapping(from, dest.add(l + r - 1), 1);
+    }
+    ptr::copy_nonoverlapping(left[l..].as_ptr(), dest.add(l + r), left.len() - l);
+    ptr::copy_nonoverlapping(
+        right[r..].as_ptr(),
+        dest.add(left.len() + r),
+        right.len() - r,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::sorting::is_sorted;
+    use quickcheck_macros::quickcheck;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+    #[quickcheck]
+    fn is_sorted_property(arr: Vec<i32>) -> bool {
+        let mut arr = arr;
+        ParallelMergeSort::sort(&mut arr);
+        is_sorted(&arr)
+    }
+
+    #[test]
+    fn sorts_across_threads() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut arr: Vec<u32> = (0..200_000).map(|_| rng.gen()).collect();
+        let mut expected = arr.clone();
+        expected.sort_unstable();
+        ParallelMergeSort::sort(&mut arr);
+        assert_eq!(arr, expected);
+    }
+
+    #[test]
+    fn is_stable() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut arr: Vec<(u8, u32)> = (0..100_000).map(|i| (rng.gen(), i)).collect();
+        let mut expected = arr.clone();
+        expected.sort_by_key(|(key, _)| *key);
+        ParallelMergeSort::par_sort_by(&mut arr, |a, b| a.0.cmp(&b.0));
+        assert_eq!(arr, expected);
+    }
+
+    #[test]
+    fn panics_leave_every_element_in_place() {
+        static COMPARISONS: AtomicUsize = AtomicUsize::new(0);
+        static PANIC_AT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+        #[derive(PartialEq, Eq)]
+        struct Bomb(u32);
+
+        impl PartialOrd for Bomb {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for Bomb {
+            fn cmp(&self, other: &Self) -> Ordering {
+                if COMPARISONS.fetch_add(1, SeqCst) == PANIC_AT.load(SeqCst) {
+                    panic!("boom");
+                }
+                self.0.cmp(&other.0)
+            }
+        }
+
+        let mut values: Vec<u32> = (0..4 * SEQUENTIAL_CUTOFF as u32).collect();
+        values.shuffle(&mut ChaCha8Rng::seed_from_u64(2));
+        let bombs = || values.iter().copied().map(Bomb).collect::<Vec<_>>();
+
+        ParallelMergeSort::sort(&mut bombs());
+        let total = COMPARISONS.load(SeqCst);
+
+        // The last comparisons are in the merges at the top, which are done in parallel
+        for panic_at in [0, total / 3, total - total / 10, total - 1] {
+            COMPARISONS.store(0, SeqCst);
+            PANIC_AT.store(panic_at, SeqCst);
+
+            let mut arr = bombs();
+            let res = panic::catch_unwind(AssertUnwindSafe(|| ParallelMergeSort::sort(&mut arr)));
+            assert!(res.is_err());
+
+            let mut after: Vec<u32> = arr.iter().map(|b| b.0).collect();
+            after.sort_unstable();
+            assert!(after.into_iter().eq(0..values.len() as u32));
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::cmp::Ordering;
+
+use crate::algorithms::sorting::pdqsort::{choose_pivot, partition, pdqsort_by};
+use crate::algorithms::sorting::{less, Sort};
+
+/// Slices this short are sorted on one thread, as splitting them further costs more than it saves
+const SEQUENTIAL_CUTOFF: usize = 1 << 13;
+
+/// A quicksort which sorts the two sides of each partition on rayon's thread pool, in parallel.
+///
+/// Pivots are chosen and slices partitioned as in [`PatternDefeatingQuickSort`], which also sorts
+/// short slices, and slices whose partitions keep coming out uneven. So, like it, this sort takes
+/// O(n log n) time whatever the input, and is not stable.
+///
+/// [`Sort::sort`] sorts in parallel. A comparator has to be shared between threads for that, so
+/// it has to be `Fn` and `Sync` and is given to [`ParallelQuickSort::par_sort_by`].
+/// [`Sort::sort_by`] takes any `FnMut`, and so sorts on the current thread.
+///
+/// [`PatternDefeatingQuickSort`]: crate::sorting::PatternDefeatingQuickSort
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::sorting::{ParallelQuickSort, Sort};
+///
+/// let mut arr: Vec<u64> = (0..100_000).rev().collect();
+/// ParallelQuickSort::sort(&mut arr);
+/// assert!(arr.iter().copied().eq(0..100_000));
+/// ```
+pub struct ParallelQuickSort {}
+
+impl<T: Send> Sort<T> for ParallelQuickSort {
+    fn sort(arr: &mut [T])
+    where
+        T: Ord,
+    {
+        ParallelQuickSort::par_sort_by(arr, T::cmp)
+    }
+
+    fn sort_by<F>(arr: &mut [T], compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        pdqsort_by(arr, &mut less(compare))
+    }
+}
+
+impl ParallelQuickSort {
+    /// Sorts a slice in parallel, in the order given by a comparator
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use lib_wc::sorting::ParallelQuickSort;
+    ///
+    /// let mut arr: Vec<i32> = (0..50_000).collect();
+    /// ParallelQuickSort::par_sort_by(&mut arr, |a, b| b.cmp(a));
+    /// assert!(arr.iter().copied().eq((0..50_000).rev()));
+    /// ```
+    pub fn par_sort_by<T, F>(arr: &mut [T], compare: F)
+    where
+        T: Send,
+        F: Fn(&T, &T) -> Ordering + Sync,
+    {
+        // The number of levels of partitions which are sorted in parallel
+        let limit = usize::BITS - arr.len().leading_zeros();
+        par_quicksort(arr, &|a: &T, b: &T| compare(a, b) == Ordering::Less, limit)
+    }
+}
+
+fn par_quicksort<T, F>(arr: &mut [T], is_less: &F, limit: u32)
+where
+    T: Send,
+    F: Fn(&T, &T) -> bool + Sync,
+{
+    // Partitions which keep coming out uneven, such as when most elements are equal, are left to
+    // pdqsort, which deals with them
+    if arr.len() <= SEQUENTIAL_CUTOFF || limit == 0 {
+        pdqsort_by(arr, &mut |a, b| is_less(a, b));
+        return;
+    }
+
+    let (pivot, _) = choose_pivot(arr, &mut |a, b| is_less(a, b));
+    arr.swap(0, pivot);
+    let (mid, _) = partition(arr, &mut |a, b| is_less(a, b));
+
+    let (left, right) = arr.split_at_mut(mid);
+    let right = &mut right[1..];
+    rayon::join(
+        || par_quicksort(left, is_less, limit - 1),
+        || par_quicksort(right, is_less, limit - 1),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::sorting::is_sorted;
+    use quickcheck_macros::quickcheck;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    #[quickcheck]
+    fn is_sorted_property(arr: Vec<i32>) -> bool {
+        let mut arr = arr;
+        ParallelQuickSort::sort(&mut arr);
+        is_sorted(&arr)
+    }
+
+    #[test]
+    fn sorts_across_threads() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let inputs: Vec<Vec<u32>> = vec![
+            (0..200_000).map(|_| rng.gen()).collect(),
+            (0..200_000).map(|_| rng.gen_range(0..4)).collect(),
+            (0..200_000).rev().collect(),
+        ];
+
+        for mut arr in inputs {
+            let mut expected = arr.clone();
+            expected.sort_unstable();
+            ParallelQuickSort::sort(&mut arr);
+            assert_eq!(arr, expected);
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use std::cmp::Ordering;
+use std::mem;
+
+use crate::algorithms::sorting::heap_sort::heap_sort_by;
+use crate::algorithms::sorting::insertion_sort::insertion_sort_by;
+use crate::algorithms::sorting::{less, Sort};
+
+/// Slices this short are insertion sorted rather than partitioned
+const MAX_INSERTION: usize = 20;
+
+/// Slices this long take the pivot from the medians of three groups of three
+const NINTHER_THRESHOLD: usize = 50;
+
+/// Choosing a pivot makes at most this many swaps, which it makes if the
+/// slice is descending
+const MAX_PIVOT_SWAPS: usize = 12;
+
+/// A pattern-defeating quicksort, after Orson Peters' pdqsort.
+///
+/// It insertion sorts short slices, and picks pivots from the median of three
+/// elements, or of three medians of three for longer slices. It notices runs
+/// which are already sorted or descending, and slices with many equal elements.
+/// When the pivots keep splitting a slice unevenly it shuffles a few elements
+/// to break up the pattern, and if that keeps happening it falls back to
+/// heapsort, so that it takes O(n log n) time whatever the input.
+///
+/// The sort is in place and not stable.
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::sorting::{PatternDefeatingQuickSort, Sort};
+///
+/// let mut arr: Vec<u32> = (0..1000).rev().collect();
+/// PatternDefeatingQuickSort::sort(&mut arr);
+/// assert!(arr.iter().copied().eq(0..1000));
+/// ```
+pub struct PatternDefeatingQuickSort {}
+
+impl<T> Sort<T> for PatternDefeatingQuickSort {
+    fn sort_by<F>(arr: &mut [T], compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        pdqsort_by(arr, &mut less(compare))
+    }
+}
+
+pub(crate) fn pdqsort_by<T, F>(arr: &mut [T], is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    if mem::size_of::<T>() == 0 {
+        return;
+    }
+    // The number of unbalanced partitions allowed before falling back to heapsort
+    let limit = usize::BITS - arr.len().leading_zeros();
+    recurse(arr, is_less, None, limit);
+}
+
+/// Sorts `arr`, whose elements are all no less than `pred`, the pivot of the
+/// partition it came from.
+fn recurse<'a, T, F>(mut arr: &'a mut [T], is_less: &mut F, mut pred: Option<&'a T>, mut limit: u32)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let mut was_balanced = true;
+    let mut was_partitioned = true;
+
+    loop {
+        let len = arr.len();
+        if len <= MAX_INSERTION {
+            insertion_sort_by(arr, is_less);
+            return;
+        }
+        if limit == 0 {
+            heap_sort_by(arr, is_less);
+            return;
+        }
+        if !was_balanced {
+            break_patterns(arr);
+            limit -= 1;
+        }
+
+        let (pivot, likely_sorted) = choose_pivot(arr, is_less);
+        // The last partition was even and moved nothing, and the pivot looks
+        // sorted too, so the slice may well be sorted already
+        if was_balanced && was_partitioned && likely_sorted && partial_insertion_sort(arr, is_less)
+        {
+            return;
+        }
+
+        arr.swap(0, pivot);
+        // If the pivot equals the pivot of the partition this slice came from,
+        // it is the least element, so the elements equal to it are in place.
+        // This makes slices with many duplicates quick to sort.
+        if let Some(pred) = pred {
+            if !is_less(pred, &arr[0]) {
+                let mid = partition_equal(arr, is_less);
+                arr = &mut { arr }[mid..];
+                continue;
+            }
+        }
+
+        let (mid, moved_nothing) = partition(arr, is_less);
+        was_balanced = mid.min(len - mid) >= len / 8;
+        was_partitioned = moved_nothing;
+
+        // Recursing into the shorter side keeps the stack O(log n) deep
+        let (left, right) = { arr }.split_at_mut(mid);
+        let (pivot, right) = right.split_at_mut(1);
+        let pivot = &pivot[0];
+        if left.len() < right.len() {
+            recurse(left, is_less, pred, limit);
+            arr = right;
+            pred = Some(pivot);
+        } else {
+            recurse(right, is_less, Some(pivot), limit);
+            arr = left;
+        }
+    }
+}
+
+/// Picks the index of a pivot, and whether the slice looks sorted. A slice that
+/// looks descending is reversed first.
+pub(crate) fn choose_pivot<T, F>(arr: &mut [T], is_less: &mut F) -> (usize, bool)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let len = arr.len();
+    let mut swaps = 0;
+    let mut a = len / 4;
+    let mut b = len / 4 * 2;
+    let mut c = len / 4 * 3;
+
+    {
+        let arr = &*arr;
+        let mut sort2 = |a: &mut usize, b: &mut usize| {
+            if is_less(&arr[*b], &arr[*a]) {
+                mem::swap(a, b);
+                swaps += 1;
+            }
+        };
+        let mut sort3 = |a: &mut usize, b: &mut usize, c: &mut usize| {
+            sort2(a, b);
+            sort2(b, c);
+            sort2(a, b);
+        };
+
+        if len >= NINTHER_THRESHOLD {
+            // Replaces each of a, b and c with the median of it and its neighbours
+            let mut median_of_neighbours = |a: &mut usize| {
+                let (mut before, mut after) = (*a - 1, *a + 1);
+                sort3(&mut before, a, &mut after);
+            };
+            median_of_neighbours(&mut a);
+            median_of_neighbours(&mut b);
+            median_of_neighbours(&mut c);
+        }
+        sort3(&mut a, &mut b, &mut c);
+    }
+
+    if swaps < MAX_PIVOT_SWAPS {
+        (b, swaps == 0)
+    } else {
+        arr.reverse();
+        (len - 1 - b, true)
+    }
+}
+
+/// Partitions `arr[1..]` around the pivot `arr[0]`, into the elements which are
+/// less than it and those which aren't, and moves the pivot between them.
+/// Returns the pivot's new index, and whether the slice was already partitioned.
+pub(crate) fn partition<T, F>(arr: &mut [T], is_less: &mut F) -> (usize, bool)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let (head, rest) = arr.split_at_mut(1);
+    let pivot = &head[0];
+    let mut l = 0;
+    let mut r = rest.len();
+
+    while l < r && is_less(&rest[l], pivot) {
+        l += 1;
+    }
+    while l < r && !is_less(&rest[r - 1], pivot) {
+        r -= 1;
+    }
+    let moved_nothing = l >= r;
+
+    loop {
+        while l < r && is_less(&rest[l], pivot) {
+            l += 1;
+        }
+        while l < r && !is_less(&rest[r - 1], pivot) {
+            r -= 1;
+        }
+        if l >= r {
+            break;
+        }
+        r -= 1;
+        rest.swap(l, r);
+        l += 1;
+    }
+
+    // `arr[l]` is the last element less than the pivot, if any
+    arr.swap(0, l);
+    (l, moved_nothing)
+}
+
+/// Moves the elements of `arr[1..]` which are equal to the pivot `arr[0]` to
+/// the front, when none are less than it. Returns how many elements, including
+/// the pivot, are equal to it.
+fn partition_equal<T, F>(arr: &mut [T], is_less: &mut F) -> usize
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let (head, rest) = arr.split_at_mut(1);
+    let pivot = &head[0];
+    let mut l = 0;
+    let mut r = rest.len();
+
+    loop {
+        while l < r && !is_less(pivot, &rest[l]) {
+            l += 1;
+        }
+        while l < r && is_less(pivot, &rest[r - 1]) {
+            r -= 1;
+        }
+        if l >= r {
+            break;
+        }
+        r -= 1;
+        rest.swap(l, r);
+        l += 1;
+    }
+    l + 1
+}
+
+/// Sorts a slice that has only a few elements out of place, giving up and
+/// returning false if there are more.
+fn partial_insertion_sort<T, F>(arr: &mut [T], is_less: &mut F) -> bool
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    /// How many elements out of place are moved before giving up
+    const MAX_STEPS: usize = 5;
+    /// Shorter slices are left to be partitioned, which is as quick
+    const SHORTEST_SHIFTING: usize = 50;
+
+    let len = arr.len();
+    let mut i = 1;
+    for _ in 0..MAX_STEPS {
+        while i < len && !is_less(&arr[i], &arr[i - 1]) {
+            i += 1;
+        }
+        if i == len {
+            return true;
+        }
+        if len < SHORTEST_SHIFTING {
+            return false;
+        }
+
+        // Move the pair out of order to where each of them belongs
+        arr.swap(i - 1, i);
+        shift_tail(&mut arr[..i], is_less);
+        shift_head(&mut arr[i..], is_less);
+    }
+    false
+}
+
+/// Moves the last element left until it is in order
+fn shift_tail<T, F>(arr: &mut [T], is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let mut i = arr.len();
+    while i >= 2 && is_less(&arr[i - 1], &arr[i - 2]) {
+        arr.swap(i - 1, i - 2);
+        i -= 1;
+    }
+}
+
+/// Moves the first element right until it is in order
+fn shift_head<T, F>(arr: &mut [T], is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let mut i = 0;
+    while i + 1 < arr.len() && is_less(&arr[i + 1], &arr[i]) {
+        arr.swap(i, i + 1);
+        i += 1;
+    }
+}
+
+/// Swaps a few elements near the middle with others picked at random, so that
+/// the next pivot is unlikely to split the slice as badly as the last did
+fn break_patterns<T>(arr: &mut [T]) {
+    let len = arr.len();
+    // A xorshift generator seeded with the length is random enough, and keeps
+    // sorting deterministic
+    let mut seed = len as u64;
+    let mut next = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    let modulus = len.next_power_of_two() as u64;
+
+    let pos = len / 4 * 2;
+    for i in 0..3 {
+        let mut other = (next() & (modulus - 1)) as usize;
+        if other >= len {
+            other -= len;
+        }
+        arr.swap(pos - 1 + i, other);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::sorting::is_sorted;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn is_sorted_property(arr: Vec<i32>) -> bool {
+        let mut arr = arr;
+        PatternDefeatingQuickSort::sort(&mut arr);
+        is_sorted(&arr)
+    }
+
+    #[quickcheck]
+    fn sorts_like_std(arr: Vec<u8>) -> bool {
+        let mut expected = arr.clone();
+        expected.sort();
+        let mut arr = arr;
+        PatternDefeatingQuickSort::sort(&mut arr);
+        arr == expected
+    }
+
+    #[test]
+    fn basic_string() {
+        let mut res = vec!["a", "bb", "d", "cc"];
+        PatternDefeatingQuickSort::sort(&mut res);
+        assert_eq!(res, vec!["a", "bb", "cc", "d"]);
+    }
+
+    #[test]
+    fn patterns() {
+        let n = 10_000;
+        let inputs: Vec<Vec<u32>> = vec![
+            (0..n).collect(),
+            (0..n).rev().collect(),
+            vec![7; n as usize],
+            (0..n).map(|i| i % 16).collect(),
+            (0..n).map(|i| i % 1000).rev().collect(),
+            // Sorted apart from the last element, and organ pipe
+            (1..n).chain([0]).collect(),
+            (0..n / 2).chain((0..n / 2).rev()).collect(),
+        ];
+
+        for input in inputs {
+            let mut expected = input.clone();
+            expected.sort();
+            let mut arr = input;
+            PatternDefeatingQuickSort::sort(&mut arr);
+            assert_eq!(arr, expected);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_heapsort() {
+        let mut arr: Vec<u32> = (0..5000).rev().collect();
+        let mut less = |a: &u32, b: &u32| a < b;
+        // Without any partitions to spare it has to use heapsort
+        recurse(&mut arr, &mut less, None, 0);
+        assert!(is_sorted(&arr));
+    }
+}
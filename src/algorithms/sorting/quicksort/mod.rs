@@ -1,40 +1,58 @@
-use crate::algorithms::sorting::Sort;
+use std::cmp::Ordering;
+
+use crate::algorithms::sorting::{less, Sort};
 
 pub struct QuickSort {}
 
-impl<T: Ord> Sort<T> for QuickSort {
-    fn sort(arr: &mut [T]) {
-        quick_sort(arr)
+impl<T> Sort<T> for QuickSort {
+    fn sort_by<F>(arr: &mut [T], compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        quick_sort_by(arr, &mut less(compare))
     }
 }
 
 pub fn quick_sort<T: Ord>(arr: &mut [T]) {
+    quick_sort_by(arr, &mut T::lt)
+}
+
+fn quick_sort_by<T, F>(arr: &mut [T], is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
     let len = arr.len();
     if len > 1 {
-        _quick_sort(arr, 0, (len - 1) as isize);
+        _quick_sort(arr, 0, (len - 1) as isize, is_less);
     }
 }
 
-fn _quick_sort<T: Ord>(arr: &mut [T], lo: isize, hi: isize) {
+fn _quick_sort<T, F>(arr: &mut [T], lo: isize, hi: isize, is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
     if lo < hi {
-        let p = partition(arr, lo, hi);
-        _quick_sort(arr, lo, p - 1);
-        _quick_sort(arr, p + 1, hi);
+        let p = partition(arr, lo, hi, is_less);
+        _quick_sort(arr, lo, p - 1, is_less);
+        _quick_sort(arr, p + 1, hi, is_less);
     }
 }
 
-fn partition<T: PartialOrd>(arr: &mut [T], lo: isize, hi: isize) -> isize {
+pub(crate) fn partition<T, F>(arr: &mut [T], lo: isize, hi: isize, is_less: &mut F) -> isize
+where
+    F: FnMut(&T, &T) -> bool,
+{
     let pivot = hi as usize;
     let mut i = lo - 1;
     let mut j = hi;
 
     loop {
         i += 1;
-        while arr[i as usize] < arr[pivot] {
+        while is_less(&arr[i as usize], &arr[pivot]) {
             i += 1;
         }
         j -= 1;
-        while j >= 0 && arr[j as usize] > arr[pivot] {
+        while j >= 0 && is_less(&arr[pivot], &arr[j as usize]) {
             j -= 1;
         }
         if i >= j {
@@ -0,0 +1,283 @@
+use std::cmp::Ordering;
+use std::mem;
+
+use crate::algorithms::sorting::insertion_sort::insertion_sort_by;
+use crate::algorithms::sorting::merge_sort::merge_sort_by;
+use crate::algorithms::sorting::{less, Sort};
+
+/// Slices and buckets this short are insertion sorted rather than distributed by their bytes
+const MAX_INSERTION: usize = 20;
+
+/// Keys which can be sorted a byte at a time, as the integer types can.
+///
+/// [`RadixKey::to_bits`] maps each key to an unsigned integer which sorts the same way, so its
+/// bytes can be sorted on from the least significant up.
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::sorting::RadixKey;
+///
+/// assert!((-1i32).to_bits() < 0i32.to_bits());
+/// assert_eq!(0x1234u16.byte(1), 0x12);
+/// ```
+pub trait RadixKey: Copy + Ord {
+    /// How many of the least significant bytes of [`RadixKey::to_bits`] are sorted on
+    const BYTES: usize;
+
+    /// The key as an unsigned integer, which orders keys as `Ord` does. Only the lowest
+    /// [`RadixKey::BYTES`] bytes may be set.
+    fn to_bits(self) -> u128;
+
+    /// The `i`th least significant byte of [`RadixKey::to_bits`]
+    fn byte(self, i: usize) -> u8 {
+        (self.to_bits() >> (8 * i)) as u8
+    }
+}
+
+macro_rules! unsigned_radix_key {
+    ($($T: ty),*) => {$(
+        impl RadixKey for $T {
+            const BYTES: usize = mem::size_of::<$T>();
+
+            fn to_bits(self) -> u128 {
+                self as u128
+            }
+        }
+    )*};
+}
+
+// Flipping the sign bit of a signed integer puts the negative numbers below the others, as
+// unsigned numbers
+macro_rules! signed_radix_key {
+    ($($T: ty => $U: ty),*) => {$(
+        impl RadixKey for $T {
+            const BYTES: usize = mem::size_of::<$T>();
+
+            fn to_bits(self) -> u128 {
+                (self as $U ^ <$T>::MIN as $U) as u128
+            }
+        }
+    )*};
+}
+
+unsigned_radix_key!(u8, u16, u32, u64, u128, usize);
+signed_radix_key!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128, isize => usize);
+
+/// A radix sort, which sorts keys by their bytes rather than by comparing them, and so takes time
+/// linear in the length of the slice and the length of the keys.
+///
+/// [`Sort::sort`] sorts integers, or any other [`RadixKey`], from the least significant byte up
+/// (LSD), and is stable. It needs a buffer as long as the slice, and skips the bytes which are the
+/// same in every key.
+///
+/// [`RadixSort::sort_bytes`] sorts byte strings from the most significant byte down (MSD), in
+/// place.
+///
+/// [`Sort::sort_by`] has to compare elements, so it falls back to [`MergeSort`], which is stable
+/// too.
+///
+/// [`MergeSort`]: crate::sorting::MergeSort
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::sorting::{RadixSort, Sort};
+///
+/// let mut arr = [300, -7, 12, 0, -70_000, 5];
+/// RadixSort::sort(&mut arr);
+/// assert_eq!(arr, [-70_000, -7, 0, 5, 12, 300]);
+/// ```
+pub struct RadixSort {}
+
+impl<T: RadixKey> Sort<T> for RadixSort {
+    fn sort(arr: &mut [T])
+    where
+        T: Ord,
+    {
+        lsd_radix_sort(arr)
+    }
+
+    fn sort_by<F>(arr: &mut [T], compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        merge_sort_by(arr, &mut Vec::new(), &mut less(compare))
+    }
+}
+
+impl RadixSort {
+    /// Sorts byte strings, such as `String`s, `&str`s or `Vec<u8>`s, in the lexicographic order of
+    /// their bytes, which is how `Ord` orders all of those.
+    ///
+    /// Keys are put into buckets by their first byte, then each bucket by the next byte, and so
+    /// on. Buckets of a few keys are insertion sorted instead. The sort is in place and not
+    /// stable.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use lib_wc::sorting::RadixSort;
+    ///
+    /// let mut arr = ["banana", "apple", "band", "ban", "cherry"];
+    /// RadixSort::sort_bytes(&mut arr);
+    /// assert_eq!(arr, ["apple", "ban", "banana", "band", "cherry"]);
+    /// ```
+    pub fn sort_bytes<T: AsRef<[u8]>>(arr: &mut [T]) {
+        msd_radix_sort(arr, 0)
+    }
+}
+
+pub(crate) fn lsd_radix_sort<T: RadixKey>(arr: &mut [T]) {
+    let len = arr.len();
+    if len <= MAX_INSERTION {
+        insertion_sort_by(arr, &mut T::lt);
+        return;
+    }
+
+    // Counting every byte of every key up front takes one pass over the slice rather than one
+    // per byte
+    let mut counts = vec![[0usize; 256]; T::BYTES];
+    for &key in arr.iter() {
+        for (i, counts) in counts.iter_mut().enumerate() {
+            counts[key.byte(i) as usize] += 1;
+        }
+    }
+
+    let mut buf = arr.to_vec();
+    let mut sorted_into_buf = false;
+    let (mut from, mut to) = (&mut *arr, &mut buf[..]);
+    for (i, counts) in counts.iter().enumerate() {
+        // Every key has the same byte here, so they are in order by it already
+        if counts.contains(&len) {
+            continue;
+        }
+
+        let mut offsets = [0; 256];
+        let mut offset = 0;
+        for (count, start) in counts.iter().zip(offsets.iter_mut()) {
+            *start = offset;
+            offset += count;
+        }
+        for &key in from.iter() {
+            let byte = key.byte(i) as usize;
+            to[offsets[byte]] = key;
+            offsets[byte] += 1;
+        }
+        mem::swap(&mut from, &mut to);
+        sorted_into_buf = !sorted_into_buf;
+    }
+
+    if sorted_into_buf {
+        arr.copy_from_slice(&buf);
+    }
+}
+
+/// Sorts `arr`, whose keys all have the same first `depth` bytes
+fn msd_radix_sort<T: AsRef<[u8]>>(mut arr: &mut [T], mut depth: usize) {
+    // Bucket 0 holds the keys which are `depth` bytes long, which sort before any longer ones
+    let bucket = |key: &T, depth: usize| key.as_ref().get(depth).map_or(0, |&b| b as usize + 1);
+
+    loop {
+        let len = arr.len();
+        if len <= MAX_INSERTION {
+            insertion_sort_by(arr, &mut |a, b| a.as_ref()[depth..] < b.as_ref()[depth..]);
+            return;
+        }
+
+        let mut counts = [0usize; 257];
+        for key in arr.iter() {
+            counts[bucket(key, depth)] += 1;
+        }
+        let mut starts = [0; 257];
+        let mut offset = 0;
+        for (count, start) in counts.iter().zip(starts.iter_mut()) {
+            *start = offset;
+            offset += count;
+        }
+
+        // Swap each key into the next free place of its bucket, until every bucket is full
+        let mut next = starts;
+        for b in 0..counts.len() {
+            let end = starts[b] + counts[b];
+            while next[b] < end {
+                let to = bucket(&arr[next[b]], depth);
+                if to != b {
+                    arr.swap(next[b], next[to]);
+                }
+                next[to] += 1;
+            }
+        }
+
+        // The keys in bucket 0 are equal. Recursing into all but the largest of the other buckets,
+        // which are at most half as long as `arr`, keeps the stack O(log n) deep.
+        let largest = (1..counts.len()).max_by_key(|&b| counts[b]).unwrap();
+        for b in (1..counts.len()).filter(|&b| b != largest && counts[b] > 1) {
+            msd_radix_sort(&mut arr[starts[b]..starts[b] + counts[b]], depth + 1);
+        }
+        arr = &mut { arr }[starts[largest]..starts[largest] + counts[largest]];
+        depth += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::sorting::is_sorted;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn is_sorted_property(arr: Vec<i32>) -> bool {
+        let mut arr = arr;
+        RadixSort::sort(&mut arr);
+        is_sorted(&arr)
+    }
+
+    #[quickcheck]
+    fn sorts_integers_like_std(small: Vec<i8>, wide: Vec<u64>, signed: Vec<i128>) -> bool {
+        fn check<T: RadixKey + std::fmt::Debug>(arr: Vec<T>) -> bool {
+            let mut expected = arr.clone();
+            expected.sort();
+            let mut arr = arr;
+            RadixSort::sort(&mut arr);
+            arr == expected
+        }
+        check(small) && check(wide) && check(signed)
+    }
+
+    #[quickcheck]
+    fn sorts_bytes_like_std(arr: Vec<String>, bytes: Vec<Vec<u8>>) -> bool {
+        let mut expected = arr.clone();
+        expected.sort();
+        let mut arr = arr;
+        RadixSort::sort_bytes(&mut arr);
+
+        let mut expected_bytes = bytes.clone();
+        expected_bytes.sort();
+        let mut bytes = bytes;
+        RadixSort::sort_bytes(&mut bytes);
+
+        arr == expected && bytes == expected_bytes
+    }
+
+    #[test]
+    fn long_runs_of_bytes() {
+        // Keys which differ only in length, or only near the end, take many passes
+        let mut arr: Vec<String> = (0..2000).rev().map(|i| "a".repeat(i)).collect();
+        arr.extend((0..2000).map(|i| format!("{}{:05}", "b".repeat(100), i * 7919 % 2000)));
+        let mut expected = arr.clone();
+        expected.sort();
+        RadixSort::sort_bytes(&mut arr);
+        assert_eq!(arr, expected);
+    }
+
+    #[test]
+    fn extremes() {
+        let mut arr = vec![i64::MAX, 0, i64::MIN, -1, 1, i64::MIN + 1, i64::MAX - 1];
+        RadixSort::sort(&mut arr);
+        assert_eq!(
+            arr,
+            [i64::MIN, i64::MIN + 1, -1, 0, 1, i64::MAX - 1, i64::MAX]
+        );
+    }
+}
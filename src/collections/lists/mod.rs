@@ -1,4 +1,5 @@
 pub use basic_list::BasicList;
 pub use vec::Vec;
 mod basic_list;
+mod skip_list;
 mod vec;
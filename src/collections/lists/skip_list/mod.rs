@@ -0,0 +1,385 @@
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Bound, RangeBounds};
+use std::ptr;
+
+use rand::Rng;
+
+#[cfg(test)]
+use crate::collections::trees::model::Checked;
+use crate::collections::trees::Tree;
+
+/// No node is taller than this, which is plenty for 2³² entries
+const MAX_HEIGHT: usize = 32;
+
+type Link<K, V> = *mut Node<K, V>;
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    /// The next node at each level the node is on
+    next: Box<[Link<K, V>]>,
+}
+
+/// An ordered map on a skip list, after William Pugh.
+///
+/// The entries are kept in a sorted linked list, and each node is also on the lists above it up to
+/// a random height, each half as likely as the one before. Each level skips over about half the
+/// nodes of the level below, so a search that starts at the sparsest level and drops down a level
+/// whenever the next node is too far takes O(log n) steps on average, with no rebalancing.
+pub struct SkipList<K, V> {
+    /// The first node at each level
+    head: [Link<K, V>; MAX_HEIGHT],
+    /// The number of levels with any nodes on them
+    height: usize,
+    len: usize,
+    marker: PhantomData<Box<Node<K, V>>>,
+}
+
+unsafe impl<K: Send, V: Send> Send for SkipList<K, V> {}
+unsafe impl<K: Sync, V: Sync> Sync for SkipList<K, V> {}
+
+impl<K: Ord, V> SkipList<K, V> {
+    pub fn new() -> Self {
+        SkipList {
+            head: [ptr::null_mut(); MAX_HEIGHT],
+            height: 0,
+            len: 0,
+            marker: PhantomData,
+        }
+    }
+
+    /// Inserts an entry, returning the value it replaced, if its key was already in the list
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let links = self.links_to(&key);
+        if let Some(node) = unsafe { (*links[0]).as_mut() } {
+            if node.key == key {
+                return Some(mem::replace(&mut node.value, value));
+            }
+        }
+
+        let height = random_height();
+        let node = Box::into_raw(Box::new(Node {
+            key,
+            value,
+            next: vec![ptr::null_mut(); height].into_boxed_slice(),
+        }));
+        for (level, &link) in links.iter().enumerate().take(height) {
+            unsafe {
+                (*node).next[level] = *link;
+                *link = node;
+            }
+        }
+        self.height = self.height.max(height);
+        self.len += 1;
+        None
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let (_, node) = self.search(|k| k < key);
+        let node = unsafe { node.as_ref()? };
+        (node.key == *key).then_some(&node.value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let links = self.links_to(key);
+        let node = unsafe { (*links[0]).as_mut()? };
+        (node.key == *key).then_some(&mut node.value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Removes the entry with `key`, returning its value
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let links = self.links_to(key);
+        let node = unsafe { *links[0] };
+        if node.is_null() || unsafe { (*node).key != *key } {
+            return None;
+        }
+
+        // Every level the node is on leads to it from the link found for that level
+        let node = unsafe { Box::from_raw(node) };
+        for (&link, &next) in links.iter().zip(node.next.iter()) {
+            unsafe { *link = next };
+        }
+        while self.height > 0 && self.head[self.height - 1].is_null() {
+            self.height -= 1;
+        }
+        self.len -= 1;
+        Some(node.value)
+    }
+
+    /// The entry with the least key
+    pub fn first(&self) -> Option<(&K, &V)> {
+        let node = unsafe { self.head[0].as_ref()? };
+        Some((&node.key, &node.value))
+    }
+
+    /// The entry with the greatest key
+    pub fn last(&self) -> Option<(&K, &V)> {
+        let (node, _) = self.search(|_| true);
+        let node = unsafe { node.as_ref()? };
+        Some((&node.key, &node.value))
+    }
+
+    /// The entries of the list, in order of their keys
+    pub fn iter(&self) -> Range<'_, K, V> {
+        self.range(..)
+    }
+
+    /// The entries of the list whose keys are in a range, in order of their keys
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V> {
+        let (_, mut next) = self.search(|key| match range.start_bound() {
+            Bound::Included(start) => key < start,
+            Bound::Excluded(start) => key <= start,
+            Bound::Unbounded => false,
+        });
+        let (last, _) = self.search(|key| match range.end_bound() {
+            Bound::Included(end) => key <= end,
+            Bound::Excluded(end) => key < end,
+            Bound::Unbounded => true,
+        });
+        if next.is_null() || last.is_null() || unsafe { (*next).key > (*last).key } {
+            next = ptr::null_mut();
+        }
+
+        Range {
+            next,
+            last,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Finds the last node whose key comes `before` whatever is being searched for, which must hold
+    /// for a prefix of the keys, and the node after it. Either is null if there is no such node.
+    fn search(&self, before: impl Fn(&K) -> bool) -> (Link<K, V>, Link<K, V>) {
+        let mut prev = ptr::null_mut();
+        let mut tower: &[Link<K, V>] = &self.head;
+        for level in (0..self.height).rev() {
+            while let Some(node) = unsafe { tower[level].as_ref() } {
+                if !before(&node.key) {
+                    break;
+                }
+                prev = tower[level];
+                tower = &node.next;
+            }
+        }
+        let next = match unsafe { prev.as_ref() } {
+            Some(prev) => prev.next[0],
+            None => self.head[0],
+        };
+        (prev, next)
+    }
+
+    /// Finds, at every level, the link that leads to the first node whose key isn't less than
+    /// `key`, or that ends the level
+    fn links_to(&mut self, key: &K) -> [*mut Link<K, V>; MAX_HEIGHT] {
+        let mut links = [ptr::null_mut(); MAX_HEIGHT];
+        let mut tower: *mut [Link<K, V>] = &mut self.head[..];
+        // The levels above the height are empty, so they end at the head
+        for (level, link) in links.iter_mut().enumerate().skip(self.height) {
+            *link = unsafe { &mut (*tower)[level] };
+        }
+        for level in (0..self.height).rev() {
+            unsafe {
+                while let Some(node) = (*tower)[level].as_mut() {
+                    if node.key >= *key {
+                        break;
+                    }
+                    tower = &mut *node.next;
+                }
+                links[level] = &mut (*tower)[level];
+            }
+        }
+        links
+    }
+}
+
+/// A height of `h` with probability 2⁻ʰ
+fn random_height() -> usize {
+    let height = rand::thread_rng().gen::<u32>().trailing_ones() as usize + 1;
+    height.min(MAX_HEIGHT)
+}
+
+impl<K, V> Drop for SkipList<K, V> {
+    fn drop(&mut self) {
+        let mut link = self.head[0];
+        while !link.is_null() {
+            let node = unsafe { Box::from_raw(link) };
+            link = node.next[0];
+        }
+    }
+}
+
+/// A skip list does the job of a balanced search tree, so it can stand in for one
+impl<K: Ord, V> Tree<K, V> for SkipList<K, V> {
+    fn new() -> Self {
+        SkipList::new()
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        SkipList::insert(self, key, value);
+    }
+
+    fn contains(&self, value: K) -> bool {
+        self.contains_key(&value)
+    }
+
+    fn remove(&mut self, value: K) -> Option<V> {
+        SkipList::remove(self, &value)
+    }
+}
+
+impl<K: Ord, V> Default for SkipList<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for SkipList<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for SkipList<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut list = SkipList::new();
+        list.extend(iter);
+        list
+    }
+}
+
+/// An iterator over the entries of a [`SkipList`] whose keys are in a range
+pub struct Range<'a, K, V> {
+    next: Link<K, V>,
+    /// The last node in the range
+    last: Link<K, V>,
+    marker: PhantomData<&'a Node<K, V>>,
+}
+
+impl<'a, K, V> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = unsafe { self.next.as_ref()? };
+        self.next = if self.next == self.last {
+            ptr::null_mut()
+        } else {
+            node.next[0]
+        };
+        Some((&node.key, &node.value))
+    }
+}
+
+#[cfg(test)]
+impl<K: Ord, V> Checked<K, V> for SkipList<K, V> {
+    /// Checks that every level is in order, that each is a subset of the one below it, and that
+    /// the height is the number of levels in use
+    fn check(&self) {
+        let level = |level: usize| {
+            let mut nodes = Vec::new();
+            let mut link = self.head[level];
+            while let Some(node) = unsafe { link.as_ref() } {
+                assert!(node.next.len() > level, "node on a level above its height");
+                nodes.push(link);
+                link = node.next[level];
+            }
+            nodes
+        };
+
+        let bottom = level(0);
+        assert_eq!(bottom.len(), self.len);
+        assert!(bottom
+            .windows(2)
+            .all(|pair| unsafe { (*pair[0]).key < (*pair[1]).key }));
+        let towers = bottom
+            .iter()
+            .map(|&node| unsafe { &*node }.next.len())
+            .collect::<Vec<_>>();
+        assert_eq!(towers.iter().max().copied().unwrap_or(0), self.height);
+        for height in 1..MAX_HEIGHT {
+            let expected = bottom
+                .iter()
+                .zip(&towers)
+                .filter(|(_, &tower)| tower > height)
+                .map(|(&node, _)| node)
+                .collect::<Vec<_>>();
+            assert_eq!(level(height), expected, "level {} out of order", height);
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        SkipList::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        SkipList::get_mut(self, key)
+    }
+
+    fn min(&self) -> Option<(&K, &V)> {
+        self.first()
+    }
+
+    fn max(&self) -> Option<(&K, &V)> {
+        self.last()
+    }
+
+    fn range<R: RangeBounds<K>>(&self, range: R) -> Vec<(&K, &V)> {
+        SkipList::range(self, range).collect()
+    }
+
+    fn len(&self) -> usize {
+        SkipList::len(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::trees::model;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn behaves_like_btree_map(ops: Vec<(bool, u8)>, start: u8, end: u8) -> bool {
+        model::behaves_like_btree_map::<SkipList<_, _>>(ops, start, end)
+    }
+
+    #[test]
+    fn many_entries() {
+        let mut list = (0..10_000).map(|i| (i, i * 2)).collect::<SkipList<_, _>>();
+        list.check();
+        assert_eq!(list.len(), 10_000);
+        assert_eq!(list.insert(0, 0), Some(0));
+
+        for i in (0..10_000).step_by(2) {
+            assert_eq!(list.remove(&i), Some(i * 2));
+        }
+        list.check();
+        assert!(list.iter().map(|(&k, _)| k).eq((1..10_000).step_by(2)));
+
+        for i in (1..10_000).step_by(2) {
+            assert_eq!(list.remove(&i), Some(i * 2));
+        }
+        list.check();
+        assert!(list.is_empty());
+        assert_eq!(list.height, 0);
+    }
+
+    #[test]
+    fn updates_values() {
+        model::updates_values::<SkipList<_, _>>();
+    }
+}
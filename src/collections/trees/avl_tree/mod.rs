@@ -0,0 +1,274 @@
+use std::cmp::Ordering;
+use std::mem;
+use std::ops::RangeBounds;
+
+use crate::collections::trees::binary::{self, Link, Node};
+#[cfg(test)]
+use crate::collections::trees::model::Checked;
+use crate::collections::trees::Tree;
+
+/// The height of a node, which is 1 for a leaf
+type Height = u8;
+
+/// An AVL tree, a binary search tree in which the heights of the two subtrees of every node differ
+/// by at most one, so that it is never more than about 1.44 log₂ n deep.
+///
+/// Each node keeps its height, and inserting or removing an entry rotates the nodes above it
+/// wherever the heights of their subtrees have come to differ by two.
+pub struct AvlTree<K, V> {
+    root: Link<K, V, Height>,
+    len: usize,
+}
+
+impl<K: Ord, V> Tree<K, V> for AvlTree<K, V> {
+    fn new() -> Self {
+        AvlTree { root: None, len: 0 }
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        if insert(&mut self.root, key, value).is_none() {
+            self.len += 1;
+        }
+    }
+
+    fn contains(&self, value: K) -> bool {
+        self.get(&value).is_some()
+    }
+
+    fn remove(&mut self, value: K) -> Option<V> {
+        let removed = remove(&mut self.root, &value);
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+}
+
+impl<K: Ord, V> AvlTree<K, V> {
+    pub fn get(&self, key: &K) -> Option<&V> {
+        binary::get(&self.root, key)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        binary::get_mut(&mut self.root, key)
+    }
+
+    /// The entry with the least key
+    pub fn min(&self) -> Option<(&K, &V)> {
+        binary::min(&self.root)
+    }
+
+    /// The entry with the greatest key
+    pub fn max(&self) -> Option<(&K, &V)> {
+        binary::max(&self.root)
+    }
+
+    /// The entries of the tree, in order of their keys
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.range(..)
+    }
+
+    /// The entries of the tree whose keys are in a range, in order of their keys
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> impl Iterator<Item = (&K, &V)> {
+        binary::Range::new(&self.root, range)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<K: Ord, V> Default for AvlTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn height<K, V>(link: &Link<K, V, Height>) -> Height {
+    link.as_ref().map_or(0, |node| node.meta)
+}
+
+fn update_height<K, V>(node: &mut Node<K, V, Height>) {
+    node.meta = height(&node.left).max(height(&node.right)) + 1;
+}
+
+/// How much taller the left subtree of a node is than its right
+fn balance_factor<K, V>(node: &Node<K, V, Height>) -> i16 {
+    height(&node.left) as i16 - height(&node.right) as i16
+}
+
+/// Makes the right child of a node the root of its subtree
+fn rotate_left<K, V>(mut node: Box<Node<K, V, Height>>) -> Box<Node<K, V, Height>> {
+    let mut right = node.right.take().expect("rotating left needs a right child");
+    node.right = right.left.take();
+    update_height(&mut node);
+    right.left = Some(node);
+    update_height(&mut right);
+    right
+}
+
+/// Makes the left child of a node the root of its subtree
+fn rotate_right<K, V>(mut node: Box<Node<K, V, Height>>) -> Box<Node<K, V, Height>> {
+    let mut left = node.left.take().expect("rotating right needs a left child");
+    node.left = left.right.take();
+    update_height(&mut node);
+    left.right = Some(node);
+    update_height(&mut left);
+    left
+}
+
+/// Restores the balance of a subtree one of whose subtrees has grown or shrunk by one
+fn rebalance<K, V>(link: &mut Link<K, V, Height>) {
+    let mut node = match link.take() {
+        Some(node) => node,
+        None => return,
+    };
+    update_height(&mut node);
+
+    let balance = balance_factor(&node);
+    if balance > 1 {
+        // A left child leaning right is turned to lean left, so that one rotation balances it
+        if node.left.as_deref().map_or(0, balance_factor) < 0 {
+            node.left = node.left.take().map(rotate_left);
+        }
+        node = rotate_right(node);
+    } else if balance < -1 {
+        if node.right.as_deref().map_or(0, balance_factor) > 0 {
+            node.right = node.right.take().map(rotate_right);
+        }
+        node = rotate_left(node);
+    }
+    *link = Some(node);
+}
+
+/// Inserts an entry, returning the value it replaced, if its key was already in the tree
+fn insert<K: Ord, V>(link: &mut Link<K, V, Height>, key: K, value: V) -> Option<V> {
+    let node = match link {
+        Some(node) => node,
+        None => {
+            *link = Some(Node::new(key, value, 1));
+            return None;
+        }
+    };
+
+    let replaced = match key.cmp(&node.key) {
+        Ordering::Less => insert(&mut node.left, key, value),
+        Ordering::Greater => insert(&mut node.right, key, value),
+        Ordering::Equal => return Some(mem::replace(&mut node.value, value)),
+    };
+    rebalance(link);
+    replaced
+}
+
+fn remove<K: Ord, V>(link: &mut Link<K, V, Height>, key: &K) -> Option<V> {
+    let node = link.as_mut()?;
+    let removed = match key.cmp(&node.key) {
+        Ordering::Less => remove(&mut node.left, key),
+        Ordering::Greater => remove(&mut node.right, key),
+        Ordering::Equal => {
+            let mut node = link.take()?;
+            *link = match (node.left.take(), node.right.take()) {
+                (None, child) | (child, None) => child,
+                // The least entry of the right subtree takes the removed entry's place
+                (left, mut right) => {
+                    let mut successor = remove_min(&mut right)?;
+                    successor.left = left;
+                    successor.right = right;
+                    Some(successor)
+                }
+            };
+            rebalance(link);
+            return Some(node.value);
+        }
+    };
+    rebalance(link);
+    removed
+}
+
+/// Detaches the node with the least key from a subtree
+fn remove_min<K, V>(link: &mut Link<K, V, Height>) -> Option<Box<Node<K, V, Height>>> {
+    let node = link.as_mut()?;
+    if node.left.is_some() {
+        let min = remove_min(&mut node.left);
+        rebalance(link);
+        return min;
+    }
+
+    let mut min = link.take()?;
+    *link = min.right.take();
+    Some(min)
+}
+
+#[cfg(test)]
+impl<K: Ord, V> Checked<K, V> for AvlTree<K, V> {
+    /// Checks that the keys are in order, and that every node has the right height and a balance
+    /// factor of -1, 0 or 1
+    fn check(&self) {
+        fn check_node<K: Ord, V>(link: &Link<K, V, Height>) -> Height {
+            let node = match link {
+                Some(node) => node,
+                None => return 0,
+            };
+            let (left, right) = (check_node(&node.left), check_node(&node.right));
+            assert_eq!(node.meta, left.max(right) + 1, "wrong height");
+            assert!((left as i16 - right as i16).abs() <= 1, "unbalanced node");
+            node.meta
+        }
+
+        check_node(&self.root);
+        assert_eq!(self.iter().count(), self.len);
+        assert!(self.iter().zip(self.iter().skip(1)).all(|(a, b)| a.0 < b.0));
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        AvlTree::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        AvlTree::get_mut(self, key)
+    }
+
+    fn min(&self) -> Option<(&K, &V)> {
+        AvlTree::min(self)
+    }
+
+    fn max(&self) -> Option<(&K, &V)> {
+        AvlTree::max(self)
+    }
+
+    fn range<R: RangeBounds<K>>(&self, range: R) -> Vec<(&K, &V)> {
+        AvlTree::range(self, range).collect()
+    }
+
+    fn len(&self) -> usize {
+        AvlTree::len(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::trees::model;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn behaves_like_btree_map(ops: Vec<(bool, u8)>, start: u8, end: u8) -> bool {
+        model::behaves_like_btree_map::<AvlTree<_, _>>(ops, start, end)
+    }
+
+    #[test]
+    fn stays_balanced() {
+        // A perfectly balanced tree of 1024 entries is 11 deep, and an AVL tree at most 1.44 times
+        // that
+        model::stays_balanced::<AvlTree<_, _>>(1024, 15, |tree| height(&tree.root).into());
+    }
+
+    #[test]
+    fn updates_values() {
+        model::updates_values::<AvlTree<_, _>>();
+    }
+}
@@ -0,0 +1,458 @@
+use std::cmp::Ordering;
+use std::mem;
+use std::ops::RangeBounds;
+
+use crate::collections::trees::binary::{after_start, before_end};
+#[cfg(test)]
+use crate::collections::trees::model::Checked;
+use crate::collections::trees::Tree;
+
+/// Every node but the root has between `MIN_DEGREE - 1` and `MAX_KEYS` keys
+const MIN_DEGREE: usize = 6;
+const MAX_KEYS: usize = 2 * MIN_DEGREE - 1;
+
+/// An in-memory B-tree, after Cormen et al.
+///
+/// Each node holds up to [`MAX_KEYS`] entries in order, with a child between each pair of them
+/// holding the entries that fall between their keys, and all the leaves are at the same depth.
+/// Keeping many entries together makes for fewer, larger allocations and better locality than a
+/// binary tree. Nodes are split on the way down to an insertion, and topped up from a sibling on
+/// the way down to a removal, so that neither ever has to go back up the tree.
+pub struct BTree<K, V> {
+    root: Node<K, V>,
+    len: usize,
+}
+
+struct Node<K, V> {
+    keys: Vec<K>,
+    values: Vec<V>,
+    /// Empty for a leaf, and one more than there are keys otherwise
+    children: Vec<Node<K, V>>,
+}
+
+impl<K, V> Node<K, V> {
+    fn new() -> Self {
+        Node {
+            keys: Vec::with_capacity(MAX_KEYS),
+            values: Vec::with_capacity(MAX_KEYS),
+            children: Vec::new(),
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+}
+
+impl<K: Ord, V> Tree<K, V> for BTree<K, V> {
+    fn new() -> Self {
+        BTree {
+            root: Node::new(),
+            len: 0,
+        }
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        if self.root.keys.len() == MAX_KEYS {
+            let old_root = mem::replace(&mut self.root, Node::new());
+            self.root.children.push(old_root);
+            split_child(&mut self.root, 0);
+        }
+        if insert(&mut self.root, key, value).is_none() {
+            self.len += 1;
+        }
+    }
+
+    fn contains(&self, value: K) -> bool {
+        self.get(&value).is_some()
+    }
+
+    fn remove(&mut self, value: K) -> Option<V> {
+        let removed = remove(&mut self.root, &value);
+        if self.root.keys.is_empty() {
+            // The root's last key went into a merge of its only two children, which takes its place
+            if let Some(child) = self.root.children.pop() {
+                self.root = child;
+            }
+        }
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+}
+
+impl<K: Ord, V> BTree<K, V> {
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let mut node = &self.root;
+        loop {
+            match node.keys.binary_search(key) {
+                Ok(i) => return Some(&node.values[i]),
+                Err(i) => node = node.children.get(i)?,
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let mut node = &mut self.root;
+        loop {
+            match node.keys.binary_search(key) {
+                Ok(i) => return Some(&mut node.values[i]),
+                Err(i) => node = node.children.get_mut(i)?,
+            }
+        }
+    }
+
+    /// The entry with the least key
+    pub fn min(&self) -> Option<(&K, &V)> {
+        let mut node = &self.root;
+        while let Some(child) = node.children.first() {
+            node = child;
+        }
+        Some((node.keys.first()?, node.values.first()?))
+    }
+
+    /// The entry with the greatest key
+    pub fn max(&self) -> Option<(&K, &V)> {
+        let mut node = &self.root;
+        while let Some(child) = node.children.last() {
+            node = child;
+        }
+        Some((node.keys.last()?, node.values.last()?))
+    }
+
+    /// The entries of the tree, in order of their keys
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.range(..)
+    }
+
+    /// The entries of the tree whose keys are in a range, in order of their keys
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> impl Iterator<Item = (&K, &V)> {
+        Range::new(&self.root, range)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<K: Ord, V> Default for BTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Splits the full child `i` of a node in two around its middle entry, which moves up into the
+/// node
+fn split_child<K, V>(node: &mut Node<K, V>, i: usize) {
+    let child = &mut node.children[i];
+    let mut right = Node::new();
+    right.keys.extend(child.keys.drain(MIN_DEGREE..));
+    right.values.extend(child.values.drain(MIN_DEGREE..));
+    if !child.is_leaf() {
+        right.children = child.children.split_off(MIN_DEGREE);
+    }
+    let key = child.keys.pop().expect("a full node has a middle key");
+    let value = child.values.pop().expect("a full node has a middle value");
+
+    node.keys.insert(i, key);
+    node.values.insert(i, value);
+    node.children.insert(i + 1, right);
+}
+
+/// Inserts an entry below a node that isn't full, returning the value it replaced, if its key was
+/// already in the tree
+fn insert<K: Ord, V>(node: &mut Node<K, V>, key: K, value: V) -> Option<V> {
+    let mut i = match node.keys.binary_search(&key) {
+        Ok(i) => return Some(mem::replace(&mut node.values[i], value)),
+        Err(i) => i,
+    };
+    if node.is_leaf() {
+        node.keys.insert(i, key);
+        node.values.insert(i, value);
+        return None;
+    }
+
+    if node.children[i].keys.len() == MAX_KEYS {
+        split_child(node, i);
+        match key.cmp(&node.keys[i]) {
+            Ordering::Less => {}
+            Ordering::Greater => i += 1,
+            Ordering::Equal => return Some(mem::replace(&mut node.values[i], value)),
+        }
+    }
+    insert(&mut node.children[i], key, value)
+}
+
+/// Removes the entry with `key` from below a node that has at least [`MIN_DEGREE`] keys, unless it
+/// is the root
+fn remove<K: Ord, V>(node: &mut Node<K, V>, key: &K) -> Option<V> {
+    match node.keys.binary_search(key) {
+        Ok(i) if node.is_leaf() => {
+            node.keys.remove(i);
+            Some(node.values.remove(i))
+        }
+        Ok(i) => {
+            // The entry is replaced by its predecessor or successor, if either child can spare one,
+            // and otherwise moves down into the merge of the two children
+            let (key, value) = if node.children[i].keys.len() >= MIN_DEGREE {
+                remove_max(&mut node.children[i])
+            } else if node.children[i + 1].keys.len() >= MIN_DEGREE {
+                remove_min(&mut node.children[i + 1])
+            } else {
+                merge(node, i);
+                return remove(&mut node.children[i], key);
+            };
+            node.keys[i] = key;
+            Some(mem::replace(&mut node.values[i], value))
+        }
+        Err(_) if node.is_leaf() => None,
+        Err(mut i) => {
+            if node.children[i].keys.len() < MIN_DEGREE {
+                i = fill(node, i);
+            }
+            remove(&mut node.children[i], key)
+        }
+    }
+}
+
+/// Removes the entry with the greatest key from below a node with at least [`MIN_DEGREE`] keys
+fn remove_max<K, V>(node: &mut Node<K, V>) -> (K, V) {
+    if node.is_leaf() {
+        let key = node.keys.pop().expect("only the root can be empty");
+        let value = node.values.pop().expect("only the root can be empty");
+        return (key, value);
+    }
+    let mut i = node.children.len() - 1;
+    if node.children[i].keys.len() < MIN_DEGREE {
+        i = fill(node, i);
+    }
+    remove_max(&mut node.children[i])
+}
+
+/// Removes the entry with the least key from below a node with at least [`MIN_DEGREE`] keys
+fn remove_min<K, V>(node: &mut Node<K, V>) -> (K, V) {
+    if node.is_leaf() {
+        return (node.keys.remove(0), node.values.remove(0));
+    }
+    if node.children[0].keys.len() < MIN_DEGREE {
+        fill(node, 0);
+    }
+    remove_min(&mut node.children[0])
+}
+
+/// Gives child `i` of a node, which has the fewest keys a node can have, another one to spare,
+/// from a sibling if it has one to spare itself, or else by merging it with a sibling. Returns the
+/// index the child ends up at.
+fn fill<K, V>(node: &mut Node<K, V>, i: usize) -> usize {
+    let spare = |sibling: Option<&Node<K, V>>| {
+        sibling.is_some_and(|sibling| sibling.keys.len() >= MIN_DEGREE)
+    };
+
+    if i > 0 && spare(node.children.get(i - 1)) {
+        // The separating entry moves down to the front of the child, and the left sibling's last
+        // entry moves up to take its place
+        let (left, right) = node.children.split_at_mut(i);
+        let (sibling, child) = (left.last_mut().expect("i > 0"), &mut right[0]);
+        let key = sibling.keys.pop().expect("sibling has keys to spare");
+        let value = sibling.values.pop().expect("sibling has keys to spare");
+        child.keys.insert(0, mem::replace(&mut node.keys[i - 1], key));
+        child.values.insert(0, mem::replace(&mut node.values[i - 1], value));
+        if let Some(grandchild) = sibling.children.pop() {
+            child.children.insert(0, grandchild);
+        }
+        i
+    } else if spare(node.children.get(i + 1)) {
+        let (left, right) = node.children.split_at_mut(i + 1);
+        let (child, sibling) = (&mut left[i], &mut right[0]);
+        let key = sibling.keys.remove(0);
+        let value = sibling.values.remove(0);
+        child.keys.push(mem::replace(&mut node.keys[i], key));
+        child.values.push(mem::replace(&mut node.values[i], value));
+        if !sibling.is_leaf() {
+            child.children.push(sibling.children.remove(0));
+        }
+        i
+    } else if i + 1 < node.children.len() {
+        merge(node, i);
+        i
+    } else {
+        merge(node, i - 1);
+        i - 1
+    }
+}
+
+/// Merges children `i` and `i + 1` of a node, and the entry that separates them, into one child
+fn merge<K, V>(node: &mut Node<K, V>, i: usize) {
+    let right = node.children.remove(i + 1);
+    let key = node.keys.remove(i);
+    let value = node.values.remove(i);
+
+    let child = &mut node.children[i];
+    child.keys.push(key);
+    child.keys.extend(right.keys);
+    child.values.push(value);
+    child.values.extend(right.values);
+    child.children.extend(right.children);
+}
+
+/// An in-order iterator over the entries of a B-tree whose keys are in a range
+struct Range<'a, K, V> {
+    /// The nodes on the path to the next entry, each with the index of its next entry, whose
+    /// child before it is done or on the stack above it
+    stack: Vec<(&'a Node<K, V>, usize)>,
+    /// The greatest key in the range
+    last: Option<&'a K>,
+}
+
+impl<'a, K: Ord, V> Range<'a, K, V> {
+    fn new<R: RangeBounds<K>>(root: &'a Node<K, V>, range: R) -> Self {
+        let mut stack = Vec::new();
+        let mut node = root;
+        loop {
+            let i = node
+                .keys
+                .partition_point(|key| !after_start(key, range.start_bound()));
+            stack.push((node, i));
+            match node.children.get(i) {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+
+        let mut last = None;
+        let mut node = root;
+        loop {
+            let i = node
+                .keys
+                .partition_point(|key| before_end(key, range.end_bound()));
+            if i > 0 {
+                last = Some(&node.keys[i - 1]);
+            }
+            match node.children.get(i) {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+
+        Range { stack, last }
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (node, i) = loop {
+            let (node, i) = self.stack.last_mut()?;
+            if *i < node.keys.len() {
+                *i += 1;
+                break (*node, *i - 1);
+            }
+            self.stack.pop();
+        };
+        if self.last.is_none_or(|last| node.keys[i] > *last) {
+            self.stack.clear();
+            return None;
+        }
+
+        let mut child = node.children.get(i + 1);
+        while let Some(next) = child {
+            self.stack.push((next, 0));
+            child = next.children.first();
+        }
+        Some((&node.keys[i], &node.values[i]))
+    }
+}
+
+#[cfg(test)]
+impl<K: Ord, V> Checked<K, V> for BTree<K, V> {
+    /// Checks that the keys are in order, that every node but the root is at least half full and
+    /// none is overfull, that every inner node has one more child than it has keys, and that all
+    /// the leaves are at the same depth
+    fn check(&self) {
+        fn check_node<K, V>(node: &Node<K, V>, is_root: bool) -> usize {
+            assert!(node.keys.len() <= MAX_KEYS, "overfull node");
+            assert!(is_root || node.keys.len() >= MIN_DEGREE - 1, "underfull node");
+            assert_eq!(node.keys.len(), node.values.len());
+            if node.is_leaf() {
+                return 0;
+            }
+            assert_eq!(node.children.len(), node.keys.len() + 1, "wrong number of children");
+            let depth = check_node(&node.children[0], false);
+            for child in &node.children[1..] {
+                assert_eq!(check_node(child, false), depth, "leaves at different depths");
+            }
+            depth + 1
+        }
+
+        check_node(&self.root, true);
+        assert_eq!(self.iter().count(), self.len);
+        assert!(self.iter().zip(self.iter().skip(1)).all(|(a, b)| a.0 < b.0));
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        BTree::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        BTree::get_mut(self, key)
+    }
+
+    fn min(&self) -> Option<(&K, &V)> {
+        BTree::min(self)
+    }
+
+    fn max(&self) -> Option<(&K, &V)> {
+        BTree::max(self)
+    }
+
+    fn range<R: RangeBounds<K>>(&self, range: R) -> Vec<(&K, &V)> {
+        BTree::range(self, range).collect()
+    }
+
+    fn len(&self) -> usize {
+        BTree::len(self)
+    }
+}
+
+#[cfg(test)]
+impl<K, V> BTree<K, V> {
+    /// The number of levels below the root
+    fn depth(&self) -> usize {
+        let mut node = &self.root;
+        let mut depth = 0;
+        while let Some(child) = node.children.first() {
+            node = child;
+            depth += 1;
+        }
+        depth
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::trees::model;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn behaves_like_btree_map(ops: Vec<(bool, u8)>, start: u8, end: u8) -> bool {
+        model::behaves_like_btree_map::<BTree<_, _>>(ops, start, end)
+    }
+
+    #[test]
+    fn stays_balanced() {
+        // Every node but the root has at least 6 children
+        model::stays_balanced::<BTree<_, _>>(10_000, 5, BTree::depth);
+    }
+
+    #[test]
+    fn updates_values() {
+        model::updates_values::<BTree<_, _>>();
+    }
+}
@@ -0,0 +1,147 @@
+//! The nodes of binary search trees, and the lookups and iterators that don't depend on how a tree
+//! is balanced. `M` is whatever a tree keeps in each node to balance it.
+use std::cmp::Ordering;
+use std::ops::{Bound, RangeBounds};
+
+pub(crate) type Link<K, V, M> = Option<Box<Node<K, V, M>>>;
+
+pub(crate) struct Node<K, V, M> {
+    pub(crate) key: K,
+    pub(crate) value: V,
+    pub(crate) meta: M,
+    pub(crate) left: Link<K, V, M>,
+    pub(crate) right: Link<K, V, M>,
+}
+
+impl<K, V, M> Node<K, V, M> {
+    pub(crate) fn new(key: K, value: V, meta: M) -> Box<Self> {
+        Box::new(Node {
+            key,
+            value,
+            meta,
+            left: None,
+            right: None,
+        })
+    }
+}
+
+pub(crate) fn get<'a, K: Ord, V, M>(mut link: &'a Link<K, V, M>, key: &K) -> Option<&'a V> {
+    while let Some(node) = link {
+        link = match key.cmp(&node.key) {
+            Ordering::Less => &node.left,
+            Ordering::Greater => &node.right,
+            Ordering::Equal => return Some(&node.value),
+        };
+    }
+    None
+}
+
+pub(crate) fn get_mut<'a, K: Ord, V, M>(
+    mut link: &'a mut Link<K, V, M>,
+    key: &K,
+) -> Option<&'a mut V> {
+    while let Some(node) = link {
+        link = match key.cmp(&node.key) {
+            Ordering::Less => &mut node.left,
+            Ordering::Greater => &mut node.right,
+            Ordering::Equal => return Some(&mut node.value),
+        };
+    }
+    None
+}
+
+pub(crate) fn min<K, V, M>(link: &Link<K, V, M>) -> Option<(&K, &V)> {
+    let mut node = link.as_deref()?;
+    while let Some(left) = &node.left {
+        node = left;
+    }
+    Some((&node.key, &node.value))
+}
+
+pub(crate) fn max<K, V, M>(link: &Link<K, V, M>) -> Option<(&K, &V)> {
+    let mut node = link.as_deref()?;
+    while let Some(right) = &node.right {
+        node = right;
+    }
+    Some((&node.key, &node.value))
+}
+
+/// An in-order iterator over the entries of a tree whose keys are in a range
+pub(crate) struct Range<'a, K, V, M> {
+    /// The nodes whose entries are still to come, and whose left subtrees are done, next on top
+    stack: Vec<&'a Node<K, V, M>>,
+    /// The greatest key in the range
+    last: Option<&'a K>,
+}
+
+impl<'a, K: Ord, V, M> Range<'a, K, V, M> {
+    pub(crate) fn new<R: RangeBounds<K>>(root: &'a Link<K, V, M>, range: R) -> Self {
+        let mut stack = Vec::new();
+        let mut link = root;
+        while let Some(node) = link {
+            link = if after_start(&node.key, range.start_bound()) {
+                stack.push(&**node);
+                &node.left
+            } else {
+                &node.right
+            };
+        }
+
+        let mut last = None;
+        let mut link = root;
+        while let Some(node) = link {
+            link = if before_end(&node.key, range.end_bound()) {
+                last = Some(&node.key);
+                &node.right
+            } else {
+                &node.left
+            };
+        }
+
+        Range { stack, last }
+    }
+}
+
+impl<'a, K: Ord, V, M> Iterator for Range<'a, K, V, M> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        if self.last.is_none_or(|last| node.key > *last) {
+            self.stack.clear();
+            return None;
+        }
+
+        let mut link = &node.right;
+        while let Some(next) = link {
+            self.stack.push(next);
+            link = &next.left;
+        }
+        Some((&node.key, &node.value))
+    }
+}
+
+/// Whether `key` is not before the start of a range
+pub(crate) fn after_start<K: Ord>(key: &K, start: Bound<&K>) -> bool {
+    match start {
+        Bound::Included(start) => key >= start,
+        Bound::Excluded(start) => key > start,
+        Bound::Unbounded => true,
+    }
+}
+
+/// Whether `key` is not after the end of a range
+pub(crate) fn before_end<K: Ord>(key: &K, end: Bound<&K>) -> bool {
+    match end {
+        Bound::Included(end) => key <= end,
+        Bound::Excluded(end) => key < end,
+        Bound::Unbounded => true,
+    }
+}
+
+/// The number of nodes on the longest path down from a link
+#[cfg(test)]
+pub(crate) fn depth<K, V, M>(link: &Link<K, V, M>) -> usize {
+    link.as_ref()
+        .map_or(0, |node| 1 + depth(&node.left).max(depth(&node.right)))
+}
@@ -1,4 +1,9 @@
-trait Tree<K: Ord, V> {
+mod avl_tree;
+mod b_tree;
+mod binary;
+mod red_black_tree;
+
+pub trait Tree<K: Ord, V> {
     /// Creates a new tree
     fn new() -> Self;
     /// Inserts a value into the tree
@@ -8,3 +13,98 @@ trait Tree<K: Ord, V> {
     /// Attempts to remove a value from the tree
     fn remove(&mut self, value: K) -> Option<V>;
 }
+
+/// Tests shared by every [`Tree`], which compare it with std's `BTreeMap`
+#[cfg(test)]
+pub(crate) mod model {
+    use std::collections::BTreeMap;
+    use std::ops::RangeBounds;
+
+    use super::Tree;
+
+    /// What the tests need of a tree besides [`Tree`]
+    pub(crate) trait Checked<K: Ord, V>: Tree<K, V> {
+        /// Panics if the tree's invariants don't hold
+        fn check(&self);
+        fn get(&self, key: &K) -> Option<&V>;
+        fn get_mut(&mut self, key: &K) -> Option<&mut V>;
+        /// The entry with the least key
+        fn min(&self) -> Option<(&K, &V)>;
+        /// The entry with the greatest key
+        fn max(&self) -> Option<(&K, &V)>;
+        /// The entries whose keys are in a range, in order of their keys
+        fn range<R: RangeBounds<K>>(&self, range: R) -> Vec<(&K, &V)>;
+        fn len(&self) -> usize;
+    }
+
+    /// Inserts and removes keys in a tree and in a `BTreeMap`, checking the tree after each
+    /// change, and then reads both every way there is
+    pub(crate) fn behaves_like_btree_map<T>(ops: Vec<(bool, u8)>, start: u8, end: u8) -> bool
+    where
+        T: Checked<u8, usize>,
+    {
+        let mut tree = T::new();
+        let mut model = BTreeMap::new();
+        for (i, (insert, key)) in ops.into_iter().enumerate() {
+            if insert {
+                tree.insert(key, i);
+                model.insert(key, i);
+            } else if tree.remove(key) != model.remove(&key) {
+                return false;
+            }
+            tree.check();
+        }
+
+        tree.len() == model.len()
+            && tree.range(..).into_iter().eq(model.iter())
+            && tree.range(start..end).into_iter().eq(model.range(start..end.max(start)))
+            && tree.range(..=end).into_iter().eq(model.range(..=end))
+            && tree.min() == model.iter().next()
+            && tree.max() == model.iter().next_back()
+            && (0..=u8::MAX).all(|k| tree.get(&k) == model.get(&k) && tree.contains(k) == model.contains_key(&k))
+    }
+
+    /// Inserts `n` keys in order, which would leave a tree that doesn't rebalance as deep as it is
+    /// long, and then removes them, checking that the tree is never deeper than `max_depth`
+    pub(crate) fn stays_balanced<T>(n: u32, max_depth: usize, depth: impl Fn(&T) -> usize)
+    where
+        T: Checked<u32, u32>,
+    {
+        let mut tree = T::new();
+        for i in 0..n {
+            tree.insert(i, i * 2);
+        }
+        tree.check();
+        assert!(depth(&tree) <= max_depth);
+
+        for i in (0..n).filter(|i| i % 3 != 0) {
+            assert_eq!(tree.remove(i), Some(i * 2));
+            assert_eq!(tree.remove(i), None);
+        }
+        tree.check();
+        assert!(depth(&tree) <= max_depth);
+        assert_eq!(tree.len(), (0..n).step_by(3).len());
+
+        for i in (0..n).step_by(3) {
+            assert_eq!(tree.remove(i), Some(i * 2));
+        }
+        tree.check();
+        assert_eq!(tree.len(), 0);
+        assert_eq!(depth(&tree), 0);
+    }
+
+    /// Changes a value through `get_mut`, and another by inserting its key again
+    pub(crate) fn updates_values<T>()
+    where
+        T: Checked<&'static str, i32>,
+    {
+        let mut tree = T::new();
+        tree.insert("a", 1);
+        tree.insert("b", 2);
+        *tree.get_mut(&"a").unwrap() += 10;
+        tree.insert("b", 20);
+        assert_eq!(tree.get_mut(&"c"), None);
+        assert_eq!(tree.range(..), [(&"a", &11), (&"b", &20)]);
+        assert_eq!(tree.len(), 2);
+    }
+}
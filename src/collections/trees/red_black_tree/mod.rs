@@ -0,0 +1,353 @@
+use std::cmp::Ordering;
+use std::mem;
+use std::ops::RangeBounds;
+
+use crate::collections::trees::binary::{self, Link, Node};
+#[cfg(test)]
+use crate::collections::trees::model::Checked;
+use crate::collections::trees::Tree;
+
+/// The color of the link from a node's parent to the node
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Color {
+    Red,
+    Black,
+}
+
+impl Color {
+    fn flip(self) -> Color {
+        match self {
+            Color::Red => Color::Black,
+            Color::Black => Color::Red,
+        }
+    }
+}
+
+type RbLink<K, V> = Link<K, V, Color>;
+type RbNode<K, V> = Box<Node<K, V, Color>>;
+
+/// A red-black tree, in its left-leaning form, after Robert Sedgewick.
+///
+/// Every path from the root to a leaf passes through the same number of black nodes, and no red
+/// node has a red child, so the tree is never more than 2 log₂ n deep. In the left-leaning form
+/// only left children are red, which makes the tree a 2-3 tree in which a node and its red left
+/// child are one node with two keys, and leaves fewer cases to handle when rebalancing.
+pub struct RedBlackTree<K, V> {
+    root: RbLink<K, V>,
+    len: usize,
+}
+
+impl<K: Ord, V> Tree<K, V> for RedBlackTree<K, V> {
+    fn new() -> Self {
+        RedBlackTree { root: None, len: 0 }
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        let (mut root, replaced) = insert(self.root.take(), key, value);
+        root.meta = Color::Black;
+        self.root = Some(root);
+        if replaced.is_none() {
+            self.len += 1;
+        }
+    }
+
+    fn contains(&self, value: K) -> bool {
+        self.get(&value).is_some()
+    }
+
+    fn remove(&mut self, value: K) -> Option<V> {
+        // Removing moves red links down the path to the key, which only works if the key is there
+        if !self.contains_key(&value) {
+            return None;
+        }
+
+        let mut root = self.root.take()?;
+        if !is_red(&root.left) && !is_red(&root.right) {
+            root.meta = Color::Red;
+        }
+        let (mut root, removed) = remove(root, &value);
+        if let Some(root) = &mut root {
+            root.meta = Color::Black;
+        }
+        self.root = root;
+        self.len -= 1;
+        removed
+    }
+}
+
+impl<K: Ord, V> RedBlackTree<K, V> {
+    pub fn get(&self, key: &K) -> Option<&V> {
+        binary::get(&self.root, key)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        binary::get_mut(&mut self.root, key)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// The entry with the least key
+    pub fn min(&self) -> Option<(&K, &V)> {
+        binary::min(&self.root)
+    }
+
+    /// The entry with the greatest key
+    pub fn max(&self) -> Option<(&K, &V)> {
+        binary::max(&self.root)
+    }
+
+    /// The entries of the tree, in order of their keys
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.range(..)
+    }
+
+    /// The entries of the tree whose keys are in a range, in order of their keys
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> impl Iterator<Item = (&K, &V)> {
+        binary::Range::new(&self.root, range)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<K: Ord, V> Default for RedBlackTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_red<K, V>(link: &RbLink<K, V>) -> bool {
+    matches!(link, Some(node) if node.meta == Color::Red)
+}
+
+/// Whether the left child of a node's left child is red
+fn is_left_left_red<K, V>(link: &RbLink<K, V>) -> bool {
+    matches!(link, Some(node) if is_red(&node.left))
+}
+
+/// Turns a right-leaning red link into a left-leaning one
+fn rotate_left<K, V>(mut node: RbNode<K, V>) -> RbNode<K, V> {
+    let mut right = node.right.take().expect("rotating left needs a right child");
+    node.right = right.left.take();
+    right.meta = node.meta;
+    node.meta = Color::Red;
+    right.left = Some(node);
+    right
+}
+
+/// Turns a left-leaning red link into a right-leaning one
+fn rotate_right<K, V>(mut node: RbNode<K, V>) -> RbNode<K, V> {
+    let mut left = node.left.take().expect("rotating right needs a left child");
+    node.left = left.right.take();
+    left.meta = node.meta;
+    node.meta = Color::Red;
+    left.right = Some(node);
+    left
+}
+
+/// Splits a node with two red children into two nodes, passing the middle key up to its parent,
+/// or undoes that
+fn flip_colors<K, V>(node: &mut Node<K, V, Color>) {
+    node.meta = node.meta.flip();
+    for child in [&mut node.left, &mut node.right].into_iter().flatten() {
+        child.meta = child.meta.flip();
+    }
+}
+
+/// Restores the invariants below a node on the way back up from an insertion or a removal
+fn fix_up<K, V>(mut node: RbNode<K, V>) -> RbNode<K, V> {
+    if is_red(&node.right) && !is_red(&node.left) {
+        node = rotate_left(node);
+    }
+    if is_red(&node.left) && is_left_left_red(&node.left) {
+        node = rotate_right(node);
+    }
+    if is_red(&node.left) && is_red(&node.right) {
+        flip_colors(&mut node);
+    }
+    node
+}
+
+/// Inserts an entry, returning the new root of the subtree and the value the entry replaced, if
+/// its key was already there
+fn insert<K: Ord, V>(link: RbLink<K, V>, key: K, value: V) -> (RbNode<K, V>, Option<V>) {
+    let mut node = match link {
+        Some(node) => node,
+        None => return (Node::new(key, value, Color::Red), None),
+    };
+
+    let replaced = match key.cmp(&node.key) {
+        Ordering::Less => {
+            let (left, replaced) = insert(node.left.take(), key, value);
+            node.left = Some(left);
+            replaced
+        }
+        Ordering::Greater => {
+            let (right, replaced) = insert(node.right.take(), key, value);
+            node.right = Some(right);
+            replaced
+        }
+        Ordering::Equal => Some(mem::replace(&mut node.value, value)),
+    };
+    (fix_up(node), replaced)
+}
+
+/// Makes the left child of a node, or one of its children, red, so that a key can be removed
+/// from below it without leaving the left subtree a black node short
+fn move_red_left<K, V>(mut node: RbNode<K, V>) -> RbNode<K, V> {
+    flip_colors(&mut node);
+    if matches!(&node.right, Some(right) if is_red(&right.left)) {
+        node.right = node.right.take().map(rotate_right);
+        node = rotate_left(node);
+        flip_colors(&mut node);
+    }
+    node
+}
+
+/// Makes the right child of a node, or one of its children, red
+fn move_red_right<K, V>(mut node: RbNode<K, V>) -> RbNode<K, V> {
+    flip_colors(&mut node);
+    if is_left_left_red(&node.left) {
+        node = rotate_right(node);
+        flip_colors(&mut node);
+    }
+    node
+}
+
+/// Removes the entry with `key`, which must be in the subtree, returning the new root of the
+/// subtree and the entry's value
+fn remove<K: Ord, V>(mut node: RbNode<K, V>, key: &K) -> (RbLink<K, V>, Option<V>) {
+    let removed;
+    if *key < node.key {
+        if !is_red(&node.left) && !is_left_left_red(&node.left) {
+            node = move_red_left(node);
+        }
+        let (left, value) = match node.left.take() {
+            Some(left) => remove(left, key),
+            None => (None, None),
+        };
+        node.left = left;
+        removed = value;
+    } else {
+        if is_red(&node.left) {
+            node = rotate_right(node);
+        }
+        if *key == node.key && node.right.is_none() {
+            return (None, Some(node.value));
+        }
+        if !is_red(&node.right) && !is_left_left_red(&node.right) {
+            node = move_red_right(node);
+        }
+        if *key == node.key {
+            // The least entry of the right subtree takes the removed entry's place
+            let (right, min) = remove_min(node.right.take().expect("checked above"));
+            node.right = right;
+            let min = *min;
+            node.key = min.key;
+            removed = Some(mem::replace(&mut node.value, min.value));
+        } else {
+            let (right, value) = match node.right.take() {
+                Some(right) => remove(right, key),
+                None => (None, None),
+            };
+            node.right = right;
+            removed = value;
+        }
+    }
+    (Some(fix_up(node)), removed)
+}
+
+/// Removes the node with the least key, returning the new root of the subtree and the node
+fn remove_min<K, V>(mut node: RbNode<K, V>) -> (RbLink<K, V>, RbNode<K, V>) {
+    if node.left.is_none() {
+        // Only a left child can be red, so a node without one has no children at all
+        return (None, node);
+    }
+    if !is_red(&node.left) && !is_left_left_red(&node.left) {
+        node = move_red_left(node);
+    }
+    let (left, min) = remove_min(node.left.take().expect("checked above"));
+    node.left = left;
+    (Some(fix_up(node)), min)
+}
+
+#[cfg(test)]
+impl<K: Ord, V> Checked<K, V> for RedBlackTree<K, V> {
+    /// Checks that the keys are in order, that the root is black, that no red node has a red
+    /// child, that only left children are red, and that every path to a leaf has the same number
+    /// of black nodes
+    fn check(&self) {
+        fn black_height<K, V>(link: &RbLink<K, V>) -> usize {
+            let node = match link {
+                Some(node) => node,
+                None => return 1,
+            };
+            assert!(!is_red(&node.right), "red right child");
+            if node.meta == Color::Red {
+                assert!(!is_red(&node.left), "red node with a red child");
+            }
+            let left = black_height(&node.left);
+            assert_eq!(left, black_height(&node.right), "unequal black heights");
+            left + (node.meta == Color::Black) as usize
+        }
+
+        assert!(!is_red(&self.root), "red root");
+        black_height(&self.root);
+        assert_eq!(self.iter().count(), self.len);
+        assert!(self.iter().zip(self.iter().skip(1)).all(|(a, b)| a.0 < b.0));
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        RedBlackTree::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        RedBlackTree::get_mut(self, key)
+    }
+
+    fn min(&self) -> Option<(&K, &V)> {
+        RedBlackTree::min(self)
+    }
+
+    fn max(&self) -> Option<(&K, &V)> {
+        RedBlackTree::max(self)
+    }
+
+    fn range<R: RangeBounds<K>>(&self, range: R) -> Vec<(&K, &V)> {
+        RedBlackTree::range(self, range).collect()
+    }
+
+    fn len(&self) -> usize {
+        RedBlackTree::len(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::trees::model;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn behaves_like_btree_map(ops: Vec<(bool, u8)>, start: u8, end: u8) -> bool {
+        model::behaves_like_btree_map::<RedBlackTree<_, _>>(ops, start, end)
+    }
+
+    #[test]
+    fn stays_balanced() {
+        // A red-black tree of 1024 entries is at most 2 log₂ 1025 deep
+        model::stays_balanced::<RedBlackTree<_, _>>(1024, 20, |tree| binary::depth(&tree.root));
+    }
+
+    #[test]
+    fn updates_values() {
+        model::updates_values::<RedBlackTree<_, _>>();
+    }
+}
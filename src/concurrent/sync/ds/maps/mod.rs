@@ -1 +1,2 @@
 pub mod shared;
+pub mod skip_list;
@@ -0,0 +1,635 @@
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::ptr;
+use std::sync::atomic::AtomicIsize;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, SeqCst};
+use std::sync::atomic::{fence, AtomicUsize};
+
+use crossbeam::epoch::{self, pin, Atomic, Guard, Owned, Shared};
+use rand::Rng;
+
+/// No node is taller than this, which is plenty for 2³² entries
+const MAX_HEIGHT: usize = 32;
+
+/// The tag on a node's link to the next node at a level once the node is removed from that level
+const REMOVED: usize = 1;
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    /// The next node at each level the node is on, tagged with [`REMOVED`] once it is removed
+    next: Box<[Atomic<Node<K, V>>]>,
+    /// How many levels the node has been unlinked from, or was never linked into. It is destroyed
+    /// once that is all of them.
+    unlinked: AtomicUsize,
+}
+
+/// A lock-free ordered map on a skip list, after Fraser, and Herlihy and Shavit.
+///
+/// Usable from any number of threads at once. An entry is inserted by linking it into the bottom
+/// level with a compare-and-swap, which is the moment it appears in the map, and then into the
+/// levels above. It is removed by tagging its links to the next node at each level, top down, and
+/// tagging the bottom one is the moment it disappears. Searches unlink any tagged nodes they come
+/// across, and nodes are freed once they are unlinked from every level, with the same epoch-based
+/// reclamation as `TreiberStack`.
+///
+/// Values are cloned out, since another thread may remove an entry while it is being read.
+///
+/// # Examples
+///
+/// ```
+/// use lib_wc::sync::ds::SkipListMap;
+///
+/// let map = SkipListMap::new();
+/// assert!(map.insert(2, "b"));
+/// assert!(map.insert(1, "a"));
+/// assert!(!map.insert(1, "z"));
+///
+/// assert_eq!(map.get(&1), Some("a"));
+/// assert_eq!(map.iter().collect::<Vec<_>>(), [(1, "a"), (2, "b")]);
+/// assert_eq!(map.remove(&2), Some("b"));
+/// ```
+pub struct SkipListMap<K, V> {
+    /// The first node at each level
+    head: [Atomic<Node<K, V>>; MAX_HEIGHT],
+    /// Signed, since a removal can be counted before the insertion of the entry it removed
+    len: AtomicIsize,
+}
+
+/// Where a key belongs on each level of the list
+struct Position<'g, K, V> {
+    /// The link into the key's place, from the last node before it or from the head
+    links: [&'g Atomic<Node<K, V>>; MAX_HEIGHT],
+    /// The first node that isn't before the key, or null
+    nodes: [Shared<'g, Node<K, V>>; MAX_HEIGHT],
+}
+
+impl<K: Ord, V> SkipListMap<K, V> {
+    /// Creates a new, empty map.
+    pub fn new() -> Self {
+        SkipListMap {
+            head: std::array::from_fn(|_| Atomic::null()),
+            len: AtomicIsize::new(0),
+        }
+    }
+
+    /// Inserts an entry, unless the map already has one with the same key.
+    ///
+    /// Returns `true` if the entry was inserted.
+    pub fn insert(&self, key: K, value: V) -> bool {
+        let guard = &pin();
+        let height = random_height();
+        let mut new = Owned::new(Node {
+            key,
+            value,
+            next: (0..height).map(|_| Atomic::null()).collect(),
+            unlinked: AtomicUsize::new(0),
+        });
+
+        let (node, mut position) = loop {
+            let position = self.find(|key| *key < new.key, guard);
+            if let Some(found) = unsafe { position.nodes[0].as_ref() } {
+                if found.key == new.key {
+                    return false;
+                }
+            }
+
+            new.next[0].store(position.nodes[0], Relaxed);
+            match position.links[0].compare_exchange(position.nodes[0], new, AcqRel, Acquire, guard)
+            {
+                Ok(node) => break (node, position),
+                Err(e) => new = e.new,
+            }
+        };
+        self.len.fetch_add(1, Relaxed);
+
+        // The entry is in the map now, and the levels above only speed up searches for it. If it is
+        // removed while they are being linked, the rest are left unlinked.
+        let entry = unsafe { node.deref() };
+        let mut linked = 1;
+        'levels: while linked < height {
+            let level = linked;
+            loop {
+                let next = entry.next[level].load(Acquire, guard);
+                let succ = position.nodes[level];
+                if next.tag() == REMOVED
+                    || entry.next[level]
+                        .compare_exchange(next, succ, AcqRel, Acquire, guard)
+                        .is_err()
+                {
+                    break 'levels;
+                }
+                if position.links[level]
+                    .compare_exchange(succ, node, AcqRel, Acquire, guard)
+                    .is_ok()
+                {
+                    break;
+                }
+                position = self.find(|key| *key < entry.key, guard);
+            }
+            linked += 1;
+        }
+
+        if linked < height {
+            unsafe { release(node, height - linked, guard) };
+        }
+        // If the entry was removed before the search that removal ends with could see all of its
+        // levels, they are unlinked here instead. The fence pairs with the one in `remove`, so that
+        // either that search sees every level linked above, or this load sees the removal.
+        fence(SeqCst);
+        if entry.next[0].load(Acquire, guard).tag() == REMOVED {
+            self.find(|key| *key < entry.key, guard);
+        }
+        true
+    }
+
+    /// Returns a clone of the value for `key`.
+    pub fn get(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        let guard = &pin();
+        let node = unsafe { self.find(|k| k < key, guard).nodes[0].as_ref()? };
+        (node.key == *key).then(|| node.value.clone())
+    }
+
+    /// Returns `true` if the map has an entry for `key`.
+    pub fn contains_key(&self, key: &K) -> bool {
+        let guard = &pin();
+        let node = self.find(|k| k < key, guard).nodes[0];
+        unsafe { node.as_ref() }.is_some_and(|node| node.key == *key)
+    }
+
+    /// Removes the entry for `key`, returning a clone of its value.
+    pub fn remove(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        let guard = &pin();
+        let node = unsafe { self.find(|k| k < key, guard).nodes[0].as_ref()? };
+        if node.key != *key {
+            return None;
+        }
+
+        for level in (1..node.next.len()).rev() {
+            node.next[level].fetch_or(REMOVED, AcqRel, guard);
+        }
+        // Whichever thread tags the bottom level removes the entry
+        if node.next[0].fetch_or(REMOVED, AcqRel, guard).tag() == REMOVED {
+            return None;
+        }
+        self.len.fetch_sub(1, Relaxed);
+
+        let value = node.value.clone();
+        // Unlinks the node from every level. An insertion of it may still be linking the levels
+        // above, and the fence pairs with the one at the end of `insert`, so that either this
+        // search sees those levels, or the insertion sees the removal and unlinks them itself.
+        fence(SeqCst);
+        self.find(|k| k < key, guard);
+        Some(value)
+    }
+
+    /// Returns an iterator over clones of the entries of the map, in order of their keys.
+    ///
+    /// See [`range`](Self::range).
+    pub fn iter(&self) -> Range<'_, K, V>
+    where
+        K: Clone,
+    {
+        self.range(..)
+    }
+
+    /// Returns an iterator over clones of the entries of the map whose keys are in a range, in
+    /// order of their keys.
+    ///
+    /// The iterator sees every entry that is in the map from when it is created until it is done,
+    /// and none that is removed before it is created, and it may or may not see the others. It
+    /// keeps the thread pinned, which holds up the reclamation of removed nodes, so it shouldn't be
+    /// kept around.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V>
+    where
+        K: Clone,
+    {
+        let guard = pin();
+        let next = self
+            .find(|key| before_start(key, range.start_bound()), &guard)
+            .nodes[0]
+            .as_raw();
+
+        Range {
+            guard,
+            next,
+            end: range.end_bound().cloned(),
+            map: PhantomData,
+        }
+    }
+
+    /// Returns the number of entries in the map, which may already be out of date if other
+    /// threads are changing it.
+    pub fn len(&self) -> usize {
+        self.len.load(Relaxed).max(0) as usize
+    }
+
+    /// Returns `true` if the map has no entries, which may already be out of date if other
+    /// threads are changing it.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Finds where on each level the keys for which `before` holds end, which must be a prefix of
+    /// them, and unlinks any removed nodes on the way
+    fn find<'g>(&'g self, before: impl Fn(&K) -> bool, guard: &'g Guard) -> Position<'g, K, V> {
+        'retry: loop {
+            let mut position = Position {
+                links: [&self.head[0]; MAX_HEIGHT],
+                nodes: [Shared::null(); MAX_HEIGHT],
+            };
+            let mut tower: &'g [Atomic<Node<K, V>>] = &self.head;
+            for level in (0..MAX_HEIGHT).rev() {
+                let mut curr = tower[level].load(Acquire, guard);
+                if curr.tag() == REMOVED {
+                    // The node we were going to continue from has been removed from this level
+                    continue 'retry;
+                }
+
+                while let Some(node) = unsafe { curr.as_ref() } {
+                    let next = node.next[level].load(Acquire, guard);
+                    if next.tag() == REMOVED {
+                        let next = next.with_tag(0);
+                        if tower[level]
+                            .compare_exchange(curr, next, AcqRel, Acquire, guard)
+                            .is_err()
+                        {
+                            continue 'retry;
+                        }
+                        unsafe { release(curr, 1, guard) };
+                        curr = next;
+                    } else if before(&node.key) {
+                        tower = &node.next;
+                        curr = next;
+                    } else {
+                        break;
+                    }
+                }
+
+                position.links[level] = &tower[level];
+                position.nodes[level] = curr;
+            }
+            return position;
+        }
+    }
+}
+
+/// Counts `levels` more levels that a node is off, and destroys it once it is off all of them
+///
+/// # Safety
+///
+/// The node must be off those levels, and not have been counted as off them already.
+unsafe fn release<K, V>(node: Shared<'_, Node<K, V>>, levels: usize, guard: &Guard) {
+    let entry = node.deref();
+    if entry.unlinked.fetch_add(levels, AcqRel) + levels == entry.next.len() {
+        guard.defer_destroy(node);
+    }
+}
+
+/// A height of `h` with probability 2⁻ʰ
+fn random_height() -> usize {
+    let height = rand::thread_rng().gen::<u32>().trailing_ones() as usize + 1;
+    height.min(MAX_HEIGHT)
+}
+
+/// Whether `key` is before the start of a range
+fn before_start<K: Ord>(key: &K, start: Bound<&K>) -> bool {
+    match start {
+        Bound::Included(start) => key < start,
+        Bound::Excluded(start) => key <= start,
+        Bound::Unbounded => false,
+    }
+}
+
+/// Whether `key` is after the end of a range
+fn after_end<K: Ord>(key: &K, end: Bound<&K>) -> bool {
+    match end {
+        Bound::Included(end) => key > end,
+        Bound::Excluded(end) => key >= end,
+        Bound::Unbounded => false,
+    }
+}
+
+impl<K, V> Drop for SkipListMap<K, V> {
+    fn drop(&mut self) {
+        // Nothing else can be using the map. Nodes off every level have been left to the
+        // collector, but a removed node may still be on some of the levels above the bottom, so
+        // each node is counted off every level it is found on, and freed after the last of them.
+        unsafe {
+            let guard = epoch::unprotected();
+            for level in (0..MAX_HEIGHT).rev() {
+                let mut node = self.head[level].load(Relaxed, guard);
+                while let Some(entry) = node.as_ref() {
+                    let next = entry.next[level].load(Relaxed, guard).with_tag(0);
+                    if entry.unlinked.fetch_add(1, Relaxed) + 1 == entry.next.len() {
+                        drop(node.into_owned());
+                    }
+                    node = next;
+                }
+            }
+        }
+    }
+}
+
+impl<K: Ord, V> Default for SkipListMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An iterator over clones of the entries of a [`SkipListMap`] whose keys are in a range
+pub struct Range<'a, K, V> {
+    /// Keeps the nodes the iterator is on from being destroyed
+    guard: Guard,
+    next: *const Node<K, V>,
+    end: Bound<K>,
+    map: PhantomData<&'a SkipListMap<K, V>>,
+}
+
+impl<'a, K: Ord + Clone, V: Clone> Iterator for Range<'a, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let node = unsafe { self.next.as_ref()? };
+            if after_end(&node.key, self.end.as_ref()) {
+                self.next = ptr::null();
+                return None;
+            }
+
+            let next = node.next[0].load(Acquire, &self.guard);
+            self.next = next.with_tag(0).as_raw();
+            if next.tag() != REMOVED {
+                return Some((node.key.clone(), node.value.clone()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl<K: Ord, V> SkipListMap<K, V> {
+    /// Checks, while no other thread is using the map, that every level is in order, that no
+    /// removed node is left on any of them, and that the length is right
+    fn check(&self) {
+        let guard = &pin();
+        for level in 0..MAX_HEIGHT {
+            let mut len = 0;
+            let mut prev: Option<&K> = None;
+            let mut node = self.head[level].load(Acquire, guard);
+            while let Some(entry) = unsafe { node.as_ref() } {
+                let next = entry.next[level].load(Acquire, guard);
+                assert_eq!(next.tag(), 0, "removed node left on level {}", level);
+                assert!(
+                    prev.is_none_or(|prev| *prev < entry.key),
+                    "level {} out of order",
+                    level
+                );
+                prev = Some(&entry.key);
+                len += 1;
+                node = next;
+            }
+            if level == 0 {
+                assert_eq!(len, self.len());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::{BTreeMap, HashSet};
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::Release;
+    use std::sync::Arc;
+    use std::thread::scope;
+
+    const THREADS: u64 = 8;
+
+    #[test]
+    fn one_thread() {
+        let map = SkipListMap::new();
+        assert!(map.is_empty());
+
+        for i in (0..1000).rev() {
+            assert!(map.insert(i, i * 2));
+        }
+        assert!(!map.insert(10, 0));
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.get(&10), Some(20));
+        assert_eq!(map.get(&1000), None);
+
+        for i in (0..1000).step_by(2) {
+            assert_eq!(map.remove(&i), Some(i * 2));
+            assert_eq!(map.remove(&i), None);
+        }
+        assert!(!map.contains_key(&10));
+        assert!(map.iter().map(|(k, _)| k).eq((1..1000).step_by(2)));
+        assert!(map.range(10..=15).eq([(11, 22), (13, 26), (15, 30)]));
+        assert_eq!(
+            map.range((Bound::Excluded(15), Bound::Excluded(16))).next(),
+            None
+        );
+    }
+
+    #[test]
+    fn disjoint_keys_match_serial_model() {
+        let map = SkipListMap::new();
+
+        // Each thread has keys of its own, so a model of just its operations predicts the results
+        // of all of them, even though the threads share the list's nodes
+        let models = scope(|s| {
+            let threads = (0..THREADS)
+                .map(|t| {
+                    let map = &map;
+                    s.spawn(move || {
+                        let mut rng = StdRng::seed_from_u64(t);
+                        let mut model = BTreeMap::new();
+                        for i in 0..20_000 {
+                            let key = rng.gen_range(0..512) * THREADS + t;
+                            match rng.gen_range(0..3) {
+                                0 => {
+                                    let absent = !model.contains_key(&key);
+                                    if absent {
+                                        model.insert(key, i);
+                                    }
+                                    assert_eq!(map.insert(key, i), absent);
+                                }
+                                1 => assert_eq!(map.remove(&key), model.remove(&key)),
+                                _ => assert_eq!(map.get(&key), model.get(&key).copied()),
+                            }
+                        }
+                        model
+                    })
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        let model = models.into_iter().flatten().collect::<BTreeMap<_, _>>();
+        assert!(map.iter().eq(model.clone()));
+        assert!(map
+            .range(100..200)
+            .eq(model.range(100..200).map(|(&k, &v)| (k, v))));
+        assert_eq!(map.len(), model.len());
+        map.check();
+    }
+
+    #[test]
+    fn contended_keys_match_serial_model() {
+        let map = SkipListMap::new();
+
+        // Every value is inserted at most once, so each removal can be matched to an insertion
+        let histories = scope(|s| {
+            let threads = (0..THREADS)
+                .map(|t| {
+                    let map = &map;
+                    s.spawn(move || {
+                        let mut rng = StdRng::seed_from_u64(t);
+                        let (mut inserted, mut removed) = (Vec::new(), Vec::new());
+                        for i in 0..20_000 {
+                            let key = rng.gen_range(0..32);
+                            if rng.gen() {
+                                if map.insert(key, (t, i)) {
+                                    inserted.push((key, (t, i)));
+                                }
+                            } else if let Some(value) = map.remove(&key) {
+                                removed.push((key, value));
+                            }
+                        }
+                        (inserted, removed)
+                    })
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        let mut live = HashSet::new();
+        let mut removed = Vec::new();
+        for (inserts, removes) in histories {
+            live.extend(inserts);
+            removed.extend(removes);
+        }
+        for entry in removed {
+            assert!(
+                live.remove(&entry),
+                "{:?} removed but never inserted",
+                entry
+            );
+        }
+
+        // What was inserted and not removed is what's left, and there's at most one per key
+        let mut live = live.into_iter().collect::<Vec<_>>();
+        live.sort();
+        assert!(map.iter().eq(live.iter().copied()));
+        assert_eq!(map.len(), live.len());
+        map.check();
+    }
+
+    #[test]
+    fn scans_stay_sorted_while_writers_run() {
+        let map = SkipListMap::new();
+        // Multiples of 10 are never removed, so every scan must see them
+        for key in (0..1000).step_by(10) {
+            map.insert(key, key);
+        }
+        let done = AtomicBool::new(false);
+
+        scope(|s| {
+            for t in 0..THREADS / 2 {
+                let (map, done) = (&map, &done);
+                s.spawn(move || {
+                    let mut rng = StdRng::seed_from_u64(t);
+                    for _ in 0..50_000 {
+                        let key = rng.gen_range(0..1000);
+                        if key % 10 == 0 {
+                            continue;
+                        }
+                        if rng.gen() {
+                            map.insert(key, key);
+                        } else {
+                            map.remove(&key);
+                        }
+                    }
+                    done.store(true, SeqCst);
+                });
+            }
+
+            for _ in 0..THREADS / 2 {
+                let (map, done) = (&map, &done);
+                s.spawn(move || {
+                    while !done.load(SeqCst) {
+                        let keys = map.range(200..800).map(|(k, _)| k).collect::<Vec<_>>();
+                        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+                        assert!(keys.iter().all(|k| (200..800).contains(k)));
+                        let fixed = keys.iter().filter(|&&k| k % 10 == 0);
+                        assert!(fixed.copied().eq((200..800).step_by(10)));
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn removals_racing_insertions_leave_no_levels_behind() {
+        let map = SkipListMap::new();
+
+        // Half the threads insert a few keys and the other half remove them, so removals often
+        // start while the insertion of the same entry is still linking the levels above the bottom
+        scope(|s| {
+            for t in 0..THREADS {
+                let map = &map;
+                s.spawn(move || {
+                    for i in 0..50_000 {
+                        let key = i % 4;
+                        if t % 2 == 0 {
+                            map.insert(key, i);
+                        } else {
+                            map.remove(&key);
+                        }
+                    }
+                });
+            }
+        });
+        map.check();
+    }
+
+    #[test]
+    fn drop_frees_nodes_left_on_upper_levels() {
+        let values = (0..64).map(|_| Arc::new(())).collect::<Vec<_>>();
+        let map = SkipListMap::new();
+        for (key, value) in values.iter().enumerate() {
+            map.insert(key, value.clone());
+        }
+
+        // Take a tall node off the bottom level only, as a removal would if nothing unlinked the
+        // levels above
+        let guard = &pin();
+        let node = map.head[1].load(Acquire, guard);
+        let entry = unsafe { node.deref() };
+        let position = map.find(|key| *key < entry.key, guard);
+        for next in entry.next.iter() {
+            next.fetch_or(REMOVED, AcqRel, guard);
+        }
+        let next = entry.next[0].load(Acquire, guard).with_tag(0);
+        position.links[0].store(next, Release);
+        unsafe { release(node, 1, guard) };
+
+        drop(map);
+        assert!(values.iter().all(|value| Arc::strong_count(value) == 1));
+    }
+}
@@ -1,4 +1,5 @@
 //! Concurrent data structures
 pub use maps::shared::BasicSharedMap;
+pub use maps::skip_list::SkipListMap;
 mod maps;
 mod treiber_stack;
@@ -5,6 +5,7 @@
 //! # Concurrency Tools
 //!
 //! * [`sync::ds::BasicSharedMap`], a concurrent map that can be cloned and shared between threads
+//! * [`sync::ds::SkipListMap`], a lock-free ordered map that any number of threads can read and write at once
 //! * [`executors::RayonThreadPool`], a thread pool which can wait for all tasks to complete before shutting down
 //!
 //! # Concurrency Primitives
@@ -17,7 +18,7 @@
 //! * [`sync::oneshot::Channel`], a single-producer single-consumer channel that sends a single value
 //! * [`sync::mpmc::Channel`], an unbounded multi-producer multi-consumer channel for message passing
 
-pub use algorithms::sorting;
+pub use algorithms::{searching, selection, sorting};
 pub use concurrent::{executors, sync};
 
 #[macro_use]